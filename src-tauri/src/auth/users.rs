@@ -1,11 +1,18 @@
 //! User storage and management
 //!
-//! Provides in-memory user storage with persistence to JSON file.
-//! The host stores user credentials; clients authenticate via tokens.
+//! Provides in-memory user storage persisted as JSON through the active
+//! storage backend. The host stores user credentials; clients authenticate
+//! via tokens.
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+
+use crate::clock::now_ms;
+use crate::storage::{Space, Storage};
+
+/// User-space key holding all accounts
+const USERS_KEY: &str = "users";
 
 /// User role
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -39,8 +46,8 @@ pub struct User {
 /// User store for managing user accounts
 pub struct UserStore {
     users: RwLock<HashMap<String, User>>,
-    /// Path to persist users (optional)
-    persist_path: Option<String>,
+    /// Backend and key to persist users to (optional)
+    persistence: RwLock<Option<(Arc<dyn Storage>, String)>>,
 }
 
 impl Default for UserStore {
@@ -54,25 +61,68 @@ impl UserStore {
     pub fn new() -> Self {
         Self {
             users: RwLock::new(HashMap::new()),
-            persist_path: None,
+            persistence: RwLock::new(None),
         }
     }
 
-    /// Create a user store with persistence
-    pub fn with_persistence(path: String) -> Self {
-        let store = Self {
-            users: RwLock::new(HashMap::new()),
-            persist_path: Some(path.clone()),
-        };
+    /// Create a user store persisted through a storage backend
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        Self::load(storage, USERS_KEY.to_string())
+    }
+
+    fn load(storage: Arc<dyn Storage>, key: String) -> Self {
+        Self {
+            users: RwLock::new(Self::read_users(storage.as_ref(), &key)),
+            persistence: RwLock::new(Some((storage, key))),
+        }
+    }
 
-        // Try to load existing users
-        if let Ok(data) = std::fs::read_to_string(&path) {
-            if let Ok(users) = serde_json::from_str::<HashMap<String, User>>(&data) {
-                *store.users.write().unwrap() = users;
-            }
+    fn read_users(storage: &dyn Storage, key: &str) -> HashMap<String, User> {
+        // Try to load existing users. Data that fails to parse is moved
+        // aside rather than being overwritten by the next persist, since
+        // user accounts cannot be reconstructed from anything else.
+        let mut users = HashMap::new();
+        match storage.get(Space::Users, key) {
+            Ok(Some(data)) => match serde_json::from_slice::<HashMap<String, User>>(&data) {
+                Ok(loaded) => users = loaded,
+                Err(e) => {
+                    log::error!("User store {} is corrupt: {}", key, e);
+                    match storage.quarantine(Space::Users, key) {
+                        Ok(()) => log::error!("Corrupt user store moved aside"),
+                        Err(e) => log::error!("Failed to move corrupt user store aside: {}", e),
+                    }
+                }
+            },
+            Ok(None) => {}
+            Err(e) => log::error!("Failed to read user store: {}", e),
         }
+        users
+    }
 
-        store
+    /// Re-read accounts from storage (e.g. after a backup was restored).
+    /// Returns the number of users loaded.
+    pub fn reload(&self) -> Result<usize, String> {
+        let persistence = self.persistence.read().map_err(|e| e.to_string())?;
+        let Some((storage, key)) = persistence.as_ref() else {
+            return Ok(0);
+        };
+        let loaded = Self::read_users(storage.as_ref(), key);
+        let count = loaded.len();
+        *self.users.write().map_err(|e| e.to_string())? = loaded;
+        Ok(count)
+    }
+
+    /// Switch persistence to another storage backend (e.g. after the server
+    /// migrated data to it). In-memory accounts are kept and written to the
+    /// new backend if it does not have them yet.
+    pub fn attach_storage(&self, storage: Arc<dyn Storage>) -> Result<(), String> {
+        let has_users = storage.exists(Space::Users, USERS_KEY)?;
+        *self.persistence.write().map_err(|e| e.to_string())? =
+            Some((storage, USERS_KEY.to_string()));
+        if !has_users {
+            self.persist()?;
+        }
+        Ok(())
     }
 
     /// Add a new user
@@ -111,12 +161,7 @@ impl UserStore {
         let mut users = self.users.write().map_err(|e| e.to_string())?;
 
         if let Some(user) = users.get_mut(id) {
-            user.last_login_at = Some(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .map(|d| d.as_millis() as u64)
-                    .unwrap_or(0),
-            );
+            user.last_login_at = Some(now_ms());
         }
 
         drop(users);
@@ -181,13 +226,16 @@ impl UserStore {
             .unwrap_or(false)
     }
 
-    /// Persist users to file
+    /// Persist users to the storage backend
     fn persist(&self) -> Result<(), String> {
-        if let Some(path) = &self.persist_path {
+        let persistence = self.persistence.read().map_err(|e| e.to_string())?;
+        if let Some((storage, key)) = &*persistence {
             let users = self.users.read().map_err(|e| e.to_string())?;
             let json =
-                serde_json::to_string_pretty(&*users).map_err(|e| format!("Serialize error: {}", e))?;
-            std::fs::write(path, json).map_err(|e| format!("Write error: {}", e))?;
+                serde_json::to_vec_pretty(&*users).map_err(|e| format!("Serialize error: {}", e))?;
+            storage
+                .put(Space::Users, key, &json)
+                .map_err(|e| format!("Write error: {}", e))?;
         }
         Ok(())
     }
@@ -196,6 +244,7 @@ impl UserStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::fs::FsStorage;
 
     fn create_test_user(id: &str, username: &str, role: UserRole) -> User {
         User {
@@ -254,4 +303,29 @@ mod tests {
         assert!(store.remove_user("1").unwrap());
         assert!(store.get_user("1").is_none());
     }
+
+    #[test]
+    fn test_corrupt_persistence_file_is_preserved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::write(&path, "{\"user-1\": {").unwrap();
+
+        let store = UserStore::with_storage(Arc::new(FsStorage::new(dir.path().to_path_buf())));
+        assert!(!store.has_users());
+
+        // Adding a user must not clobber the unreadable original
+        store
+            .add_user(create_test_user("user-2", "bob", UserRole::User))
+            .unwrap();
+        let preserved: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with("users.json.corrupt-"))
+            .collect();
+        assert_eq!(preserved.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(preserved[0].path()).unwrap(),
+            "{\"user-1\": {"
+        );
+    }
 }
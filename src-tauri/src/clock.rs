@@ -0,0 +1,19 @@
+//! Wall-clock helpers shared across the stores, server and MCP tools
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current Unix time in milliseconds, or 0 if the clock is before the epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Current Unix time in seconds, or 0 if the clock is before the epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
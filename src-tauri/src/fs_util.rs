@@ -0,0 +1,232 @@
+//! Crash-safe filesystem helpers shared by the on-disk stores
+//!
+//! A plain `std::fs::write` truncates the destination before writing, so a
+//! crash or power loss mid-write leaves a partial file behind. The helpers
+//! here write to a temporary sibling, fsync it, and rename it over the
+//! destination so readers only ever see the old or the new contents.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Suffix for in-flight temporary files. Directory scans should skip these.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Per-process counter that keeps concurrent temp files apart
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Temp files younger than this may belong to a write still in progress in
+/// another process (e.g. the MCP server), so sweeps leave them alone
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Atomically replace `path` with `data`.
+///
+/// The temporary file lives in the same directory so the final rename never
+/// crosses filesystems. Every call gets its own temp name, so two writers
+/// racing on the same destination never share (and tear) one temp file.
+/// The parent directory is synced after the rename so the new directory
+/// entry itself survives a crash.
+pub fn write_atomic(path: &Path, data: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp_path = temp_path_for(path);
+
+    let result = (|| {
+        let mut file = File::options().write(true).create_new(true).open(&tmp_path)?;
+        file.write_all(data.as_ref())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, path)?;
+        sync_parent_dir(path);
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Move an unreadable file aside so it is not overwritten by the next save.
+///
+/// Returns the path it was moved to. Used when a store finds a file it
+/// cannot parse: starting empty is fine, silently destroying the evidence
+/// is not.
+pub fn quarantine_corrupt(path: &Path) -> io::Result<PathBuf> {
+    let millis = crate::clock::now_ms();
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let target = path.with_file_name(format!("{}.corrupt-{}", file_name, millis));
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Whether a directory entry is an in-flight temporary file
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.starts_with('.') && n.ends_with(TEMP_SUFFIX))
+        .unwrap_or(false)
+}
+
+/// Remove temp files in `dir` left by writes that never reached the rename
+/// (e.g. after a crash), descending into subdirectories if `recursive`.
+/// Best-effort; returns how many were removed.
+pub fn remove_stale_temp_files(dir: &Path, recursive: bool) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if recursive {
+                removed += remove_stale_temp_files(&path, true);
+            }
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age >= STALE_TEMP_AGE);
+        if file_type.is_file() && stale && is_temp_file(&path) && fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let seq = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(
+        ".{}.{}-{}{}",
+        file_name,
+        std::process::id(),
+        seq,
+        TEMP_SUFFIX
+    ))
+}
+
+/// Sync the directory containing `path` so a completed rename is durable.
+/// Best-effort: not all platforms allow opening a directory for syncing.
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_atomic_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.json");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        // No temporary files left behind
+        let leftovers: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .flatten()
+            .filter(|e| is_temp_file(&e.path()))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_temp_paths_are_unique() {
+        let path = Path::new("/tmp/data.json");
+        let a = temp_path_for(path);
+        let b = temp_path_for(path);
+        assert_ne!(a, b);
+        assert!(is_temp_file(&a));
+        assert!(is_temp_file(&b));
+    }
+
+    #[test]
+    fn test_concurrent_writes_never_tear() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let body = i.to_string().repeat(64 * 1024);
+                    for _ in 0..10 {
+                        write_atomic(&path, &body).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.len(), 64 * 1024);
+        let first = contents.chars().next().unwrap();
+        assert!(contents.chars().all(|c| c == first));
+    }
+
+    #[test]
+    fn test_write_atomic_failure_keeps_original() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("missing-dir").join("data.json");
+        assert!(write_atomic(&path, "data").is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_stale_temp_files_are_removed() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("docs");
+        fs::create_dir(&nested).unwrap();
+        let old = SystemTime::now() - STALE_TEMP_AGE * 2;
+
+        let stale = temp_path_for(&nested.join("doc-1.json"));
+        File::create(&stale).unwrap().set_modified(old).unwrap();
+        let in_flight = temp_path_for(&nested.join("doc-2.json"));
+        fs::write(&in_flight, "partial").unwrap();
+        let data = nested.join("doc-3.json");
+        File::create(&data).unwrap().set_modified(old).unwrap();
+
+        assert_eq!(remove_stale_temp_files(dir.path(), false), 0);
+        assert_eq!(remove_stale_temp_files(dir.path(), true), 1);
+        assert!(!stale.exists());
+        assert!(in_flight.exists());
+        assert!(data.exists());
+    }
+
+    #[test]
+    fn test_quarantine_corrupt() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(&path, "{not json").unwrap();
+
+        let moved = quarantine_corrupt(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(moved).unwrap(), "{not json");
+    }
+}
@@ -4,8 +4,11 @@
 //! including WebSocket server for Protected Local mode collaboration.
 
 mod auth;
+mod clock;
+mod fs_util;
 mod mcp;
 mod server;
+mod storage;
 
 use auth::{
     create_token, hash_password, verify_password, LoginResponse, SessionToken, TokenConfig, User,
@@ -15,7 +18,7 @@ use mcp::{McpServer, McpStatus};
 use server::{get_local_ips, ServerConfig, ServerStatus, WebSocketServer};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use clock::now_ms;
 use tauri::Manager;
 use tokio::sync::RwLock;
 
@@ -256,10 +259,7 @@ fn create_user(
     let id = nanoid::nanoid!();
 
     // Get current timestamp
-    let created_at = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0);
+    let created_at = now_ms();
 
     let user = User {
         id,
@@ -362,8 +362,8 @@ async fn list_team_documents(
 #[tauri::command]
 async fn save_team_document(
     state: tauri::State<'_, AppState>,
-    document: serde_json::Value,
-) -> Result<(), String> {
+    mut document: serde_json::Value,
+) -> Result<Vec<server::validation::ValidationIssue>, String> {
     let doc_id = document
         .get("id")
         .and_then(|v| v.as_str())
@@ -387,7 +387,9 @@ async fn save_team_document(
     // Check if document exists (for event type)
     let is_new = doc_store.get_metadata(&doc_id).is_none();
 
-    // Save the document
+    // Validate, then save the document
+    let mode = server.get_config().await.validation_mode;
+    let repairs = server::validation::validate_document(&mut document, mode)?;
     doc_store.save_document(document)?;
 
     log::info!("Saved team document '{}' ({})", doc_name, doc_id);
@@ -400,7 +402,7 @@ async fn save_team_document(
     };
     server.broadcast_doc_event(&doc_id, event_type, None).await;
 
-    Ok(())
+    Ok(repairs)
 }
 
 /// Get a team document by ID (host only - direct access)
@@ -432,10 +434,10 @@ async fn delete_team_document(
         .await
         .ok_or("Server not running")?;
 
-    let deleted = doc_store.delete_document(&doc_id)?;
+    let deleted = doc_store.delete_document(&doc_id, None, None)?;
 
     if deleted {
-        log::info!("Deleted team document: {}", doc_id);
+        log::info!("Moved team document to trash: {}", doc_id);
 
         // Broadcast delete event to connected clients
         server
@@ -446,6 +448,248 @@ async fn delete_team_document(
     Ok(deleted)
 }
 
+/// List documents in the trash (host only)
+#[tauri::command]
+async fn list_team_trash(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<server::trash::TrashEntry>, String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    Ok(doc_store.list_trash())
+}
+
+/// Restore a document from the trash (host only)
+#[tauri::command]
+async fn restore_team_document_from_trash(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+) -> Result<(), String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    doc_store.restore_from_trash(&doc_id)?;
+
+    log::info!("Restored team document from trash: {}", doc_id);
+
+    server
+        .broadcast_doc_event(&doc_id, server::protocol::DocEventType::Created, None)
+        .await;
+
+    Ok(())
+}
+
+/// List recorded versions of a team document, newest first (host only)
+#[tauri::command]
+async fn list_team_document_versions(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+) -> Result<Vec<server::history::VersionInfo>, String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    doc_store.list_versions(&doc_id)
+}
+
+/// Get a team document as it was at a recorded version (host only)
+#[tauri::command]
+async fn get_team_document_version(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+    server_version: u64,
+) -> Result<serde_json::Value, String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    doc_store.get_version(&doc_id, server_version)
+}
+
+/// Restore a recorded version of a team document (host only)
+#[tauri::command]
+async fn restore_team_document_version(
+    state: tauri::State<'_, AppState>,
+    doc_id: String,
+    server_version: u64,
+) -> Result<u64, String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    let metadata = doc_store.restore_version(&doc_id, server_version, None, None)?;
+
+    log::info!("Restored team document {} from version {}", doc_id, server_version);
+
+    server
+        .broadcast_doc_event(&doc_id, server::protocol::DocEventType::Updated, None)
+        .await;
+
+    Ok(metadata.server_version)
+}
+
+/// Get the version history snapshot and retention policy
+#[tauri::command]
+async fn get_history_config(
+    state: tauri::State<'_, AppState>,
+) -> Result<server::history::HistoryConfig, String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    Ok(doc_store.history_config())
+}
+
+/// Update the version history snapshot and retention policy
+#[tauri::command]
+async fn set_history_config(
+    state: tauri::State<'_, AppState>,
+    config: server::history::HistoryConfig,
+) -> Result<(), String> {
+    let server = state.server.read().await;
+    let doc_store = server
+        .get_doc_store()
+        .await
+        .ok_or("Server not running")?;
+
+    doc_store.set_history_config(config)
+}
+
+/// Storage usage per user against the configured quotas (host only)
+#[tauri::command]
+async fn get_storage_usage(
+    state: tauri::State<'_, AppState>,
+) -> Result<server::quotas::UsageReport, String> {
+    let server = state.server.read().await;
+    server.usage_report().await.ok_or_else(|| "Server not running".to_string())
+}
+
+/// Page through stored blobs with size, type, uploader and the documents
+/// using them (host only)
+#[tauri::command]
+async fn list_blob_inventory(
+    state: tauri::State<'_, AppState>,
+    query: server::blob_inventory::BlobQuery,
+) -> Result<server::blob_inventory::BlobPage, String> {
+    let server = state.server.read().await;
+    server.blob_inventory(query).await.ok_or_else(|| "Server not running".to_string())
+}
+
+/// Blob storage per uploader and per document (host only)
+#[tauri::command]
+async fn get_blob_usage(
+    state: tauri::State<'_, AppState>,
+) -> Result<server::blob_inventory::BlobUsageSummary, String> {
+    let server = state.server.read().await;
+    server.blob_usage().await.ok_or_else(|| "Server not running".to_string())
+}
+
+/// Delete blobs no document, template or version uses any more (host
+/// only). With `dry_run` only report what would be reclaimed.
+#[tauri::command]
+async fn collect_blob_garbage(
+    state: tauri::State<'_, AppState>,
+    dry_run: bool,
+) -> Result<server::blob_gc::GcReport, String> {
+    let server = state.server.read().await;
+    server.collect_blob_garbage(dry_run).await
+}
+
+/// Check stored blobs for corruption, missing content and files absent
+/// from the index (host only). With `repair` corrupt blobs are quarantined
+/// and unindexed ones re-indexed.
+#[tauri::command]
+async fn scrub_blobs(
+    state: tauri::State<'_, AppState>,
+    repair: bool,
+) -> Result<server::blob_scrub::ScrubReport, String> {
+    let server = state.server.read().await;
+    server.scrub_blobs(repair).await
+}
+
+/// Provide the passphrase unlocking encrypted team storage. Call before
+/// `start_server`; it is kept in memory only and used once.
+#[tauri::command]
+async fn unlock_team_storage(
+    state: tauri::State<'_, AppState>,
+    passphrase: String,
+) -> Result<(), String> {
+    let server = state.server.read().await;
+    server.set_storage_passphrase(passphrase).await;
+    Ok(())
+}
+
+/// Re-encrypt team storage under a new passphrase or key file (host only).
+/// Take a backup first: on the filesystem backend an interrupted re-key
+/// leaves values under both keys.
+#[tauri::command]
+async fn rekey_team_storage(
+    state: tauri::State<'_, AppState>,
+    source: storage::encrypted::KeySource,
+    passphrase: Option<String>,
+) -> Result<usize, String> {
+    let server = state.server.read().await;
+    server.rekey_storage(source, passphrase).await
+}
+
+/// Back up the team store now (documents, blobs, users, MCP config)
+#[tauri::command]
+async fn create_backup(
+    state: tauri::State<'_, AppState>,
+) -> Result<server::backup::BackupInfo, String> {
+    let server = state.server.read().await;
+    server.create_backup().await
+}
+
+/// List backups, newest first
+#[tauri::command]
+async fn list_backups(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<server::backup::BackupInfo>, String> {
+    let server = state.server.read().await;
+    server.list_backups().await
+}
+
+/// Check a backup against its manifest checksums
+#[tauri::command]
+async fn verify_backup(
+    state: tauri::State<'_, AppState>,
+    id: String,
+) -> Result<server::backup::VerifyReport, String> {
+    let server = state.server.read().await;
+    server.verify_backup(id).await
+}
+
+/// Restore a backup. Both the collaboration and MCP servers must be
+/// stopped; the current data is backed up first.
+#[tauri::command]
+async fn restore_backup(
+    state: tauri::State<'_, AppState>,
+    id: String,
+) -> Result<server::backup::BackupInfo, String> {
+    if let Some(mcp) = state.mcp_server.read().await.as_ref() {
+        if mcp.is_running().await {
+            return Err("Stop the MCP server before restoring a backup".to_string());
+        }
+    }
+    let server = state.server.read().await;
+    server.restore_backup(id).await
+}
+
 // ============ MCP Server Commands ============
 
 /// Get current MCP server status (running, port, address).
@@ -755,14 +999,13 @@ pub fn run() {
             std::fs::create_dir_all(&app_data_dir)
                 .map_err(|e| format!("Failed to create app data directory: {}", e))?;
 
-            // Create users.json path
-            let users_path = app_data_dir.join("users.json");
-            let users_path_str = users_path.to_string_lossy().to_string();
-
-            log::info!("User store path: {}", users_path_str);
+            // Users live in whichever storage backend currently holds the
+            // data; the server re-attaches the store if it migrates backends
+            let storage = storage::open_configured(&app_data_dir)?;
+            log::info!("User store backend: {:?}", storage.backend());
 
             // Initialize UserStore with persistence
-            let user_store = Arc::new(UserStore::with_persistence(users_path_str));
+            let user_store = Arc::new(UserStore::with_storage(storage));
             let has_existing_users = user_store.has_users();
             log::info!("Existing users found: {}", has_existing_users);
 
@@ -784,29 +1027,27 @@ pub fn run() {
 
             log::info!("WebSocket server initialized with document store and user store");
 
+            let shared_stores = server.shared_stores();
             let server_arc = Arc::new(RwLock::new(server));
 
-            // Build the MCP server. on_doc_changed forwards into the
-            // WebSocketServer's broadcast so connected clients reload the
-            // mutated doc. We capture an Arc to the same RwLock the rest
-            // of AppState uses, dispatched onto the Tauri async runtime.
+            // Build the MCP server. While the collaboration server runs, the
+            // MCP server writes through its stores (`shared_stores`) so
+            // neither overwrites the other's indexes. on_doc_changed makes the
+            // WebSocketServer broadcast the write right away so connected
+            // clients reload the mutated doc. We capture an Arc to the same
+            // RwLock the rest of AppState uses, dispatched onto the Tauri
+            // async runtime.
             let server_for_mcp = server_arc.clone();
             let on_doc_changed: Arc<dyn Fn(String) + Send + Sync> =
                 Arc::new(move |doc_id: String| {
                     let server = server_for_mcp.clone();
                     tauri::async_runtime::spawn(async move {
                         let guard = server.read().await;
-                        guard
-                            .broadcast_doc_event(
-                                &doc_id,
-                                server::protocol::DocEventType::Updated,
-                                None,
-                            )
-                            .await;
+                        guard.document_changed(&doc_id).await;
                     });
                 });
 
-            let mcp_server = match McpServer::new(app_data_dir.clone(), on_doc_changed) {
+            let mcp_server = match McpServer::new(app_data_dir.clone(), shared_stores, on_doc_changed) {
                 Ok(s) => Some(Arc::new(s)),
                 Err(e) => {
                     log::error!("Failed to initialize MCP server: {}", e);
@@ -876,6 +1117,32 @@ pub fn run() {
             save_team_document,
             get_team_document,
             delete_team_document,
+            // Team document trash
+            list_team_trash,
+            restore_team_document_from_trash,
+            // Team document version history
+            list_team_document_versions,
+            get_team_document_version,
+            restore_team_document_version,
+            get_history_config,
+            set_history_config,
+            // Storage quotas
+            get_storage_usage,
+            // Blob inventory
+            list_blob_inventory,
+            get_blob_usage,
+            // Blob collection
+            collect_blob_garbage,
+            // Blob integrity
+            scrub_blobs,
+            // Encryption at rest
+            unlock_team_storage,
+            rekey_team_storage,
+            // Backups
+            create_backup,
+            list_backups,
+            verify_backup,
+            restore_backup,
             // Documentation
             open_docs,
             // MCP server
@@ -415,6 +415,111 @@ fn text(dsl: &DslShape, id: &str) -> Value {
     Value::Object(o)
 }
 
+/// A stored blob to show in a `file` shape
+#[derive(Debug, Clone)]
+pub struct FileSpec {
+    pub blob_ref: String,
+    pub file_name: String,
+    pub mime_type: String,
+    pub file_size: u64,
+    /// Pixel dimensions of images
+    pub dimensions: Option<(u32, u32)>,
+    /// Number of pages of PDFs
+    pub page_count: Option<u32>,
+}
+
+/// Viewer category of a file. Keep in sync with `detectFileCategory` in
+/// src/utils/fileUtils.ts.
+pub fn file_category(mime_type: &str, file_name: &str) -> &'static str {
+    let mime = mime_type.to_ascii_lowercase();
+    let ext = file_name.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    let ext = ext.as_str();
+
+    if mime == "application/pdf" || ext == "pdf" {
+        return "pdf";
+    }
+    let spreadsheet_types = [
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "application/vnd.ms-excel",
+        "application/vnd.oasis.opendocument.spreadsheet",
+        "text/csv",
+    ];
+    if spreadsheet_types.contains(&mime.as_str()) || ["xlsx", "xls", "ods", "csv", "tsv"].contains(&ext) {
+        return "spreadsheet";
+    }
+    let image_exts = ["png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "bmp", "ico", "tiff", "tif"];
+    if mime.starts_with("image/") || image_exts.contains(&ext) {
+        return "image";
+    }
+    let text_types = [
+        "application/json",
+        "application/xml",
+        "application/javascript",
+        "application/typescript",
+        "application/x-yaml",
+        "application/toml",
+    ];
+    let text_exts = [
+        "txt", "md", "markdown", "json", "xml", "yaml", "yml", "toml", "js", "ts", "jsx", "tsx", "css", "scss",
+        "less", "html", "htm", "py", "rb", "rs", "go", "java", "kt", "swift", "c", "cpp", "h", "hpp", "sh",
+        "bash", "zsh", "fish", "ps1", "bat", "cmd", "sql", "graphql", "gql", "proto", "env", "ini", "cfg",
+        "conf", "config", "log", "diff", "patch", "dockerfile", "makefile", "cmake",
+    ];
+    if mime.starts_with("text/") || text_types.contains(&mime.as_str()) || text_exts.contains(&ext) {
+        return "text";
+    }
+    "generic"
+}
+
+/// Build a `file` shape showing a stored blob, `w`×`h` (default size if
+/// omitted) at `x`, `y`. Defaults from DEFAULT_FILE_SHAPE in
+/// src/shapes/Shape.ts.
+pub fn file_shape_json(
+    id: &str,
+    x: f64,
+    y: f64,
+    w: Option<f64>,
+    h: Option<f64>,
+    label: Option<&str>,
+    file: &FileSpec,
+) -> Value {
+    let mut o = serde_json::Map::new();
+    o.insert("id".into(), json!(id));
+    o.insert("type".into(), json!("file"));
+    o.insert("x".into(), json!(x));
+    o.insert("y".into(), json!(y));
+    o.insert("rotation".into(), json!(0));
+    o.insert("opacity".into(), json!(1));
+    o.insert("locked".into(), json!(false));
+    o.insert("visible".into(), json!(true));
+    o.insert("fill".into(), json!("#f8fafc"));
+    o.insert("stroke".into(), json!("#cbd5e1"));
+    o.insert("strokeWidth".into(), json!(1));
+    o.insert("width".into(), json!(w.unwrap_or(200.0)));
+    o.insert("height".into(), json!(h.unwrap_or(160.0)));
+    o.insert("blobRef".into(), json!(file.blob_ref));
+    o.insert("fileName".into(), json!(file.file_name));
+    o.insert("mimeType".into(), json!(file.mime_type));
+    o.insert("fileSize".into(), json!(file.file_size));
+    o.insert("fileCategory".into(), json!(file_category(&file.mime_type, &file.file_name)));
+    o.insert("labelFontSize".into(), json!(12));
+    o.insert("labelColor".into(), json!("#475569"));
+    if let Some(label) = label {
+        o.insert("label".into(), json!(label));
+    }
+    let mut preview = serde_json::Map::new();
+    if let Some(pages) = file.page_count {
+        preview.insert("pageCount".into(), json!(pages));
+    }
+    if let Some((width, height)) = file.dimensions {
+        preview.insert("dimensions".into(), json!({"width": width, "height": height}));
+    }
+    if !preview.is_empty() {
+        o.insert("preview".into(), Value::Object(preview));
+    }
+    Value::Object(o)
+}
+
 fn resolve_style(
     style: Option<&DslStyle>,
     default_fill: Option<&str>,
@@ -493,6 +598,30 @@ mod tests {
         assert_eq!(s["cornerRadius"], 0);
     }
 
+    #[test]
+    fn file_shape_carries_blob_fields_and_category() {
+        let file = FileSpec {
+            blob_ref: "abc".into(),
+            file_name: "shot.png".into(),
+            mime_type: "image/png".into(),
+            file_size: 1234,
+            dimensions: Some((640, 480)),
+            page_count: None,
+        };
+        let s = file_shape_json("f1", 5.0, 6.0, None, None, None, &file);
+        assert_eq!(s["type"], "file");
+        assert_eq!(s["blobRef"], "abc");
+        assert_eq!(s["fileSize"], 1234);
+        assert_eq!(s["fileCategory"], "image");
+        assert_eq!((s["width"].as_f64(), s["height"].as_f64()), (Some(200.0), Some(160.0)));
+        assert_eq!(s["preview"]["dimensions"]["width"], 640);
+
+        assert_eq!(file_category("application/octet-stream", "report.PDF"), "pdf");
+        assert_eq!(file_category("text/csv", "data.csv"), "spreadsheet");
+        assert_eq!(file_category("application/json", "x"), "text");
+        assert_eq!(file_category("application/zip", "a.zip"), "generic");
+    }
+
     #[test]
     fn ellipse_converts_diameter_to_radius() {
         let mut d = make(DslKind::Ellipse, 0.0, 0.0);
@@ -10,6 +10,8 @@ use std::sync::RwLock;
 
 use serde::{Deserialize, Serialize};
 
+use crate::server::validation::ValidationMode;
+
 const CONFIG_FILENAME: &str = "mcp_config.json";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +22,10 @@ pub struct McpFeatureConfig {
     /// hidden from MCP and the mirror directory is wiped.
     #[serde(default = "default_true")]
     pub local_access_enabled: bool,
+    /// How tool writes that leave a document structurally invalid are
+    /// handled: repaired (`lenient`, the default) or rejected (`strict`).
+    #[serde(default)]
+    pub validation_mode: ValidationMode,
 }
 
 fn default_true() -> bool {
@@ -30,6 +36,7 @@ impl Default for McpFeatureConfig {
     fn default() -> Self {
         Self {
             local_access_enabled: true,
+            validation_mode: ValidationMode::default(),
         }
     }
 }
@@ -69,6 +76,13 @@ impl McpFeatureConfigStore {
             .unwrap_or(true)
     }
 
+    pub fn validation_mode(&self) -> ValidationMode {
+        self.config
+            .read()
+            .map(|c| c.validation_mode)
+            .unwrap_or_default()
+    }
+
     /// Update the toggle and persist. Returns the new value.
     pub fn set_local_access(&self, enabled: bool) -> Result<bool, String> {
         {
@@ -18,6 +18,8 @@ use std::sync::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::fs_util;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MirroredDocumentMetadata {
@@ -88,7 +90,7 @@ impl LocalDocumentMirror {
 
         let json = serde_json::to_string_pretty(&doc)
             .map_err(|e| format!("Serialize error: {}", e))?;
-        std::fs::write(self.doc_path(&meta.id), json)
+        fs_util::write_atomic(&self.doc_path(&meta.id), json)
             .map_err(|e| format!("Failed to write mirrored doc: {}", e))?;
 
         let mut guard = self
@@ -21,7 +21,11 @@ use std::sync::Arc;
 use tokio::sync::oneshot;
 use tokio::sync::RwLock;
 
+use crate::server::blobs::BlobStore;
 use crate::server::documents::DocumentStore;
+use crate::server::watcher::{self, StoreSync};
+use crate::server::SharedStores;
+use crate::storage;
 use config::McpFeatureConfigStore;
 use local_mirror::LocalDocumentMirror;
 use token::TokenStore;
@@ -54,32 +58,37 @@ pub struct McpServer {
     bound_port: RwLock<u16>,
     shutdown: RwLock<Option<oneshot::Sender<()>>>,
     token: Arc<TokenStore>,
-    doc_store: Arc<DocumentStore>,
+    app_data_dir: PathBuf,
     local_mirror: Arc<LocalDocumentMirror>,
     feature_config: Arc<McpFeatureConfigStore>,
+    /// Stores of the collaboration server while it runs
+    shared_stores: SharedStores,
     on_doc_changed: Arc<dyn Fn(String) + Send + Sync>,
 }
 
 impl McpServer {
     /// Build a new MCP server. The token is loaded (or generated) from
-    /// `app_data_dir`. `on_doc_changed` is invoked after each successful
-    /// write so the caller can broadcast a `DocEvent` to connected clients.
+    /// `app_data_dir`. Tools use the collaboration server's stores from
+    /// `shared_stores` while it runs, and stores of their own otherwise.
+    /// `on_doc_changed` is invoked after each successful write so the caller
+    /// can broadcast a `DocEvent` to connected clients.
     pub fn new(
         app_data_dir: PathBuf,
+        shared_stores: SharedStores,
         on_doc_changed: Arc<dyn Fn(String) + Send + Sync>,
     ) -> Result<Self, String> {
         let token = Arc::new(TokenStore::load_or_create(&app_data_dir)?);
         let feature_config = Arc::new(McpFeatureConfigStore::load_or_create(&app_data_dir));
         let local_mirror = Arc::new(LocalDocumentMirror::new(app_data_dir.clone()));
-        let doc_store = Arc::new(DocumentStore::new(app_data_dir));
         Ok(Self {
             config: RwLock::new(McpConfig::default()),
             bound_port: RwLock::new(0),
             shutdown: RwLock::new(None),
             token,
-            doc_store,
+            app_data_dir,
             local_mirror,
             feature_config,
+            shared_stores,
             on_doc_changed,
         })
     }
@@ -144,10 +153,31 @@ impl McpServer {
             .local_addr()
             .map_err(|e| format!("Failed to read MCP local addr: {}", e))?;
 
+        // Open whichever backend the collaboration server last selected so
+        // both see the same team documents, decrypted with the key it unlocked
+        let storage = storage::encrypted::attach(storage::open_configured(&self.app_data_dir)?)?;
+        let doc_store = Arc::new(DocumentStore::with_storage(storage.clone()));
+        let blob_store = Arc::new(BlobStore::with_storage(storage.clone()));
+
+        // Follow writes made by the collaboration server. Without a watcher
+        // every tool call reloads the index instead.
+        let sync = Arc::new(StoreSync::new(doc_store.clone(), Some(blob_store.clone())));
+        let watch_task = match watcher::spawn_watch_task(storage.as_ref(), sync, |_| {}) {
+            Ok(task) => Some(task),
+            Err(e) => {
+                log::warn!("MCP server will reload the index on every call: {}", e);
+                None
+            }
+        };
+
         let state = McpAppState {
-            doc_store: self.doc_store.clone(),
+            watching: watch_task.is_some(),
+            doc_store,
+            blob_store,
+            shared_stores: self.shared_stores.clone(),
             local_mirror: self.local_mirror.clone(),
             feature_config: self.feature_config.clone(),
+            app_data_dir: self.app_data_dir.clone(),
             token: self.token.clone(),
             on_doc_changed: self.on_doc_changed.clone(),
         };
@@ -164,6 +194,9 @@ impl McpServer {
             if let Err(e) = server.await {
                 log::error!("MCP server error: {}", e);
             }
+            if let Some(task) = watch_task {
+                task.abort();
+            }
         });
 
         log::info!("MCP server listening on http://{}", local_addr);
@@ -11,12 +11,19 @@
 
 use std::sync::Arc;
 
+use base64::Engine as _;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
+use crate::clock::now_ms;
+use crate::server::blobs::BlobStore;
 use crate::server::documents::DocumentStore;
+use crate::server::previews;
+use crate::server::quotas::UsageSnapshot;
+use crate::server::validation::{self, ValidationIssue, ValidationMode};
+use crate::server::ServerConfig;
 
-use super::adapter::{apply_dsl_patch, dsl_to_shape_json, shape_json_to_dsl, DslPatch, DslShape};
+use super::adapter::{apply_dsl_patch, dsl_to_shape_json, file_shape_json, shape_json_to_dsl, DslPatch, DslShape, FileSpec};
 use super::local_mirror::LocalDocumentMirror;
 
 /// Where a document came from, surfaced in MCP tool results so clients
@@ -25,14 +32,23 @@ use super::local_mirror::LocalDocumentMirror;
 const SOURCE_TEAM: &str = "team";
 const SOURCE_LOCAL: &str = "local";
 
+/// Uploader recorded for blobs stored through MCP
+const MCP_UPLOADER: &str = "mcp";
+
 /// Bundle of stores + flags passed to tool handlers. The tools used to
 /// receive only the team `DocumentStore`; this grew when local-document
 /// mirroring was added so the foundation could read renderer-owned docs
 /// alongside team-shared ones.
 pub struct ToolContext<'a> {
     pub team: &'a Arc<DocumentStore>,
+    /// Team blob store, for files embedded in team documents
+    pub blobs: &'a Arc<BlobStore>,
     pub local: &'a Arc<LocalDocumentMirror>,
     pub local_enabled: bool,
+    pub validation_mode: ValidationMode,
+    /// The collaboration server's saved config, for its upload policy and
+    /// quotas
+    pub server_config: &'a ServerConfig,
 }
 
 /// A single MCP tool descriptor (name, description, input schema).
@@ -153,6 +169,40 @@ pub fn descriptors() -> Vec<ToolDescriptor> {
                 "additionalProperties": false
             }),
         },
+        ToolDescriptor {
+            name: "diagrammer.add_file",
+            description:
+                "Embed a file (screenshot, PDF, ...) in a team document as a file shape. Supply exactly one of: base64 'data', an embedded MCP 'resource' ({uri, mimeType?, blob or text}), or the 'blobRef' of a file already stored. Content is stored once per SHA-256 hash. x/y is the shape's top-left; w/h default to 200x160. Returns the shape id and blob details. Refuses local documents.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "docId": {"type": "string"},
+                    "pageId": {"type": "string"},
+                    "fileName": {"type": "string", "description": "Defaults to the last segment of the resource URI."},
+                    "data": {"type": "string", "description": "Base64-encoded file content."},
+                    "mimeType": {"type": "string", "description": "Hint for 'data'; the stored type is detected from the content."},
+                    "resource": {
+                        "type": "object",
+                        "properties": {
+                            "uri": {"type": "string"},
+                            "mimeType": {"type": "string"},
+                            "blob": {"type": "string", "description": "Base64-encoded content."},
+                            "text": {"type": "string"}
+                        },
+                        "required": ["uri"]
+                    },
+                    "blobRef": {"type": "string", "description": "SHA-256 hash of a stored file."},
+                    "x": {"type": "number"},
+                    "y": {"type": "number"},
+                    "w": {"type": "number"},
+                    "h": {"type": "number"},
+                    "label": {"type": "string"},
+                    "id": {"type": "string"}
+                },
+                "required": ["docId", "pageId"],
+                "additionalProperties": false
+            }),
+        },
     ]
 }
 
@@ -228,10 +278,6 @@ pub struct ToolOutcome {
 /// Dispatch a `tools/call` request. `name` is the tool name as advertised
 /// in `descriptors()`; `args` is the `arguments` object from the call.
 pub fn dispatch(ctx: &ToolContext, name: &str, args: &Value) -> Result<ToolOutcome, String> {
-    // Always refresh the index from disk first so reads see any writes the
-    // WebSocket server (or another MCP write) made since last call.
-    ctx.team.reload_index();
-
     match name {
         "diagrammer.list_documents" => list_documents(ctx),
         "diagrammer.get_document" => get_document(ctx, args),
@@ -240,6 +286,7 @@ pub fn dispatch(ctx: &ToolContext, name: &str, args: &Value) -> Result<ToolOutco
         "diagrammer.add_shapes" => add_shapes(ctx, args),
         "diagrammer.connect" => connect(ctx, args),
         "diagrammer.update_shape" => update_shape(ctx, args),
+        "diagrammer.add_file" => add_file(ctx, args),
         _ => Err(format!("Unknown tool: {}", name)),
     }
 }
@@ -416,12 +463,6 @@ fn reject_if_local(ctx: &ToolContext, doc_id: &str) -> Result<(), String> {
     Ok(())
 }
 
-fn now_ms() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0)
-}
 
 fn lock_warning(doc: &Value) -> Option<String> {
     doc.get("lockedBy")
@@ -429,6 +470,14 @@ fn lock_warning(doc: &Value) -> Option<String> {
         .map(|uid| format!("Document is locked by user '{}' — write may be overwritten.", uid))
 }
 
+/// Validate and persist a team document. Returns the repairs made in
+/// lenient mode, `None` if the document was already consistent.
+fn save_team_doc(ctx: &ToolContext, mut doc: Value) -> Result<Option<Vec<ValidationIssue>>, String> {
+    let repairs = validation::validate_document(&mut doc, ctx.validation_mode)?;
+    ctx.team.save_document(doc)?;
+    Ok(if repairs.is_empty() { None } else { Some(repairs) })
+}
+
 /// Insert one DSL shape into a doc that's already in memory. Returns the
 /// id used. Does **not** save — callers batch a sequence of these and
 /// then call `save_document` once, so a partial failure rolls back by
@@ -439,7 +488,13 @@ fn append_shape_in_place(doc: &mut Value, page_id: &str, shape: &DslShape) -> Re
         .clone()
         .unwrap_or_else(|| format!("shape-{}", nanoid::nanoid!(10)));
     let shape_json = dsl_to_shape_json(shape, &id);
+    insert_shape_json(doc, page_id, &id, shape_json)?;
+    Ok(id)
+}
 
+/// Add a built shape to the top of a page's z-order. Like
+/// `append_shape_in_place`, does not save.
+fn insert_shape_json(doc: &mut Value, page_id: &str, id: &str, shape_json: Value) -> Result<(), String> {
     let pages = doc
         .get_mut("pages")
         .and_then(|v| v.as_object_mut())
@@ -454,19 +509,19 @@ fn append_shape_in_place(doc: &mut Value, page_id: &str, shape: &DslShape) -> Re
         .or_insert_with(|| json!({}))
         .as_object_mut()
         .ok_or("Page 'shapes' is not an object")?;
-    if shapes.contains_key(&id) {
+    if shapes.contains_key(id) {
         return Err(format!("Shape id '{}' already exists on page", id));
     }
-    shapes.insert(id.clone(), shape_json);
+    shapes.insert(id.to_string(), shape_json);
 
     let order = page
         .entry("shapeOrder")
         .or_insert_with(|| json!([]))
         .as_array_mut()
         .ok_or("Page 'shapeOrder' is not an array")?;
-    order.push(json!(id.clone()));
+    order.push(json!(id));
 
-    Ok(id)
+    Ok(())
 }
 
 fn add_shape(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String> {
@@ -478,10 +533,10 @@ fn add_shape(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String> {
     let warning = lock_warning(&doc);
     let id = append_shape_in_place(&mut doc, &parsed.page_id, &parsed.shape)?;
     stamp_modified(&mut doc, &parsed.page_id);
-    ctx.team.save_document(doc)?;
+    let repairs = save_team_doc(ctx, doc)?;
 
     Ok(ToolOutcome {
-        result: json!({"id": id, "warning": warning}),
+        result: json!({"id": id, "warning": warning, "repairs": repairs}),
         changed_doc_id: Some(parsed.doc_id),
     })
 }
@@ -526,10 +581,10 @@ fn add_shapes(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String> {
         }
     }
     stamp_modified(&mut doc, &parsed.page_id);
-    ctx.team.save_document(doc)?;
+    let repairs = save_team_doc(ctx, doc)?;
 
     Ok(ToolOutcome {
-        result: json!({"ids": ids, "warning": warning}),
+        result: json!({"ids": ids, "warning": warning, "repairs": repairs}),
         changed_doc_id: Some(parsed.doc_id),
     })
 }
@@ -596,10 +651,10 @@ fn connect(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String> {
     };
     let id = append_shape_in_place(&mut doc, &parsed.page_id, &dsl)?;
     stamp_modified(&mut doc, &parsed.page_id);
-    ctx.team.save_document(doc)?;
+    let repairs = save_team_doc(ctx, doc)?;
 
     Ok(ToolOutcome {
-        result: json!({"id": id, "warning": warning}),
+        result: json!({"id": id, "warning": warning, "repairs": repairs}),
         changed_doc_id: Some(parsed.doc_id),
     })
 }
@@ -644,10 +699,177 @@ fn update_shape(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String>
     }
 
     stamp_modified(&mut doc, &parsed.page_id);
-    ctx.team.save_document(doc)?;
+    let repairs = save_team_doc(ctx, doc)?;
+
+    Ok(ToolOutcome {
+        result: json!({"id": parsed.id, "changed": changed, "warning": warning, "repairs": repairs}),
+        changed_doc_id: Some(parsed.doc_id),
+    })
+}
+
+#[derive(Deserialize)]
+struct ResourceContents {
+    uri: String,
+    #[serde(rename = "mimeType")]
+    mime_type: Option<String>,
+    blob: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AddFileArgs {
+    #[serde(rename = "docId")]
+    doc_id: String,
+    #[serde(rename = "pageId")]
+    page_id: String,
+    #[serde(rename = "fileName")]
+    file_name: Option<String>,
+    data: Option<String>,
+    #[serde(rename = "mimeType")]
+    mime_type: Option<String>,
+    resource: Option<ResourceContents>,
+    #[serde(rename = "blobRef")]
+    blob_ref: Option<String>,
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    w: Option<f64>,
+    h: Option<f64>,
+    label: Option<String>,
+    id: Option<String>,
+}
+
+fn decode_base64(field: &str, data: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| format!("'{}' is not valid base64: {}", field, e))
+}
+
+/// Store new content in the team blob store, or find an already stored
+/// blob. Returns the blob's hash and whether it was stored before.
+///
+/// New content is checked against the collaboration server's upload policy
+/// and quotas, the same as uploads over HTTP.
+fn store_file_content(ctx: &ToolContext, parsed: &AddFileArgs) -> Result<(String, bool), String> {
+    let (content, declared) = match (&parsed.data, &parsed.resource, &parsed.blob_ref) {
+        (Some(data), None, None) => (decode_base64("data", data)?, parsed.mime_type.clone()),
+        (None, Some(resource), None) => {
+            let content = match (&resource.blob, &resource.text) {
+                (Some(blob), None) => decode_base64("resource.blob", blob)?,
+                (None, Some(text)) => text.clone().into_bytes(),
+                _ => return Err("'resource' needs exactly one of 'blob' or 'text'".into()),
+            };
+            (content, resource.mime_type.clone())
+        }
+        (None, None, Some(hash)) => {
+            ctx.blobs.reload_index();
+            if ctx.blobs.get_metadata(hash).is_none() {
+                return Err(format!("No stored file with blobRef '{}'", hash));
+            }
+            return Ok((hash.clone(), true));
+        }
+        _ => return Err("Supply exactly one of 'data', 'resource' or 'blobRef'".into()),
+    };
+
+    let policy = &ctx.server_config.blob_policy;
+    policy.check_size(content.len() as u64)?;
+    let content_type = policy.resolve(&content, true, declared.as_deref().unwrap_or(""))?;
+    let hash = BlobStore::compute_hash(&content);
+
+    // The collaboration server may have written to the index meanwhile
+    ctx.blobs.reload_index();
+    let existed = ctx.blobs.get_metadata(&hash).is_some();
+    if !existed {
+        let usage = UsageSnapshot::collect(ctx.team, ctx.blobs);
+        ctx.server_config
+            .quotas
+            .check_blob_upload(&usage, MCP_UPLOADER, content.len() as u64)?;
+        ctx.blobs.save_blob(&hash, &content, &content_type.mime_type, MCP_UPLOADER)?;
+        ctx.blobs.set_content_type(&hash, &content_type.mime_type, content_type.active)?;
+    }
+    Ok((hash, existed))
+}
+
+/// Add `hash` to the document's `blobReferences` if it is not listed yet
+fn add_blob_reference(doc: &mut Value, hash: &str) -> Result<(), String> {
+    let obj = doc.as_object_mut().ok_or("Document is not an object")?;
+    let references = obj
+        .entry("blobReferences")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or("Document 'blobReferences' is not an array")?;
+    if !references.iter().any(|r| r.as_str() == Some(hash)) {
+        references.push(json!(hash));
+    }
+    Ok(())
+}
+
+fn add_file(ctx: &ToolContext, args: &Value) -> Result<ToolOutcome, String> {
+    let parsed: AddFileArgs =
+        serde_json::from_value(args.clone()).map_err(|e| format!("Invalid arguments: {}", e))?;
+
+    reject_if_local(ctx, &parsed.doc_id)?;
+    let mut doc = ctx.team.get_document(&parsed.doc_id)?;
+    let warning = lock_warning(&doc);
+    let file_name = parsed
+        .file_name
+        .clone()
+        .or_else(|| {
+            parsed
+                .resource
+                .as_ref()
+                .and_then(|r| r.uri.trim_end_matches('/').rsplit('/').next())
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+        })
+        .ok_or("'fileName' is required")?;
+
+    // Stored before the document is saved; if the save fails the blob is
+    // left unreferenced and collected later
+    let (hash, deduplicated) = store_file_content(ctx, &parsed)?;
+    // Dimensions and page count for the shape; thumbnails come along
+    let preview = previews::ensure(ctx.blobs, &hash).unwrap_or_else(|e| {
+        log::warn!("No preview for blob {}: {}", hash, e);
+        Default::default()
+    });
+    let metadata = ctx
+        .blobs
+        .get_metadata(&hash)
+        .ok_or_else(|| format!("Blob '{}' disappeared while storing", hash))?;
+    let file = FileSpec {
+        blob_ref: hash.clone(),
+        file_name,
+        mime_type: metadata.mime_type,
+        file_size: metadata.size,
+        dimensions: preview.width.zip(preview.height),
+        page_count: preview.page_count,
+    };
+
+    let id = parsed
+        .id
+        .clone()
+        .unwrap_or_else(|| format!("shape-{}", nanoid::nanoid!(10)));
+    let shape = file_shape_json(&id, parsed.x, parsed.y, parsed.w, parsed.h, parsed.label.as_deref(), &file);
+    let file_category = shape["fileCategory"].clone();
+    insert_shape_json(&mut doc, &parsed.page_id, &id, shape)?;
+    add_blob_reference(&mut doc, &hash)?;
+    stamp_modified(&mut doc, &parsed.page_id);
+    let repairs = save_team_doc(ctx, doc)?;
+    // Lets collaborators who can read the document fetch the blob
+    ctx.blobs.add_document(&hash, &parsed.doc_id)?;
 
     Ok(ToolOutcome {
-        result: json!({"id": parsed.id, "changed": changed, "warning": warning}),
+        result: json!({
+            "id": id,
+            "blobRef": hash,
+            "mimeType": file.mime_type,
+            "fileSize": file.file_size,
+            "fileCategory": file_category,
+            "deduplicated": deduplicated,
+            "warning": warning,
+            "repairs": repairs,
+        }),
         changed_doc_id: Some(parsed.doc_id),
     })
 }
@@ -660,15 +882,20 @@ mod tests {
 
     struct Fixture {
         team: Arc<DocumentStore>,
+        blobs: Arc<BlobStore>,
         local: Arc<LocalDocumentMirror>,
+        config: ServerConfig,
     }
 
     impl Fixture {
         fn ctx(&self, local_enabled: bool) -> ToolContext<'_> {
             ToolContext {
                 team: &self.team,
+                blobs: &self.blobs,
                 local: &self.local,
                 local_enabled,
+                validation_mode: ValidationMode::Lenient,
+                server_config: &self.config,
             }
         }
     }
@@ -697,9 +924,10 @@ mod tests {
 
     fn seed(dir: &PathBuf) -> Fixture {
         let team = Arc::new(DocumentStore::new(dir.clone()));
+        let blobs = Arc::new(BlobStore::new(dir.clone()));
         let local = Arc::new(LocalDocumentMirror::new(dir.clone()));
         team.save_document(make_doc("doc1", "p1", "Team Doc")).unwrap();
-        Fixture { team, local }
+        Fixture { team, blobs, local, config: ServerConfig::default() }
     }
 
     #[test]
@@ -1086,4 +1314,131 @@ mod tests {
         let err = dispatch(&f.ctx(true), "diagrammer.nope", &json!({})).unwrap_err();
         assert!(err.contains("Unknown tool"));
     }
+
+    #[test]
+    fn add_shape_refuses_newer_schema() {
+        let dir = TempDir::new().unwrap();
+        let f = seed(&dir.path().to_path_buf());
+
+        let mut future = make_doc("doc2", "p1", "From the future");
+        future["version"] = json!(crate::server::schema::CURRENT_SCHEMA_VERSION + 1);
+        let path = dir.path().join("team_documents").join("docs").join("doc2.json");
+        std::fs::write(&path, future.to_string()).unwrap();
+        f.team.rebuild_index().unwrap();
+
+        let err = dispatch(
+            &f.ctx(true),
+            "diagrammer.add_shape",
+            &json!({
+                "docId": "doc2",
+                "pageId": "p1",
+                "shape": {"kind": "rectangle", "x": 0, "y": 0}
+            }),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("ERR_UNSUPPORTED_VERSION"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), future.to_string());
+    }
+
+    #[test]
+    fn writes_repair_dangling_references_in_lenient_mode() {
+        let dir = TempDir::new().unwrap();
+        let f = seed(&dir.path().to_path_buf());
+
+        let mut doc = f.team.get_document("doc1").unwrap();
+        doc["pages"]["p1"]["shapeOrder"] = json!(["gone"]);
+        f.team.save_document(doc).unwrap();
+        let args = json!({
+            "docId": "doc1",
+            "pageId": "p1",
+            "shape": {"kind": "rectangle", "x": 0, "y": 0}
+        });
+
+        let mut ctx = f.ctx(true);
+        ctx.validation_mode = ValidationMode::Strict;
+        let err = dispatch(&ctx, "diagrammer.add_shape", &args).unwrap_err();
+        assert!(err.starts_with("ERR_INVALID_DOCUMENT"));
+
+        let out = dispatch(&f.ctx(true), "diagrammer.add_shape", &args).unwrap();
+        assert_eq!(out.result["repairs"][0]["path"], "/pages/p1/shapeOrder/0");
+        let id = out.result["id"].as_str().unwrap();
+        let saved = f.team.get_document("doc1").unwrap();
+        assert_eq!(saved["pages"]["p1"]["shapeOrder"], json!([id]));
+    }
+
+    #[test]
+    fn add_file_stores_blob_and_places_file_shape() {
+        let dir = TempDir::new().unwrap();
+        let f = seed(&dir.path().to_path_buf());
+        let pdf = b"%PDF-1.4 not much of a pdf";
+        let data = base64::engine::general_purpose::STANDARD.encode(pdf);
+        let args = json!({"docId": "doc1", "pageId": "p1", "fileName": "spec.pdf", "data": data, "x": 10, "y": 20});
+
+        let out = dispatch(&f.ctx(true), "diagrammer.add_file", &args).unwrap();
+        let hash = BlobStore::compute_hash(pdf);
+        assert_eq!(out.result["blobRef"], hash);
+        assert_eq!(out.result["deduplicated"], false);
+        assert_eq!(out.changed_doc_id.as_deref(), Some("doc1"));
+        assert!(f.blobs.exists(&hash));
+        assert_eq!(f.blobs.get_metadata(&hash).unwrap().documents, vec!["doc1"]);
+
+        let saved = f.team.get_document("doc1").unwrap();
+        let id = out.result["id"].as_str().unwrap();
+        let shape = &saved["pages"]["p1"]["shapes"][id];
+        assert_eq!(shape["type"], "file");
+        assert_eq!(shape["blobRef"], hash);
+        assert_eq!(shape["mimeType"], "application/pdf");
+        assert_eq!(shape["fileSize"], pdf.len());
+        assert_eq!(shape["fileCategory"], "pdf");
+        assert_eq!(saved["blobReferences"], json!([hash]));
+
+        // Same content again, as an MCP resource: stored once, listed once
+        let args = json!({
+            "docId": "doc1",
+            "pageId": "p1",
+            "resource": {"uri": "file:///tmp/copy.pdf", "blob": data}
+        });
+        let out = dispatch(&f.ctx(true), "diagrammer.add_file", &args).unwrap();
+        assert_eq!(out.result["deduplicated"], true);
+        let saved = f.team.get_document("doc1").unwrap();
+        let id = out.result["id"].as_str().unwrap();
+        assert_eq!(saved["pages"]["p1"]["shapes"][id]["fileName"], "copy.pdf");
+        assert_eq!(saved["blobReferences"], json!([hash]));
+        assert_eq!(f.blobs.get_blob_count(), 1);
+
+        let by_ref = json!({"docId": "doc1", "pageId": "p1", "fileName": "again.pdf", "blobRef": hash});
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &by_ref).is_ok());
+    }
+
+    #[test]
+    fn add_file_rejects_bad_sources() {
+        let dir = TempDir::new().unwrap();
+        let f = seed(&dir.path().to_path_buf());
+        let both = json!({"docId": "doc1", "pageId": "p1", "fileName": "a", "data": "aGk=", "blobRef": "abc"});
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &both).unwrap_err().contains("exactly one"));
+        let unknown = json!({"docId": "doc1", "pageId": "p1", "fileName": "a", "blobRef": "abc"});
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &unknown).unwrap_err().contains("No stored file"));
+        let garbage = json!({"docId": "doc1", "pageId": "p1", "fileName": "a", "data": "***"});
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &garbage).unwrap_err().contains("base64"));
+        assert_eq!(f.blobs.get_blob_count(), 0);
+    }
+
+    #[test]
+    fn add_file_applies_server_policy_and_quotas() {
+        let dir = TempDir::new().unwrap();
+        let mut f = seed(&dir.path().to_path_buf());
+        let data = base64::engine::general_purpose::STANDARD.encode(b"%PDF-1.4 not much of a pdf");
+        let args = json!({"docId": "doc1", "pageId": "p1", "fileName": "spec.pdf", "data": data});
+
+        f.config.blob_policy.allowed_types = vec!["image/*".into()];
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &args).is_err());
+
+        f.config.blob_policy = Default::default();
+        f.config.quotas.global.max_bytes = Some(10);
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &args).is_err());
+        assert_eq!(f.blobs.get_blob_count(), 0);
+
+        f.config.quotas = Default::default();
+        assert!(dispatch(&f.ctx(true), "diagrammer.add_file", &args).is_ok());
+    }
 }
@@ -9,6 +9,7 @@
 //! token stored in `TokenStore`. Localhost binding alone is not a security
 //! boundary on multi-user machines.
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use std::convert::Infallible;
@@ -27,7 +28,10 @@ use axum::{
 use futures_util::stream;
 use serde_json::{json, Value};
 
+use crate::server::blobs::BlobStore;
 use crate::server::documents::DocumentStore;
+use crate::server::quotas;
+use crate::server::{ServerConfig, SharedStores};
 
 use super::config::McpFeatureConfigStore;
 use super::local_mirror::LocalDocumentMirror;
@@ -41,9 +45,20 @@ const MCP_PROTOCOL_VERSION: &str = "2025-06-18";
 /// Shared state passed into the Axum handler.
 #[derive(Clone)]
 pub struct McpAppState {
+    /// Stores used while the collaboration server is stopped
     pub doc_store: Arc<DocumentStore>,
+    pub blob_store: Arc<BlobStore>,
+    /// Whether a storage watcher keeps `doc_store` current; otherwise the
+    /// index is reloaded before every tool call
+    pub watching: bool,
+    /// Stores of the running collaboration server, used instead of ours so
+    /// neither instance overwrites the other's indexes
+    pub shared_stores: SharedStores,
     pub local_mirror: Arc<LocalDocumentMirror>,
     pub feature_config: Arc<McpFeatureConfigStore>,
+    /// Where the collaboration server saves its config (upload policy and
+    /// quotas apply to tool writes too)
+    pub app_data_dir: PathBuf,
     pub token: Arc<TokenStore>,
     /// Called after a successful write so the running app can refresh.
     pub on_doc_changed: Arc<dyn Fn(String) + Send + Sync>,
@@ -187,10 +202,26 @@ fn handle_tools_call(state: &McpAppState, id: Value, params: &Value) -> Response
     };
     let args = params.get("arguments").cloned().unwrap_or(json!({}));
 
+    let server_config = ServerConfig::load(&state.app_data_dir);
+    let shared = state.shared_stores.read().ok().and_then(|stores| stores.clone());
+    let (team, blobs) = match &shared {
+        Some(stores) => (&stores.doc_store, &stores.blob_store),
+        None => {
+            if !state.watching {
+                state.doc_store.reload_index();
+            }
+            // The running server enforces quotas on its own stores
+            quotas::enforce(&state.doc_store, &state.blob_store, server_config.quotas.clone());
+            (&state.doc_store, &state.blob_store)
+        }
+    };
     let ctx = ToolContext {
-        team: &state.doc_store,
+        team,
+        blobs,
         local: &state.local_mirror,
         local_enabled: state.feature_config.local_access_enabled(),
+        validation_mode: state.feature_config.validation_mode(),
+        server_config: &server_config,
     };
     match dispatch(&ctx, name, &args) {
         Ok(outcome) => {
@@ -249,13 +280,18 @@ mod tests {
     fn make_state(dir: &TempDir) -> (McpAppState, String) {
         let token = Arc::new(TokenStore::load_or_create(dir.path()).unwrap());
         let store = Arc::new(DocumentStore::new(dir.path().to_path_buf()));
+        let blobs = Arc::new(BlobStore::new(dir.path().to_path_buf()));
         let local = Arc::new(LocalDocumentMirror::new(dir.path().to_path_buf()));
         let cfg = Arc::new(McpFeatureConfigStore::load_or_create(dir.path()));
         let token_str = token.current();
         let state = McpAppState {
             doc_store: store,
+            blob_store: blobs,
+            watching: false,
+            shared_stores: Default::default(),
             local_mirror: local,
             feature_config: cfg,
+            app_data_dir: dir.path().to_path_buf(),
             token,
             on_doc_changed: Arc::new(|_| {}),
         };
@@ -335,7 +371,8 @@ mod tests {
         assert!(names.contains(&"diagrammer.add_shapes"));
         assert!(names.contains(&"diagrammer.connect"));
         assert!(names.contains(&"diagrammer.update_shape"));
-        assert_eq!(tools.len(), 7);
+        assert!(names.contains(&"diagrammer.add_file"));
+        assert_eq!(tools.len(), 8);
     }
 
     #[tokio::test]
@@ -0,0 +1,797 @@
+//! Backups of the team store
+//!
+//! A backup is a gzip-compressed tar archive in `<app_data_dir>/backups/`
+//! holding every storage space (documents, indexes, blobs, users, ...), the
+//! update logs and the MCP config. `manifest.json` comes first and lists each
+//! entry with its size and SHA-256, so archives can be listed cheaply and
+//! verified before anything is restored.
+//!
+//! Values are copied exactly as stored: backups of encrypted storage stay
+//! encrypted and need the same key after a restore. The snapshot is streamed
+//! into a staging file while all storage writes are paused, then compressed
+//! behind the manifest once writes resume.
+//!
+//! Scheduled backups run when the newest one is older than the configured
+//! interval and only the most recent `keep` of them are retained; manual and
+//! pre-restore backups are never rotated away.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::documents::DocumentStore;
+use crate::clock::now_ms;
+use crate::fs_util;
+use crate::storage::{encrypted, pause_writes, Space, Storage, StorageBackend, WriteOp};
+
+const BACKUP_DIR: &str = "backups";
+const ARCHIVE_EXTENSION: &str = ".tar.gz";
+const MANIFEST_PATH: &str = "manifest.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Files in the app data directory that are backed up alongside storage
+const BACKED_UP_FILES: [&str; 1] = ["mcp_config.json"];
+
+/// How often the scheduler checks whether a backup is due
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+const MILLIS_PER_HOUR: u64 = 60 * 60 * 1000;
+
+/// Bytes of storage values written per batch while restoring
+const RESTORE_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// Backup schedule (part of the server config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupConfig {
+    /// Whether scheduled backups run while the server is up
+    #[serde(default)]
+    pub enabled: bool,
+    /// Hours between scheduled backups
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+    /// Scheduled backups to keep (0 = keep all)
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+fn default_interval_hours() -> u64 {
+    24
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_hours: default_interval_hours(),
+            keep: default_keep(),
+        }
+    }
+}
+
+/// Why a backup was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BackupKind {
+    Manual,
+    Scheduled,
+    /// Taken automatically before a restore overwrites the store
+    PreRestore,
+}
+
+impl BackupKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BackupKind::Manual => "manual",
+            BackupKind::Scheduled => "scheduled",
+            BackupKind::PreRestore => "pre-restore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupManifest {
+    format_version: u32,
+    id: String,
+    kind: BackupKind,
+    created_at: u64,
+    app_version: String,
+    backend: StorageBackend,
+    encrypted: bool,
+    entries: Vec<ManifestEntry>,
+}
+
+/// Backup listing entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub id: String,
+    pub kind: BackupKind,
+    pub created_at: u64,
+    pub app_version: String,
+    /// Backend the snapshot was taken from
+    pub backend: StorageBackend,
+    /// Whether the stored data is encrypted (restoring needs the same key)
+    pub encrypted: bool,
+    pub entry_count: usize,
+    /// Uncompressed size of all entries
+    pub data_bytes: u64,
+    /// Size of the archive file
+    pub archive_bytes: u64,
+}
+
+/// Result of checking an archive against its manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub id: String,
+    pub valid: bool,
+    pub entries_checked: usize,
+    /// Missing, unexpected or corrupted entries
+    pub problems: Vec<String>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Backup IDs become file names, so only allow a safe character set
+fn validate_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid backup ID '{}'", id));
+    }
+    Ok(())
+}
+
+/// Keys restored from an archive must not escape their space
+fn safe_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('/')
+        && !key.contains('\\')
+        && key.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+fn space_named(name: &str) -> Option<Space> {
+    Space::ALL.into_iter().find(|space| space.as_str() == name)
+}
+
+/// Where an archive entry is restored to
+enum Target {
+    Value(Space, String),
+    Update(String, usize),
+    File(&'static str),
+}
+
+fn parse_path(path: &str) -> Result<Target, String> {
+    let invalid = || format!("Unexpected archive entry '{}'", path);
+    if let Some(rest) = path.strip_prefix("storage/") {
+        let (space, key) = rest.split_once('/').ok_or_else(invalid)?;
+        let space = space_named(space).ok_or_else(invalid)?;
+        if !safe_key(key) {
+            return Err(invalid());
+        }
+        return Ok(Target::Value(space, key.to_string()));
+    }
+    if let Some(rest) = path.strip_prefix("updates/") {
+        let (doc_id, index) = rest.rsplit_once('/').ok_or_else(invalid)?;
+        let index = index.parse().map_err(|_| invalid())?;
+        if !safe_key(doc_id) {
+            return Err(invalid());
+        }
+        return Ok(Target::Update(doc_id.to_string(), index));
+    }
+    if let Some(name) = path.strip_prefix("files/") {
+        let name = BACKED_UP_FILES.into_iter().find(|f| *f == name).ok_or_else(invalid)?;
+        return Ok(Target::File(name));
+    }
+    Err(invalid())
+}
+
+/// Stream every entry to back up into an uncompressed tar at `staging`,
+/// reading one value at a time and hashing it as it is written. Callers
+/// pause writes around this.
+fn stage(storage: &dyn Storage, app_data_dir: &Path, staging: &Path, mtime: u64) -> Result<Vec<ManifestEntry>, String> {
+    let file = File::create(staging).map_err(|e| format!("Failed to create backup file: {}", e))?;
+    let mut builder = tar::Builder::new(BufWriter::new(file));
+    let mut listed = Vec::new();
+    let mut add = |path: String, data: Vec<u8>| -> Result<(), String> {
+        append_entry(&mut builder, &path, &data, mtime)?;
+        listed.push(ManifestEntry {
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+            path,
+        });
+        Ok(())
+    };
+    for space in Space::ALL {
+        for key in storage.list(space, "")? {
+            if let Some(value) = storage.get(space, &key)? {
+                add(format!("storage/{}/{}", space.as_str(), key), value)?;
+            }
+        }
+    }
+    for doc_id in storage.list_update_logs()? {
+        for (index, update) in storage.load_updates(&doc_id)?.into_iter().enumerate() {
+            add(format!("updates/{}/{:06}", doc_id, index), update)?;
+        }
+    }
+    for name in BACKED_UP_FILES {
+        match std::fs::read(app_data_dir.join(name)) {
+            Ok(data) => add(format!("files/{}", name), data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read {}: {}", name, e)),
+        }
+    }
+    builder
+        .into_inner()
+        .and_then(|writer| writer.into_inner().map_err(|e| e.into_error()))
+        .map_err(|e| format!("Failed to write backup entries: {}", e))?;
+    Ok(listed)
+}
+
+fn append_entry<W: Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8], mtime: u64) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(mtime);
+    builder
+        .append_data(&mut header, path, data)
+        .map_err(|e| format!("Failed to write backup entry {}: {}", path, e))
+}
+
+/// Write the manifest followed by the staged entries, via a temporary file
+fn write_archive(path: &Path, manifest: &BackupManifest, staging: &Path) -> Result<(), String> {
+    let partial = path.with_extension("partial");
+    let written = (|| {
+        let file = File::create(&partial).map_err(|e| format!("Failed to create backup file: {}", e))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(BufWriter::new(file), Compression::default()));
+        let mtime = manifest.created_at / 1000;
+        let manifest_json = serde_json::to_vec_pretty(manifest).map_err(|e| format!("Serialize error: {}", e))?;
+        append_entry(&mut builder, MANIFEST_PATH, &manifest_json, mtime)?;
+
+        let staged = File::open(staging).map_err(|e| format!("Failed to read backup entries: {}", e))?;
+        let mut staged = tar::Archive::new(BufReader::new(staged));
+        for entry in staged.entries().map_err(|e| format!("Failed to read backup entries: {}", e))? {
+            let mut entry = entry.map_err(|e| format!("Failed to read backup entries: {}", e))?;
+            let entry_path = entry
+                .path()
+                .map_err(|e| format!("Invalid backup entry path: {}", e))?
+                .into_owned();
+            let mut header = entry.header().clone();
+            builder
+                .append_data(&mut header, &entry_path, &mut entry)
+                .map_err(|e| format!("Failed to write backup entry {}: {}", entry_path.display(), e))?;
+        }
+
+        let mut writer = builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(|e| format!("Failed to finish backup archive: {}", e))?;
+        writer.flush().map_err(|e| format!("Failed to flush backup archive: {}", e))?;
+        writer
+            .into_inner()
+            .map_err(|e| format!("Failed to flush backup archive: {}", e.error()))?
+            .sync_all()
+            .map_err(|e| format!("Failed to sync backup archive: {}", e))
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, path).map_err(|e| format!("Failed to store backup archive: {}", e))
+}
+
+fn open_archive(path: &Path) -> Result<tar::Archive<GzDecoder<BufReader<File>>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open backup: {}", e))?;
+    Ok(tar::Archive::new(GzDecoder::new(BufReader::new(file))))
+}
+
+fn read_entry<R: Read>(entry: &mut tar::Entry<R>) -> Result<(String, Vec<u8>), String> {
+    let path = entry
+        .path()
+        .map_err(|e| format!("Invalid backup entry path: {}", e))?
+        .to_string_lossy()
+        .into_owned();
+    let mut data = Vec::new();
+    entry
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Failed to read backup entry {}: {}", path, e))?;
+    Ok((path, data))
+}
+
+fn parse_manifest(path: &str, data: &[u8]) -> Result<BackupManifest, String> {
+    if path != MANIFEST_PATH {
+        return Err("Backup archive does not start with a manifest".to_string());
+    }
+    let manifest: BackupManifest =
+        serde_json::from_slice(data).map_err(|e| format!("Failed to parse backup manifest: {}", e))?;
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "Backup format {} is newer than this version supports",
+            manifest.format_version
+        ));
+    }
+    Ok(manifest)
+}
+
+/// Read the manifest only (the first entry)
+fn read_manifest(path: &Path) -> Result<BackupManifest, String> {
+    let mut archive = open_archive(path)?;
+    let mut entries = archive.entries().map_err(|e| format!("Failed to read backup: {}", e))?;
+    let mut first = entries
+        .next()
+        .ok_or("Backup archive is empty")?
+        .map_err(|e| format!("Failed to read backup: {}", e))?;
+    let (entry_path, data) = read_entry(&mut first)?;
+    parse_manifest(&entry_path, &data)
+}
+
+/// Read the archive one entry at a time and check each against the
+/// manifest. Entries that match are handed to `apply` as they are read, so
+/// only the current entry is held in memory. Returns the manifest and the
+/// missing, unexpected or corrupted entries.
+fn read_verified(
+    path: &Path,
+    mut apply: impl FnMut(Target, Vec<u8>) -> Result<(), String>,
+) -> Result<(BackupManifest, Vec<String>), String> {
+    let mut archive = open_archive(path)?;
+    let mut entries = archive.entries().map_err(|e| format!("Failed to read backup: {}", e))?;
+    let mut first = entries
+        .next()
+        .ok_or("Backup archive is empty")?
+        .map_err(|e| format!("Failed to read backup: {}", e))?;
+    let (entry_path, data) = read_entry(&mut first)?;
+    let manifest = parse_manifest(&entry_path, &data)?;
+
+    let mut expected: HashMap<&str, &ManifestEntry> =
+        manifest.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut problems = Vec::new();
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                problems.push(format!("Archive is truncated or corrupt: {}", e));
+                break;
+            }
+        };
+        let (entry_path, data) = match read_entry(&mut entry) {
+            Ok(read) => read,
+            Err(e) => {
+                problems.push(e);
+                break;
+            }
+        };
+        match expected.remove(entry_path.as_str()) {
+            None => problems.push(format!("{}: not listed in the manifest", entry_path)),
+            Some(listed) if listed.size != data.len() as u64 || listed.sha256 != sha256_hex(&data) => {
+                problems.push(format!("{}: checksum mismatch", entry_path))
+            }
+            Some(_) => match parse_path(&entry_path) {
+                Ok(target) => apply(target, data)?,
+                Err(e) => problems.push(e),
+            },
+        }
+    }
+    let mut missing: Vec<&str> = expected.into_keys().collect();
+    missing.sort_unstable();
+    problems.extend(missing.into_iter().map(|p| format!("{}: missing from the archive", p)));
+    Ok((manifest, problems))
+}
+
+/// Backup archives kept in the app data directory
+pub struct BackupStore {
+    app_data_dir: PathBuf,
+    dir: PathBuf,
+}
+
+impl BackupStore {
+    pub fn new(app_data_dir: &Path) -> Self {
+        Self {
+            app_data_dir: app_data_dir.to_path_buf(),
+            dir: app_data_dir.join(BACKUP_DIR),
+        }
+    }
+
+    fn archive_path(&self, id: &str) -> Result<PathBuf, String> {
+        validate_id(id)?;
+        let path = self.dir.join(format!("{}{}", id, ARCHIVE_EXTENSION));
+        if !path.is_file() {
+            return Err(format!("Backup '{}' not found", id));
+        }
+        Ok(path)
+    }
+
+    fn info(path: &Path, manifest: BackupManifest) -> BackupInfo {
+        BackupInfo {
+            entry_count: manifest.entries.len(),
+            data_bytes: manifest.entries.iter().map(|e| e.size).sum(),
+            archive_bytes: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            id: manifest.id,
+            kind: manifest.kind,
+            created_at: manifest.created_at,
+            app_version: manifest.app_version,
+            backend: manifest.backend,
+            encrypted: manifest.encrypted,
+        }
+    }
+
+    /// Take a backup of `storage` (the raw backend, so encrypted data stays
+    /// encrypted). Every storage write in the process (documents, blobs,
+    /// users, other store instances) waits while the snapshot is read; the
+    /// server's `doc_store`, when given, is also paused so its document and
+    /// index writes are not split.
+    pub fn create(
+        &self,
+        storage: &dyn Storage,
+        doc_store: Option<&DocumentStore>,
+        kind: BackupKind,
+    ) -> Result<BackupInfo, String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+
+        let created_at = now_ms();
+        let mut id = format!("{}-{}", created_at, kind.as_str());
+        let mut suffix = 1;
+        while self.dir.join(format!("{}{}", id, ARCHIVE_EXTENSION)).exists() {
+            suffix += 1;
+            id = format!("{}-{}-{}", created_at, kind.as_str(), suffix);
+        }
+        let path = self.dir.join(format!("{}{}", id, ARCHIVE_EXTENSION));
+        let staging = self.dir.join(format!("{}.staging", id));
+
+        let written = (|| {
+            let read_snapshot =
+                || pause_writes(|| stage(storage, &self.app_data_dir, &staging, created_at / 1000));
+            let entries = match doc_store {
+                Some(doc_store) => doc_store.pause_writes(read_snapshot)??,
+                None => read_snapshot()?,
+            };
+            let manifest = BackupManifest {
+                format_version: FORMAT_VERSION,
+                id: id.clone(),
+                kind,
+                created_at,
+                app_version: env!("CARGO_PKG_VERSION").to_string(),
+                backend: storage.backend(),
+                encrypted: encrypted::is_encrypted(storage)?,
+                entries,
+            };
+            write_archive(&path, &manifest, &staging)?;
+            Ok::<_, String>(manifest)
+        })();
+        let _ = std::fs::remove_file(&staging);
+        let manifest = written?;
+
+        let info = Self::info(&path, manifest);
+        log::info!(
+            "Created {} backup {} ({} entries, {} bytes)",
+            kind.as_str(),
+            info.id,
+            info.entry_count,
+            info.archive_bytes
+        );
+        Ok(info)
+    }
+
+    /// Backups, newest first. Unreadable archives are skipped.
+    pub fn list(&self) -> Vec<BackupInfo> {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut backups: Vec<BackupInfo> = dir
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.to_string_lossy().ends_with(ARCHIVE_EXTENSION))
+            .filter_map(|path| match read_manifest(&path) {
+                Ok(manifest) => Some(Self::info(&path, manifest)),
+                Err(e) => {
+                    log::warn!("Skipping unreadable backup {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        backups.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+        backups
+    }
+
+    /// Check every entry of a backup against its manifest
+    pub fn verify(&self, id: &str) -> Result<VerifyReport, String> {
+        let (manifest, problems) = read_verified(&self.archive_path(id)?, |_, _| Ok(()))?;
+        Ok(VerifyReport {
+            id: id.to_string(),
+            valid: problems.is_empty(),
+            entries_checked: manifest.entries.len(),
+            problems,
+        })
+    }
+
+    /// Replace everything in `storage` (and the backed-up files) with the
+    /// contents of a backup. The archive is verified first and a pre-restore
+    /// backup of `storage` is taken; entries are then streamed into storage.
+    /// If applying fails part-way, the pre-restore backup holds the previous
+    /// state. The server must be stopped.
+    pub fn restore(&self, id: &str, storage: &dyn Storage) -> Result<BackupInfo, String> {
+        let path = self.archive_path(id)?;
+        let (_, problems) = read_verified(&path, |_, _| Ok(()))?;
+        if !problems.is_empty() {
+            return Err(format!(
+                "Backup '{}' failed verification: {}",
+                id,
+                problems.join("; ")
+            ));
+        }
+        self.create(storage, None, BackupKind::PreRestore)?;
+
+        let mut cleared = Vec::new();
+        for space in Space::ALL {
+            for key in storage.list(space, "")? {
+                cleared.push(WriteOp::Delete { space, key });
+            }
+        }
+        storage
+            .write_batch(cleared)
+            .map_err(|e| format!("Failed to restore backup: {}", e))?;
+        for doc_id in storage.list_update_logs()? {
+            storage.clear_updates(&doc_id)?;
+        }
+
+        // Update frames are archived in log order, so they are appended as read
+        let mut next_frame: HashMap<String, usize> = HashMap::new();
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let write = |batch: Vec<WriteOp>| {
+            storage
+                .write_batch(batch)
+                .map_err(|e| format!("Failed to restore backup: {}", e))
+        };
+        let (manifest, problems) = read_verified(&path, |target, data| {
+            match target {
+                Target::Value(space, key) => {
+                    batch_bytes += data.len();
+                    batch.push(WriteOp::Put { space, key, value: data });
+                    if batch_bytes >= RESTORE_BATCH_BYTES {
+                        write(std::mem::take(&mut batch))?;
+                        batch_bytes = 0;
+                    }
+                }
+                Target::Update(doc_id, index) => {
+                    let next = next_frame.entry(doc_id.clone()).or_default();
+                    if index != *next {
+                        return Err(format!("Update {} of document {} is out of order", index, doc_id));
+                    }
+                    *next += 1;
+                    storage.append_update(&doc_id, &data)?;
+                }
+                Target::File(name) => fs_util::write_atomic(&self.app_data_dir.join(name), data)
+                    .map_err(|e| format!("Failed to restore {}: {}", name, e))?,
+            }
+            Ok(())
+        })?;
+        if !problems.is_empty() {
+            return Err(format!(
+                "Backup '{}' changed while it was restored: {}",
+                id,
+                problems.join("; ")
+            ));
+        }
+        write(batch)?;
+
+        log::info!("Restored backup {} ({} entries)", id, manifest.entries.len());
+        Ok(Self::info(&path, manifest))
+    }
+
+    /// Delete scheduled backups beyond the newest `keep` (0 = keep all).
+    /// Returns the IDs removed.
+    pub fn rotate(&self, keep: usize) -> Vec<String> {
+        if keep == 0 {
+            return Vec::new();
+        }
+        let mut removed = Vec::new();
+        for backup in self
+            .list()
+            .into_iter()
+            .filter(|b| b.kind == BackupKind::Scheduled)
+            .skip(keep)
+        {
+            let path = self.dir.join(format!("{}{}", backup.id, ARCHIVE_EXTENSION));
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed.push(backup.id),
+                Err(e) => log::warn!("Failed to remove old backup {}: {}", backup.id, e),
+            }
+        }
+        removed
+    }
+
+    /// Whether a scheduled backup is due at `now`
+    fn is_due(&self, config: &BackupConfig, now: u64) -> bool {
+        let interval = config.interval_hours.max(1).saturating_mul(MILLIS_PER_HOUR);
+        !self
+            .list()
+            .iter()
+            .any(|b| b.kind == BackupKind::Scheduled && b.created_at.saturating_add(interval) > now)
+    }
+}
+
+/// Spawn the scheduled backup task. The caller aborts the handle on shutdown.
+pub fn spawn_backup_task(
+    backups: Arc<BackupStore>,
+    storage: Arc<dyn Storage>,
+    doc_store: Arc<DocumentStore>,
+    config: BackupConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let backups = backups.clone();
+            let storage = storage.clone();
+            let doc_store = doc_store.clone();
+            let config = config.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                if !backups.is_due(&config, now_ms()) {
+                    return;
+                }
+                match backups.create(storage.as_ref(), Some(&doc_store), BackupKind::Scheduled) {
+                    Ok(_) => {
+                        let removed = backups.rotate(config.keep);
+                        if !removed.is_empty() {
+                            log::info!("Rotated out {} old backup(s)", removed.len());
+                        }
+                    }
+                    Err(e) => log::error!("Scheduled backup failed: {}", e),
+                }
+            })
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use tempfile::tempdir;
+
+    fn populated(dir: &Path) -> Arc<dyn Storage> {
+        let storage: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.to_path_buf()));
+        storage.put(Space::Documents, "doc-1", br#"{"id":"doc-1"}"#).unwrap();
+        storage.put(Space::Revisions, "doc-1/3", br#"{"id":"doc-1"}"#).unwrap();
+        storage.put(Space::Blobs, "abcd", b"blob bytes").unwrap();
+        storage.put(Space::Users, "users", b"{}").unwrap();
+        storage.append_update("doc-1", b"first").unwrap();
+        storage.append_update("doc-1", b"second").unwrap();
+        std::fs::write(dir.join("mcp_config.json"), b"{\"localAccessEnabled\":false}").unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_backup_and_restore_roundtrip() {
+        let dir = tempdir().unwrap();
+        let storage = populated(dir.path());
+        let backups = BackupStore::new(dir.path());
+
+        let info = backups.create(storage.as_ref(), None, BackupKind::Manual).unwrap();
+        assert_eq!(info.entry_count, 7);
+        assert!(!info.encrypted);
+        assert!(backups.verify(&info.id).unwrap().valid);
+        let leftover = std::fs::read_dir(dir.path().join(BACKUP_DIR)).unwrap().count();
+        assert_eq!(leftover, 1, "staging file should be removed");
+
+        // Changes after the backup are rolled back by the restore
+        storage.put(Space::Documents, "doc-2", b"{}").unwrap();
+        storage.delete(Space::Blobs, "abcd").unwrap();
+        storage.append_update("doc-1", b"third").unwrap();
+        std::fs::remove_file(dir.path().join("mcp_config.json")).unwrap();
+
+        backups.restore(&info.id, storage.as_ref()).unwrap();
+        assert_eq!(storage.list(Space::Documents, "").unwrap(), vec!["doc-1"]);
+        assert_eq!(storage.get(Space::Blobs, "abcd").unwrap().unwrap(), b"blob bytes");
+        assert!(storage.exists(Space::Revisions, "doc-1/3").unwrap());
+        assert_eq!(
+            storage.load_updates("doc-1").unwrap(),
+            vec![b"first".to_vec(), b"second".to_vec()]
+        );
+        assert!(dir.path().join("mcp_config.json").is_file());
+
+        // The state before the restore was backed up first
+        let listed = backups.list();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().any(|b| b.id == info.id));
+        assert!(listed.iter().any(|b| b.kind == BackupKind::PreRestore));
+        assert!(backups.restore("../escape", storage.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let dir = tempdir().unwrap();
+        let storage = populated(dir.path());
+        let backups = BackupStore::new(dir.path());
+        let info = backups.create(storage.as_ref(), None, BackupKind::Manual).unwrap();
+
+        let path = backups.archive_path(&info.id).unwrap();
+        let manifest = read_manifest(&path).unwrap();
+        let mut archive = open_archive(&path).unwrap();
+        let mut contents: Vec<(String, Vec<u8>)> = archive
+            .entries()
+            .unwrap()
+            .skip(1)
+            .map(|entry| read_entry(&mut entry.unwrap()).unwrap())
+            .collect();
+        contents[0].1.push(b'!');
+        contents.pop();
+        let staging = dir.path().join("tampered.staging");
+        let mut builder = tar::Builder::new(File::create(&staging).unwrap());
+        for (entry_path, data) in &contents {
+            append_entry(&mut builder, entry_path, data, 0).unwrap();
+        }
+        builder.finish().unwrap();
+        write_archive(&path, &manifest, &staging).unwrap();
+
+        let report = backups.verify(&info.id).unwrap();
+        assert!(!report.valid);
+        assert_eq!(report.problems.len(), 2);
+        assert!(backups.restore(&info.id, storage.as_ref()).is_err());
+        assert!(storage.exists(Space::Blobs, "abcd").unwrap());
+        assert_eq!(backups.list().len(), 1, "no pre-restore backup for an invalid archive");
+    }
+
+    #[test]
+    fn test_rotation_keeps_recent_scheduled_backups() {
+        let dir = tempdir().unwrap();
+        let storage = populated(dir.path());
+        let backups = BackupStore::new(dir.path());
+        let config = BackupConfig {
+            enabled: true,
+            interval_hours: 1,
+            keep: 2,
+        };
+        assert!(backups.is_due(&config, now_ms()));
+
+        let manual = backups.create(storage.as_ref(), None, BackupKind::Manual).unwrap();
+        let mut scheduled = Vec::new();
+        for _ in 0..3 {
+            scheduled.push(backups.create(storage.as_ref(), None, BackupKind::Scheduled).unwrap().id);
+        }
+        assert!(!backups.is_due(&config, now_ms()));
+        assert!(backups.is_due(&config, now_ms() + MILLIS_PER_HOUR));
+
+        let removed = backups.rotate(config.keep);
+        assert_eq!(removed.len(), 1);
+        let remaining: Vec<String> = backups.list().into_iter().map(|b| b.id).collect();
+        assert_eq!(remaining.len(), 3);
+        assert!(remaining.contains(&manual.id));
+        assert!(!remaining.contains(&removed[0]));
+    }
+
+    #[test]
+    fn test_archive_paths_are_checked() {
+        assert!(matches!(parse_path("storage/documents/doc-1"), Ok(Target::Value(Space::Documents, _))));
+        assert!(matches!(parse_path("storage/history/doc-1/index"), Ok(Target::Value(Space::History, _))));
+        assert!(matches!(parse_path("updates/doc-1/000002"), Ok(Target::Update(_, 2))));
+        assert!(parse_path("storage/documents/../../etc/passwd").is_err());
+        assert!(parse_path("storage/unknown/key").is_err());
+        assert!(parse_path("files/users.json").is_err());
+        assert!(validate_id("1700000000000-manual").is_ok());
+        assert!(validate_id("../x").is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Garbage collection of unreferenced blobs
+//!
+//! Mark and sweep: every blob hash used by a live or trashed document, a
+//! template or a version history snapshot (through `blobReferences` or a
+//! file shape's `blobRef`) is marked, and stored blobs outside that set are
+//! orphans. Blobs are uploaded before the document that uses them is saved,
+//! so orphans uploaded within the grace period are left alone. That counts
+//! from the latest upload: re-uploading an old orphan is deduplicated, but
+//! still restarts its grace period.
+//!
+//! Runs on a schedule and on demand; a dry run reports what would be
+//! reclaimed without deleting anything.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::blobs::BlobStore;
+use super::documents::DocumentStore;
+use crate::clock::now_ms;
+
+const MILLIS_PER_HOUR: u64 = 60 * 60 * 1000;
+
+/// Blob collection settings (part of the server config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobGcConfig {
+    /// Whether collection runs on a schedule while the server is up
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Hours between scheduled runs
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+    /// Unreferenced blobs younger than this many hours are kept
+    #[serde(default = "default_grace_period_hours")]
+    pub grace_period_hours: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_hours() -> u64 {
+    24
+}
+
+fn default_grace_period_hours() -> u64 {
+    24
+}
+
+impl Default for BlobGcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_hours: default_interval_hours(),
+            grace_period_hours: default_grace_period_hours(),
+        }
+    }
+}
+
+/// An unreferenced blob
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanBlob {
+    pub hash: String,
+    pub size: u64,
+    pub created_at: u64,
+}
+
+/// Result of a collection run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub scanned_blobs: usize,
+    pub referenced_blobs: usize,
+    /// Orphans past the grace period (deleted unless this was a dry run)
+    pub orphans: Vec<OrphanBlob>,
+    pub reclaimable_bytes: u64,
+    /// Orphans still inside the grace period
+    pub recent_orphans: usize,
+    pub deleted_blobs: usize,
+    pub deleted_bytes: u64,
+}
+
+/// Mark blobs in use and sweep the rest that are older than `grace_ms`
+pub fn collect_garbage(
+    doc_store: &DocumentStore,
+    blob_store: &BlobStore,
+    grace_ms: u64,
+    now: u64,
+    dry_run: bool,
+) -> Result<GcReport, String> {
+    // Documents saved mid-run could start using a blob after it was marked
+    // as an orphan, so hold document writes for the whole run
+    // If any document cannot be read, its blobs would look unused, so
+    // nothing is collected until marking is complete
+    doc_store.pause_writes(|| {
+        let referenced = doc_store
+            .referenced_blobs()
+            .map_err(|e| format!("Blob collection aborted, could not mark blobs in use: {}", e))?;
+        let cutoff = now.saturating_sub(grace_ms);
+        let mut report = GcReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        let mut blobs = blob_store.list_blobs();
+        blobs.sort_by(|a, b| a.hash.cmp(&b.hash));
+        for blob in blobs {
+            report.scanned_blobs += 1;
+            if referenced.contains(&blob.hash) {
+                report.referenced_blobs += 1;
+            } else if blob.created_at.max(blob.last_uploaded_at.unwrap_or(0)) > cutoff {
+                report.recent_orphans += 1;
+            } else {
+                report.reclaimable_bytes += blob.size;
+                report.orphans.push(OrphanBlob {
+                    hash: blob.hash,
+                    size: blob.size,
+                    created_at: blob.created_at,
+                });
+            }
+        }
+
+        if !dry_run {
+            for orphan in &report.orphans {
+                match blob_store.delete_blob(&orphan.hash) {
+                    Ok(true) => {
+                        report.deleted_blobs += 1;
+                        report.deleted_bytes += orphan.size;
+                    }
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Failed to delete orphaned blob {}: {}", orphan.hash, e),
+                }
+            }
+            log::info!(
+                "Blob collection deleted {} orphan(s), {} bytes ({} within grace period)",
+                report.deleted_blobs,
+                report.deleted_bytes,
+                report.recent_orphans
+            );
+        }
+        Ok(report)
+    })?
+}
+
+/// Collect garbage with the configured grace period
+pub fn run(doc_store: &DocumentStore, blob_store: &BlobStore, config: &BlobGcConfig, dry_run: bool) -> Result<GcReport, String> {
+    let grace_ms = config.grace_period_hours.saturating_mul(MILLIS_PER_HOUR);
+    collect_garbage(doc_store, blob_store, grace_ms, now_ms(), dry_run)
+}
+
+/// Spawn the periodic collection task. The caller aborts the handle on
+/// shutdown.
+pub fn spawn_gc_task(
+    doc_store: Arc<DocumentStore>,
+    blob_store: Arc<BlobStore>,
+    config: BlobGcConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = Duration::from_millis(config.interval_hours.max(1).saturating_mul(MILLIS_PER_HOUR));
+        let mut interval = tokio::time::interval(period);
+        // The first tick fires immediately; leave startup alone
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let doc_store = doc_store.clone();
+            let blob_store = blob_store.clone();
+            let config = config.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                if let Err(e) = run(&doc_store, &blob_store, &config, false) {
+                    log::error!("Blob collection failed: {}", e);
+                }
+            })
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::templates::TemplateDetails;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_collects_only_old_orphans() {
+        let dir = tempdir().unwrap();
+        let doc_store = DocumentStore::new(dir.path().to_path_buf());
+        let blob_store = BlobStore::new(dir.path().to_path_buf());
+
+        let mut hashes = Vec::new();
+        for data in [b"listed".as_slice(), b"shape only", b"orphan", b"template"] {
+            let hash = BlobStore::compute_hash(data);
+            blob_store.save_blob(&hash, data, "image/png", "u1").unwrap();
+            hashes.push(hash);
+        }
+        doc_store
+            .save_document(json!({
+                "id": "doc-1",
+                "name": "Doc",
+                "pageOrder": ["p1"],
+                "pages": {"p1": {
+                    "id": "p1",
+                    "shapes": {"s1": {"id": "s1", "type": "file", "blobRef": hashes[1]}},
+                    "shapeOrder": ["s1"]
+                }},
+                "blobReferences": [hashes[0]]
+            }))
+            .unwrap();
+        doc_store
+            .save_document(json!({"id": "doc-2", "name": "T", "pages": {}, "pageOrder": [], "blobReferences": [hashes[3]]}))
+            .unwrap();
+        let details = TemplateDetails {
+            name: "T",
+            description: "",
+            category: "",
+        };
+        doc_store.save_as_template("doc-2", details, ("u1", "Alice")).unwrap();
+        doc_store.delete_document("doc-2", None, None).unwrap();
+        doc_store.purge_from_trash("doc-2").unwrap();
+
+        // Everything is within the grace period at first
+        let now = now_ms();
+        let report = collect_garbage(&doc_store, &blob_store, MILLIS_PER_HOUR, now, false).unwrap();
+        assert_eq!(report.recent_orphans, 1);
+        assert_eq!(report.deleted_blobs, 0);
+
+        let later = now + 2 * MILLIS_PER_HOUR;
+        let dry = collect_garbage(&doc_store, &blob_store, MILLIS_PER_HOUR, later, true).unwrap();
+        assert_eq!(dry.scanned_blobs, 4);
+        assert_eq!(dry.referenced_blobs, 3);
+        assert_eq!(dry.orphans.len(), 1);
+        assert_eq!(dry.orphans[0].hash, hashes[2]);
+        assert_eq!(dry.reclaimable_bytes, 6);
+        assert!(blob_store.exists(&hashes[2]));
+
+        let report = collect_garbage(&doc_store, &blob_store, MILLIS_PER_HOUR, later, false).unwrap();
+        assert_eq!(report.deleted_blobs, 1);
+        assert_eq!(report.deleted_bytes, 6);
+        assert!(!blob_store.exists(&hashes[2]));
+        assert!(blob_store.exists(&hashes[1]));
+        assert!(blob_store.exists(&hashes[3]));
+    }
+
+    #[test]
+    fn test_unreadable_document_aborts_collection() {
+        let dir = tempdir().unwrap();
+        let doc_store = DocumentStore::new(dir.path().to_path_buf());
+        let blob_store = BlobStore::new(dir.path().to_path_buf());
+
+        let hash = BlobStore::compute_hash(b"in use");
+        blob_store.save_blob(&hash, b"in use", "image/png", "u1").unwrap();
+        doc_store
+            .save_document(json!({"id": "doc-1", "name": "Doc", "blobReferences": [hash]}))
+            .unwrap();
+        std::fs::write(
+            dir.path().join("team_documents").join("docs").join("doc-1.json"),
+            b"not json",
+        )
+        .unwrap();
+
+        let later = now_ms() + 2 * MILLIS_PER_HOUR;
+        assert!(collect_garbage(&doc_store, &blob_store, MILLIS_PER_HOUR, later, false).is_err());
+        assert!(blob_store.exists(&hash));
+    }
+
+    #[test]
+    fn test_reupload_restarts_grace_period() {
+        let dir = tempdir().unwrap();
+        let doc_store = DocumentStore::new(dir.path().to_path_buf());
+        let blob_store = BlobStore::new(dir.path().to_path_buf());
+
+        let data = b"uploaded twice";
+        let hash = BlobStore::compute_hash(data);
+        let first = blob_store.save_blob(&hash, data, "image/png", "u1").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let again = blob_store.save_blob(&hash, data, "image/png", "u1").unwrap();
+        assert_eq!(again.created_at, first.created_at);
+        let reuploaded_at = again.last_uploaded_at.unwrap();
+        assert!(reuploaded_at >= first.created_at + 20);
+
+        // Past the grace period counted from creation, but not from the re-upload
+        let grace_ms = 10;
+        let now = first.created_at + grace_ms + 1;
+        let report = collect_garbage(&doc_store, &blob_store, grace_ms, now, false).unwrap();
+        assert_eq!(report.recent_orphans, 1);
+        assert!(blob_store.exists(&hash));
+
+        let later = reuploaded_at + grace_ms + 1;
+        let report = collect_garbage(&doc_store, &blob_store, grace_ms, later, false).unwrap();
+        assert_eq!(report.deleted_blobs, 1);
+    }
+}
@@ -0,0 +1,346 @@
+//! Blob inventory for admins
+//!
+//! Pages through the blob index with the documents using each blob, and
+//! sums blob storage per uploader and per document. A blob used by several
+//! documents counts in full for each of them; its bytes are only
+//! *exclusive* to a document that is its sole user, which is what deleting
+//! that document would let collection reclaim.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::blobs::BlobMetadata;
+
+/// Blobs per page when the query does not say
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest page served
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Order of an inventory page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlobSort {
+    #[default]
+    Largest,
+    Newest,
+    Oldest,
+}
+
+/// Which blobs to list
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobQuery {
+    #[serde(default)]
+    pub offset: usize,
+    /// Page size (server default if omitted, capped at [`MAX_PAGE_SIZE`])
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub sort: BlobSort,
+    /// Only blobs uploaded by this user
+    #[serde(default)]
+    pub uploaded_by: Option<String>,
+    /// Only blobs used by this document
+    #[serde(default)]
+    pub document_id: Option<String>,
+    /// Only blobs no document uses
+    #[serde(default)]
+    pub unreferenced: bool,
+}
+
+/// A document using a blob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobDocument {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// One blob in an inventory page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobEntry {
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub created_at: u64,
+    pub uploaded_by: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader_name: Option<String>,
+    pub documents: Vec<BlobDocument>,
+}
+
+/// A page of the inventory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobPage {
+    pub blobs: Vec<BlobEntry>,
+    pub offset: usize,
+    /// Blobs matching the query, across all pages
+    pub matching: usize,
+    pub total_blobs: usize,
+    pub total_bytes: u64,
+}
+
+/// Blob storage charged to one uploader
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserBlobUsage {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub blob_count: u64,
+    pub blob_bytes: u64,
+}
+
+/// Blob storage used by one document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentBlobUsage {
+    pub document_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub blob_count: u64,
+    pub blob_bytes: u64,
+    /// Bytes of blobs no other document uses
+    pub exclusive_bytes: u64,
+}
+
+/// Blob storage by uploader and by document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobUsageSummary {
+    pub total_blobs: u64,
+    pub total_bytes: u64,
+    pub unreferenced_blobs: u64,
+    pub unreferenced_bytes: u64,
+    /// Largest first
+    pub by_user: Vec<UserBlobUsage>,
+    /// Largest first
+    pub by_document: Vec<DocumentBlobUsage>,
+}
+
+/// Display names of documents and users, by ID
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    pub documents: HashMap<String, String>,
+    pub users: HashMap<String, String>,
+}
+
+impl Names {
+    fn document(&self, doc_id: &str) -> Option<String> {
+        self.documents.get(doc_id).cloned()
+    }
+
+    fn user(&self, user_id: &str) -> Option<String> {
+        self.users.get(user_id).filter(|name| !name.is_empty()).cloned()
+    }
+}
+
+impl BlobQuery {
+    fn matches(&self, blob: &BlobMetadata) -> bool {
+        if self.uploaded_by.as_ref().is_some_and(|user| *user != blob.uploaded_by) {
+            return false;
+        }
+        if let Some(doc_id) = &self.document_id {
+            if !blob.documents.contains(doc_id) {
+                return false;
+            }
+        }
+        !self.unreferenced || blob.documents.is_empty()
+    }
+}
+
+/// The page of `blobs` selected by `query`
+pub fn page(mut blobs: Vec<BlobMetadata>, query: &BlobQuery, names: &Names) -> BlobPage {
+    let total_blobs = blobs.len();
+    let total_bytes = blobs.iter().map(|b| b.size).sum();
+
+    blobs.retain(|b| query.matches(b));
+    match query.sort {
+        BlobSort::Largest => blobs.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.hash.cmp(&b.hash))),
+        BlobSort::Newest => blobs.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.hash.cmp(&b.hash))),
+        BlobSort::Oldest => blobs.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.hash.cmp(&b.hash))),
+    }
+    let matching = blobs.len();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+    let entries = blobs
+        .into_iter()
+        .skip(query.offset)
+        .take(limit)
+        .map(|blob| BlobEntry {
+            uploader_name: names.user(&blob.uploaded_by),
+            documents: blob
+                .documents
+                .iter()
+                .map(|id| BlobDocument {
+                    id: id.clone(),
+                    name: names.document(id),
+                })
+                .collect(),
+            hash: blob.hash,
+            size: blob.size,
+            mime_type: blob.mime_type,
+            created_at: blob.created_at,
+            uploaded_by: blob.uploaded_by,
+        })
+        .collect();
+
+    BlobPage {
+        blobs: entries,
+        offset: query.offset,
+        matching,
+        total_blobs,
+        total_bytes,
+    }
+}
+
+/// Blob storage per uploader and per document
+pub fn summarize(blobs: &[BlobMetadata], names: &Names) -> BlobUsageSummary {
+    let mut summary = BlobUsageSummary {
+        total_blobs: 0,
+        total_bytes: 0,
+        unreferenced_blobs: 0,
+        unreferenced_bytes: 0,
+        by_user: Vec::new(),
+        by_document: Vec::new(),
+    };
+    let mut by_user: HashMap<&str, UserBlobUsage> = HashMap::new();
+    let mut by_document: HashMap<&str, DocumentBlobUsage> = HashMap::new();
+
+    for blob in blobs {
+        summary.total_blobs += 1;
+        summary.total_bytes += blob.size;
+        if blob.documents.is_empty() {
+            summary.unreferenced_blobs += 1;
+            summary.unreferenced_bytes += blob.size;
+        }
+
+        let user = by_user.entry(&blob.uploaded_by).or_insert_with(|| UserBlobUsage {
+            user_id: blob.uploaded_by.clone(),
+            username: names.user(&blob.uploaded_by),
+            blob_count: 0,
+            blob_bytes: 0,
+        });
+        user.blob_count += 1;
+        user.blob_bytes += blob.size;
+
+        for doc_id in &blob.documents {
+            let document = by_document.entry(doc_id).or_insert_with(|| DocumentBlobUsage {
+                document_id: doc_id.clone(),
+                name: names.document(doc_id),
+                blob_count: 0,
+                blob_bytes: 0,
+                exclusive_bytes: 0,
+            });
+            document.blob_count += 1;
+            document.blob_bytes += blob.size;
+            if blob.documents.len() == 1 {
+                document.exclusive_bytes += blob.size;
+            }
+        }
+    }
+
+    summary.by_user = by_user.into_values().collect();
+    summary
+        .by_user
+        .sort_by(|a, b| b.blob_bytes.cmp(&a.blob_bytes).then_with(|| a.user_id.cmp(&b.user_id)));
+    summary.by_document = by_document.into_values().collect();
+    summary
+        .by_document
+        .sort_by(|a, b| b.blob_bytes.cmp(&a.blob_bytes).then_with(|| a.document_id.cmp(&b.document_id)));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(hash: &str, size: u64, created_at: u64, uploaded_by: &str, documents: &[&str]) -> BlobMetadata {
+        BlobMetadata {
+            hash: hash.to_string(),
+            size,
+            mime_type: "image/png".to_string(),
+            created_at,
+            last_uploaded_at: None,
+            uploaded_by: uploaded_by.to_string(),
+            documents: documents.iter().map(|d| d.to_string()).collect(),
+            active_content: None,
+            preview: None,
+        }
+    }
+
+    fn fixture() -> (Vec<BlobMetadata>, Names) {
+        let blobs = vec![
+            blob("a", 100, 3, "u1", &["doc-1"]),
+            blob("b", 300, 1, "u1", &["doc-1", "doc-2"]),
+            blob("c", 50, 2, "u2", &[]),
+        ];
+        let mut names = Names::default();
+        names.documents.insert("doc-1".to_string(), "Plan".to_string());
+        names.users.insert("u1".to_string(), "Alice".to_string());
+        (blobs, names)
+    }
+
+    #[test]
+    fn test_page_filters_sorts_and_names() {
+        let (blobs, names) = fixture();
+
+        let all = page(blobs.clone(), &BlobQuery::default(), &names);
+        assert_eq!((all.matching, all.total_blobs, all.total_bytes), (3, 3, 450));
+        let hashes: Vec<&str> = all.blobs.iter().map(|b| b.hash.as_str()).collect();
+        assert_eq!(hashes, ["b", "a", "c"]);
+        assert_eq!(all.blobs[0].uploader_name.as_deref(), Some("Alice"));
+        assert_eq!(
+            all.blobs[0].documents,
+            vec![
+                BlobDocument { id: "doc-1".to_string(), name: Some("Plan".to_string()) },
+                BlobDocument { id: "doc-2".to_string(), name: None },
+            ]
+        );
+
+        let query = BlobQuery {
+            offset: 1,
+            limit: Some(1),
+            sort: BlobSort::Newest,
+            ..Default::default()
+        };
+        let second = page(blobs.clone(), &query, &names);
+        assert_eq!(second.blobs.len(), 1);
+        assert_eq!(second.blobs[0].hash, "c");
+        assert_eq!(second.matching, 3);
+
+        let query = BlobQuery {
+            document_id: Some("doc-2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(page(blobs.clone(), &query, &names).blobs[0].hash, "b");
+        let query = BlobQuery {
+            unreferenced: true,
+            uploaded_by: Some("u2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(page(blobs, &query, &names).matching, 1);
+    }
+
+    #[test]
+    fn test_summarize_by_user_and_document() {
+        let (blobs, names) = fixture();
+        let summary = summarize(&blobs, &names);
+        assert_eq!((summary.total_blobs, summary.total_bytes), (3, 450));
+        assert_eq!((summary.unreferenced_blobs, summary.unreferenced_bytes), (1, 50));
+
+        assert_eq!(summary.by_user[0].user_id, "u1");
+        assert_eq!(summary.by_user[0].username.as_deref(), Some("Alice"));
+        assert_eq!((summary.by_user[0].blob_count, summary.by_user[0].blob_bytes), (2, 400));
+        assert_eq!(summary.by_user[1].blob_bytes, 50);
+
+        let doc_1 = &summary.by_document[0];
+        assert_eq!((doc_1.document_id.as_str(), doc_1.blob_bytes, doc_1.exclusive_bytes), ("doc-1", 400, 100));
+        let doc_2 = &summary.by_document[1];
+        assert_eq!((doc_2.blob_count, doc_2.blob_bytes, doc_2.exclusive_bytes), (1, 300, 0));
+    }
+}
@@ -0,0 +1,355 @@
+//! Which blobs may be uploaded, and how their content type is decided
+//!
+//! The client's Content-Type is only a hint. The type is sniffed from the
+//! content's magic bytes, and the sniffed type is what gets checked against
+//! the allowlist and stored. Plain text keeps a declared text subtype (CSV,
+//! Markdown, JSON), since those cannot be told apart by content.
+//!
+//! HTML, XML and SVG with scripts would run in the webview if opened
+//! directly, so they are flagged as active content and served as
+//! attachments under a sandboxing CSP (see [`is_active_type`]). Script
+//! detection in SVG is best-effort, so every SVG is served under the
+//! sandbox CSP as well (see [`needs_sandbox`]); `<img>` use is unaffected.
+//!
+//! Unrecognized binary content sniffs as `application/octet-stream`. It is
+//! not allowed by default, since allowing it would let any payload in.
+
+use serde::{Deserialize, Serialize};
+
+use super::blobs::BlobMetadata;
+use super::permissions::error_codes;
+
+/// Bytes of markup scanned for scripts. Larger markup is assumed active.
+pub const MARKUP_SCAN_BYTES: usize = 4 * 1024 * 1024;
+
+/// Upload limits (part of the server config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobPolicyConfig {
+    /// Largest blob accepted, in bytes
+    #[serde(default = "default_max_blob_bytes")]
+    pub max_blob_bytes: u64,
+    /// Content types that may be uploaded; `type/*` matches a whole family
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: Vec<String>,
+}
+
+fn default_max_blob_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_allowed_types() -> Vec<String> {
+    [
+        "image/*",
+        "video/*",
+        "audio/*",
+        "application/pdf",
+        "application/zip",
+        "application/gzip",
+        "application/json",
+        "text/plain",
+        "text/csv",
+        "text/markdown",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+impl Default for BlobPolicyConfig {
+    fn default() -> Self {
+        Self {
+            max_blob_bytes: default_max_blob_bytes(),
+            allowed_types: default_allowed_types(),
+        }
+    }
+}
+
+/// The type a blob is stored and served with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+    pub mime_type: String,
+    /// Could run scripts if rendered (see the module docs)
+    pub active: bool,
+}
+
+/// Text subtypes a client may declare for content sniffed as plain text
+const TEXT_SUBTYPES: &[&str] = &["text/plain", "text/csv", "text/markdown", "application/json"];
+
+impl BlobPolicyConfig {
+    /// Refuse blobs over the size limit
+    pub fn check_size(&self, size: u64) -> Result<(), String> {
+        if size > self.max_blob_bytes {
+            return Err(format!(
+                "{}: Blob is {} bytes, the limit is {} bytes",
+                error_codes::BLOB_TOO_LARGE,
+                size,
+                self.max_blob_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether a content type is on the allowlist
+    pub fn allows(&self, mime_type: &str) -> bool {
+        self.allowed_types.iter().any(|allowed| {
+            let allowed = allowed.trim().to_ascii_lowercase();
+            match allowed.strip_suffix("/*") {
+                Some(family) => mime_type.split('/').next() == Some(family),
+                None => allowed == mime_type,
+            }
+        })
+    }
+
+    /// Refuse a declared type early, before any content arrives
+    pub fn check_declared(&self, declared: &str) -> Result<(), String> {
+        let declared = normalize(declared);
+        if declared.is_empty() || self.allows(&declared) {
+            Ok(())
+        } else {
+            Err(type_not_allowed(&declared))
+        }
+    }
+
+    /// Decide the type of `content` (the whole blob, or its start when
+    /// `complete` is false) and check it against the allowlist
+    pub fn resolve(&self, content: &[u8], complete: bool, declared: &str) -> Result<ContentType, String> {
+        let declared = normalize(declared);
+        let sniffed = sniff(content).unwrap_or("application/octet-stream");
+        let mime_type = if sniffed == "text/plain" && TEXT_SUBTYPES.contains(&declared.as_str()) {
+            declared
+        } else {
+            sniffed.to_string()
+        };
+        if !self.allows(&mime_type) {
+            return Err(type_not_allowed(&mime_type));
+        }
+
+        let active = match mime_type.as_str() {
+            "image/svg+xml" => !complete || svg_has_script(content),
+            other => is_active_type(other),
+        };
+        Ok(ContentType { mime_type, active })
+    }
+}
+
+fn type_not_allowed(mime_type: &str) -> String {
+    format!("{}: Files of type {} are not allowed", error_codes::BLOB_TYPE_NOT_ALLOWED, mime_type)
+}
+
+/// Lower-case a Content-Type and drop its parameters
+fn normalize(mime_type: &str) -> String {
+    mime_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Whether a stored blob must be served as active content. Blobs stored
+/// before this was recorded are judged by type alone, treating any SVG as
+/// active.
+pub fn is_active(metadata: &BlobMetadata) -> bool {
+    metadata
+        .active_content
+        .unwrap_or_else(|| metadata.mime_type == "image/svg+xml" || is_active_type(&metadata.mime_type))
+}
+
+/// Whether a stored blob must be served under the sandboxing CSP: active
+/// content, and any SVG since script detection can be evaded
+pub fn needs_sandbox(metadata: &BlobMetadata) -> bool {
+    metadata.mime_type == "image/svg+xml" || is_active(metadata)
+}
+
+/// Types that are active whatever their content. SVG counts only when it
+/// carries scripts, which is recorded per blob at upload.
+pub fn is_active_type(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "text/html" | "application/xhtml+xml" | "application/xml" | "text/xml" | "text/javascript"
+    )
+}
+
+/// Content type from magic bytes (`None` for unrecognized binary data)
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"II*\x00", "image/tiff"),
+        (b"MM\x00*", "image/tiff"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"BM", "image/bmp"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, mime_type)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(mime_type);
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(match &data[8..12] {
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            b"avif" => "image/avif",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            _ => "video/mp4",
+        });
+    }
+    if data.len() >= 2 && data[0] == 0xff && matches!(data[1], 0xfb | 0xf3 | 0xf2) {
+        return Some("audio/mpeg");
+    }
+    sniff_text(data)
+}
+
+/// Markup or plain text, judging by the start of the content
+fn sniff_text(data: &[u8]) -> Option<&'static str> {
+    let text = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    if text.contains(&0) {
+        return None;
+    }
+    // A chunk boundary may split the last character
+    let valid = match std::str::from_utf8(text) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if !valid {
+        return None;
+    }
+
+    let head = String::from_utf8_lossy(&text[..text.len().min(4096)]).to_ascii_lowercase();
+    let start = head.trim_start();
+    let is_svg = |s: &str| s.contains("<svg");
+    if start.starts_with("<?xml") {
+        return Some(if is_svg(start) { "image/svg+xml" } else { "application/xml" });
+    }
+    if start.starts_with("<svg") || (start.starts_with("<!doctype svg") && is_svg(start)) {
+        return Some("image/svg+xml");
+    }
+    if start.starts_with("<!--") && is_svg(start) && !start.contains("<html") {
+        return Some("image/svg+xml");
+    }
+    const HTML_STARTS: &[&str] = &["<!doctype html", "<html", "<head", "<body", "<script", "<iframe", "<!--"];
+    if HTML_STARTS.iter().any(|tag| start.starts_with(tag)) {
+        return Some("text/html");
+    }
+    Some("text/plain")
+}
+
+/// Whether SVG markup can run scripts: script elements, `javascript:`
+/// URLs, embedded HTML or `on*` event handler attributes
+fn svg_has_script(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data).to_ascii_lowercase();
+    if ["<script", "javascript:", "<foreignobject", "<iframe", "<embed", "<object"]
+        .iter()
+        .any(|needle| text.contains(needle))
+    {
+        return true;
+    }
+    // Event handler attributes: whitespace, "on", letters, optional space, "="
+    let bytes = text.as_bytes();
+    bytes.windows(3).enumerate().any(|(i, window)| {
+        if !window[0].is_ascii_whitespace() || &window[1..] != b"on" {
+            return false;
+        }
+        let rest = &bytes[i + 3..];
+        let letters = rest.iter().take_while(|b| b.is_ascii_alphabetic()).count();
+        letters > 0 && rest[letters..].iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'=')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\x00\x00\x00\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(sniff(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff(b"  <!DOCTYPE html><p>hi"), Some("text/html"));
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"x\"/>"), Some("image/svg+xml"));
+        assert_eq!(sniff(b"a,b\n1,2\n"), Some("text/plain"));
+        assert_eq!(sniff(b"\x00\x01\x02binary"), None);
+    }
+
+    #[test]
+    fn test_resolve_uses_sniffed_type() {
+        let policy = BlobPolicyConfig::default();
+
+        // A PNG declared as something else is stored as a PNG
+        let png = policy.resolve(b"\x89PNG\r\n\x1a\n", true, "text/html").unwrap();
+        assert_eq!(png, ContentType { mime_type: "image/png".to_string(), active: false });
+
+        // Text keeps a declared text subtype, but cannot claim to be an image
+        let csv = policy.resolve(b"a,b\n1,2\n", true, "text/csv; charset=utf-8").unwrap();
+        assert_eq!(csv.mime_type, "text/csv");
+        assert_eq!(policy.resolve(b"a,b", true, "image/png").unwrap().mime_type, "text/plain");
+
+        // HTML is refused whatever it is declared as
+        let html = policy.resolve(b"<html><script>alert(1)</script>", true, "image/png").unwrap_err();
+        assert!(html.starts_with(error_codes::BLOB_TYPE_NOT_ALLOWED));
+
+        let safe_svg = policy.resolve(b"<svg><rect width=\"1\"/></svg>", true, "image/svg+xml").unwrap();
+        assert!(!safe_svg.active);
+        let onload = policy.resolve(b"<svg onload = \"alert(1)\"></svg>", true, "").unwrap();
+        assert!(onload.active);
+        let script = policy.resolve(b"<svg><script>x()</script></svg>", true, "").unwrap();
+        assert!(script.active);
+        // Only the start of a large upload was scanned
+        assert!(policy.resolve(b"<svg><rect/>", false, "").unwrap().active);
+
+        // Unrecognized binary is not allowed by default
+        let binary = policy.resolve(b"\x00\x01\x02binary", true, "application/octet-stream").unwrap_err();
+        assert!(binary.starts_with(error_codes::BLOB_TYPE_NOT_ALLOWED));
+    }
+
+    #[test]
+    fn test_every_svg_is_sandboxed() {
+        let policy = BlobPolicyConfig::default();
+        // Evades the script scan, but is still served under the sandbox CSP
+        let encoded = b"<svg><a href=\"&#106;avascript:alert(1)\"><rect/></a></svg>";
+        let content = policy.resolve(encoded, true, "").unwrap();
+        assert!(!content.active);
+
+        let metadata = |mime_type: &str, active: bool| BlobMetadata {
+            hash: "h".to_string(),
+            size: 1,
+            mime_type: mime_type.to_string(),
+            created_at: 0,
+            last_uploaded_at: None,
+            uploaded_by: String::new(),
+            documents: Vec::new(),
+            preview: None,
+            active_content: Some(active),
+        };
+        assert!(needs_sandbox(&metadata(&content.mime_type, content.active)));
+        assert!(needs_sandbox(&metadata("text/html", true)));
+        assert!(!needs_sandbox(&metadata("image/png", false)));
+    }
+
+    #[test]
+    fn test_limits() {
+        let policy = BlobPolicyConfig {
+            max_blob_bytes: 10,
+            allowed_types: vec!["image/*".to_string()],
+        };
+        assert!(policy.check_size(10).is_ok());
+        assert!(policy.check_size(11).unwrap_err().starts_with(error_codes::BLOB_TOO_LARGE));
+        assert!(policy.allows("image/webp"));
+        assert!(!policy.allows("application/pdf"));
+        assert!(policy.check_declared("Image/PNG").is_ok());
+        assert!(policy.check_declared("application/pdf").is_err());
+        assert!(policy.resolve(b"%PDF-1.4", true, "image/png").is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Integrity checks of stored blobs
+//!
+//! Hashes are verified on upload only, so bit rot, partial copies during a
+//! migration or a deleted shard directory would otherwise go unnoticed until
+//! a file shape fails to load. A scrub re-hashes every stored blob and
+//! cross-checks the metadata index against the stored contents:
+//!
+//! - corrupt: the content no longer hashes to its name
+//! - missing: indexed, but the content is gone
+//! - unindexed: stored, intact, but absent from the index
+//!
+//! With `repair`, corrupt blobs are quarantined (moved aside, not deleted),
+//! unindexed ones are added back to the index, and index entries of missing
+//! blobs are dropped so the files can be uploaded again. Either way the
+//! report lists what was found, including the documents that used each
+//! missing blob.
+//!
+//! Runs on a schedule and on demand.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::blob_policy;
+use super::blobs::BlobStore;
+use super::documents::DocumentStore;
+
+const MILLIS_PER_HOUR: u64 = 60 * 60 * 1000;
+
+/// Bytes read to sniff the type of a re-indexed blob
+const SNIFF_BYTES: usize = 4096;
+
+/// Blob scrub settings (part of the server config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobScrubConfig {
+    /// Whether scrubs run on a schedule while the server is up
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Hours between scheduled runs
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+    /// Whether scheduled runs repair what they find, or only report it
+    #[serde(default)]
+    pub repair: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_hours() -> u64 {
+    24 * 7
+}
+
+impl Default for BlobScrubConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_hours: default_interval_hours(),
+            repair: false,
+        }
+    }
+}
+
+/// A stored blob whose content does not match its hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CorruptBlob {
+    pub hash: String,
+    /// Whether the index has an entry for it
+    pub indexed: bool,
+    /// What the content hashes to now (`None` if it could not be read)
+    pub actual_hash: Option<String>,
+    pub actual_size: Option<u64>,
+    /// Read error, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An indexed blob whose content is gone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingBlob {
+    pub hash: String,
+    pub size: u64,
+    /// Documents that use it
+    pub documents: Vec<String>,
+}
+
+/// An intact stored blob absent from the index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnindexedBlob {
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Result of a scrub
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubReport {
+    pub repair: bool,
+    pub scanned_blobs: usize,
+    pub verified_blobs: usize,
+    pub corrupt: Vec<CorruptBlob>,
+    pub missing: Vec<MissingBlob>,
+    pub unindexed: Vec<UnindexedBlob>,
+    pub quarantined_blobs: usize,
+    pub reindexed_blobs: usize,
+    /// Index entries of missing blobs that were dropped
+    pub forgotten_blobs: usize,
+}
+
+impl ScrubReport {
+    /// Whether nothing was found
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.missing.is_empty() && self.unindexed.is_empty()
+    }
+}
+
+/// Check every stored and indexed blob, repairing what was found with
+/// `repair`
+pub fn scrub(doc_store: &DocumentStore, blob_store: &BlobStore, repair: bool) -> Result<ScrubReport, String> {
+    let mut report = ScrubReport {
+        repair,
+        ..Default::default()
+    };
+
+    // Index first: a blob uploaded in between then shows up as unindexed
+    // rather than missing, and repairs re-check the live index
+    let mut indexed = blob_store.list_blobs();
+    indexed.sort_by(|a, b| a.hash.cmp(&b.hash));
+    let stored = blob_store.stored_hashes()?;
+    let stored_set: HashSet<&str> = stored.iter().map(String::as_str).collect();
+    let indexed_set: HashSet<&str> = indexed.iter().map(|m| m.hash.as_str()).collect();
+
+    for hash in &stored {
+        report.scanned_blobs += 1;
+        let is_indexed = indexed_set.contains(hash.as_str());
+        let (actual_hash, actual_size, error) = match blob_store.hash_stored(hash) {
+            // Deleted since it was listed
+            Ok(None) => continue,
+            Ok(Some((actual_hash, size))) if actual_hash == *hash => {
+                report.verified_blobs += 1;
+                if !is_indexed {
+                    report.unindexed.push(UnindexedBlob { hash: hash.clone(), size });
+                }
+                continue;
+            }
+            Ok(Some((actual_hash, size))) => (Some(actual_hash), Some(size), None),
+            Err(e) => (None, None, Some(e)),
+        };
+        report.corrupt.push(CorruptBlob {
+            hash: hash.clone(),
+            indexed: is_indexed,
+            actual_hash,
+            actual_size,
+            error,
+        });
+    }
+
+    for metadata in indexed {
+        if !stored_set.contains(metadata.hash.as_str()) {
+            report.missing.push(MissingBlob {
+                hash: metadata.hash,
+                size: metadata.size,
+                documents: metadata.documents,
+            });
+        }
+    }
+
+    if repair {
+        for corrupt in &report.corrupt {
+            match blob_store.quarantine_blob(&corrupt.hash) {
+                Ok(()) => report.quarantined_blobs += 1,
+                Err(e) => log::warn!("Failed to quarantine blob {}: {}", corrupt.hash, e),
+            }
+        }
+        for unindexed in &report.unindexed {
+            let head = blob_store.read_range(&unindexed.hash, 0, SNIFF_BYTES).unwrap_or_default();
+            let mime_type = blob_policy::sniff(&head).unwrap_or("application/octet-stream");
+            match blob_store.reindex_blob(&unindexed.hash, unindexed.size, mime_type) {
+                Ok(true) => report.reindexed_blobs += 1,
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to re-index blob {}: {}", unindexed.hash, e),
+            }
+        }
+        for missing in &report.missing {
+            // Uploaded again since the scan
+            if blob_store.is_stored(&missing.hash) {
+                continue;
+            }
+            match blob_store.delete_blob(&missing.hash) {
+                Ok(true) => report.forgotten_blobs += 1,
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to drop index entry of blob {}: {}", missing.hash, e),
+            }
+        }
+        if report.reindexed_blobs > 0 {
+            // Re-indexed blobs are readable through the documents using them
+            blob_store.sync_references(&doc_store.blob_references_by_document())?;
+        }
+    }
+
+    if report.is_clean() {
+        log::info!("Blob scrub verified {} blob(s)", report.verified_blobs);
+    } else {
+        log::warn!(
+            "Blob scrub found {} corrupt, {} missing and {} unindexed blob(s) ({} quarantined, {} re-indexed, {} forgotten)",
+            report.corrupt.len(),
+            report.missing.len(),
+            report.unindexed.len(),
+            report.quarantined_blobs,
+            report.reindexed_blobs,
+            report.forgotten_blobs
+        );
+    }
+    Ok(report)
+}
+
+/// Spawn the periodic scrub task. The caller aborts the handle on shutdown.
+pub fn spawn_scrub_task(
+    doc_store: Arc<DocumentStore>,
+    blob_store: Arc<BlobStore>,
+    config: BlobScrubConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = Duration::from_millis(config.interval_hours.max(1).saturating_mul(MILLIS_PER_HOUR));
+        let mut interval = tokio::time::interval(period);
+        // The first tick fires immediately; leave startup alone
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let doc_store = doc_store.clone();
+            let blob_store = blob_store.clone();
+            let repair = config.repair;
+            let _ = tokio::task::spawn_blocking(move || {
+                if let Err(e) = scrub(&doc_store, &blob_store, repair) {
+                    log::error!("Blob scrub failed: {}", e);
+                }
+            })
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    fn shard_path(dir: &Path, hash: &str) -> PathBuf {
+        dir.join("team_documents").join("blobs").join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    #[test]
+    fn test_scrub_reports_and_repairs() {
+        let dir = tempdir().unwrap();
+        let doc_store = DocumentStore::new(dir.path().to_path_buf());
+        let blob_store = BlobStore::new(dir.path().to_path_buf());
+
+        let mut hashes = Vec::new();
+        for data in [b"intact".as_slice(), b"rotten", b"deleted"] {
+            let hash = BlobStore::compute_hash(data);
+            blob_store.save_blob(&hash, data, "text/plain", "u1").unwrap();
+            hashes.push(hash);
+        }
+        doc_store
+            .save_document(json!({"id": "doc-1", "name": "Doc", "pages": {}, "pageOrder": [], "blobReferences": [hashes[2]]}))
+            .unwrap();
+        blob_store.add_document(&hashes[2], "doc-1").unwrap();
+
+        std::fs::write(shard_path(dir.path(), &hashes[1]), b"rotted").unwrap();
+        std::fs::remove_file(shard_path(dir.path(), &hashes[2])).unwrap();
+        // Copied in behind the index's back
+        let png = b"\x89PNG\r\n\x1a\nrest";
+        let stray = BlobStore::compute_hash(png);
+        std::fs::create_dir_all(shard_path(dir.path(), &stray).parent().unwrap()).unwrap();
+        std::fs::write(shard_path(dir.path(), &stray), png).unwrap();
+
+        let report = scrub(&doc_store, &blob_store, false).unwrap();
+        assert_eq!(report.scanned_blobs, 3);
+        assert_eq!(report.verified_blobs, 2);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].hash, hashes[1]);
+        assert_eq!(report.corrupt[0].actual_hash, Some(BlobStore::compute_hash(b"rotted")));
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].documents, vec!["doc-1"]);
+        assert_eq!(report.unindexed.len(), 1);
+        assert_eq!(report.unindexed[0].hash, stray);
+        assert_eq!(report.quarantined_blobs + report.reindexed_blobs + report.forgotten_blobs, 0);
+        assert!(blob_store.get_metadata(&stray).is_none());
+
+        let report = scrub(&doc_store, &blob_store, true).unwrap();
+        assert_eq!((report.quarantined_blobs, report.reindexed_blobs, report.forgotten_blobs), (1, 1, 1));
+        assert!(!blob_store.exists(&hashes[1]));
+        assert!(!blob_store.exists(&hashes[2]));
+        assert_eq!(blob_store.get_metadata(&stray).unwrap().mime_type, "image/png");
+
+        // The corrupt copy is kept aside, out of the way of a new upload
+        let quarantined = std::fs::read_dir(shard_path(dir.path(), &hashes[1]).parent().unwrap())
+            .unwrap()
+            .count();
+        assert_eq!(quarantined, 1);
+        blob_store.save_blob(&hashes[1], b"rotten", "text/plain", "u1").unwrap();
+        blob_store.save_blob(&hashes[2], b"deleted", "text/plain", "u1").unwrap();
+
+        let report = scrub(&doc_store, &blob_store, true).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.verified_blobs, 4);
+    }
+}
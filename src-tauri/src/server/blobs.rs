@@ -1,10 +1,11 @@
 //! Blob storage module for embedded files
 //!
 //! Provides content-addressed blob storage for files embedded in documents.
-//! Blobs are stored with two-level directory sharding by hash prefix to avoid
-//! filesystem issues with large numbers of files.
+//! Contents live in the blob space of the active [`Storage`] backend and the
+//! metadata index is stored as `blob_index` in the meta space. The filesystem
+//! backend shards blobs by hash prefix to avoid filesystem issues with large
+//! numbers of files:
 //!
-//! Storage structure:
 //! ```text
 //! app_data_dir/team_documents/
 //!   blobs/
@@ -13,13 +14,27 @@
 //!         abcd1234...  # Full SHA-256 hash as filename
 //!   blob_index.json    # Metadata index
 //! ```
+//!
+//! The index also records which live documents use each blob, so access to
+//! a blob can follow access to those documents.
 
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::io::Read;
-use std::path::PathBuf;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::previews::{BlobPreview, Thumbnails};
+use super::quotas::UsageSnapshot;
+use crate::clock::now_ms;
+use crate::storage::fs::FsStorage;
+use crate::storage::{Space, Storage, WriteOp};
+
+/// Meta-space key of the blob metadata index
+const INDEX_KEY: &str = "blob_index";
+
+/// Bytes read per step when streaming a blob from a backend with ranged reads
+const STREAM_CHUNK_SIZE: u64 = 256 * 1024;
 
 /// Metadata for a stored blob
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,29 +48,65 @@ pub struct BlobMetadata {
     pub mime_type: String,
     /// Upload timestamp (Unix milliseconds)
     pub created_at: u64,
+    /// Latest upload of the same content, when it was uploaded again after
+    /// `created_at` (deduplicated, so nothing was written)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_uploaded_at: Option<u64>,
     /// User ID who uploaded the blob
     pub uploaded_by: String,
+    /// Live documents using the blob, plus the document it was uploaded for
+    #[serde(default)]
+    pub documents: Vec<String>,
+    /// Whether the content could run scripts if rendered (`None` for blobs
+    /// stored before this was recorded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_content: Option<bool>,
+    /// Dimensions, page count and thumbnails (`None` until generated)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<BlobPreview>,
+}
+
+/// A new blob about to be stored, as shown to the upload check
+pub struct PendingUpload<'a> {
+    pub user_id: &'a str,
+    pub size: u64,
+    /// Blob usage before the upload
+    pub usage: &'a UsageSnapshot,
 }
 
+/// Consulted under the write lock before a new blob is stored; an error
+/// refuses the upload
+type UploadCheck = Arc<dyn Fn(&PendingUpload) -> Result<(), String> + Send + Sync>;
+
 /// Content-addressed blob storage
 pub struct BlobStore {
-    /// Directory for storing blobs
-    blobs_dir: PathBuf,
+    /// Backend holding blob contents and the metadata index
+    storage: Arc<dyn Storage>,
     /// In-memory metadata index for fast lookups
     index: RwLock<HashMap<String, BlobMetadata>>,
+    /// Serializes storing new blobs, so concurrent uploads cannot both pass
+    /// the upload check
+    write_lock: Mutex<()>,
+    /// Can refuse new blobs (e.g. storage quotas)
+    upload_check: RwLock<Option<UploadCheck>>,
 }
 
 impl BlobStore {
-    /// Create a new blob store
+    /// Create a blob store using the filesystem layout under `app_data_dir`
     pub fn new(app_data_dir: PathBuf) -> Self {
-        let blobs_dir = app_data_dir.join("team_documents").join("blobs");
-
         // Ensure blobs directory exists
-        let _ = std::fs::create_dir_all(&blobs_dir);
+        let _ = std::fs::create_dir_all(app_data_dir.join("team_documents").join("blobs"));
+
+        Self::with_storage(Arc::new(FsStorage::new(app_data_dir)))
+    }
 
+    /// Create a blob store on top of an already opened storage backend
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
         let store = Self {
-            blobs_dir: blobs_dir.clone(),
+            storage,
             index: RwLock::new(HashMap::new()),
+            write_lock: Mutex::new(()),
+            upload_check: RwLock::new(None),
         };
 
         // Load existing index
@@ -64,46 +115,103 @@ impl BlobStore {
         store
     }
 
-    /// Get path to the metadata index file
-    fn index_path(&self) -> PathBuf {
-        self.blobs_dir.parent()
-            .map(|p| p.join("blob_index.json"))
-            .unwrap_or_else(|| self.blobs_dir.join("blob_index.json"))
+    /// Re-read the metadata index from storage (e.g. after another store
+    /// instance wrote to it)
+    pub fn reload_index(&self) {
+        self.load_index();
     }
 
-    /// Get the sharded path for a blob hash
-    /// Uses two-level sharding: first 2 chars / next 2 chars / full hash
-    fn get_blob_path(&self, hash: &str) -> PathBuf {
-        if hash.len() < 4 {
-            // Fallback for short hashes (shouldn't happen with SHA-256)
-            return self.blobs_dir.join(hash);
+    /// Load the metadata index from storage.
+    ///
+    /// If the index is missing or corrupt it is rebuilt from the stored
+    /// blobs. Rebuilt entries only know the size; they get a generic MIME
+    /// type, an unknown uploader and the rebuild time as creation time.
+    fn load_index(&self) {
+        match self.storage.get(Space::Meta, INDEX_KEY) {
+            Ok(Some(data)) => match serde_json::from_slice::<HashMap<String, BlobMetadata>>(&data) {
+                Ok(index) => {
+                    if let Ok(mut current) = self.index.write() {
+                        *current = index;
+                        log::info!("Loaded blob index with {} entries", current.len());
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Blob index is corrupt ({}), rebuilding from blob files", e);
+                    if let Err(e) = self.storage.quarantine(Space::Meta, INDEX_KEY) {
+                        log::warn!("Failed to move corrupt blob index aside: {}", e);
+                    }
+                }
+            },
+            Ok(None) => {
+                if self.storage.list(Space::Blobs, "").map(|k| k.is_empty()).unwrap_or(true) {
+                    if let Ok(mut current) = self.index.write() {
+                        current.clear();
+                    }
+                    return;
+                }
+                log::warn!("Blob index missing, rebuilding from blob files");
+            }
+            Err(e) => {
+                log::error!("Failed to read blob index: {}", e);
+                return;
+            }
+        }
+
+        if let Err(e) = self.rebuild_index() {
+            log::error!("Failed to rebuild blob index: {}", e);
         }
-        let level1 = &hash[0..2];
-        let level2 = &hash[2..4];
-        self.blobs_dir.join(level1).join(level2).join(hash)
     }
 
-    /// Load the metadata index from disk
-    fn load_index(&self) {
-        let path = self.index_path();
-        if let Ok(data) = std::fs::read_to_string(&path) {
-            if let Ok(index) = serde_json::from_str::<HashMap<String, BlobMetadata>>(&data) {
-                if let Ok(mut current) = self.index.write() {
-                    *current = index;
-                    log::info!("Loaded blob index with {} entries", current.len());
+    /// Rebuild the metadata index from the stored blobs
+    pub fn rebuild_index(&self) -> Result<usize, String> {
+        let now = now_ms();
+        let mut rebuilt = HashMap::new();
+        for hash in self.stored_hashes()? {
+            let size = match self.storage.get(Space::Blobs, &hash) {
+                Ok(Some(data)) => data.len() as u64,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("Skipping unreadable blob {}: {}", hash, e);
+                    continue;
                 }
-            }
+            };
+            rebuilt.insert(
+                hash.clone(),
+                BlobMetadata {
+                    hash,
+                    size,
+                    mime_type: "application/octet-stream".to_string(),
+                    created_at: now,
+                    last_uploaded_at: None,
+                    uploaded_by: "unknown".to_string(),
+                    documents: Vec::new(),
+                    active_content: None,
+                    preview: None,
+                },
+            );
+        }
+
+        let count = rebuilt.len();
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            *index = rebuilt;
         }
+        self.save_index()?;
+
+        log::info!("Rebuilt blob index with {} entries", count);
+        Ok(count)
     }
 
-    /// Save the metadata index to disk
-    fn save_index(&self) -> Result<(), String> {
+    /// Serialized metadata index
+    fn index_bytes(&self) -> Result<Vec<u8>, String> {
         let index = self.index.read().map_err(|e| e.to_string())?;
-        let json = serde_json::to_string_pretty(&*index)
-            .map_err(|e| format!("Serialize error: {}", e))?;
-        std::fs::write(self.index_path(), json)
-            .map_err(|e| format!("Write error: {}", e))?;
-        Ok(())
+        serde_json::to_vec_pretty(&*index).map_err(|e| format!("Serialize error: {}", e))
+    }
+
+    /// Save the metadata index to storage
+    fn save_index(&self) -> Result<(), String> {
+        self.storage.put(Space::Meta, INDEX_KEY, &self.index_bytes()?)
     }
 
     /// Compute SHA-256 hash of data
@@ -114,6 +222,16 @@ impl BlobStore {
         hex::encode(result)
     }
 
+    /// Compute the SHA-256 hash and size of a file without loading it
+    pub fn compute_file_hash(path: &Path) -> Result<(String, u64), String> {
+        let mut file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hasher)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok((hex::encode(hasher.finalize()), size))
+    }
+
     /// Check if a blob exists
     pub fn exists(&self, hash: &str) -> bool {
         // First check index (fast path)
@@ -122,8 +240,8 @@ impl BlobStore {
                 return true;
             }
         }
-        // Fallback to filesystem check
-        self.get_blob_path(hash).exists()
+        // Fallback to storage check
+        self.storage.exists(Space::Blobs, hash).unwrap_or(false)
     }
 
     /// Get blob metadata
@@ -139,9 +257,64 @@ impl BlobStore {
             .unwrap_or_default()
     }
 
+    /// Blob bytes and count per uploader, from the in-memory index
+    pub fn blob_usage(&self) -> UsageSnapshot {
+        let mut usage = UsageSnapshot::default();
+        if let Ok(index) = self.index.read() {
+            for blob in index.values() {
+                usage.add_blob(&blob.uploaded_by, blob.size);
+            }
+        }
+        usage
+    }
+
+    /// Register the check that can refuse new blobs
+    pub fn on_upload_check(&self, check: impl Fn(&PendingUpload) -> Result<(), String> + Send + Sync + 'static) {
+        if let Ok(mut current) = self.upload_check.write() {
+            *current = Some(Arc::new(check));
+        }
+    }
+
+    /// Refuse an upload before its content arrives if storing it now would
+    /// fail the upload check. Stored blobs are deduplicated and always pass.
+    /// Storing the blob checks again.
+    pub fn check_upload(&self, hash: &str, user_id: &str, size: u64) -> Result<(), String> {
+        if self.exists(hash) {
+            return Ok(());
+        }
+        self.run_upload_check(user_id, size)
+    }
+
+    /// Run the upload check against the in-memory index. Callers storing
+    /// the blob must hold `write_lock`.
+    fn run_upload_check(&self, user_id: &str, size: u64) -> Result<(), String> {
+        let check = self.upload_check.read().ok().and_then(|check| check.clone());
+        match check {
+            Some(check) => check(&PendingUpload { user_id, size, usage: &self.blob_usage() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Record that existing content was uploaded again, so garbage
+    /// collection gives it a fresh grace period. Returns the updated
+    /// metadata, `None` if the blob is not indexed.
+    fn touch_upload(&self, hash: &str) -> Option<BlobMetadata> {
+        let metadata = {
+            let mut index = self.index.write().ok()?;
+            let metadata = index.get_mut(hash)?;
+            metadata.last_uploaded_at = Some(now_ms());
+            metadata.clone()
+        };
+        if let Err(e) = self.save_index() {
+            log::warn!("Failed to record re-upload of blob {}: {}", hash, e);
+        }
+        Some(metadata)
+    }
+
     /// Save a blob with hash verification
     ///
-    /// Returns an error if the computed hash doesn't match the expected hash.
+    /// Returns an error if the computed hash doesn't match the expected hash
+    /// or the upload check refuses a new blob.
     pub fn save_blob(
         &self,
         expected_hash: &str,
@@ -158,47 +331,54 @@ impl BlobStore {
             ));
         }
 
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+
         // Check if blob already exists (deduplication)
         if self.exists(&actual_hash) {
             log::debug!("Blob {} already exists, skipping write", actual_hash);
-            if let Some(metadata) = self.get_metadata(&actual_hash) {
+            if let Some(metadata) = self.touch_upload(&actual_hash) {
                 return Ok(metadata);
             }
         }
-
-        // Get blob path and ensure parent directories exist
-        let blob_path = self.get_blob_path(&actual_hash);
-        if let Some(parent) = blob_path.parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create directories: {}", e))?;
-        }
-
-        // Write blob data
-        std::fs::write(&blob_path, data)
-            .map_err(|e| format!("Failed to write blob: {}", e))?;
+        self.run_upload_check(user_id, data.len() as u64)?;
 
         // Create metadata
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
+        let now = now_ms();
 
         let metadata = BlobMetadata {
             hash: actual_hash.clone(),
             size: data.len() as u64,
             mime_type: mime_type.to_string(),
             created_at: now,
+            last_uploaded_at: None,
             uploaded_by: user_id.to_string(),
+            documents: Vec::new(),
+            active_content: None,
+            preview: None,
         };
 
         // Update index
-        {
+        let previous = {
             let mut index = self.index.write().map_err(|e| e.to_string())?;
-            index.insert(actual_hash.clone(), metadata.clone());
-        }
+            index.insert(actual_hash.clone(), metadata.clone())
+        };
 
-        // Save index
-        self.save_index()?;
+        // Write blob data together with the index
+        let written = self.index_bytes().and_then(|index| {
+            self.storage.write_batch(vec![
+                WriteOp::Put { space: Space::Blobs, key: actual_hash.clone(), value: data.to_vec() },
+                WriteOp::Put { space: Space::Meta, key: INDEX_KEY.to_string(), value: index },
+            ])
+        });
+        if let Err(e) = written {
+            if let Ok(mut index) = self.index.write() {
+                match previous {
+                    Some(previous) => index.insert(actual_hash.clone(), previous),
+                    None => index.remove(&actual_hash),
+                };
+            }
+            return Err(format!("Failed to write blob: {}", e));
+        }
 
         log::info!(
             "Saved blob: {} ({} bytes, {})",
@@ -209,50 +389,243 @@ impl BlobStore {
         Ok(metadata)
     }
 
-    /// Load a blob by hash
-    pub fn load_blob(&self, hash: &str) -> Result<Vec<u8>, String> {
-        let blob_path = self.get_blob_path(hash);
+    /// Associate a stored blob with the document it was uploaded for
+    pub fn add_document(&self, hash: &str, doc_id: &str) -> Result<(), String> {
+        let added = {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            let metadata = index.get_mut(hash).ok_or_else(|| format!("Blob not found: {}", hash))?;
+            let added = !metadata.documents.iter().any(|d| d == doc_id);
+            if added {
+                metadata.documents.push(doc_id.to_string());
+                metadata.documents.sort();
+            }
+            added
+        };
+        if added {
+            self.save_index()?;
+        }
+        Ok(())
+    }
+
+    /// Record the blobs a document uses, replacing what was recorded for it
+    /// before. Hashes without a stored blob are ignored.
+    pub fn set_document_references(&self, doc_id: &str, hashes: &[String]) -> Result<(), String> {
+        let changed = {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            let mut changed = false;
+            for (hash, metadata) in index.iter_mut() {
+                let listed = metadata.documents.iter().position(|d| d == doc_id);
+                match (hashes.contains(hash), listed) {
+                    (true, None) => {
+                        metadata.documents.push(doc_id.to_string());
+                        metadata.documents.sort();
+                        changed = true;
+                    }
+                    (false, Some(i)) => {
+                        metadata.documents.remove(i);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            changed
+        };
+        if changed {
+            self.save_index()?;
+        }
+        Ok(())
+    }
+
+    /// Replace every blob's document list with one derived from `references`
+    /// (blob hashes by document ID)
+    pub fn sync_references(&self, references: &HashMap<String, Vec<String>>) -> Result<(), String> {
+        let mut users: HashMap<&str, Vec<String>> = HashMap::new();
+        for (doc_id, hashes) in references {
+            for hash in hashes {
+                users.entry(hash.as_str()).or_default().push(doc_id.clone());
+            }
+        }
+        let changed = {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            let mut changed = false;
+            for (hash, metadata) in index.iter_mut() {
+                let mut documents = users.remove(hash.as_str()).unwrap_or_default();
+                documents.sort();
+                if metadata.documents != documents {
+                    metadata.documents = documents;
+                    changed = true;
+                }
+            }
+            changed
+        };
+        if changed {
+            self.save_index()?;
+        }
+        Ok(())
+    }
+
+    /// Save a blob from a staged file with hash verification, without
+    /// loading it into memory. The file is consumed on success. Like
+    /// `save_blob`, a new blob must pass the upload check.
+    pub fn save_blob_file(
+        &self,
+        expected_hash: &str,
+        path: &Path,
+        mime_type: &str,
+        user_id: &str,
+    ) -> Result<BlobMetadata, String> {
+        let (actual_hash, size) = Self::compute_file_hash(path)?;
+        if actual_hash != expected_hash {
+            return Err(format!(
+                "Hash mismatch: expected {}, got {}",
+                expected_hash, actual_hash
+            ));
+        }
 
-        if !blob_path.exists() {
-            return Err(format!("Blob not found: {}", hash));
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        if self.exists(&actual_hash) {
+            if let Some(metadata) = self.touch_upload(&actual_hash) {
+                log::debug!("Blob {} already exists, skipping write", actual_hash);
+                let _ = std::fs::remove_file(path);
+                return Ok(metadata);
+            }
         }
+        self.run_upload_check(user_id, size)?;
 
-        let mut file = std::fs::File::open(&blob_path)
-            .map_err(|e| format!("Failed to open blob: {}", e))?;
+        let metadata = BlobMetadata {
+            hash: actual_hash.clone(),
+            size,
+            mime_type: mime_type.to_string(),
+            created_at: now_ms(),
+            last_uploaded_at: None,
+            uploaded_by: user_id.to_string(),
+            documents: Vec::new(),
+            active_content: None,
+            preview: None,
+        };
+
+        self.storage
+            .put_file(Space::Blobs, &actual_hash, path)
+            .map_err(|e| format!("Failed to write blob: {}", e))?;
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            index.insert(actual_hash.clone(), metadata.clone());
+        }
+        if let Err(e) = self.save_index() {
+            if let Ok(mut index) = self.index.write() {
+                index.remove(&actual_hash);
+            }
+            let _ = self.storage.delete(Space::Blobs, &actual_hash);
+            return Err(format!("Failed to write blob: {}", e));
+        }
 
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)
-            .map_err(|e| format!("Failed to read blob: {}", e))?;
+        log::info!("Saved blob: {} ({} bytes, {})", actual_hash, size, mime_type);
+        Ok(metadata)
+    }
 
-        Ok(data)
+    /// Load at most `len` bytes of a blob starting at `offset`
+    pub fn read_range(&self, hash: &str, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        self.storage
+            .get_range(Space::Blobs, hash, offset, len)
+            .map_err(|e| format!("Failed to read blob: {}", e))?
+            .ok_or_else(|| format!("Blob not found: {}", hash))
     }
 
-    /// Delete a blob
-    pub fn delete_blob(&self, hash: &str) -> Result<bool, String> {
-        let blob_path = self.get_blob_path(hash);
+    /// Bytes to read per step when streaming a blob. Backends without ranged
+    /// reads would load the whole blob for every step, so they get one.
+    pub fn stream_chunk_size(&self) -> u64 {
+        if self.storage.ranged_reads() {
+            STREAM_CHUNK_SIZE
+        } else {
+            u64::MAX
+        }
+    }
 
-        // Remove from index first
-        let existed = {
+    /// Record the content type decided for a blob
+    pub fn set_content_type(&self, hash: &str, mime_type: &str, active: bool) -> Result<(), String> {
+        let changed = {
             let mut index = self.index.write().map_err(|e| e.to_string())?;
-            index.remove(hash).is_some()
+            let metadata = index.get_mut(hash).ok_or_else(|| format!("Blob not found: {}", hash))?;
+            let changed = metadata.mime_type != mime_type || metadata.active_content != Some(active);
+            metadata.mime_type = mime_type.to_string();
+            metadata.active_content = Some(active);
+            changed
         };
+        if changed {
+            self.save_index()?;
+        }
+        Ok(())
+    }
 
-        // Remove file if it exists
-        if blob_path.exists() {
-            std::fs::remove_file(&blob_path)
-                .map_err(|e| format!("Failed to delete blob file: {}", e))?;
+    /// Previews-space key of a thumbnail
+    fn thumbnail_key(hash: &str, size: u32) -> String {
+        format!("{}/{}", hash, size)
+    }
 
-            // Try to clean up empty parent directories
-            if let Some(parent) = blob_path.parent() {
-                let _ = std::fs::remove_dir(parent); // Ignore errors (dir might not be empty)
-                if let Some(grandparent) = parent.parent() {
-                    let _ = std::fs::remove_dir(grandparent);
+    /// Store a blob's thumbnails and record its preview
+    pub fn save_preview(&self, hash: &str, preview: &BlobPreview, thumbnails: Thumbnails) -> Result<(), String> {
+        let previous = {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            let metadata = index.get_mut(hash).ok_or_else(|| format!("Blob not found: {}", hash))?;
+            metadata.preview.replace(preview.clone())
+        };
+
+        let mut ops: Vec<WriteOp> = thumbnails
+            .into_iter()
+            .map(|(size, png)| WriteOp::Put {
+                space: Space::Previews,
+                key: Self::thumbnail_key(hash, size),
+                value: png,
+            })
+            .collect();
+        let written = self.index_bytes().and_then(|index| {
+            ops.push(WriteOp::Put { space: Space::Meta, key: INDEX_KEY.to_string(), value: index });
+            self.storage.write_batch(ops)
+        });
+        if let Err(e) = written {
+            if let Ok(mut index) = self.index.write() {
+                if let Some(metadata) = index.get_mut(hash) {
+                    metadata.preview = previous;
                 }
             }
+            return Err(format!("Failed to write preview: {}", e));
         }
+        Ok(())
+    }
 
-        // Save index
-        self.save_index()?;
+    /// Load a stored thumbnail (PNG)
+    pub fn load_thumbnail(&self, hash: &str, size: u32) -> Result<Option<Vec<u8>>, String> {
+        self.storage
+            .get(Space::Previews, &Self::thumbnail_key(hash, size))
+            .map_err(|e| format!("Failed to read thumbnail: {}", e))
+    }
+
+    /// Load a blob by hash
+    pub fn load_blob(&self, hash: &str) -> Result<Vec<u8>, String> {
+        self.storage
+            .get(Space::Blobs, hash)
+            .map_err(|e| format!("Failed to read blob: {}", e))?
+            .ok_or_else(|| format!("Blob not found: {}", hash))
+    }
+
+    /// Delete a blob
+    pub fn delete_blob(&self, hash: &str) -> Result<bool, String> {
+        // Remove from index first
+        let existed = {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            index.remove(hash).is_some()
+        };
+
+        // Remove contents and thumbnails and save index
+        let index = self.index_bytes()?;
+        let mut ops = vec![WriteOp::Delete { space: Space::Blobs, key: hash.to_string() }];
+        for key in self.storage.list(Space::Previews, &format!("{}/", hash))? {
+            ops.push(WriteOp::Delete { space: Space::Previews, key });
+        }
+        ops.push(WriteOp::Put { space: Space::Meta, key: INDEX_KEY.to_string(), value: index });
+        self.storage
+            .write_batch(ops)
+            .map_err(|e| format!("Failed to delete blob: {}", e))?;
 
         if existed {
             log::info!("Deleted blob: {}", hash);
@@ -260,6 +633,90 @@ impl BlobStore {
         Ok(existed)
     }
 
+    /// Whether a blob-space key is a content hash. Quarantined copies and
+    /// other stray files are not.
+    fn is_hash(key: &str) -> bool {
+        key.len() == 64 && key.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
+
+    /// Hashes of the blobs in storage, whether indexed or not
+    pub fn stored_hashes(&self) -> Result<Vec<String>, String> {
+        let mut hashes: Vec<String> = self
+            .storage
+            .list(Space::Blobs, "")?
+            .into_iter()
+            .filter(|key| Self::is_hash(key))
+            .collect();
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Whether a blob's content is in storage, regardless of the index
+    pub fn is_stored(&self, hash: &str) -> bool {
+        self.storage.exists(Space::Blobs, hash).unwrap_or(false)
+    }
+
+    /// Re-hash a blob's stored content, streaming it where the backend
+    /// allows. Returns the actual hash and size, or `None` if the content
+    /// is not stored.
+    pub fn hash_stored(&self, hash: &str) -> Result<Option<(String, u64)>, String> {
+        if !self.is_stored(hash) {
+            return Ok(None);
+        }
+        let chunk = usize::try_from(self.stream_chunk_size()).unwrap_or(usize::MAX);
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        loop {
+            let data = self.read_range(hash, size, chunk)?;
+            hasher.update(&data);
+            size += data.len() as u64;
+            if data.len() < chunk {
+                break;
+            }
+        }
+        Ok(Some((hex::encode(hasher.finalize()), size)))
+    }
+
+    /// Add an index entry for a stored blob the index does not know about.
+    /// Like rebuilt entries it has an unknown uploader and the current time
+    /// as creation time. Returns false if the blob was indexed meanwhile.
+    pub fn reindex_blob(&self, hash: &str, size: u64, mime_type: &str) -> Result<bool, String> {
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            if index.contains_key(hash) {
+                return Ok(false);
+            }
+            index.insert(
+                hash.to_string(),
+                BlobMetadata {
+                    hash: hash.to_string(),
+                    size,
+                    mime_type: mime_type.to_string(),
+                    created_at: now_ms(),
+                    last_uploaded_at: None,
+                    uploaded_by: "unknown".to_string(),
+                    documents: Vec::new(),
+                    active_content: None,
+                    preview: None,
+                },
+            );
+        }
+        self.save_index()?;
+        log::info!("Re-indexed blob: {} ({} bytes, {})", hash, size, mime_type);
+        Ok(true)
+    }
+
+    /// Move a blob's content aside and drop it from the index, so it can be
+    /// uploaded again
+    pub fn quarantine_blob(&self, hash: &str) -> Result<(), String> {
+        self.storage
+            .quarantine(Space::Blobs, hash)
+            .map_err(|e| format!("Failed to quarantine blob: {}", e))?;
+        self.delete_blob(hash)?;
+        log::warn!("Quarantined blob: {}", hash);
+        Ok(())
+    }
+
     /// Get total storage used by all blobs
     pub fn get_total_size(&self) -> u64 {
         self.index
@@ -357,20 +814,6 @@ mod tests {
         assert_eq!(store.get_blob_count(), 1);
     }
 
-    #[test]
-    fn test_blob_path_sharding() {
-        let dir = tempdir().unwrap();
-        let store = BlobStore::new(dir.path().to_path_buf());
-
-        let hash = "abcd1234567890abcd1234567890abcd1234567890abcd1234567890abcd1234";
-        let path = store.get_blob_path(hash);
-
-        // Should have two-level sharding
-        assert!(path.to_string_lossy().contains("ab"));
-        assert!(path.to_string_lossy().contains("cd"));
-        assert!(path.to_string_lossy().ends_with(hash));
-    }
-
     #[test]
     fn test_persistence() {
         let dir = tempdir().unwrap();
@@ -391,4 +834,62 @@ mod tests {
             assert_eq!(loaded, data);
         }
     }
+
+    #[test]
+    fn test_index_rebuilt_from_shard_tree() {
+        let dir = tempdir().unwrap();
+        let data = b"indexed content";
+        let hash = BlobStore::compute_hash(data);
+        {
+            let store = BlobStore::new(dir.path().to_path_buf());
+            store.save_blob(&hash, data, "text/plain", "user-1").unwrap();
+        }
+
+        let index_path = dir.path().join("team_documents").join("blob_index.json");
+        std::fs::write(&index_path, "[truncated").unwrap();
+
+        let store = BlobStore::new(dir.path().to_path_buf());
+        let metadata = store.get_metadata(&hash).unwrap();
+        assert_eq!(metadata.size, data.len() as u64);
+        assert_eq!(store.get_blob_count(), 1);
+    }
+
+    #[test]
+    fn test_document_references_follow_saves() {
+        use crate::server::documents::DocumentStore;
+        use serde_json::json;
+
+        let dir = tempdir().unwrap();
+        let docs = DocumentStore::new(dir.path().to_path_buf());
+        let store = Arc::new(BlobStore::new(dir.path().to_path_buf()));
+        let listener = store.clone();
+        docs.on_blob_references(move |doc_id, hashes| {
+            listener.set_document_references(doc_id, hashes).unwrap();
+        });
+
+        let data = b"embedded";
+        let hash = BlobStore::compute_hash(data);
+        store.save_blob(&hash, data, "text/plain", "user-1").unwrap();
+        store.add_document(&hash, "draft").unwrap();
+        assert_eq!(store.get_metadata(&hash).unwrap().documents, vec!["draft"]);
+
+        let doc = |id: &str, refs: Vec<&str>| json!({"id": id, "name": id, "pages": {}, "pageOrder": [], "blobReferences": refs});
+        docs.save_document(doc("draft", vec![&hash])).unwrap();
+        docs.save_document(doc("copy", vec![&hash])).unwrap();
+        assert_eq!(store.get_metadata(&hash).unwrap().documents, vec!["copy", "draft"]);
+
+        docs.save_document(doc("draft", vec![])).unwrap();
+        docs.delete_document("copy", None, None).unwrap();
+        assert!(store.get_metadata(&hash).unwrap().documents.is_empty());
+
+        docs.restore_from_trash("copy").unwrap();
+        assert_eq!(store.get_metadata(&hash).unwrap().documents, vec!["copy"]);
+
+        // A fresh store sees the persisted list; a sync derives it from scratch
+        let reopened = BlobStore::new(dir.path().to_path_buf());
+        assert_eq!(reopened.get_metadata(&hash).unwrap().documents, vec!["copy"]);
+        reopened.sync_references(&HashMap::from([("other".to_string(), vec![hash.clone()])])).unwrap();
+        assert_eq!(reopened.get_metadata(&hash).unwrap().documents, vec!["other"]);
+        assert_eq!(docs.blob_references_by_document().get("copy"), Some(&vec![hash]));
+    }
 }
@@ -0,0 +1,361 @@
+//! Deep copies of documents and pages with fresh IDs
+//!
+//! Copying by round-tripping JSON keeps every page and shape ID, so a page
+//! pasted into another document can collide with what is already there.
+//! These helpers assign new IDs to the document, its pages, shapes and rich
+//! text pages, and rewrite every reference to them:
+//!
+//! - `pageOrder` / `activePageId` (diagram and rich text pages)
+//! - `shapeOrder`, connector `startShapeId` / `endShapeId`, group `childIds`
+//! - internal links in rich text HTML (`diagrammer://page/<id>` and
+//!   `diagrammer://heading/<pageId>/<index>`)
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::clock::now_ms;
+
+/// Old ID → new ID
+type IdMap = HashMap<String, String>;
+
+const LINK_PREFIXES: [&str; 2] = ["diagrammer://page/", "diagrammer://heading/"];
+
+pub(super) fn new_id() -> String {
+    nanoid::nanoid!()
+}
+
+
+/// Map each string in an ID array, dropping IDs that are not in `map`
+fn remap_array(value: Option<&mut Value>, map: &IdMap) {
+    if let Some(arr) = value.and_then(|v| v.as_array_mut()) {
+        *arr = arr
+            .iter()
+            .filter_map(|id| id.as_str().and_then(|id| map.get(id)))
+            .map(|id| json!(id))
+            .collect();
+    }
+}
+
+/// Map a single ID field; references to IDs outside `map` become null
+fn remap_field(obj: &mut Map<String, Value>, field: &str, map: &IdMap) {
+    if let Some(id) = obj.get(field).and_then(|v| v.as_str()) {
+        let mapped = map.get(id).map(|id| json!(id)).unwrap_or(Value::Null);
+        obj.insert(field.to_string(), mapped);
+    }
+}
+
+/// Re-key an ID-keyed object, updating each entry's own `id`
+fn rekey(entries: &Map<String, Value>, map: &IdMap) -> Map<String, Value> {
+    entries
+        .iter()
+        .filter_map(|(old, entry)| {
+            let new = map.get(old)?;
+            let mut entry = entry.clone();
+            if let Some(obj) = entry.as_object_mut() {
+                obj.insert("id".to_string(), json!(new));
+            }
+            Some((new.clone(), entry))
+        })
+        .collect()
+}
+
+/// Fresh IDs for every key of an ID-keyed object
+fn fresh_ids(entries: Option<&Value>) -> IdMap {
+    entries
+        .and_then(|v| v.as_object())
+        .map(|o| o.keys().map(|k| (k.clone(), new_id())).collect())
+        .unwrap_or_default()
+}
+
+/// Copy a diagram page under `new_page_id` with fresh shape IDs
+fn copy_page(page: &Value, new_page_id: &str) -> Value {
+    let mut page = page.clone();
+    let Some(obj) = page.as_object_mut() else {
+        return page;
+    };
+    obj.insert("id".to_string(), json!(new_page_id));
+
+    let shape_ids = fresh_ids(obj.get("shapes"));
+    if let Some(shapes) = obj.get("shapes").and_then(|v| v.as_object()) {
+        let mut shapes = rekey(shapes, &shape_ids);
+        for shape in shapes.values_mut().filter_map(|s| s.as_object_mut()) {
+            match shape.get("type").and_then(|v| v.as_str()) {
+                Some("connector") => {
+                    remap_field(shape, "startShapeId", &shape_ids);
+                    remap_field(shape, "endShapeId", &shape_ids);
+                }
+                Some("group") => remap_array(shape.get_mut("childIds"), &shape_ids),
+                _ => {}
+            }
+        }
+        obj.insert("shapes".to_string(), Value::Object(shapes));
+    }
+    remap_array(obj.get_mut("shapeOrder"), &shape_ids);
+    page
+}
+
+/// Rewrite the page ID in internal links. Links to pages outside `map`
+/// are left alone.
+fn rewrite_links(html: &str, map: &IdMap) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    loop {
+        let next = LINK_PREFIXES
+            .iter()
+            .filter_map(|prefix| rest.find(prefix).map(|pos| (pos, *prefix)))
+            .min_by_key(|(pos, _)| *pos);
+        let Some((pos, prefix)) = next else {
+            out.push_str(rest);
+            return out;
+        };
+        let id_start = pos + prefix.len();
+        out.push_str(&rest[..id_start]);
+        rest = &rest[id_start..];
+
+        let id_len = rest
+            .find(|c: char| c == '/' || c == '"' || c == '\'' || c == '<' || c == '#' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let id = &rest[..id_len];
+        out.push_str(map.get(id).map(String::as_str).unwrap_or(id));
+        rest = &rest[id_len..];
+    }
+}
+
+/// Copy the `richTextPages` block with fresh page IDs, rewriting links
+/// through `links` (which must include the new rich text page IDs)
+fn copy_rich_text(rich: &mut Map<String, Value>, rich_ids: &IdMap, links: &IdMap) {
+    if let Some(pages) = rich.get("pages").and_then(|v| v.as_object()) {
+        let mut pages = rekey(pages, rich_ids);
+        for page in pages.values_mut().filter_map(|p| p.as_object_mut()) {
+            if let Some(html) = page.get("content").and_then(|v| v.as_str()) {
+                let html = rewrite_links(html, links);
+                page.insert("content".to_string(), json!(html));
+            }
+        }
+        rich.insert("pages".to_string(), Value::Object(pages));
+    }
+    remap_array(rich.get_mut("pageOrder"), rich_ids);
+    remap_field(rich, "activePageId", rich_ids);
+}
+
+/// Deep-copy a document with fresh IDs throughout, owned by `owner`.
+///
+/// Sharing, locks and the server revision are not copied; the folder, tags
+/// and `blobReferences` are.
+pub fn duplicate_document(doc: &Value, name: Option<&str>, owner: (&str, &str)) -> Result<Value, String> {
+    let mut copy = doc.clone();
+    let obj = copy.as_object_mut().ok_or("Document is not a JSON object")?;
+
+    let page_ids = fresh_ids(obj.get("pages"));
+    if let Some(pages) = obj.get("pages").and_then(|v| v.as_object()) {
+        let pages: Map<String, Value> = pages
+            .iter()
+            .filter_map(|(old, page)| {
+                let new = page_ids.get(old)?;
+                Some((new.clone(), copy_page(page, new)))
+            })
+            .collect();
+        obj.insert("pages".to_string(), Value::Object(pages));
+    }
+    remap_array(obj.get_mut("pageOrder"), &page_ids);
+    remap_field(obj, "activePageId", &page_ids);
+
+    if let Some(rich) = obj.get_mut("richTextPages").and_then(|v| v.as_object_mut()) {
+        let rich_ids = fresh_ids(rich.get("pages"));
+        // Links may point at either kind of page
+        let mut links = page_ids.clone();
+        links.extend(rich_ids.clone());
+        copy_rich_text(rich, &rich_ids, &links);
+    }
+
+    let source_name = obj.get("name").and_then(|v| v.as_str()).unwrap_or("Untitled");
+    let name = name
+        .map(String::from)
+        .unwrap_or_else(|| format!("{} (copy)", source_name));
+    let now = now_ms();
+    obj.insert("id".to_string(), json!(new_id()));
+    obj.insert("name".to_string(), json!(name));
+    obj.insert("ownerId".to_string(), json!(owner.0));
+    obj.insert("ownerName".to_string(), json!(owner.1));
+    obj.insert("createdAt".to_string(), json!(now));
+    obj.insert("modifiedAt".to_string(), json!(now));
+    for field in [
+        "sharedWith",
+        "lockedBy",
+        "lockedByName",
+        "lockedAt",
+        "lastModifiedBy",
+        "lastModifiedByName",
+        "serverVersion",
+    ] {
+        obj.remove(field);
+    }
+    Ok(copy)
+}
+
+/// Copy page `page_id` of `source` to the end of `target` with fresh IDs.
+///
+/// Blob references used by the page are added to the target's
+/// `blobReferences`. Returns the new page ID.
+pub fn copy_page_into(source: &Value, page_id: &str, target: &mut Value) -> Result<String, String> {
+    let page = source
+        .get("pages")
+        .and_then(|p| p.get(page_id))
+        .ok_or_else(|| format!("Page '{}' not found", page_id))?;
+    let new_page_id = new_id();
+    let mut page = copy_page(page, &new_page_id);
+    if let Some(obj) = page.as_object_mut() {
+        let now = now_ms();
+        obj.insert("createdAt".to_string(), json!(now));
+        obj.insert("modifiedAt".to_string(), json!(now));
+    }
+
+    let used_blobs: Vec<String> = source
+        .get("blobReferences")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|b| b.as_str())
+        .filter(|hash| contains_string(&page, hash))
+        .map(String::from)
+        .collect();
+
+    let target = target.as_object_mut().ok_or("Target document is not a JSON object")?;
+    target
+        .entry("pages")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or("Target 'pages' is not an object")?
+        .insert(new_page_id.clone(), page);
+    target
+        .entry("pageOrder")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or("Target 'pageOrder' is not an array")?
+        .push(json!(new_page_id));
+
+    if !used_blobs.is_empty() {
+        let refs = target
+            .entry("blobReferences")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .ok_or("Target 'blobReferences' is not an array")?;
+        for hash in used_blobs {
+            if !refs.iter().any(|r| r.as_str() == Some(hash.as_str())) {
+                refs.push(json!(hash));
+            }
+        }
+    }
+    target.insert("modifiedAt".to_string(), json!(now_ms()));
+
+    Ok(new_page_id)
+}
+
+/// Whether any string inside `value` equals `needle`
+fn contains_string(value: &Value, needle: &str) -> bool {
+    match value {
+        Value::String(s) => s == needle,
+        Value::Array(arr) => arr.iter().any(|v| contains_string(v, needle)),
+        Value::Object(obj) => obj.values().any(|v| contains_string(v, needle)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> Value {
+        json!({
+            "id": "doc-1",
+            "name": "Plan",
+            "ownerId": "u1",
+            "sharedWith": [{"userId": "u2", "userName": "Bob", "permission": "edit", "sharedAt": 1}],
+            "lockedBy": "u2",
+            "serverVersion": 7,
+            "pageOrder": ["p1"],
+            "activePageId": "p1",
+            "pages": {"p1": {
+                "id": "p1",
+                "name": "Main",
+                "shapes": {
+                    "a": {"id": "a", "type": "rectangle"},
+                    "f": {"id": "f", "type": "file", "blobRef": "hash-1"},
+                    "c": {"id": "c", "type": "connector", "startShapeId": "a", "endShapeId": "f"},
+                    "g": {"id": "g", "type": "group", "childIds": ["a", "f"]}
+                },
+                "shapeOrder": ["g", "c"]
+            }},
+            "richTextPages": {
+                "pageOrder": ["r1"],
+                "activePageId": "r1",
+                "pages": {"r1": {
+                    "id": "r1",
+                    "name": "Notes",
+                    "content": "<a href=\"diagrammer://page/p1\">Main</a> <a href=\"diagrammer://heading/r1/2\">H</a> <a href=\"diagrammer://page/other\">X</a>"
+                }}
+            },
+            "blobReferences": ["hash-1", "hash-2"]
+        })
+    }
+
+    #[test]
+    fn test_duplicate_rewrites_every_reference() {
+        let copy = duplicate_document(&source(), None, ("u3", "Carol")).unwrap();
+
+        assert_ne!(copy["id"], "doc-1");
+        assert_eq!(copy["name"], "Plan (copy)");
+        assert_eq!(copy["ownerId"], "u3");
+        assert!(copy.get("sharedWith").is_none());
+        assert!(copy.get("lockedBy").is_none());
+        assert!(copy.get("serverVersion").is_none());
+        assert_eq!(copy["blobReferences"], json!(["hash-1", "hash-2"]));
+
+        let page_id = copy["pageOrder"][0].as_str().unwrap();
+        assert_ne!(page_id, "p1");
+        assert_eq!(copy["activePageId"], page_id);
+        let page = &copy["pages"][page_id];
+        assert_eq!(page["id"], page_id);
+
+        let shapes = page["shapes"].as_object().unwrap();
+        assert_eq!(shapes.len(), 4);
+        assert!(!shapes.contains_key("a"));
+        let id_of = |kind: &str| {
+            shapes.iter().find(|(_, s)| s["type"] == kind).map(|(k, _)| k.clone()).unwrap()
+        };
+        let (rect, file, group, conn) = (id_of("rectangle"), id_of("file"), id_of("group"), id_of("connector"));
+        assert_eq!(shapes[&conn]["startShapeId"], rect);
+        assert_eq!(shapes[&conn]["endShapeId"], file);
+        assert_eq!(shapes[&group]["childIds"], json!([rect, file]));
+        assert_eq!(page["shapeOrder"], json!([group, conn]));
+
+        let rich = &copy["richTextPages"];
+        let rich_id = rich["pageOrder"][0].as_str().unwrap();
+        assert_ne!(rich_id, "r1");
+        assert_eq!(rich["activePageId"], rich_id);
+        let html = rich["pages"][rich_id]["content"].as_str().unwrap();
+        assert!(html.contains(&format!("diagrammer://page/{}\"", page_id)));
+        assert!(html.contains(&format!("diagrammer://heading/{}/2", rich_id)));
+        assert!(html.contains("diagrammer://page/other"));
+    }
+
+    #[test]
+    fn test_copy_page_into_other_document() {
+        let mut target = json!({
+            "id": "doc-2",
+            "pageOrder": ["p1"],
+            "pages": {"p1": {"id": "p1", "shapes": {"a": {"id": "a"}}, "shapeOrder": ["a"]}}
+        });
+        let new_id = copy_page_into(&source(), "p1", &mut target).unwrap();
+
+        assert_eq!(target["pageOrder"], json!(["p1", new_id]));
+        let shapes = target["pages"][&new_id]["shapes"].as_object().unwrap();
+        assert!(!shapes.contains_key("a"));
+        // Original page untouched
+        assert_eq!(target["pages"]["p1"]["shapeOrder"], json!(["a"]));
+        // Only blobs used by the copied page are carried over
+        assert_eq!(target["blobReferences"], json!(["hash-1"]));
+
+        assert!(copy_page_into(&source(), "missing", &mut target).is_err());
+    }
+}
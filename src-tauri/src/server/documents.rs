@@ -1,12 +1,29 @@
 //! Team document storage and management
 //!
-//! Provides file-based storage for team documents that are shared across clients.
-//! Documents are stored as JSON files in the app data directory.
+//! Provides storage for team documents that are shared across clients.
+//! Documents are stored as JSON in the active [`Storage`] backend; the
+//! filesystem backend keeps one file per document in the app data directory.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::copy;
+use super::folders::{self, Folder, FolderStore};
+use super::history::{HistoryConfig, HistoryStore, VersionInfo};
+use super::json_patch::{self, PatchOperation};
+use super::merge::{self, MergeConflict};
+use super::permissions::error_codes;
+use super::quotas::UsageSnapshot;
+use super::schema;
+use super::search::{SearchHit, SearchIndex};
+use super::templates::{TemplateDetails, TemplateInfo, TemplateStore};
+use super::trash::TrashEntry;
+use super::validation::{self, ValidationIssue, ValidationMode};
+use crate::clock::now_ms;
+use crate::storage::fs::FsStorage;
+use crate::storage::{Space, Storage, WriteOp};
 
 /// Document share entry for tracking who has access
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,73 +64,342 @@ pub struct DocumentMetadata {
     pub last_modified_by: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_modified_by_name: Option<String>,
+    /// Folder containing the document (top level if absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+
+    /// Server-assigned revision, incremented on every write
+    #[serde(default)]
+    pub server_version: u64,
 }
 
-/// Team document store with file-based persistence
+/// Number of recent revisions kept per document as three-way merge bases
+const MERGE_BASE_RETENTION: usize = 20;
+
+/// Result of a save that may have been based on a stale revision
+#[derive(Debug, Clone)]
+pub enum SaveOutcome {
+    /// Saved as-is (new document, or base revision was current), after any
+    /// lenient-mode structural repairs
+    Saved {
+        metadata: DocumentMetadata,
+        repairs: Vec<ValidationIssue>,
+    },
+    /// Base was stale; non-overlapping changes were merged and saved
+    Merged {
+        metadata: DocumentMetadata,
+        document: serde_json::Value,
+        repairs: Vec<ValidationIssue>,
+    },
+    /// Base was stale and both sides changed the same fields; nothing written
+    Conflict {
+        server_version: u64,
+        conflicts: Vec<MergeConflict>,
+    },
+}
+
+/// Meta-space key of the document index
+const INDEX_KEY: &str = "index";
+
+/// Meta-space key of the trash index
+const TRASH_INDEX_KEY: &str = "trash_index";
+
+/// Told which blobs a live document uses whenever that changes (an empty
+/// list when it leaves the live set)
+type BlobListener = Arc<dyn Fn(&str, &[String]) + Send + Sync>;
+
+/// A document about to be written, as shown to the write check
+pub struct PendingWrite<'a> {
+    pub doc_id: &'a str,
+    /// Owner of the document after the write
+    pub owner_id: Option<&'a str>,
+    /// Stored size, when the document already exists with the same owner
+    pub old_size: Option<u64>,
+    pub new_size: u64,
+    /// Usage of live documents before the write
+    pub usage: &'a UsageSnapshot,
+}
+
+/// Consulted under the write lock before a document is written; an error
+/// refuses the write
+type WriteCheck = Arc<dyn Fn(&PendingWrite) -> Result<(), String> + Send + Sync>;
+
+/// Team document store on top of a pluggable [`Storage`] backend
 pub struct DocumentStore {
-    /// Directory for storing documents
-    documents_dir: PathBuf,
+    /// Backend holding documents, revisions, history and indexes
+    storage: Arc<dyn Storage>,
     /// In-memory metadata index for fast lookups
     index: RwLock<HashMap<String, DocumentMetadata>>,
+    /// Serializes read-modify-write cycles so revisions are assigned in order
+    write_lock: Mutex<()>,
+    /// Prior versions kept for restore
+    history: HistoryStore,
+    /// Soft-deleted documents awaiting restore or purge
+    trash: RwLock<HashMap<String, TrashEntry>>,
+    /// Full-text index of live documents
+    search: SearchIndex,
+    /// Folder tree documents are filed in
+    folders: FolderStore,
+    /// Templates new documents can be created from
+    templates: TemplateStore,
+    /// Stored size in bytes of live documents, filled on write or first use
+    sizes: RwLock<HashMap<String, u64>>,
+    /// Receives blob references of written, trashed and restored documents
+    blob_listener: RwLock<Option<BlobListener>>,
+    /// Usage of live documents per owner, computed on first use and kept
+    /// current by writes
+    usage: RwLock<Option<UsageSnapshot>>,
+    /// Can refuse document writes (e.g. storage quotas)
+    write_check: RwLock<Option<WriteCheck>>,
 }
 
 impl DocumentStore {
-    /// Create a new document store
+    /// Create a document store using the filesystem layout under `app_data_dir`
     pub fn new(app_data_dir: PathBuf) -> Self {
         let documents_dir = app_data_dir.join("team_documents");
 
         // Ensure directories exist
-        let _ = std::fs::create_dir_all(&documents_dir);
         let _ = std::fs::create_dir_all(documents_dir.join("docs"));
+        let _ = std::fs::create_dir_all(documents_dir.join("trash"));
+
+        Self::with_storage(Arc::new(FsStorage::new(app_data_dir)))
+    }
 
+    /// Create a document store on top of an already opened storage backend
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
         let store = Self {
-            documents_dir: documents_dir.clone(),
+            storage: storage.clone(),
             index: RwLock::new(HashMap::new()),
+            write_lock: Mutex::new(()),
+            history: HistoryStore::new(storage.clone()),
+            trash: RwLock::new(HashMap::new()),
+            search: SearchIndex::new(),
+            folders: FolderStore::new(storage.clone()),
+            templates: TemplateStore::new(storage),
+            sizes: RwLock::new(HashMap::new()),
+            blob_listener: RwLock::new(None),
+            usage: RwLock::new(None),
+            write_check: RwLock::new(None),
         };
 
         // Load existing index
         store.load_index();
+        store.load_trash_index();
+        store.rebuild_search_index();
 
         store
     }
 
-    /// Get path to the index file
-    fn index_path(&self) -> PathBuf {
-        self.documents_dir.join("index.json")
-    }
-
-    /// Get path to a document file
-    fn doc_path(&self, doc_id: &str) -> PathBuf {
-        self.documents_dir.join("docs").join(format!("{}.json", doc_id))
+    /// Revision key of a stored merge base
+    fn revision_key(doc_id: &str, server_version: u64) -> String {
+        format!("{}/{}", doc_id, server_version)
     }
 
-    /// Reload the metadata index from disk. Public so external callers
+    /// Reload the metadata index from storage. Public so external callers
     /// (e.g. the MCP server) can refresh their view after another component
-    /// has written to the same documents directory.
+    /// has written to the same storage.
     pub fn reload_index(&self) {
+        let revision = |m: &DocumentMetadata| (m.server_version, m.modified_at);
+        let before: HashMap<String, (u64, u64)> = self
+            .list_documents()
+            .iter()
+            .map(|metadata| (metadata.id.clone(), revision(metadata)))
+            .collect();
         self.load_index();
+        self.load_trash_index();
+        self.history.reload_config();
+        self.folders.reload();
+        self.templates.reload();
+
+        // Cached sizes and search entries stay valid for documents nobody rewrote
+        let after: HashMap<String, (u64, u64)> = self
+            .list_documents()
+            .iter()
+            .map(|metadata| (metadata.id.clone(), revision(metadata)))
+            .collect();
+        let stale: HashSet<&String> = after
+            .iter()
+            .filter(|(id, rev)| before.get(*id) != Some(*rev))
+            .map(|(id, _)| id)
+            .chain(before.keys().filter(|id| !after.contains_key(*id)))
+            .collect();
+        if let Ok(mut sizes) = self.sizes.write() {
+            sizes.retain(|id, _| !stale.contains(id));
+        }
+        for doc_id in stale {
+            match self.read_document(doc_id) {
+                Ok(doc) => self.search.index_document(&doc),
+                Err(_) => self.search.remove_document(doc_id),
+            }
+        }
     }
 
-    /// Load the metadata index from disk
+    /// Load the metadata index from storage.
+    ///
+    /// The index is derived data: if it is missing or unreadable it is
+    /// rebuilt from the stored documents (a corrupt index is moved aside first).
     fn load_index(&self) {
-        let path = self.index_path();
-        if let Ok(data) = std::fs::read_to_string(&path) {
-            if let Ok(index) = serde_json::from_str::<HashMap<String, DocumentMetadata>>(&data) {
+        self.invalidate_usage();
+        let loaded = match self.storage.get(Space::Meta, INDEX_KEY) {
+            Ok(Some(data)) => match serde_json::from_slice::<HashMap<String, DocumentMetadata>>(&data) {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    log::error!("Document index is corrupt ({}), rebuilding from documents", e);
+                    if let Err(e) = self.storage.quarantine(Space::Meta, INDEX_KEY) {
+                        log::warn!("Failed to move corrupt document index aside: {}", e);
+                    }
+                    None
+                }
+            },
+            Ok(None) => {
+                if self.storage.list(Space::Documents, "").map(|k| k.is_empty()).unwrap_or(true) {
+                    if let Ok(mut current) = self.index.write() {
+                        current.clear();
+                    }
+                    return;
+                }
+                log::warn!("Document index missing, rebuilding from documents");
+                None
+            }
+            Err(e) => {
+                log::error!("Failed to read document index: {}", e);
+                return;
+            }
+        };
+
+        match loaded {
+            Some(index) => {
                 if let Ok(mut current) = self.index.write() {
                     *current = index;
                 }
             }
+            None => {
+                if let Err(e) = self.rebuild_index() {
+                    log::error!("Failed to rebuild document index: {}", e);
+                }
+            }
         }
     }
 
-    /// Save the metadata index to disk
-    fn save_index(&self) -> Result<(), String> {
+    /// Rebuild the metadata index by reading every stored document
+    pub fn rebuild_index(&self) -> Result<usize, String> {
+        let mut rebuilt = HashMap::new();
+        for key in self.storage.list(Space::Documents, "")? {
+            let doc = self
+                .storage
+                .get(Space::Documents, &key)
+                .and_then(|data| data.ok_or_else(|| "Document vanished".to_string()))
+                .and_then(|data| {
+                    serde_json::from_slice::<serde_json::Value>(&data).map_err(|e| e.to_string())
+                })
+                .and_then(|doc| build_metadata(&doc));
+            match doc {
+                Ok(metadata) => {
+                    rebuilt.insert(metadata.id.clone(), metadata);
+                }
+                Err(e) => log::warn!("Skipping unreadable document {}: {}", key, e),
+            }
+        }
+
+        let count = rebuilt.len();
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            *index = rebuilt;
+        }
+        self.invalidate_usage();
+        self.save_index()?;
+
+        log::info!("Rebuilt document index with {} documents", count);
+        Ok(count)
+    }
+
+    /// Re-read every live document into the full-text index
+    fn rebuild_search_index(&self) {
+        let docs: Vec<serde_json::Value> = self
+            .list_documents()
+            .iter()
+            .filter_map(|m| self.read_document(&m.id).ok())
+            .collect();
+        self.search.rebuild(&docs);
+        log::debug!("Indexed {} documents for search", docs.len());
+    }
+
+    /// Stored size of a live document in bytes
+    pub fn document_size(&self, doc_id: &str) -> Option<u64> {
+        self.get_metadata(doc_id)?;
+        if let Some(size) = self.sizes.read().ok()?.get(doc_id) {
+            return Some(*size);
+        }
+        let size = self.storage.get(Space::Documents, doc_id).ok()??.len() as u64;
+        if let Ok(mut sizes) = self.sizes.write() {
+            sizes.insert(doc_id.to_string(), size);
+        }
+        Some(size)
+    }
+
+    /// Every live document with its stored size in bytes
+    pub fn document_sizes(&self) -> Vec<(DocumentMetadata, u64)> {
+        self.list_documents()
+            .into_iter()
+            .filter_map(|m| self.document_size(&m.id).map(|size| (m, size)))
+            .collect()
+    }
+
+    /// Bytes and count of live documents per owner
+    pub fn document_usage(&self) -> UsageSnapshot {
+        match self.write_lock.lock() {
+            Ok(_guard) => self.cached_usage(),
+            Err(_) => UsageSnapshot::default(),
+        }
+    }
+
+    /// Usage of live documents, computed if not cached. Callers must hold
+    /// `write_lock` so no write slips in between computing and caching.
+    fn cached_usage(&self) -> UsageSnapshot {
+        if let Some(usage) = self.usage.read().ok().and_then(|usage| usage.clone()) {
+            return usage;
+        }
+        let mut usage = UsageSnapshot::default();
+        for (metadata, size) in self.document_sizes() {
+            usage.add_document(metadata.owner_id.as_deref(), size);
+        }
+        if let Ok(mut cached) = self.usage.write() {
+            *cached = Some(usage.clone());
+        }
+        usage
+    }
+
+    /// Drop the cached usage; it is recomputed on next use
+    fn invalidate_usage(&self) {
+        if let Ok(mut usage) = self.usage.write() {
+            *usage = None;
+        }
+    }
+
+    /// Register the check that can refuse document writes
+    pub fn on_write_check(&self, check: impl Fn(&PendingWrite) -> Result<(), String> + Send + Sync + 'static) {
+        if let Ok(mut current) = self.write_check.write() {
+            *current = Some(Arc::new(check));
+        }
+    }
+
+    /// Search live documents. `visible` filters by document ID (e.g. read
+    /// permission) before `limit` is applied.
+    pub fn search(&self, query: &str, limit: usize, visible: impl Fn(&str) -> bool) -> Vec<SearchHit> {
+        self.search.search(query, limit, visible)
+    }
+
+    /// Serialized metadata index, for writing alongside a document change
+    fn index_bytes(&self) -> Result<Vec<u8>, String> {
         let index = self.index.read().map_err(|e| e.to_string())?;
-        let json = serde_json::to_string_pretty(&*index)
-            .map_err(|e| format!("Serialize error: {}", e))?;
-        std::fs::write(self.index_path(), json)
-            .map_err(|e| format!("Write error: {}", e))?;
-        Ok(())
+        serde_json::to_vec_pretty(&*index).map_err(|e| format!("Serialize error: {}", e))
+    }
+
+    /// Save the metadata index to storage
+    fn save_index(&self) -> Result<(), String> {
+        self.storage.put(Space::Meta, INDEX_KEY, &self.index_bytes()?)
     }
 
     /// List all team documents
@@ -125,7 +411,31 @@ impl DocumentStore {
     }
 
     /// Get a document by ID (returns full document as JSON value)
+    ///
+    /// Documents stored in an older schema are upgraded in memory; the
+    /// upgrade is written with the next real save, so reading never bumps
+    /// `serverVersion` under connected editors. Documents newer than this
+    /// server understands are refused with `ERR_UNSUPPORTED_VERSION`.
     pub fn get_document(&self, doc_id: &str) -> Result<serde_json::Value, String> {
+        self.load_migrated(doc_id)
+    }
+
+    /// Run `f` while no document write is in progress
+    /// (e.g. to take a consistent backup snapshot)
+    pub fn pause_writes<T>(&self, f: impl FnOnce() -> T) -> Result<T, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        Ok(f())
+    }
+
+    /// Load a document and upgrade it in memory only
+    fn load_migrated(&self, doc_id: &str) -> Result<serde_json::Value, String> {
+        let mut doc = self.read_document(doc_id)?;
+        schema::migrate(&mut doc)?;
+        Ok(doc)
+    }
+
+    /// Read a stored document as-is
+    fn read_document(&self, doc_id: &str) -> Result<serde_json::Value, String> {
         // Check if document exists in index
         {
             let index = self.index.read().map_err(|e| e.to_string())?;
@@ -134,276 +444,1197 @@ impl DocumentStore {
             }
         }
 
-        // Load document from file
-        let path = self.doc_path(doc_id);
-        let data = std::fs::read_to_string(&path)
-            .map_err(|e| format!("Failed to read document: {}", e))?;
-        let doc: serde_json::Value = serde_json::from_str(&data)
+        // Load document from storage
+        let data = self
+            .storage
+            .get(Space::Documents, doc_id)
+            .map_err(|e| format!("Failed to read document: {}", e))?
+            .ok_or("Document not found")?;
+        let doc: serde_json::Value = serde_json::from_slice(&data)
             .map_err(|e| format!("Failed to parse document: {}", e))?;
 
         Ok(doc)
     }
 
     /// Save a document (creates or updates)
+    ///
+    /// Stamps the document with the next `serverVersion`, regardless of the
+    /// value supplied by the caller.
     pub fn save_document(&self, doc: serde_json::Value) -> Result<(), String> {
-        // Extract required fields from document
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        self.write_document(doc).map(|_| ())
+    }
+
+    /// Save a document that was edited starting from `base_version`.
+    ///
+    /// If the base is stale, the incoming document is three-way merged with
+    /// the stored one. Non-overlapping edits are saved; if any field was
+    /// changed on both sides, nothing is written and the conflicts are
+    /// returned. Without a base version this behaves like `save_document`.
+    ///
+    /// The document that would be written (incoming or merged) is validated
+    /// with `mode` first, so a merge cannot persist dangling references.
+    /// `authorize` then sees it under the write lock and can refuse it.
+    pub fn save_document_with_base(
+        &self,
+        mut doc: serde_json::Value,
+        base_version: Option<u64>,
+        mode: ValidationMode,
+        authorize: impl FnOnce(&serde_json::Value) -> Result<(), String>,
+    ) -> Result<SaveOutcome, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        schema::migrate(&mut doc)?;
+
         let id = doc.get("id")
             .and_then(|v| v.as_str())
             .ok_or("Document missing 'id' field")?
             .to_string();
 
-        let name = doc.get("name")
+        let current_version = self.get_metadata(&id).map(|m| m.server_version);
+        let base_version = match (base_version, current_version) {
+            (Some(base), Some(current)) if base != current => base,
+            _ => {
+                let repairs = validation::validate_document(&mut doc, mode)?;
+                authorize(&doc)?;
+                let metadata = self.write_document(doc)?;
+                return Ok(SaveOutcome::Saved { metadata, repairs });
+            }
+        };
+
+        let mut base = self.load_revision(&id, base_version).map_err(|e| {
+            format!("{}: Cannot merge from version {}: {}", error_codes::VERSION_CONFLICT, base_version, e)
+        })?;
+        schema::migrate(&mut base)?;
+        let current = self.load_migrated(&id)?;
+
+        let result = merge::three_way_merge(&base, &current, &doc);
+        if !result.conflicts.is_empty() {
+            log::info!(
+                "Save of document {} from version {} has {} conflict(s)",
+                id,
+                base_version,
+                result.conflicts.len()
+            );
+            return Ok(SaveOutcome::Conflict {
+                server_version: current_version.unwrap_or(0),
+                conflicts: result.conflicts,
+            });
+        }
+
+        let mut merged = result.merged;
+        let repairs = validation::validate_document(&mut merged, mode)?;
+        authorize(&merged)?;
+        let metadata = self.write_document(merged)?;
+        let document = self.read_document(&id)?;
+        log::info!("Merged save of document {} from version {}", id, base_version);
+        Ok(SaveOutcome::Merged { metadata, document, repairs })
+    }
+
+    /// Load a stored revision of a document
+    fn load_revision(&self, doc_id: &str, server_version: u64) -> Result<serde_json::Value, String> {
+        let data = self
+            .storage
+            .get(Space::Revisions, &Self::revision_key(doc_id, server_version))?
+            .ok_or("Revision not available")?;
+        serde_json::from_slice(&data).map_err(|e| format!("Failed to parse revision: {}", e))
+    }
+
+    /// Deletions that drop revisions beyond the merge-base window once
+    /// `new_version` has been stored. Revisions that are `recorded` history
+    /// versions are kept.
+    fn expired_revisions(&self, doc_id: &str, new_version: u64, recorded: &[u64]) -> Vec<WriteOp> {
+        let mut versions: Vec<u64> = self
+            .storage
+            .list(Space::Revisions, &format!("{}/", doc_id))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|key| key.rsplit('/').next()?.parse().ok())
+            .filter(|v| *v != new_version)
+            .collect();
+        versions.push(new_version);
+        versions.sort_unstable();
+
+        let excess = versions.len().saturating_sub(MERGE_BASE_RETENTION);
+        versions[..excess]
+            .iter()
+            .filter(|old| !recorded.contains(old))
+            .map(|old| WriteOp::Delete {
+                space: Space::Revisions,
+                key: Self::revision_key(doc_id, *old),
+            })
+            .collect()
+    }
+
+    /// Apply an RFC 6902 patch to a stored document.
+    ///
+    /// The patch must be based on the document's current `serverVersion`;
+    /// otherwise an `ERR_VERSION_CONFLICT` error is returned and nothing is
+    /// written. Operations are applied atomically and the index metadata is
+    /// re-derived from the patched document, which is validated with `mode`.
+    /// `authorize` sees the patched document before it is written and can
+    /// refuse it. Returns the new metadata and any lenient-mode repairs.
+    pub fn apply_patch(
+        &self,
+        doc_id: &str,
+        base_version: u64,
+        operations: &[PatchOperation],
+        mode: ValidationMode,
+        authorize: impl FnOnce(&serde_json::Value) -> Result<(), String>,
+    ) -> Result<(DocumentMetadata, Vec<ValidationIssue>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+
+        let current_version = self
+            .get_metadata(doc_id)
+            .ok_or("Document not found")?
+            .server_version;
+        if current_version != base_version {
+            return Err(format!(
+                "{}: Patch based on version {} but server is at version {}",
+                error_codes::VERSION_CONFLICT,
+                base_version,
+                current_version
+            ));
+        }
+
+        let mut doc = self.load_migrated(doc_id)?;
+        json_patch::apply_patch(&mut doc, operations)?;
+
+        if doc.get("id").and_then(|v| v.as_str()) != Some(doc_id) {
+            return Err("Patch must not change the document id".to_string());
+        }
+
+        let repairs = validation::validate_document(&mut doc, mode)?;
+        authorize(&doc)?;
+        Ok((self.write_document(doc)?, repairs))
+    }
+
+    /// Apply a page-level edit (see `pages`) to a document under the write
+    /// lock and validate the result with `mode`. Returns the new metadata,
+    /// the edit's result and any lenient-mode repairs.
+    pub fn edit_pages<T>(
+        &self,
+        doc_id: &str,
+        mode: ValidationMode,
+        edit: impl FnOnce(&mut serde_json::Value) -> Result<T, String>,
+    ) -> Result<(DocumentMetadata, T, Vec<ValidationIssue>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let mut doc = self.load_migrated(doc_id)?;
+        let result = edit(&mut doc)?;
+        let repairs = validation::validate_document(&mut doc, mode)?;
+        let metadata = self.write_document(doc)?;
+        Ok((metadata, result, repairs))
+    }
+
+    /// Write a document, its merge-base revision and its index entry in one
+    /// batch. Older schemas are upgraded first; newer ones are refused, as
+    /// are writes the write check rejects. Callers must hold `write_lock`.
+    fn write_document(&self, doc: serde_json::Value) -> Result<DocumentMetadata, String> {
+        self.write_document_checked(doc, true)
+    }
+
+    /// `write_document`, optionally bypassing the write check
+    fn write_document_checked(&self, mut doc: serde_json::Value, checked: bool) -> Result<DocumentMetadata, String> {
+        schema::migrate(&mut doc)?;
+
+        let id = doc.get("id")
             .and_then(|v| v.as_str())
-            .unwrap_or("Untitled")
+            .ok_or("Document missing 'id' field")?
             .to_string();
 
-        let page_order = doc.get("pageOrder")
-            .and_then(|v| v.as_array())
-            .map(|arr| arr.len())
-            .unwrap_or(1);
-
-        let modified_at = doc.get("modifiedAt")
-            .and_then(|v| v.as_u64())
-            .unwrap_or_else(|| {
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .map(|d| d.as_millis() as u64)
-                    .unwrap_or(0)
-            });
+        let existing = self.get_metadata(&id);
+        self.apply_organization(&mut doc, existing.is_none())?;
 
-        let created_at = doc.get("createdAt")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(modified_at);
-
-        // Build metadata
-        let metadata = DocumentMetadata {
-            id: id.clone(),
-            name,
-            page_count: page_order,
-            modified_at,
-            created_at,
-            is_team_document: doc.get("isTeamDocument").and_then(|v| v.as_bool()),
-            locked_by: doc.get("lockedBy").and_then(|v| v.as_str()).map(String::from),
-            locked_by_name: doc.get("lockedByName").and_then(|v| v.as_str()).map(String::from),
-            locked_at: doc.get("lockedAt").and_then(|v| v.as_u64()),
-            owner_id: doc.get("ownerId").and_then(|v| v.as_str()).map(String::from),
-            owner_name: doc.get("ownerName").and_then(|v| v.as_str()).map(String::from),
-            shared_with: doc.get("sharedWith").and_then(|v| {
-                serde_json::from_value(v.clone()).ok()
-            }),
-            last_modified_by: doc.get("lastModifiedBy").and_then(|v| v.as_str()).map(String::from),
-            last_modified_by_name: doc.get("lastModifiedByName").and_then(|v| v.as_str()).map(String::from),
-        };
+        let server_version = existing.as_ref().map(|m| m.server_version).unwrap_or(0) + 1;
+        doc["serverVersion"] = serde_json::json!(server_version);
 
-        // Save document to file
+        let metadata = build_metadata(&doc)?;
         let doc_json = serde_json::to_string_pretty(&doc)
             .map_err(|e| format!("Serialize error: {}", e))?;
-        std::fs::write(self.doc_path(&id), doc_json)
-            .map_err(|e| format!("Write error: {}", e))?;
+
+        let old_size = existing.as_ref().and_then(|_| self.document_size(&id));
+        let new_size = doc_json.len() as u64;
+        let check = self.write_check.read().ok().and_then(|check| check.clone());
+        if let Some(check) = check.filter(|_| checked) {
+            let same_owner = existing.as_ref().is_some_and(|m| m.owner_id == metadata.owner_id);
+            check(&PendingWrite {
+                doc_id: &id,
+                owner_id: metadata.owner_id.as_deref(),
+                old_size: old_size.filter(|_| same_owner),
+                new_size,
+                usage: &self.cached_usage(),
+            })?;
+        }
 
         // Update index
-        {
+        let previous = {
             let mut index = self.index.write().map_err(|e| e.to_string())?;
-            index.insert(id.clone(), metadata);
+            index.insert(id.clone(), metadata.clone())
+        };
+
+        let mut ops = vec![
+            WriteOp::Put {
+                space: Space::Documents,
+                key: id.clone(),
+                value: doc_json.clone().into_bytes(),
+            },
+            // Keep as a merge base for stale saves
+            WriteOp::Put {
+                space: Space::Revisions,
+                key: Self::revision_key(&id, server_version),
+                value: doc_json.clone().into_bytes(),
+            },
+        ];
+        // The revision doubles as the history snapshot when it is recorded
+        let history = self.history.record(&id, &doc, new_size, now_ms());
+        let recorded = match &history {
+            Some(versions) => versions.iter().map(|v| v.server_version).collect(),
+            None => self.history.recorded(&id),
+        };
+        ops.extend(self.expired_revisions(&id, server_version, &recorded));
+        let written = self.index_bytes().and_then(|index| {
+            ops.push(WriteOp::Put {
+                space: Space::Meta,
+                key: INDEX_KEY.to_string(),
+                value: index,
+            });
+            if let Some(versions) = &history {
+                ops.push(HistoryStore::index_write(&id, versions)?);
+            }
+            self.storage.write_batch(ops)
+        });
+        if let Err(e) = written {
+            if let Ok(mut index) = self.index.write() {
+                match previous {
+                    Some(previous) => index.insert(id.clone(), previous),
+                    None => index.remove(&id),
+                };
+            }
+            return Err(format!("Write error: {}", e));
         }
 
-        // Save index
-        self.save_index()?;
+        self.search.index_document(&doc);
+        if let Ok(mut sizes) = self.sizes.write() {
+            sizes.insert(id.clone(), new_size);
+        }
+        if let Ok(mut usage) = self.usage.write() {
+            match (usage.as_mut(), &existing, old_size) {
+                (Some(cached), None, _) => cached.add_document(metadata.owner_id.as_deref(), new_size),
+                (Some(cached), Some(previous), Some(old_size)) => {
+                    cached.remove_document(previous.owner_id.as_deref(), old_size);
+                    cached.add_document(metadata.owner_id.as_deref(), new_size);
+                }
+                (Some(_), Some(_), None) => *usage = None,
+                (None, _, _) => {}
+            }
+        }
+        self.notify_blob_references(&id, &blob_references(&doc));
 
-        log::info!("Saved team document: {}", id);
-        Ok(())
+        log::info!("Saved team document: {} (v{})", id, server_version);
+        Ok(metadata)
     }
 
-    /// Delete a document
-    pub fn delete_document(&self, doc_id: &str) -> Result<bool, String> {
-        // Check if document exists
-        {
-            let index = self.index.read().map_err(|e| e.to_string())?;
-            if !index.contains_key(doc_id) {
-                return Ok(false);
-            }
+    /// Normalize `folderId` and `tags` before a write. A reference to a
+    /// missing folder is dropped. New documents inherit the default shares
+    /// of their folder chain, without overriding explicit shares.
+    fn apply_organization(&self, doc: &mut serde_json::Value, is_new: bool) -> Result<(), String> {
+        if let Some(tags) = doc.get("tags").filter(|v| !v.is_null()) {
+            let tags: Vec<String> = serde_json::from_value(tags.clone())
+                .map_err(|_| "Document 'tags' must be a list of strings".to_string())?;
+            doc["tags"] = serde_json::json!(folders::normalize_tags(&tags)?);
         }
 
-        // Remove document file
-        let path = self.doc_path(doc_id);
-        if path.exists() {
-            std::fs::remove_file(&path)
-                .map_err(|e| format!("Failed to delete document file: {}", e))?;
+        let folder_id = doc.get("folderId").and_then(|v| v.as_str()).map(String::from);
+        let Some(folder_id) = folder_id else {
+            return Ok(());
+        };
+        if !self.folders.contains(&folder_id) {
+            log::warn!("Document refers to missing folder {}, filing at top level", folder_id);
+            if let Some(obj) = doc.as_object_mut() {
+                obj.remove("folderId");
+            }
+            return Ok(());
         }
 
-        // Remove from index
-        {
-            let mut index = self.index.write().map_err(|e| e.to_string())?;
-            index.remove(doc_id);
+        if is_new {
+            let owner_id = doc.get("ownerId").and_then(|v| v.as_str()).map(String::from);
+            let mut shares: Vec<DocumentShare> = doc
+                .get("sharedWith")
+                .and_then(|v| serde_json::from_value(v.clone()).ok())
+                .unwrap_or_default();
+            for share in self.folders.inherited_shares(&folder_id) {
+                if owner_id.as_deref() != Some(share.user_id.as_str())
+                    && !shares.iter().any(|s| s.user_id == share.user_id)
+                {
+                    shares.push(share);
+                }
+            }
+            if !shares.is_empty() {
+                doc["sharedWith"] = serde_json::to_value(&shares)
+                    .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+            }
         }
-
-        // Save index
-        self.save_index()?;
-
-        log::info!("Deleted team document: {}", doc_id);
-        Ok(true)
+        Ok(())
     }
 
-    /// Get document metadata by ID
-    pub fn get_metadata(&self, doc_id: &str) -> Option<DocumentMetadata> {
-        self.index.read().ok()?.get(doc_id).cloned()
+    /// Move a document between the live and trash spaces together with both
+    /// indexes. The in-memory indexes must already reflect the move; they are
+    /// reloaded from storage if the write fails.
+    fn write_move(&self, doc_id: &str, from: Space, to: Space, doc: Option<Vec<u8>>) -> Result<(), String> {
+        let mut ops = Vec::new();
+        if let Some(doc) = doc {
+            ops.push(WriteOp::Put { space: to, key: doc_id.to_string(), value: doc });
+            ops.push(WriteOp::Delete { space: from, key: doc_id.to_string() });
+        }
+        let result = self
+            .index_bytes()
+            .and_then(|index| Ok((index, self.trash_index_bytes()?)))
+            .and_then(|(index, trash)| {
+                ops.push(WriteOp::Put { space: Space::Meta, key: INDEX_KEY.to_string(), value: index });
+                ops.push(WriteOp::Put { space: Space::Meta, key: TRASH_INDEX_KEY.to_string(), value: trash });
+                self.storage.write_batch(ops)
+            });
+        if result.is_err() {
+            self.load_index();
+            self.load_trash_index();
+        }
+        result
     }
 
-    /// Update document lock status
-    pub fn set_lock(
+    /// Delete a document by moving it to the trash.
+    ///
+    /// History and merge bases are kept so a restored document is complete.
+    pub fn delete_document(
         &self,
         doc_id: &str,
-        user_id: Option<&str>,
-        user_name: Option<&str>,
-    ) -> Result<(), String> {
-        // Load document
-        let mut doc = self.get_document(doc_id)?;
+        deleted_by: Option<&str>,
+        deleted_by_name: Option<&str>,
+    ) -> Result<bool, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
 
-        // Update lock fields
-        if let Some(uid) = user_id {
-            doc["lockedBy"] = serde_json::json!(uid);
-            doc["lockedByName"] = serde_json::json!(user_name.unwrap_or("Unknown"));
-            doc["lockedAt"] = serde_json::json!(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .map(|d| d.as_millis() as u64)
-                    .unwrap_or(0)
-            );
-        } else {
-            doc["lockedBy"] = serde_json::Value::Null;
-            doc["lockedByName"] = serde_json::Value::Null;
-            doc["lockedAt"] = serde_json::Value::Null;
+        // Check if document exists
+        let metadata = match self.get_metadata(doc_id) {
+            Some(m) => m,
+            None => return Ok(false),
+        };
+
+        let entry = TrashEntry {
+            metadata,
+            deleted_at: now_ms(),
+            deleted_by: deleted_by.map(String::from),
+            deleted_by_name: deleted_by_name.map(String::from),
+        };
+
+        let doc = self.storage.get(Space::Documents, doc_id)?;
+
+        {
+            let mut trash = self.trash.write().map_err(|e| e.to_string())?;
+            trash.insert(doc_id.to_string(), entry);
+        }
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            index.remove(doc_id);
         }
+        self.write_move(doc_id, Space::Documents, Space::Trash, doc)
+            .map_err(|e| format!("Failed to move document to trash: {}", e))?;
+        self.search.remove_document(doc_id);
+        self.invalidate_usage();
+        self.notify_blob_references(doc_id, &[]);
 
-        // Save document
-        self.save_document(doc)
+        log::info!("Moved team document to trash: {}", doc_id);
+        Ok(true)
     }
 
-    /// Check if a document is locked by another user
-    pub fn is_locked_by_other(&self, doc_id: &str, user_id: &str) -> bool {
-        if let Some(metadata) = self.get_metadata(doc_id) {
-            if let Some(locked_by) = &metadata.locked_by {
-                return locked_by != user_id;
-            }
-        }
-        false
+    /// List trashed documents
+    pub fn list_trash(&self) -> Vec<TrashEntry> {
+        self.trash
+            .read()
+            .map(|trash| trash.values().cloned().collect())
+            .unwrap_or_default()
     }
 
-    /// Get document metadata (alias for get_metadata)
-    pub fn get_document_metadata(&self, doc_id: &str) -> Option<DocumentMetadata> {
-        self.get_metadata(doc_id)
+    /// Get a trash entry by document ID
+    pub fn get_trash_entry(&self, doc_id: &str) -> Option<TrashEntry> {
+        self.trash.read().ok()?.get(doc_id).cloned()
     }
 
-    /// Update document sharing permissions
-    pub fn update_document_shares(
-        &self,
-        doc_id: &str,
-        shares: &[super::protocol::ShareEntry],
-    ) -> Result<(), String> {
-        // Load document
-        let mut doc = self.get_document(doc_id)?;
+    /// Restore a trashed document with its owner and shares intact
+    pub fn restore_from_trash(&self, doc_id: &str) -> Result<DocumentMetadata, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
 
-        // Build new shares list
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
+        if self.get_trash_entry(doc_id).is_none() {
+            return Err("Document not in trash".to_string());
+        }
+        if self.get_metadata(doc_id).is_some() {
+            return Err("A document with this ID already exists".to_string());
+        }
 
-        let new_shares: Vec<DocumentShare> = shares
-            .iter()
-            .filter(|s| s.permission != "none") // "none" means remove access
-            .map(|s| DocumentShare {
-                user_id: s.user_id.clone(),
-                user_name: s.user_name.clone(),
-                permission: s.permission.clone(),
-                shared_at: now,
-            })
-            .collect();
+        let data = self
+            .storage
+            .get(Space::Trash, doc_id)
+            .map_err(|e| format!("Failed to read trashed document: {}", e))?
+            .ok_or("Failed to read trashed document: file missing")?;
+        let mut doc: serde_json::Value = serde_json::from_slice(&data)
+            .map_err(|e| format!("Failed to parse trashed document: {}", e))?;
+        // Its folder may have been deleted while it was in the trash
+        let data = match doc.get("folderId").and_then(|v| v.as_str()) {
+            Some(folder_id) if !self.folders.contains(folder_id) => {
+                if let Some(obj) = doc.as_object_mut() {
+                    obj.remove("folderId");
+                }
+                serde_json::to_vec_pretty(&doc).map_err(|e| format!("Serialize error: {}", e))?
+            }
+            _ => data,
+        };
+        let metadata = build_metadata(&doc)?;
 
-        // Update document JSON
-        doc["sharedWith"] = serde_json::to_value(&new_shares)
-            .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+        {
+            let mut index = self.index.write().map_err(|e| e.to_string())?;
+            index.insert(doc_id.to_string(), metadata.clone());
+        }
+        {
+            let mut trash = self.trash.write().map_err(|e| e.to_string())?;
+            trash.remove(doc_id);
+        }
+        self.write_move(doc_id, Space::Trash, Space::Documents, Some(data))
+            .map_err(|e| format!("Failed to restore document: {}", e))?;
+        self.search.index_document(&doc);
+        self.invalidate_usage();
+        self.notify_blob_references(doc_id, &blob_references(&doc));
+
+        log::info!("Restored team document from trash: {}", doc_id);
+        Ok(metadata)
+    }
 
-        // Save document
-        self.save_document(doc)?;
+    /// Permanently delete a trashed document along with its history.
+    /// Returns the blob hashes it referenced so the caller can release them.
+    pub fn purge_from_trash(&self, doc_id: &str) -> Result<Vec<String>, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
 
-        log::info!(
-            "Updated shares for document {}: {} users",
-            doc_id,
-            new_shares.len()
-        );
-        Ok(())
-    }
+        if self.get_trash_entry(doc_id).is_none() {
+            return Err("Document not in trash".to_string());
+        }
 
-    /// Transfer document ownership to another user
-    pub fn transfer_ownership(
-        &self,
-        doc_id: &str,
-        new_owner_id: &str,
-        new_owner_name: &str,
-        previous_owner_id: &str,
-    ) -> Result<(), String> {
-        // Load document
-        let mut doc = self.get_document(doc_id)?;
-
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
-
-        // Update owner fields
-        doc["ownerId"] = serde_json::json!(new_owner_id);
-        doc["ownerName"] = serde_json::json!(new_owner_name);
-
-        // Add previous owner as an editor in the shares
-        let mut shares: Vec<DocumentShare> = doc["sharedWith"]
-            .as_array()
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|v| serde_json::from_value(v.clone()).ok())
-                    .collect()
-            })
+        let blobs = self
+            .storage
+            .get(Space::Trash, doc_id)
+            .ok()
+            .flatten()
+            .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+            .map(|doc| blob_references(&doc))
             .unwrap_or_default();
 
-        // Remove new owner from shares (they're owner now)
-        shares.retain(|s| s.user_id != new_owner_id);
-
-        // Add previous owner as editor if not already in shares
-        if !shares.iter().any(|s| s.user_id == previous_owner_id) {
-            shares.push(DocumentShare {
-                user_id: previous_owner_id.to_string(),
-                user_name: doc["lastModifiedByName"]
-                    .as_str()
-                    .unwrap_or("Previous Owner")
-                    .to_string(),
-                permission: "edit".to_string(),
-                shared_at: now,
-            });
+        {
+            let mut trash = self.trash.write().map_err(|e| e.to_string())?;
+            trash.remove(doc_id);
+        }
+        let written = self.trash_index_bytes().and_then(|trash| {
+            self.storage.write_batch(vec![
+                WriteOp::Delete { space: Space::Trash, key: doc_id.to_string() },
+                WriteOp::Put { space: Space::Meta, key: TRASH_INDEX_KEY.to_string(), value: trash },
+            ])
+        });
+        if let Err(e) = written {
+            self.load_trash_index();
+            return Err(format!("Failed to delete document: {}", e));
         }
 
-        doc["sharedWith"] = serde_json::to_value(&shares)
-            .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+        let _ = self.storage.delete_prefix(Space::Revisions, &format!("{}/", doc_id));
+        self.history.delete_history(doc_id);
 
-        // Save document
-        self.save_document(doc)?;
+        log::info!("Purged team document: {}", doc_id);
+        Ok(blobs)
+    }
 
-        log::info!(
-            "Transferred ownership of document {} from {} to {}",
-            doc_id,
-            previous_owner_id,
-            new_owner_id
-        );
-        Ok(())
+    /// Blob hashes referenced by any live or trashed document, template or
+    /// version history snapshot. Fails if any of them cannot be read or
+    /// parsed: an incomplete set would make blobs still in use look unused.
+    pub fn referenced_blobs(&self) -> Result<HashSet<String>, String> {
+        let live = self.list_documents().into_iter().map(|m| (Space::Documents, m.id));
+        let trashed = self.list_trash().into_iter().map(|e| (Space::Trash, e.metadata.id));
+        let mut documents = Vec::new();
+        for (space, id) in live.chain(trashed) {
+            let Some(data) = self
+                .storage
+                .get(space, &id)
+                .map_err(|e| format!("Failed to read document {}: {}", id, e))?
+            else {
+                continue;
+            };
+            documents.push(
+                serde_json::from_slice::<serde_json::Value>(&data)
+                    .map_err(|e| format!("Failed to parse document {}: {}", id, e))?,
+            );
+        }
+        documents.extend(self.templates.documents()?);
+        documents.extend(self.history.snapshots()?);
+        Ok(documents.iter().flat_map(blob_references).collect())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    /// Blob hashes each live document uses, by document ID
+    pub fn blob_references_by_document(&self) -> HashMap<String, Vec<String>> {
+        self.list_documents()
+            .into_iter()
+            .filter_map(|m| Some((m.id.clone(), self.document_blob_references(&m.id)?)))
+            .collect()
+    }
 
-    #[test]
-    fn test_document_store_lifecycle() {
+    /// Blob hashes a live document uses (`None` if it does not exist)
+    pub fn document_blob_references(&self, doc_id: &str) -> Option<Vec<String>> {
+        self.get_metadata(doc_id)?;
+        let data = self.storage.get(Space::Documents, doc_id).ok().flatten()?;
+        let doc = serde_json::from_slice::<serde_json::Value>(&data).ok()?;
+        Some(blob_references(&doc))
+    }
+
+    /// Register the listener told about blob references as live documents
+    /// are written, trashed and restored
+    pub fn on_blob_references(&self, listener: impl Fn(&str, &[String]) + Send + Sync + 'static) {
+        if let Ok(mut current) = self.blob_listener.write() {
+            *current = Some(Arc::new(listener));
+        }
+    }
+
+    fn notify_blob_references(&self, doc_id: &str, hashes: &[String]) {
+        let listener = self.blob_listener.read().ok().and_then(|l| l.clone());
+        if let Some(listener) = listener {
+            listener(doc_id, hashes);
+        }
+    }
+
+    /// Load the trash index from storage, rebuilding it from the trash space
+    /// if it is missing or corrupt. Rebuilt entries use the current time as
+    /// their deletion time, so the retention period restarts rather than
+    /// purging them on the next run.
+    fn load_trash_index(&self) {
+        let existed = match self.storage.get(Space::Meta, TRASH_INDEX_KEY) {
+            Ok(Some(data)) => match serde_json::from_slice::<HashMap<String, TrashEntry>>(&data) {
+                Ok(trash) => {
+                    if let Ok(mut current) = self.trash.write() {
+                        *current = trash;
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Trash index is corrupt ({}), rebuilding from trash", e);
+                    if let Err(e) = self.storage.quarantine(Space::Meta, TRASH_INDEX_KEY) {
+                        log::warn!("Failed to move corrupt trash index aside: {}", e);
+                    }
+                    true
+                }
+            },
+            Ok(None) => false,
+            Err(e) => {
+                log::error!("Failed to read trash index: {}", e);
+                return;
+            }
+        };
+
+        let deleted_at = now_ms();
+        let mut rebuilt = HashMap::new();
+        for key in self.storage.list(Space::Trash, "").unwrap_or_default() {
+            let metadata = self
+                .storage
+                .get(Space::Trash, &key)
+                .ok()
+                .flatten()
+                .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+                .and_then(|doc| build_metadata(&doc).ok());
+            if let Some(metadata) = metadata {
+                rebuilt.insert(
+                    metadata.id.clone(),
+                    TrashEntry {
+                        metadata,
+                        deleted_at,
+                        deleted_by: None,
+                        deleted_by_name: None,
+                    },
+                );
+            }
+        }
+
+        if rebuilt.is_empty() && !existed {
+            return;
+        }
+        if let Ok(mut current) = self.trash.write() {
+            *current = rebuilt;
+        }
+        if let Err(e) = self.save_trash_index() {
+            log::error!("Failed to save rebuilt trash index: {}", e);
+        }
+    }
+
+    /// Serialized trash index
+    fn trash_index_bytes(&self) -> Result<Vec<u8>, String> {
+        let trash = self.trash.read().map_err(|e| e.to_string())?;
+        serde_json::to_vec_pretty(&*trash).map_err(|e| format!("Serialize error: {}", e))
+    }
+
+    /// Save the trash index to storage
+    fn save_trash_index(&self) -> Result<(), String> {
+        self.storage.put(Space::Meta, TRASH_INDEX_KEY, &self.trash_index_bytes()?)
+    }
+
+    /// List recorded versions of a document, newest first
+    pub fn list_versions(&self, doc_id: &str) -> Result<Vec<VersionInfo>, String> {
+        if self.get_metadata(doc_id).is_none() {
+            return Err("Document not found".to_string());
+        }
+        Ok(self.history.list_versions(doc_id))
+    }
+
+    /// Get the full document as it was at a recorded version
+    pub fn get_version(&self, doc_id: &str, server_version: u64) -> Result<serde_json::Value, String> {
+        if self.get_metadata(doc_id).is_none() {
+            return Err("Document not found".to_string());
+        }
+        let mut doc = self.history.get_version(doc_id, server_version)?;
+        schema::migrate(&mut doc)?;
+        Ok(doc)
+    }
+
+    /// Restore a recorded version as a new revision.
+    ///
+    /// Content comes from the snapshot; ownership, shares and lock state are
+    /// kept from the current document so a restore never re-grants access
+    /// that has since been revoked.
+    pub fn restore_version(
+        &self,
+        doc_id: &str,
+        server_version: u64,
+        user_id: Option<&str>,
+        user_name: Option<&str>,
+    ) -> Result<DocumentMetadata, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+
+        let current = self.load_migrated(doc_id)?;
+        let mut restored = self.history.get_version(doc_id, server_version)?;
+        schema::migrate(&mut restored)?;
+
+        for field in [
+            "ownerId",
+            "ownerName",
+            "sharedWith",
+            "isTeamDocument",
+            "lockedBy",
+            "lockedByName",
+            "lockedAt",
+        ] {
+            match current.get(field) {
+                Some(value) => restored[field] = value.clone(),
+                None => {
+                    if let Some(obj) = restored.as_object_mut() {
+                        obj.remove(field);
+                    }
+                }
+            }
+        }
+
+        restored["modifiedAt"] = serde_json::json!(now_ms());
+        if let Some(uid) = user_id {
+            restored["lastModifiedBy"] = serde_json::json!(uid);
+            restored["lastModifiedByName"] = serde_json::json!(user_name.unwrap_or("Unknown"));
+        }
+
+        let metadata = self.write_document(restored)?;
+        log::info!(
+            "Restored document {} from version {} as version {}",
+            doc_id,
+            server_version,
+            metadata.server_version
+        );
+        Ok(metadata)
+    }
+
+    /// Apply history retention as of `now` to every document, live or
+    /// trashed, and delete the revisions of versions it drops (unless they
+    /// are still merge bases). Returns the number of versions dropped.
+    pub fn expire_history(&self, now: u64) -> usize {
+        let Ok(_guard) = self.write_lock.lock() else {
+            return 0;
+        };
+        let mut dropped = 0;
+        for doc_id in self.history.documents() {
+            let newest = match self.get_metadata(&doc_id) {
+                Some(metadata) => metadata.server_version,
+                None => match self.get_trash_entry(&doc_id) {
+                    Some(entry) => entry.metadata.server_version,
+                    None => continue,
+                },
+            };
+            let before = self.history.recorded(&doc_id).len();
+            let Some(versions) = self.history.expire(&doc_id, now) else {
+                continue;
+            };
+            let recorded: Vec<u64> = versions.iter().map(|v| v.server_version).collect();
+            let written = HistoryStore::index_write(&doc_id, &versions).and_then(|index| {
+                let mut ops = self.expired_revisions(&doc_id, newest, &recorded);
+                ops.push(index);
+                self.storage.write_batch(ops)
+            });
+            match written {
+                Ok(()) => dropped += before - versions.len(),
+                Err(e) => log::warn!("Failed to expire history of document {}: {}", doc_id, e),
+            }
+        }
+        if dropped > 0 {
+            log::info!("Expired {} document version(s)", dropped);
+        }
+        dropped
+    }
+
+    /// Current version history policy
+    pub fn history_config(&self) -> HistoryConfig {
+        self.history.config()
+    }
+
+    /// Update and persist the version history policy
+    pub fn set_history_config(&self, config: HistoryConfig) -> Result<(), String> {
+        self.history.set_config(config)
+    }
+
+    /// Get document metadata by ID
+    pub fn get_metadata(&self, doc_id: &str) -> Option<DocumentMetadata> {
+        self.index.read().ok()?.get(doc_id).cloned()
+    }
+
+    /// Update document lock status
+    pub fn set_lock(
+        &self,
+        doc_id: &str,
+        user_id: Option<&str>,
+        user_name: Option<&str>,
+    ) -> Result<(), String> {
+        self.update_document(doc_id, |doc| {
+            if let Some(uid) = user_id {
+                doc["lockedBy"] = serde_json::json!(uid);
+                doc["lockedByName"] = serde_json::json!(user_name.unwrap_or("Unknown"));
+                doc["lockedAt"] = serde_json::json!(now_ms());
+            } else {
+                doc["lockedBy"] = serde_json::Value::Null;
+                doc["lockedByName"] = serde_json::Value::Null;
+                doc["lockedAt"] = serde_json::Value::Null;
+            }
+            Ok(())
+        })
+    }
+
+    /// Check if a document is locked by another user
+    pub fn is_locked_by_other(&self, doc_id: &str, user_id: &str) -> bool {
+        if let Some(metadata) = self.get_metadata(doc_id) {
+            if let Some(locked_by) = &metadata.locked_by {
+                return locked_by != user_id;
+            }
+        }
+        false
+    }
+
+    /// Get document metadata (alias for get_metadata)
+    pub fn get_document_metadata(&self, doc_id: &str) -> Option<DocumentMetadata> {
+        self.get_metadata(doc_id)
+    }
+
+    /// Load, change and save a document in one `write_lock` section, so a
+    /// concurrent save cannot land between the read and the write. Only for
+    /// metadata changes (locks, shares, owner): they skip the write check,
+    /// so a user over quota can still unlock or share their documents.
+    fn update_document(
+        &self,
+        doc_id: &str,
+        change: impl FnOnce(&mut serde_json::Value) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let mut doc = self.load_migrated(doc_id)?;
+        change(&mut doc)?;
+        self.write_document_checked(doc, false).map(|_| ())
+    }
+
+    /// Update document sharing permissions
+    pub fn update_document_shares(
+        &self,
+        doc_id: &str,
+        shares: &[super::protocol::ShareEntry],
+    ) -> Result<(), String> {
+        let new_shares = shares_from_entries(shares, now_ms());
+        let shared_with = serde_json::to_value(&new_shares)
+            .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+        self.update_document(doc_id, |doc| {
+            doc["sharedWith"] = shared_with;
+            Ok(())
+        })?;
+
+        log::info!(
+            "Updated shares for document {}: {} users",
+            doc_id,
+            new_shares.len()
+        );
+        Ok(())
+    }
+
+    /// Transfer document ownership to another user
+    pub fn transfer_ownership(
+        &self,
+        doc_id: &str,
+        new_owner_id: &str,
+        new_owner_name: &str,
+        previous_owner_id: &str,
+    ) -> Result<(), String> {
+        self.update_document(doc_id, |doc| {
+            let now = now_ms();
+
+            // Update owner fields
+            doc["ownerId"] = serde_json::json!(new_owner_id);
+            doc["ownerName"] = serde_json::json!(new_owner_name);
+
+            // Add previous owner as an editor in the shares
+            let mut shares: Vec<DocumentShare> = doc["sharedWith"]
+                .as_array()
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| serde_json::from_value(v.clone()).ok())
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            // Remove new owner from shares (they're owner now)
+            shares.retain(|s| s.user_id != new_owner_id);
+
+            // Add previous owner as editor if not already in shares
+            if !shares.iter().any(|s| s.user_id == previous_owner_id) {
+                shares.push(DocumentShare {
+                    user_id: previous_owner_id.to_string(),
+                    user_name: doc["lastModifiedByName"]
+                        .as_str()
+                        .unwrap_or("Previous Owner")
+                        .to_string(),
+                    permission: "edit".to_string(),
+                    shared_at: now,
+                });
+            }
+
+            doc["sharedWith"] = serde_json::to_value(&shares)
+                .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+            Ok(())
+        })?;
+
+        log::info!(
+            "Transferred ownership of document {} from {} to {}",
+            doc_id,
+            previous_owner_id,
+            new_owner_id
+        );
+        Ok(())
+    }
+
+    /// Duplicate a document with fresh IDs, owned by `owner`. The copy stays
+    /// in the source's folder and inherits that folder's default shares.
+    /// It is validated with `mode`; any lenient-mode repairs are returned.
+    pub fn duplicate_document(
+        &self,
+        doc_id: &str,
+        name: Option<&str>,
+        owner: (&str, &str),
+        mode: ValidationMode,
+    ) -> Result<(DocumentMetadata, Vec<ValidationIssue>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let source = self.load_migrated(doc_id)?;
+        let mut copy = copy::duplicate_document(&source, name, owner)?;
+        let repairs = validation::validate_document(&mut copy, mode)?;
+        let metadata = self.write_document(copy)?;
+        log::info!("Duplicated team document {} as {}", doc_id, metadata.id);
+        Ok((metadata, repairs))
+    }
+
+    /// Copy a page to the end of another (or the same) document with fresh
+    /// IDs and validate the target with `mode`. Returns the target's new
+    /// metadata, the new page ID and any lenient-mode repairs.
+    pub fn copy_page(
+        &self,
+        source_doc_id: &str,
+        page_id: &str,
+        target_doc_id: &str,
+        mode: ValidationMode,
+    ) -> Result<(DocumentMetadata, String, Vec<ValidationIssue>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let source = self.load_migrated(source_doc_id)?;
+        let mut target = if source_doc_id == target_doc_id {
+            source.clone()
+        } else {
+            self.load_migrated(target_doc_id)?
+        };
+        let new_page_id = copy::copy_page_into(&source, page_id, &mut target)?;
+        let repairs = validation::validate_document(&mut target, mode)?;
+        let metadata = self.write_document(target)?;
+        log::info!(
+            "Copied page {} of document {} to document {} as {}",
+            page_id,
+            source_doc_id,
+            target_doc_id,
+            new_page_id
+        );
+        Ok((metadata, new_page_id, repairs))
+    }
+
+    /// List all folders
+    pub fn list_folders(&self) -> Vec<Folder> {
+        self.folders.list()
+    }
+
+    /// Get a folder by ID
+    pub fn get_folder(&self, folder_id: &str) -> Option<Folder> {
+        self.folders.get(folder_id)
+    }
+
+    /// Create a folder, optionally owned by `(user_id, user_name)`
+    pub fn create_folder(
+        &self,
+        name: &str,
+        parent_id: Option<&str>,
+        owner: Option<(&str, &str)>,
+        default_shares: &[super::protocol::ShareEntry],
+    ) -> Result<Folder, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let shares = shares_from_entries(default_shares, now_ms());
+        let folder = self.folders.create(name, parent_id, owner, shares)?;
+        log::info!("Created folder '{}' ({})", folder.name, folder.id);
+        Ok(folder)
+    }
+
+    pub fn rename_folder(&self, folder_id: &str, name: &str) -> Result<Folder, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        self.folders.rename(folder_id, name)
+    }
+
+    /// Move a folder under another one (`None` = top level)
+    pub fn move_folder(&self, folder_id: &str, parent_id: Option<&str>) -> Result<Folder, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        self.folders.move_to(folder_id, parent_id)
+    }
+
+    /// Replace a folder's default shares. Existing documents keep theirs.
+    pub fn set_folder_default_shares(
+        &self,
+        folder_id: &str,
+        shares: &[super::protocol::ShareEntry],
+    ) -> Result<Folder, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        self.folders.set_default_shares(folder_id, shares_from_entries(shares, now_ms()))
+    }
+
+    /// Delete a folder. Its subfolders and documents move to its parent.
+    /// Returns the removed folder and the metadata of the moved documents.
+    pub fn delete_folder(&self, folder_id: &str) -> Result<(Folder, Vec<DocumentMetadata>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+
+        let removed = self.folders.remove(folder_id)?;
+        let mut moved = Vec::new();
+        for metadata in self.list_documents() {
+            if metadata.folder_id.as_deref() != Some(folder_id) {
+                continue;
+            }
+            let result = self.load_migrated(&metadata.id).and_then(|mut doc| {
+                set_folder_field(&mut doc, removed.parent_id.as_deref());
+                self.write_document(doc)
+            });
+            match result {
+                Ok(metadata) => moved.push(metadata),
+                // Still readable: a missing folder reads as top level
+                Err(e) => log::warn!("Failed to re-file document {}: {}", metadata.id, e),
+            }
+        }
+
+        log::info!("Deleted folder '{}' ({}), re-filed {} documents", removed.name, folder_id, moved.len());
+        Ok((removed, moved))
+    }
+
+    /// File a document in a folder (`None` = top level)
+    pub fn move_document(&self, doc_id: &str, folder_id: Option<&str>) -> Result<DocumentMetadata, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        if let Some(folder_id) = folder_id {
+            if !self.folders.contains(folder_id) {
+                return Err(format!("{}: Folder '{}' not found", error_codes::FOLDER_NOT_FOUND, folder_id));
+            }
+        }
+        let mut doc = self.load_migrated(doc_id)?;
+        set_folder_field(&mut doc, folder_id);
+        self.write_document(doc)
+    }
+
+    /// List templates, sorted by category and name
+    pub fn list_templates(&self) -> Vec<TemplateInfo> {
+        self.templates.list()
+    }
+
+    pub fn get_template(&self, template_id: &str) -> Option<TemplateInfo> {
+        self.templates.get(template_id)
+    }
+
+    /// Save a copy of a document as a template owned by `owner`
+    pub fn save_as_template(
+        &self,
+        doc_id: &str,
+        details: TemplateDetails,
+        owner: (&str, &str),
+    ) -> Result<TemplateInfo, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let doc = self.load_migrated(doc_id)?;
+        let info = self.templates.create(&doc, details, owner)?;
+        log::info!("Saved team document {} as template {}", doc_id, info.id);
+        Ok(info)
+    }
+
+    pub fn delete_template(&self, template_id: &str) -> Result<TemplateInfo, String> {
+        let info = self.templates.remove(template_id)?;
+        log::info!("Deleted template {}", template_id);
+        Ok(info)
+    }
+
+    /// Create a document from a template, owned by `owner` and optionally
+    /// filed in `folder_id` (whose default shares it inherits). It is
+    /// validated with `mode`; any lenient-mode repairs are returned.
+    pub fn create_from_template(
+        &self,
+        template_id: &str,
+        name: Option<&str>,
+        folder_id: Option<&str>,
+        owner: (&str, &str),
+        mode: ValidationMode,
+    ) -> Result<(DocumentMetadata, Vec<ValidationIssue>), String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        if let Some(folder_id) = folder_id {
+            if !self.folders.contains(folder_id) {
+                return Err(format!("{}: Folder '{}' not found", error_codes::FOLDER_NOT_FOUND, folder_id));
+            }
+        }
+        let mut doc = self.templates.instantiate(template_id, name, owner)?;
+        set_folder_field(&mut doc, folder_id);
+        let repairs = validation::validate_document(&mut doc, mode)?;
+        let metadata = self.write_document(doc)?;
+        log::info!("Created team document {} from template {}", metadata.id, template_id);
+        Ok((metadata, repairs))
+    }
+
+    /// Replace a document's tags
+    pub fn set_document_tags(&self, doc_id: &str, tags: &[String]) -> Result<DocumentMetadata, String> {
+        let _guard = self.write_lock.lock().map_err(|e| e.to_string())?;
+        let tags = folders::normalize_tags(tags)?;
+        let mut doc = self.load_migrated(doc_id)?;
+        if let Some(obj) = doc.as_object_mut() {
+            if tags.is_empty() {
+                obj.remove("tags");
+            } else {
+                obj.insert("tags".to_string(), serde_json::json!(tags));
+            }
+        }
+        self.write_document(doc)
+    }
+}
+
+
+/// Set or clear a document's `folderId`
+fn set_folder_field(doc: &mut serde_json::Value, folder_id: Option<&str>) {
+    if let Some(obj) = doc.as_object_mut() {
+        match folder_id {
+            Some(id) => obj.insert("folderId".to_string(), serde_json::json!(id)),
+            None => obj.remove("folderId"),
+        };
+    }
+}
+
+/// Blob hashes a document uses: its `blobReferences` plus the `blobRef` of
+/// every file shape, in case the list has fallen behind the shapes
+pub fn blob_references(doc: &serde_json::Value) -> Vec<String> {
+    let listed = doc
+        .get("blobReferences")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str());
+    let shapes = doc
+        .get("pages")
+        .and_then(|v| v.as_object())
+        .into_iter()
+        .flat_map(|pages| pages.values())
+        .filter_map(|page| page.get("shapes").and_then(|v| v.as_object()))
+        .flat_map(|shapes| shapes.values())
+        .filter_map(|shape| shape.get("blobRef").and_then(|v| v.as_str()));
+    let mut hashes: Vec<String> = listed.chain(shapes).map(String::from).collect();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+/// Convert requested share entries into stored shares ("none" entries,
+/// which revoke access, are dropped)
+pub fn shares_from_entries(entries: &[super::protocol::ShareEntry], shared_at: u64) -> Vec<DocumentShare> {
+    entries
+        .iter()
+        .filter(|s| s.permission != "none")
+        .map(|s| DocumentShare {
+            user_id: s.user_id.clone(),
+            user_name: s.user_name.clone(),
+            permission: s.permission.clone(),
+            shared_at,
+        })
+        .collect()
+}
+
+/// Derive index metadata from a full document JSON value
+fn build_metadata(doc: &serde_json::Value) -> Result<DocumentMetadata, String> {
+    let id = doc.get("id")
+        .and_then(|v| v.as_str())
+        .ok_or("Document missing 'id' field")?
+        .to_string();
+
+    let name = doc.get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Untitled")
+        .to_string();
+
+    let page_order = doc.get("pageOrder")
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.len())
+        .unwrap_or(1);
+
+    let modified_at = doc.get("modifiedAt")
+        .and_then(|v| v.as_u64())
+        .unwrap_or_else(|| {
+            now_ms()
+        });
+
+    let created_at = doc.get("createdAt")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(modified_at);
+
+    Ok(DocumentMetadata {
+        id,
+        name,
+        page_count: page_order,
+        modified_at,
+        created_at,
+        is_team_document: doc.get("isTeamDocument").and_then(|v| v.as_bool()),
+        locked_by: doc.get("lockedBy").and_then(|v| v.as_str()).map(String::from),
+        locked_by_name: doc.get("lockedByName").and_then(|v| v.as_str()).map(String::from),
+        locked_at: doc.get("lockedAt").and_then(|v| v.as_u64()),
+        owner_id: doc.get("ownerId").and_then(|v| v.as_str()).map(String::from),
+        owner_name: doc.get("ownerName").and_then(|v| v.as_str()).map(String::from),
+        shared_with: doc.get("sharedWith").and_then(|v| {
+            serde_json::from_value(v.clone()).ok()
+        }),
+        last_modified_by: doc.get("lastModifiedBy").and_then(|v| v.as_str()).map(String::from),
+        last_modified_by_name: doc.get("lastModifiedByName").and_then(|v| v.as_str()).map(String::from),
+        folder_id: doc.get("folderId").and_then(|v| v.as_str()).map(String::from),
+        tags: doc.get("tags").and_then(|v| serde_json::from_value(v.clone()).ok()),
+        server_version: doc.get("serverVersion").and_then(|v| v.as_u64()).unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_document_store_lifecycle() {
         let dir = tempdir().unwrap();
         let store = DocumentStore::new(dir.path().to_path_buf());
 
@@ -438,7 +1669,7 @@ mod tests {
         assert_eq!(retrieved["id"], "test-doc-1");
 
         // Delete document
-        let deleted = store.delete_document("test-doc-1").unwrap();
+        let deleted = store.delete_document("test-doc-1", None, None).unwrap();
         assert!(deleted);
 
         // List should be empty again
@@ -453,4 +1684,734 @@ mod tests {
         let result = store.get_document("nonexistent");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_save_assigns_server_version() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        let doc = serde_json::json!({"id": "doc-1", "name": "Doc", "serverVersion": 99});
+        store.save_document(doc.clone()).unwrap();
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 1);
+
+        store.save_document(doc).unwrap();
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 2);
+        assert_eq!(store.get_document("doc-1").unwrap()["serverVersion"], 2);
+    }
+
+    #[test]
+    fn test_metadata_updates_do_not_lose_concurrent_edits() {
+        use super::super::protocol::ShareEntry;
+
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store.save_document(serde_json::json!({"id": "doc-1", "name": "Doc"})).unwrap();
+
+        let shares = [ShareEntry {
+            user_id: "user-2".to_string(),
+            user_name: "Bob".to_string(),
+            permission: "editor".to_string(),
+        }];
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    store.update_document_shares("doc-1", &shares).unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    store.set_lock("doc-1", Some("user-1"), Some("Alice")).unwrap();
+                }
+            });
+        });
+
+        let doc = store.get_document("doc-1").unwrap();
+        assert_eq!(doc["lockedBy"], "user-1");
+        assert_eq!(doc["sharedWith"][0]["userId"], "user-2");
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 41);
+    }
+
+    #[test]
+    fn test_metadata_updates_skip_write_check() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store.save_document(serde_json::json!({"id": "doc-1", "name": "Doc"})).unwrap();
+        store.on_write_check(|_| Err("over quota".to_string()));
+
+        store.set_lock("doc-1", Some("user-1"), Some("Alice")).unwrap();
+        store.update_document_shares("doc-1", &[]).unwrap();
+        store.transfer_ownership("doc-1", "user-2", "Bob", "user-1").unwrap();
+        assert_eq!(store.get_metadata("doc-1").unwrap().owner_id.as_deref(), Some("user-2"));
+
+        let doc = store.get_document("doc-1").unwrap();
+        assert_eq!(store.save_document(doc).unwrap_err(), "over quota");
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Before",
+                "pageOrder": ["p1"],
+                "pages": {"p1": {"id": "p1", "shapes": {}, "shapeOrder": []}}
+            }))
+            .unwrap();
+
+        let ops: Vec<PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "replace", "path": "/name", "value": "After"},
+            {"op": "add", "path": "/pages/p2", "value": {"id": "p2", "shapes": {}, "shapeOrder": []}},
+            {"op": "add", "path": "/pageOrder/-", "value": "p2"}
+        ]))
+        .unwrap();
+
+        let (metadata, repairs) = store.apply_patch("doc-1", 1, &ops, ValidationMode::Lenient, |_| Ok(())).unwrap();
+        assert!(repairs.is_empty());
+        assert_eq!(metadata.name, "After");
+        assert_eq!(metadata.page_count, 2);
+        assert_eq!(metadata.server_version, 2);
+        assert_eq!(store.get_document("doc-1").unwrap()["pages"]["p2"]["id"], "p2");
+
+        // Stale base version is rejected without writing
+        let err = store.apply_patch("doc-1", 1, &ops, ValidationMode::Lenient, |_| Ok(())).unwrap_err();
+        assert!(err.starts_with(error_codes::VERSION_CONFLICT));
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 2);
+    }
+
+    #[test]
+    fn test_apply_patch_is_atomic() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "Doc"}))
+            .unwrap();
+
+        let ops: Vec<PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "replace", "path": "/name", "value": "Changed"},
+            {"op": "remove", "path": "/missing"}
+        ]))
+        .unwrap();
+        assert!(store.apply_patch("doc-1", 1, &ops, ValidationMode::Lenient, |_| Ok(())).is_err());
+
+        let id_change: Vec<PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "replace", "path": "/id", "value": "doc-2"}
+        ]))
+        .unwrap();
+        assert!(store.apply_patch("doc-1", 1, &id_change, ValidationMode::Lenient, |_| Ok(())).is_err());
+
+        assert_eq!(store.get_document("doc-1").unwrap()["name"], "Doc");
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 1);
+    }
+
+    #[test]
+    fn test_patched_document_is_validated() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "pageOrder": ["p1", "p2"],
+                "pages": {
+                    "p1": {"id": "p1", "shapes": {}, "shapeOrder": []},
+                    "p2": {"id": "p2", "shapes": {}, "shapeOrder": []}
+                }
+            }))
+            .unwrap();
+
+        // Removing a page without its `pageOrder` entry leaves it dangling
+        let ops: Vec<PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "remove", "path": "/pages/p2"}
+        ]))
+        .unwrap();
+        let err = store
+            .apply_patch("doc-1", 1, &ops, ValidationMode::Strict, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::INVALID_DOCUMENT));
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 1);
+
+        let (metadata, repairs) = store
+            .apply_patch("doc-1", 1, &ops, ValidationMode::Lenient, |_| Ok(()))
+            .unwrap();
+        assert_eq!(metadata.server_version, 2);
+        assert_eq!(repairs.len(), 1);
+        assert_eq!(store.get_document("doc-1").unwrap()["pageOrder"], serde_json::json!(["p1"]));
+    }
+
+    #[test]
+    fn test_stale_save_is_merged() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        let base = serde_json::json!({
+            "id": "doc-1",
+            "name": "Doc",
+            "pageOrder": ["p1"],
+            "pages": {"p1": {
+                "id": "p1",
+                "shapes": {"a": {"id": "a", "x": 0}, "b": {"id": "b", "x": 0}},
+                "shapeOrder": ["a", "b"]
+            }}
+        });
+        store.save_document(base.clone()).unwrap();
+
+        // Another client moves shape `a` (version 2)
+        let mut current = base.clone();
+        current["pages"]["p1"]["shapes"]["a"]["x"] = serde_json::json!(10);
+        store.save_document_with_base(current, Some(1), ValidationMode::Lenient, |_| Ok(())).unwrap();
+
+        // A stale client, still on version 1, moves shape `b`
+        let mut incoming = base.clone();
+        incoming["pages"]["p1"]["shapes"]["b"]["x"] = serde_json::json!(20);
+        match store.save_document_with_base(incoming, Some(1), ValidationMode::Lenient, |_| Ok(())).unwrap() {
+            SaveOutcome::Merged { metadata, document, repairs } => {
+                assert!(repairs.is_empty());
+                assert_eq!(metadata.server_version, 3);
+                assert_eq!(document["pages"]["p1"]["shapes"]["a"]["x"], 10);
+                assert_eq!(document["pages"]["p1"]["shapes"]["b"]["x"], 20);
+            }
+            other => panic!("Expected merge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stale_save_with_conflict_is_not_written() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        let base = serde_json::json!({"id": "doc-1", "name": "Doc"});
+        store.save_document(base.clone()).unwrap();
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "Theirs"}))
+            .unwrap();
+
+        let outcome = store
+            .save_document_with_base(
+                serde_json::json!({"id": "doc-1", "name": "Mine"}),
+                Some(1),
+                ValidationMode::Lenient,
+                |_| Ok(()),
+            )
+            .unwrap();
+        match outcome {
+            SaveOutcome::Conflict { server_version, conflicts } => {
+                assert_eq!(server_version, 2);
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].path, "/name");
+            }
+            other => panic!("Expected conflict, got {:?}", other),
+        }
+        assert_eq!(store.get_document("doc-1").unwrap()["name"], "Theirs");
+
+        // Bases older than the retention window cannot be merged
+        let err = store
+            .save_document_with_base(serde_json::json!({"id": "doc-1"}), Some(99), ValidationMode::Lenient, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::VERSION_CONFLICT));
+    }
+
+    #[test]
+    fn test_merged_save_is_validated() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        let base = serde_json::json!({
+            "id": "doc-1",
+            "pageOrder": ["p1"],
+            "activePageId": "p1",
+            "pages": {"p1": {
+                "id": "p1",
+                "shapes": {
+                    "a": {"id": "a", "type": "rectangle"},
+                    "b": {"id": "b", "type": "rectangle"},
+                    "c": {"id": "c", "type": "connector", "startShapeId": "a", "endShapeId": null}
+                },
+                "shapeOrder": ["a", "b", "c"]
+            }}
+        });
+        store.save_document(base.clone()).unwrap();
+
+        // Another client deletes shape `b` (version 2)
+        let mut current = base.clone();
+        current["pages"]["p1"]["shapes"].as_object_mut().unwrap().remove("b");
+        current["pages"]["p1"]["shapeOrder"] = serde_json::json!(["a", "c"]);
+        store.save_document(current).unwrap();
+
+        // A stale client attaches the connector to `b`: the merge is clean
+        // but leaves a dangling endpoint
+        let mut incoming = base.clone();
+        incoming["pages"]["p1"]["shapes"]["c"]["endShapeId"] = serde_json::json!("b");
+
+        let err = store
+            .save_document_with_base(incoming.clone(), Some(1), ValidationMode::Strict, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::INVALID_DOCUMENT));
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 2);
+
+        match store.save_document_with_base(incoming, Some(1), ValidationMode::Lenient, |_| Ok(())).unwrap() {
+            SaveOutcome::Merged { document, repairs, .. } => {
+                assert_eq!(repairs.len(), 1);
+                assert_eq!(repairs[0].path, "/pages/p1/shapes/c/endShapeId");
+                assert_eq!(document["pages"]["p1"]["shapes"]["c"]["endShapeId"], serde_json::Value::Null);
+            }
+            other => panic!("Expected merge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_search_index_follows_saves_and_trash() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        let doc = |label: &str| serde_json::json!({
+            "id": "doc-1",
+            "name": "Doc",
+            "pages": {"p1": {"id": "p1", "shapes": {"s1": {"id": "s1", "label": label}}}}
+        });
+
+        store.save_document(doc("Load balancer")).unwrap();
+        store.save_document(serde_json::json!({"id": "doc-2", "name": "Balance sheet"})).unwrap();
+        assert_eq!(store.search("balance", 10, |_| true).len(), 2);
+        assert_eq!(store.search("balance", 10, |id| id == "doc-2").len(), 1);
+
+        store.save_document(doc("Gateway")).unwrap();
+        let hits = store.search("gateway", 10, |_| true);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].shape_id.as_deref(), Some("s1"));
+
+        store.delete_document("doc-1", None, None).unwrap();
+        assert!(store.search("gateway", 10, |_| true).is_empty());
+        store.restore_from_trash("doc-1").unwrap();
+        assert_eq!(store.search("gateway", 10, |_| true).len(), 1);
+
+        // Built from storage on open
+        drop(store);
+        let reopened = DocumentStore::new(dir.path().to_path_buf());
+        assert_eq!(reopened.search("gateway", 10, |_| true).len(), 1);
+    }
+
+    #[test]
+    fn test_reload_refreshes_search() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        let other = DocumentStore::new(dir.path().to_path_buf());
+
+        other.save_document(serde_json::json!({"id": "doc-1", "name": "Gateway"})).unwrap();
+        assert!(store.search("gateway", 10, |_| true).is_empty());
+        store.reload_index();
+        assert_eq!(store.search("gateway", 10, |_| true).len(), 1);
+
+        other.save_document(serde_json::json!({"id": "doc-1", "name": "Router"})).unwrap();
+        store.reload_index();
+        assert!(store.search("gateway", 10, |_| true).is_empty());
+        assert_eq!(store.search("router", 10, |_| true).len(), 1);
+
+        other.delete_document("doc-1", None, None).unwrap();
+        store.reload_index();
+        assert!(store.search("router", 10, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_folders_tags_and_inherited_shares() {
+        use crate::server::protocol::ShareEntry;
+
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        let entry = |user_id: &str, permission: &str| ShareEntry {
+            user_id: user_id.to_string(),
+            user_name: user_id.to_string(),
+            permission: permission.to_string(),
+        };
+
+        let team = store.create_folder("Team", None, Some(("u1", "Alice")), &[entry("u2", "view")]).unwrap();
+        let sub = store.create_folder("Specs", Some(&team.id), None, &[entry("u3", "edit")]).unwrap();
+
+        // New documents inherit the folder chain's defaults; explicit shares win
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "ownerId": "u1",
+                "folderId": sub.id,
+                "sharedWith": [{"userId": "u3", "userName": "u3", "permission": "view", "sharedAt": 1}],
+                "tags": ["Infra", " infra ", "q3"]
+            }))
+            .unwrap();
+        let metadata = store.get_metadata("doc-1").unwrap();
+        let shares = metadata.shared_with.unwrap();
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares.iter().find(|s| s.user_id == "u3").unwrap().permission, "view");
+        assert_eq!(metadata.tags.unwrap(), vec!["Infra", "q3"]);
+
+        // Existing documents are not re-shared when saved again
+        store.set_folder_default_shares(&sub.id, &[entry("u4", "edit")]).unwrap();
+        store.set_document_tags("doc-1", &[]).unwrap();
+        let metadata = store.get_metadata("doc-1").unwrap();
+        assert_eq!(metadata.shared_with.unwrap().len(), 2);
+        assert!(metadata.tags.is_none());
+
+        assert!(store.move_document("doc-1", Some("missing")).is_err());
+
+        // Deleting a folder re-files its documents under the parent
+        let (_, moved) = store.delete_folder(&sub.id).unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(store.get_metadata("doc-1").unwrap().folder_id, Some(team.id.clone()));
+
+        store.move_document("doc-1", None).unwrap();
+        assert!(store.get_metadata("doc-1").unwrap().folder_id.is_none());
+
+        // Unknown folders are dropped on save
+        store
+            .save_document(serde_json::json!({"id": "doc-2", "folderId": sub.id}))
+            .unwrap();
+        assert!(store.get_metadata("doc-2").unwrap().folder_id.is_none());
+    }
+
+    #[test]
+    fn test_duplicate_and_copy_page() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Source",
+                "ownerId": "u1",
+                "sharedWith": [{"userId": "u2", "userName": "Bob", "permission": "edit", "sharedAt": 1}],
+                "pageOrder": ["p1"],
+                "activePageId": "p1",
+                "pages": {"p1": {
+                    "id": "p1",
+                    "name": "Main",
+                    "shapes": {"s1": {"id": "s1", "type": "rectangle"}},
+                    "shapeOrder": ["s1"]
+                }}
+            }))
+            .unwrap();
+        store
+            .save_document(serde_json::json!({"id": "doc-2", "name": "Target", "ownerId": "u2", "pageOrder": [], "pages": {}}))
+            .unwrap();
+
+        let (copy, _) = store.duplicate_document("doc-1", None, ("u2", "Bob"), ValidationMode::Lenient).unwrap();
+        assert_ne!(copy.id, "doc-1");
+        assert_eq!(copy.name, "Source (copy)");
+        assert_eq!(copy.owner_id.as_deref(), Some("u2"));
+        assert!(copy.shared_with.is_none());
+        let doc = store.get_document(&copy.id).unwrap();
+        assert_ne!(doc["activePageId"], "p1");
+        assert!(store.search("source", 10, |_| true).iter().any(|h| h.doc_id == copy.id));
+
+        let (metadata, page_id, _) = store.copy_page("doc-1", "p1", "doc-2", ValidationMode::Lenient).unwrap();
+        assert_eq!(metadata.server_version, 2);
+        let target = store.get_document("doc-2").unwrap();
+        assert_eq!(target["pageOrder"], serde_json::json!([page_id]));
+        assert_eq!(target["pages"][&page_id]["name"], "Main");
+
+        // The source is untouched and missing pages are reported
+        assert_eq!(store.get_document("doc-1").unwrap()["pageOrder"], serde_json::json!(["p1"]));
+        assert!(store.copy_page("doc-1", "missing", "doc-2", ValidationMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn test_edit_pages() {
+        use crate::server::pages;
+
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Doc",
+                "pageOrder": ["p1"],
+                "activePageId": "p1",
+                "pages": {"p1": {"id": "p1", "name": "Main", "shapes": {}, "shapeOrder": []}}
+            }))
+            .unwrap();
+
+        let (metadata, page, _) = store.edit_pages("doc-1", ValidationMode::Lenient, |doc| pages::create_page(doc, "Second", None)).unwrap();
+        assert_eq!(metadata.server_version, 2);
+        assert_eq!(metadata.page_count, 2);
+        let doc = store.get_document("doc-1").unwrap();
+        assert_eq!(doc["pageOrder"][1], page["id"]);
+
+        // A failed edit leaves the document untouched
+        assert!(store.edit_pages("doc-1", ValidationMode::Lenient, |doc| pages::delete_page(doc, "missing")).is_err());
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 2);
+    }
+
+    #[test]
+    fn test_documents_from_templates() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "ERD",
+                "ownerId": "u1",
+                "pageOrder": [],
+                "pages": {},
+                "blobReferences": ["hash-1"]
+            }))
+            .unwrap();
+        let details = TemplateDetails {
+            name: "ERD scaffold",
+            description: "Entities and relations",
+            category: "Data",
+        };
+        let template = store.save_as_template("doc-1", details, ("u1", "Alice")).unwrap();
+        let folder = store.create_folder("Models", None, None, &[]).unwrap();
+
+        let (metadata, _) = store
+            .create_from_template(&template.id, Some("Billing ERD"), Some(&folder.id), ("u2", "Bob"), ValidationMode::Lenient)
+            .unwrap();
+        assert_eq!(metadata.name, "Billing ERD");
+        assert_eq!(metadata.owner_id.as_deref(), Some("u2"));
+        assert_eq!(metadata.folder_id.as_deref(), Some(folder.id.as_str()));
+        assert!(store
+            .create_from_template(&template.id, None, Some("missing"), ("u2", "Bob"), ValidationMode::Lenient)
+            .is_err());
+
+        // Blobs stay referenced through the template alone
+        store.delete_document("doc-1", None, None).unwrap();
+        store.purge_from_trash("doc-1").unwrap();
+        store.delete_document(&metadata.id, None, None).unwrap();
+        store.purge_from_trash(&metadata.id).unwrap();
+        assert!(store.referenced_blobs().unwrap().contains("hash-1"));
+        store.delete_template(&template.id).unwrap();
+        assert!(store.referenced_blobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_document_sizes_follow_saves() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "One", "ownerId": "u1"}))
+            .unwrap();
+        let small = store.document_size("doc-1").unwrap();
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "One".repeat(100), "ownerId": "u1"}))
+            .unwrap();
+        assert!(store.document_size("doc-1").unwrap() > small);
+
+        // Sizes are re-read from storage after a reload
+        let size = store.document_size("doc-1");
+        store.reload_index();
+        assert_eq!(store.document_size("doc-1"), size);
+        assert_eq!(store.document_sizes().len(), 1);
+
+        store.delete_document("doc-1", None, None).unwrap();
+        assert!(store.document_size("doc-1").is_none());
+        assert!(store.document_sizes().is_empty());
+    }
+
+    #[test]
+    fn test_index_rebuilt_when_missing_or_corrupt() {
+        let dir = tempdir().unwrap();
+        {
+            let store = DocumentStore::new(dir.path().to_path_buf());
+            store
+                .save_document(serde_json::json!({"id": "doc-1", "name": "One", "ownerId": "u1"}))
+                .unwrap();
+            store
+                .save_document(serde_json::json!({"id": "doc-2", "name": "Two"}))
+                .unwrap();
+        }
+        let index_path = dir.path().join("team_documents").join("index.json");
+
+        // Truncated index (e.g. power loss mid-write)
+        std::fs::write(&index_path, "{\"doc-1\": {").unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        assert_eq!(store.list_documents().len(), 2);
+        assert_eq!(store.get_metadata("doc-1").unwrap().owner_id.as_deref(), Some("u1"));
+        assert_eq!(store.get_metadata("doc-1").unwrap().server_version, 1);
+
+        // Missing index
+        std::fs::remove_file(&index_path).unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        assert_eq!(store.list_documents().len(), 2);
+        assert!(index_path.exists());
+    }
+
+    #[test]
+    fn test_version_history_restore() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Full",
+                "pageOrder": ["p1", "p2"],
+                "ownerId": "owner",
+                "lastModifiedBy": "owner"
+            }))
+            .unwrap();
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Emptied",
+                "pageOrder": [],
+                "ownerId": "owner",
+                "sharedWith": [{"userId": "u2", "userName": "U2", "permission": "edit", "sharedAt": 0}],
+                "lastModifiedBy": "editor"
+            }))
+            .unwrap();
+
+        let versions = store.list_versions("doc-1").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].author_id.as_deref(), Some("editor"));
+        assert!(versions[1].size > 0);
+        assert_eq!(store.get_version("doc-1", 1).unwrap()["name"], "Full");
+
+        let metadata = store.restore_version("doc-1", 1, Some("owner"), Some("Owner")).unwrap();
+        assert_eq!(metadata.server_version, 3);
+        assert_eq!(metadata.name, "Full");
+        assert_eq!(metadata.page_count, 2);
+        // Shares granted after the snapshot survive the restore
+        assert_eq!(metadata.shared_with.map(|s| s.len()), Some(1));
+        assert_eq!(store.list_versions("doc-1").unwrap().len(), 3);
+
+        assert!(store.restore_version("doc-1", 42, None, None).is_err());
+        assert!(store.list_versions("missing").is_err());
+    }
+
+    #[test]
+    fn test_history_reuses_revisions_and_expires_from_purge() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .set_history_config(HistoryConfig {
+                max_age_days: 1,
+                ..HistoryConfig::default()
+            })
+            .unwrap();
+        for name in ["One", "Two"] {
+            store.save_document(serde_json::json!({"id": "doc-1", "name": name})).unwrap();
+        }
+
+        // Snapshots are the revisions; history only adds its index
+        assert_eq!(store.storage.list(Space::History, "").unwrap(), vec!["doc-1/versions"]);
+        assert_eq!(store.get_version("doc-1", 1).unwrap()["name"], "One");
+
+        // Nothing expires yet, and within the merge-base window revisions stay
+        assert_eq!(store.expire_history(now_ms()), 0);
+        assert_eq!(store.expire_history(now_ms() + 2 * 24 * 60 * 60 * 1000), 1);
+        assert_eq!(store.list_versions("doc-1").unwrap().len(), 1);
+        assert!(store.get_version("doc-1", 1).is_err());
+        assert!(store.storage.exists(Space::Revisions, "doc-1/1").unwrap());
+
+        // Once rotated out of the merge-base window, unrecorded revisions go
+        store.set_history_config(HistoryConfig { enabled: false, ..HistoryConfig::default() }).unwrap();
+        for i in 0..MERGE_BASE_RETENTION {
+            store.save_document(serde_json::json!({"id": "doc-1", "name": format!("v{}", i)})).unwrap();
+        }
+        assert!(!store.storage.exists(Space::Revisions, "doc-1/1").unwrap());
+        assert!(store.storage.exists(Space::Revisions, "doc-1/2").unwrap());
+        assert_eq!(store.get_version("doc-1", 2).unwrap()["name"], "Two");
+    }
+
+    #[test]
+    fn test_trash_and_restore() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        store
+            .save_document(serde_json::json!({
+                "id": "doc-1",
+                "name": "Shared",
+                "ownerId": "owner",
+                "sharedWith": [{"userId": "u2", "userName": "U2", "permission": "edit", "sharedAt": 0}]
+            }))
+            .unwrap();
+
+        assert!(store.delete_document("doc-1", Some("owner"), Some("Owner")).unwrap());
+        assert!(store.get_document("doc-1").is_err());
+        assert!(store.list_documents().is_empty());
+
+        let trash = store.list_trash();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].deleted_by.as_deref(), Some("owner"));
+
+        // Trash survives a restart
+        let store = DocumentStore::new(dir.path().to_path_buf());
+        assert!(store.get_trash_entry("doc-1").is_some());
+
+        let metadata = store.restore_from_trash("doc-1").unwrap();
+        assert_eq!(metadata.shared_with.map(|s| s.len()), Some(1));
+        assert_eq!(store.get_document("doc-1").unwrap()["name"], "Shared");
+        assert!(store.list_trash().is_empty());
+        assert_eq!(store.list_versions("doc-1").unwrap().len(), 1);
+
+        assert!(store.restore_from_trash("doc-1").is_err());
+    }
+
+    #[test]
+    fn test_sqlite_backend() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join(crate::storage::sqlite::DATABASE_FILENAME);
+        let open = || {
+            let storage = crate::storage::sqlite::SqliteStorage::open(&db_path).unwrap();
+            DocumentStore::with_storage(Arc::new(storage))
+        };
+
+        let store = open();
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "One"}))
+            .unwrap();
+        store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "Renamed"}))
+            .unwrap();
+        store.delete_document("doc-1", None, None).unwrap();
+
+        // Nothing touches the filesystem layout
+        assert!(!dir.path().join("team_documents").exists());
+
+        let store = open();
+        assert!(store.list_documents().is_empty());
+        store.restore_from_trash("doc-1").unwrap();
+        assert_eq!(store.get_document("doc-1").unwrap()["name"], "Renamed");
+        assert_eq!(store.list_versions("doc-1").unwrap().len(), 2);
+        assert_eq!(store.rebuild_index().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_legacy_document_is_upgraded_on_load() {
+        let dir = tempdir().unwrap();
+        let store = DocumentStore::new(dir.path().to_path_buf());
+
+        // Written by a build that predates schema versioning
+        let legacy = serde_json::json!({
+            "id": "legacy",
+            "name": "Old",
+            "pages": {"p1": {"id": "p1", "shapes": {"s1": {"id": "s1"}}}}
+        });
+        std::fs::write(
+            dir.path().join("team_documents").join("docs").join("legacy.json"),
+            legacy.to_string(),
+        )
+        .unwrap();
+        store.rebuild_index().unwrap();
+
+        let doc = store.get_document("legacy").unwrap();
+        assert_eq!(doc["version"], schema::CURRENT_SCHEMA_VERSION);
+        assert_eq!(doc["pages"]["p1"]["shapeOrder"], serde_json::json!(["s1"]));
+
+        // Reading wrote nothing: no new revision, no history entry
+        assert_eq!(store.get_metadata("legacy").unwrap().server_version, 0);
+        assert!(store.list_versions("legacy").unwrap().is_empty());
+        let stored = std::fs::read(dir.path().join("team_documents").join("docs").join("legacy.json")).unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&stored).unwrap(), legacy);
+
+        // Newer schemas are refused on save
+        let err = store
+            .save_document(serde_json::json!({"id": "legacy", "version": schema::CURRENT_SCHEMA_VERSION + 1}))
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::UNSUPPORTED_VERSION));
+        assert_eq!(store.get_metadata("legacy").unwrap().server_version, 0);
+
+        // The next real save writes the upgrade
+        store.save_document(doc).unwrap();
+        let stored = std::fs::read(dir.path().join("team_documents").join("docs").join("legacy.json")).unwrap();
+        let stored: serde_json::Value = serde_json::from_slice(&stored).unwrap();
+        assert_eq!(stored["version"], schema::CURRENT_SCHEMA_VERSION);
+        assert_eq!(stored["serverVersion"], 1);
+    }
 }
@@ -0,0 +1,345 @@
+//! Server-side folders and tags for team documents
+//!
+//! Folders form a tree shared by every user of the team store. A document
+//! belongs to at most one folder (`folderId` in the document JSON) and has a
+//! free-form list of `tags`; both are mirrored into `DocumentMetadata`.
+//!
+//! A folder can carry default shares. New documents created inside a folder
+//! inherit the default shares of that folder and its ancestors, the nearest
+//! folder winning when several name the same user. Shares set explicitly on
+//! the document take precedence, and existing documents are never changed
+//! when a folder's defaults are edited.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use super::documents::DocumentShare;
+use super::permissions::error_codes;
+use crate::clock::now_ms;
+use crate::storage::{Space, Storage};
+
+/// Meta-space key of the folder tree
+const FOLDERS_KEY: &str = "folders";
+
+/// Maximum length of a folder name or tag, in characters
+const MAX_NAME_LEN: usize = 128;
+
+/// Maximum number of tags per document
+const MAX_TAGS: usize = 32;
+
+/// A folder in the team document tree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+    /// Parent folder, `None` for top-level folders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_name: Option<String>,
+    /// Shares applied to documents created inside this folder
+    #[serde(default)]
+    pub default_shares: Vec<DocumentShare>,
+    pub created_at: u64,
+    pub modified_at: u64,
+}
+
+
+fn not_found(folder_id: &str) -> String {
+    format!("{}: Folder '{}' not found", error_codes::FOLDER_NOT_FOUND, folder_id)
+}
+
+/// Trim and check a folder name
+fn clean_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("Folder name is longer than {} characters", MAX_NAME_LEN));
+    }
+    Ok(name.to_string())
+}
+
+/// Trim tags and drop empty and duplicate (case-insensitive) ones,
+/// keeping the first spelling of each.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut result: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() || result.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            continue;
+        }
+        if tag.chars().count() > MAX_NAME_LEN {
+            return Err(format!("Tag is longer than {} characters", MAX_NAME_LEN));
+        }
+        result.push(tag.to_string());
+    }
+    if result.len() > MAX_TAGS {
+        return Err(format!("A document can have at most {} tags", MAX_TAGS));
+    }
+    Ok(result)
+}
+
+/// Folder tree persisted as a single entry in the storage backend
+pub struct FolderStore {
+    storage: Arc<dyn Storage>,
+    folders: RwLock<HashMap<String, Folder>>,
+}
+
+impl FolderStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let store = Self {
+            storage,
+            folders: RwLock::new(HashMap::new()),
+        };
+        store.reload();
+        store
+    }
+
+    /// Re-read the folder tree from storage. A corrupt entry is moved aside
+    /// and the tree starts empty; documents in lost folders show at the top
+    /// level.
+    pub fn reload(&self) {
+        let folders = match self.storage.get(Space::Meta, FOLDERS_KEY) {
+            Ok(Some(data)) => match serde_json::from_slice(&data) {
+                Ok(folders) => folders,
+                Err(e) => {
+                    log::error!("Folder tree is corrupt ({}), starting empty", e);
+                    if let Err(e) = self.storage.quarantine(Space::Meta, FOLDERS_KEY) {
+                        log::warn!("Failed to move corrupt folder tree aside: {}", e);
+                    }
+                    HashMap::new()
+                }
+            },
+            Ok(None) => HashMap::new(),
+            Err(e) => {
+                log::error!("Failed to read folder tree: {}", e);
+                return;
+            }
+        };
+        if let Ok(mut current) = self.folders.write() {
+            *current = folders;
+        }
+    }
+
+    pub fn list(&self) -> Vec<Folder> {
+        self.folders
+            .read()
+            .map(|f| f.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, folder_id: &str) -> Option<Folder> {
+        self.folders.read().ok()?.get(folder_id).cloned()
+    }
+
+    pub fn contains(&self, folder_id: &str) -> bool {
+        self.get(folder_id).is_some()
+    }
+
+    /// Default shares inherited by a new document in `folder_id`
+    pub fn inherited_shares(&self, folder_id: &str) -> Vec<DocumentShare> {
+        let Ok(folders) = self.folders.read() else {
+            return Vec::new();
+        };
+        let mut shares: Vec<DocumentShare> = Vec::new();
+        for folder in ancestry(&folders, folder_id) {
+            for share in &folder.default_shares {
+                if !shares.iter().any(|s| s.user_id == share.user_id) {
+                    shares.push(share.clone());
+                }
+            }
+        }
+        shares
+    }
+
+    /// Apply `change` to a copy of the tree and persist it; the in-memory
+    /// tree only changes if the write succeeds.
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut HashMap<String, Folder>) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut folders = self.folders.write().map_err(|e| e.to_string())?;
+        let mut updated = folders.clone();
+        let result = change(&mut updated)?;
+        let data = serde_json::to_vec_pretty(&updated).map_err(|e| format!("Serialize error: {}", e))?;
+        self.storage
+            .put(Space::Meta, FOLDERS_KEY, &data)
+            .map_err(|e| format!("Failed to save folders: {}", e))?;
+        *folders = updated;
+        Ok(result)
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        parent_id: Option<&str>,
+        owner: Option<(&str, &str)>,
+        default_shares: Vec<DocumentShare>,
+    ) -> Result<Folder, String> {
+        let name = clean_name(name)?;
+        self.update(|folders| {
+            if let Some(parent_id) = parent_id {
+                if !folders.contains_key(parent_id) {
+                    return Err(not_found(parent_id));
+                }
+            }
+            let now = now_ms();
+            let folder = Folder {
+                id: nanoid::nanoid!(),
+                name,
+                parent_id: parent_id.map(String::from),
+                owner_id: owner.map(|(id, _)| id.to_string()),
+                owner_name: owner.map(|(_, name)| name.to_string()),
+                default_shares,
+                created_at: now,
+                modified_at: now,
+            };
+            folders.insert(folder.id.clone(), folder.clone());
+            Ok(folder)
+        })
+    }
+
+    pub fn rename(&self, folder_id: &str, name: &str) -> Result<Folder, String> {
+        let name = clean_name(name)?;
+        self.modify(folder_id, |folder| folder.name = name)
+    }
+
+    pub fn set_default_shares(&self, folder_id: &str, shares: Vec<DocumentShare>) -> Result<Folder, String> {
+        self.modify(folder_id, |folder| folder.default_shares = shares)
+    }
+
+    /// Move a folder under `parent_id` (`None` = top level). A folder cannot
+    /// be moved into itself or one of its descendants.
+    pub fn move_to(&self, folder_id: &str, parent_id: Option<&str>) -> Result<Folder, String> {
+        self.update(|folders| {
+            if let Some(parent_id) = parent_id {
+                if !folders.contains_key(parent_id) {
+                    return Err(not_found(parent_id));
+                }
+                if ancestry(folders, parent_id).iter().any(|f| f.id == folder_id) {
+                    return Err("Cannot move a folder into itself or one of its subfolders".to_string());
+                }
+            }
+            let folder = folders.get_mut(folder_id).ok_or_else(|| not_found(folder_id))?;
+            folder.parent_id = parent_id.map(String::from);
+            folder.modified_at = now_ms();
+            Ok(folder.clone())
+        })
+    }
+
+    fn modify(&self, folder_id: &str, change: impl FnOnce(&mut Folder)) -> Result<Folder, String> {
+        self.update(|folders| {
+            let folder = folders.get_mut(folder_id).ok_or_else(|| not_found(folder_id))?;
+            change(folder);
+            folder.modified_at = now_ms();
+            Ok(folder.clone())
+        })
+    }
+
+    /// Remove a folder, moving its subfolders to its parent. Returns the
+    /// removed folder; the caller re-files the documents it contained.
+    pub fn remove(&self, folder_id: &str) -> Result<Folder, String> {
+        self.update(|folders| {
+            let removed = folders.remove(folder_id).ok_or_else(|| not_found(folder_id))?;
+            for folder in folders.values_mut() {
+                if folder.parent_id.as_deref() == Some(folder_id) {
+                    folder.parent_id = removed.parent_id.clone();
+                }
+            }
+            Ok(removed)
+        })
+    }
+}
+
+/// A folder and its ancestors, nearest first
+fn ancestry<'a>(folders: &'a HashMap<String, Folder>, folder_id: &str) -> Vec<&'a Folder> {
+    let mut chain: Vec<&Folder> = Vec::new();
+    let mut next = Some(folder_id);
+    while let Some(id) = next {
+        // Guard against a cycle in hand-edited data
+        if chain.iter().any(|f| f.id == id) {
+            break;
+        }
+        let Some(folder) = folders.get(id) else {
+            break;
+        };
+        next = folder.parent_id.as_deref();
+        chain.push(folder);
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use tempfile::tempdir;
+
+    fn share(user_id: &str, permission: &str) -> DocumentShare {
+        DocumentShare {
+            user_id: user_id.to_string(),
+            user_name: user_id.to_string(),
+            permission: permission.to_string(),
+            shared_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_tree_operations_persist() {
+        let dir = tempdir().unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        let store = FolderStore::new(storage.clone());
+
+        let top = store.create("Projects", None, Some(("u1", "Alice")), vec![]).unwrap();
+        let child = store.create(" Alpha ", Some(&top.id), None, vec![]).unwrap();
+        let grandchild = store.create("Specs", Some(&child.id), None, vec![]).unwrap();
+        assert_eq!(child.name, "Alpha");
+        assert!(store.create("", None, None, vec![]).is_err());
+        assert!(store.create("Orphan", Some("missing"), None, vec![]).is_err());
+
+        // No cycles
+        assert!(store.move_to(&top.id, Some(&grandchild.id)).is_err());
+        assert!(store.move_to(&top.id, Some(&top.id)).is_err());
+        store.move_to(&grandchild.id, None).unwrap();
+        store.rename(&child.id, "Beta").unwrap();
+
+        // Removing re-parents subfolders
+        store.move_to(&grandchild.id, Some(&child.id)).unwrap();
+        store.remove(&child.id).unwrap();
+        assert_eq!(store.get(&grandchild.id).unwrap().parent_id.as_deref(), Some(top.id.as_str()));
+
+        let reloaded = FolderStore::new(storage);
+        assert_eq!(reloaded.list().len(), 2);
+        assert_eq!(reloaded.get(&top.id).unwrap().owner_name.as_deref(), Some("Alice"));
+    }
+
+    #[test]
+    fn test_nearest_default_share_wins() {
+        let dir = tempdir().unwrap();
+        let store = FolderStore::new(Arc::new(FsStorage::new(dir.path().to_path_buf())));
+
+        let top = store
+            .create("Team", None, None, vec![share("u2", "view"), share("u3", "view")])
+            .unwrap();
+        let child = store.create("Design", Some(&top.id), None, vec![share("u2", "edit")]).unwrap();
+
+        let shares = store.inherited_shares(&child.id);
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares.iter().find(|s| s.user_id == "u2").unwrap().permission, "edit");
+        assert!(store.inherited_shares("missing").is_empty());
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = vec![" infra ".to_string(), "Infra".to_string(), "".to_string(), "q3".to_string()];
+        assert_eq!(normalize_tags(&tags).unwrap(), vec!["infra", "q3"]);
+        let too_many: Vec<String> = (0..=MAX_TAGS).map(|i| format!("t{}", i)).collect();
+        assert!(normalize_tags(&too_many).is_err());
+    }
+}
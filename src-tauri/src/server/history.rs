@@ -0,0 +1,390 @@
+//! Document version history
+//!
+//! Keeps prior versions of team documents so destructive edits can be undone.
+//! Snapshots are the revisions `DocumentStore` already writes on every save
+//! (`<doc_id>/<serverVersion>` in the revisions space); this module only
+//! keeps, per document, the index of which revisions are recorded versions,
+//! in the history space of the active [`Storage`]:
+//!
+//! ```text
+//! <doc_id>/versions   # Vec<VersionInfo>, oldest first
+//! ```
+//!
+//! Recorded revisions are exempt from merge-base rotation until retention
+//! drops them, on the document's next save or from the purge task. The
+//! policy itself is stored as `history_config` in the meta space.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+use crate::storage::{Space, Storage, WriteOp};
+
+const CONFIG_KEY: &str = "history_config";
+const INDEX_NAME: &str = "versions";
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Snapshot and retention policy for version history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryConfig {
+    /// Record versions at all
+    pub enabled: bool,
+    /// Minimum seconds between snapshots of the same document (0 = every save)
+    pub min_interval_secs: u64,
+    /// Keep at most this many versions per document (0 = unlimited)
+    pub max_versions: usize,
+    /// Drop versions older than this many days (0 = keep forever)
+    pub max_age_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_interval_secs: 0,
+            max_versions: 50,
+            max_age_days: 30,
+        }
+    }
+}
+
+/// Description of one recorded version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    /// `serverVersion` of the document when the snapshot was taken
+    pub server_version: u64,
+    /// Document name at that version
+    pub name: String,
+    /// Snapshot timestamp (Unix milliseconds)
+    pub saved_at: u64,
+    /// User who made this version (`lastModifiedBy`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
+    /// Serialized size in bytes
+    pub size: u64,
+}
+
+/// Version history for team documents
+pub struct HistoryStore {
+    storage: Arc<dyn Storage>,
+    config: RwLock<HistoryConfig>,
+}
+
+impl HistoryStore {
+    /// Create a history store on top of the given storage backend
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let store = Self {
+            storage,
+            config: RwLock::new(HistoryConfig::default()),
+        };
+        store.reload_config();
+        store
+    }
+
+    /// Re-read the persisted policy (another store instance may have changed it)
+    pub fn reload_config(&self) {
+        let config = match self.storage.get(Space::Meta, CONFIG_KEY) {
+            Ok(Some(data)) => match serde_json::from_slice(&data) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::warn!("Invalid history config, using defaults: {}", e);
+                    HistoryConfig::default()
+                }
+            },
+            _ => HistoryConfig::default(),
+        };
+        if let Ok(mut current) = self.config.write() {
+            *current = config;
+        }
+    }
+
+    pub fn config(&self) -> HistoryConfig {
+        self.config.read().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    /// Persist a new policy. Retention is applied on the next save of each
+    /// document and by the periodic purge task.
+    pub fn set_config(&self, config: HistoryConfig) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&config)
+            .map_err(|e| format!("Serialize error: {}", e))?;
+        self.storage.put(Space::Meta, CONFIG_KEY, json.as_bytes())?;
+        *self.config.write().map_err(|e| e.to_string())? = config;
+        Ok(())
+    }
+
+    fn snapshot_key(doc_id: &str, server_version: u64) -> String {
+        format!("{}/{}", doc_id, server_version)
+    }
+
+    fn index_key(doc_id: &str) -> String {
+        format!("{}/{}", doc_id, INDEX_NAME)
+    }
+
+    /// List recorded versions of a document, newest first
+    pub fn list_versions(&self, doc_id: &str) -> Vec<VersionInfo> {
+        let mut versions = self.read_index(doc_id);
+        versions.reverse();
+        versions
+    }
+
+    /// `serverVersion`s of a document's recorded versions, whose revisions
+    /// must be kept
+    pub fn recorded(&self, doc_id: &str) -> Vec<u64> {
+        self.read_index(doc_id).iter().map(|v| v.server_version).collect()
+    }
+
+    /// Load the snapshot of a recorded version
+    pub fn get_version(&self, doc_id: &str, server_version: u64) -> Result<serde_json::Value, String> {
+        let not_found = || format!("Version {} not found", server_version);
+        if !self.recorded(doc_id).contains(&server_version) {
+            return Err(not_found());
+        }
+        let data = self
+            .storage
+            .get(Space::Revisions, &Self::snapshot_key(doc_id, server_version))?
+            .ok_or_else(not_found)?;
+        serde_json::from_slice(&data).map_err(|e| format!("Failed to parse version: {}", e))
+    }
+
+    /// Decide whether a document version being written (`size` bytes) is
+    /// recorded, subject to the configured throttle, and apply retention.
+    /// Returns the document's new index, which the caller writes with
+    /// [`Self::index_write`] in the same batch as the revision, or `None` if
+    /// the version is not recorded.
+    pub fn record(&self, doc_id: &str, doc: &serde_json::Value, size: u64, now: u64) -> Option<Vec<VersionInfo>> {
+        let config = self.config();
+        if !config.enabled {
+            return None;
+        }
+
+        let mut versions = self.read_index(doc_id);
+        if let Some(last) = versions.last() {
+            let interval_ms = config.min_interval_secs.saturating_mul(1000);
+            if interval_ms > 0 && now.saturating_sub(last.saved_at) < interval_ms {
+                return None;
+            }
+        }
+
+        let server_version = doc.get("serverVersion").and_then(|v| v.as_u64()).unwrap_or(0);
+        versions.retain(|v| v.server_version != server_version);
+        versions.push(VersionInfo {
+            server_version,
+            name: doc.get("name").and_then(|v| v.as_str()).unwrap_or("Untitled").to_string(),
+            saved_at: now,
+            author_id: doc.get("lastModifiedBy").and_then(|v| v.as_str()).map(String::from),
+            author_name: doc.get("lastModifiedByName").and_then(|v| v.as_str()).map(String::from),
+            size,
+        });
+
+        Self::apply_retention(&mut versions, &config, now);
+        Some(versions)
+    }
+
+    /// Apply retention to a document's index as of `now`. Returns the new
+    /// index if any version was dropped.
+    pub fn expire(&self, doc_id: &str, now: u64) -> Option<Vec<VersionInfo>> {
+        let mut versions = self.read_index(doc_id);
+        let before = versions.len();
+        Self::apply_retention(&mut versions, &self.config(), now);
+        (versions.len() < before).then_some(versions)
+    }
+
+    /// The write storing a document's index
+    pub fn index_write(doc_id: &str, versions: &[VersionInfo]) -> Result<WriteOp, String> {
+        let json = serde_json::to_vec_pretty(versions).map_err(|e| format!("Serialize error: {}", e))?;
+        Ok(WriteOp::Put {
+            space: Space::History,
+            key: Self::index_key(doc_id),
+            value: json,
+        })
+    }
+
+    /// IDs of the documents that have recorded versions
+    pub fn documents(&self) -> Vec<String> {
+        let suffix = format!("/{}", INDEX_NAME);
+        self.storage
+            .list(Space::History, "")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|key| key.strip_suffix(&suffix).map(String::from))
+            .collect()
+    }
+
+    /// Every recorded snapshot, across all documents. Fails if any index or
+    /// snapshot cannot be read or parsed.
+    pub fn snapshots(&self) -> Result<Vec<serde_json::Value>, String> {
+        let suffix = format!("/{}", INDEX_NAME);
+        let mut snapshots = Vec::new();
+        for key in self.storage.list(Space::History, "")? {
+            let Some(doc_id) = key.strip_suffix(&suffix) else {
+                continue;
+            };
+            for version in self.load_index(doc_id)? {
+                let key = Self::snapshot_key(doc_id, version.server_version);
+                let Some(data) = self.storage.get(Space::Revisions, &key)? else {
+                    continue;
+                };
+                snapshots.push(
+                    serde_json::from_slice(&data).map_err(|e| format!("Failed to parse version {}: {}", key, e))?,
+                );
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Remove the history index of a document (its revisions are deleted
+    /// by the caller)
+    pub fn delete_history(&self, doc_id: &str) {
+        if let Err(e) = self.storage.delete_prefix(Space::History, &format!("{}/", doc_id)) {
+            log::warn!("Failed to delete history of document {}: {}", doc_id, e);
+        }
+    }
+
+    /// Drop versions beyond the count limit or older than the age limit.
+    /// The newest version is always kept.
+    fn apply_retention(versions: &mut Vec<VersionInfo>, config: &HistoryConfig, now: u64) {
+        let max_age_ms = config.max_age_days.saturating_mul(MILLIS_PER_DAY);
+        let len = versions.len();
+        let mut kept = Vec::with_capacity(len);
+        for (idx, version) in versions.drain(..).enumerate() {
+            let is_newest = idx + 1 == len;
+            let too_many = config.max_versions > 0 && len - idx > config.max_versions;
+            let too_old = max_age_ms > 0 && now.saturating_sub(version.saved_at) > max_age_ms;
+            if is_newest || !(too_many || too_old) {
+                kept.push(version);
+            }
+        }
+        *versions = kept;
+    }
+
+    fn read_index(&self, doc_id: &str) -> Vec<VersionInfo> {
+        self.load_index(doc_id).unwrap_or_default()
+    }
+
+    fn load_index(&self, doc_id: &str) -> Result<Vec<VersionInfo>, String> {
+        match self.storage.get(Space::History, &Self::index_key(doc_id))? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|e| format!("Failed to parse history of document {}: {}", doc_id, e)),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use tempfile::tempdir;
+
+    fn open(dir: &std::path::Path) -> HistoryStore {
+        HistoryStore::new(Arc::new(FsStorage::new(dir.to_path_buf())))
+    }
+
+    /// Write a revision and record it the way `DocumentStore` does
+    fn record(store: &HistoryStore, version: u64, now: u64) -> bool {
+        let doc = serde_json::json!({
+            "id": "doc-1",
+            "name": format!("v{}", version),
+            "serverVersion": version,
+            "lastModifiedBy": "user-1"
+        });
+        let json = doc.to_string();
+        let mut ops = vec![WriteOp::Put {
+            space: Space::Revisions,
+            key: format!("doc-1/{}", version),
+            value: json.clone().into_bytes(),
+        }];
+        let recorded = store.record("doc-1", &doc, json.len() as u64, now);
+        if let Some(versions) = &recorded {
+            ops.push(HistoryStore::index_write("doc-1", versions).unwrap());
+        }
+        store.storage.write_batch(ops).unwrap();
+        recorded.is_some()
+    }
+
+    #[test]
+    fn test_record_and_list() {
+        let dir = tempdir().unwrap();
+        let store = open(dir.path());
+
+        assert!(record(&store, 1, 1000));
+        assert!(record(&store, 2, 2000));
+
+        let versions = store.list_versions("doc-1");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].server_version, 2);
+        assert_eq!(versions[0].author_id.as_deref(), Some("user-1"));
+        assert_eq!(store.get_version("doc-1", 1).unwrap()["name"], "v1");
+    }
+
+    #[test]
+    fn test_throttle_and_count_retention() {
+        let dir = tempdir().unwrap();
+        let store = open(dir.path());
+        store
+            .set_config(HistoryConfig {
+                enabled: true,
+                min_interval_secs: 10,
+                max_versions: 2,
+                max_age_days: 0,
+            })
+            .unwrap();
+
+        assert!(record(&store, 1, 0));
+        assert!(!record(&store, 2, 5_000));
+        assert!(record(&store, 3, 10_000));
+        assert!(record(&store, 4, 20_000));
+
+        let versions: Vec<u64> = store.list_versions("doc-1").iter().map(|v| v.server_version).collect();
+        assert_eq!(versions, vec![4, 3]);
+        // Revision 1 still exists, but is no longer a recorded version
+        assert!(store.get_version("doc-1", 1).is_err());
+        assert_eq!(store.recorded("doc-1"), vec![3, 4]);
+
+        // Policy persists for other instances
+        assert_eq!(open(dir.path()).config().max_versions, 2);
+    }
+
+    #[test]
+    fn test_age_retention_keeps_newest() {
+        let dir = tempdir().unwrap();
+        let store = open(dir.path());
+        store
+            .set_config(HistoryConfig {
+                max_age_days: 1,
+                ..HistoryConfig::default()
+            })
+            .unwrap();
+
+        assert!(record(&store, 1, 0));
+        assert!(record(&store, 2, 3 * MILLIS_PER_DAY));
+
+        let versions = store.list_versions("doc-1");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].server_version, 2);
+    }
+
+    #[test]
+    fn test_expire_without_a_save() {
+        let dir = tempdir().unwrap();
+        let store = open(dir.path());
+        store
+            .set_config(HistoryConfig {
+                max_age_days: 1,
+                ..HistoryConfig::default()
+            })
+            .unwrap();
+
+        assert!(record(&store, 1, 0));
+        assert!(record(&store, 2, 1000));
+        assert!(store.expire("doc-1", 1000).is_none());
+
+        let kept = store.expire("doc-1", 2 * MILLIS_PER_DAY).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].server_version, 2);
+        assert_eq!(store.documents(), vec!["doc-1".to_string()]);
+    }
+}
@@ -0,0 +1,275 @@
+//! RFC 6902 JSON Patch support for incremental document updates
+//!
+//! Clients send a list of operations computed against a known `serverVersion`
+//! instead of re-sending the whole `DiagramDocument`. Operations are applied
+//! to a working copy, so a failing operation leaves the original untouched.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single RFC 6902 operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl PatchOperation {
+    /// Operation name as it appears on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchOperation::Add { .. } => "add",
+            PatchOperation::Remove { .. } => "remove",
+            PatchOperation::Replace { .. } => "replace",
+            PatchOperation::Move { .. } => "move",
+            PatchOperation::Copy { .. } => "copy",
+            PatchOperation::Test { .. } => "test",
+        }
+    }
+}
+
+/// Apply a sequence of operations to `doc` atomically.
+///
+/// Either every operation succeeds and `doc` is replaced with the result, or
+/// an error naming the failing operation is returned and `doc` is unchanged.
+pub fn apply_patch(doc: &mut Value, operations: &[PatchOperation]) -> Result<(), String> {
+    let mut working = doc.clone();
+    for (idx, op) in operations.iter().enumerate() {
+        apply_operation(&mut working, op)
+            .map_err(|e| format!("Patch operation {} ({}) failed: {}", idx, op.name(), e))?;
+    }
+    *doc = working;
+    Ok(())
+}
+
+fn apply_operation(doc: &mut Value, op: &PatchOperation) -> Result<(), String> {
+    match op {
+        PatchOperation::Add { path, value } => add(doc, path, value.clone()),
+        PatchOperation::Remove { path } => remove(doc, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            let target = pointer_mut(doc, path)?;
+            *target = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if from == path {
+                return Ok(());
+            }
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("Cannot move '{}' into its own child '{}'", from, path));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = pointer(doc, from)?.clone();
+            add(doc, path, value)
+        }
+        PatchOperation::Test { path, value } => {
+            if pointer(doc, path)? == value {
+                Ok(())
+            } else {
+                Err(format!("Value at '{}' does not match", path))
+            }
+        }
+    }
+}
+
+/// Split a JSON Pointer (RFC 6901) into unescaped reference tokens
+fn parse_pointer(path: &str) -> Result<Vec<String>, String> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !path.starts_with('/') {
+        return Err(format!("Invalid JSON pointer '{}': must start with '/'", path));
+    }
+    Ok(path[1..]
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// Split a pointer into its parent tokens and final token
+fn split_parent(path: &str) -> Result<(Vec<String>, String), String> {
+    let mut tokens = parse_pointer(path)?;
+    let last = tokens
+        .pop()
+        .ok_or_else(|| "Operation cannot target the document root".to_string())?;
+    Ok((tokens, last))
+}
+
+fn resolve<'a>(doc: &'a Value, tokens: &[String]) -> Result<&'a Value, String> {
+    let mut current = doc;
+    for token in tokens {
+        current = match current {
+            Value::Object(map) => map
+                .get(token)
+                .ok_or_else(|| format!("Path segment '{}' not found", token))?,
+            Value::Array(arr) => {
+                let idx = parse_index(token, arr.len())?;
+                arr.get(idx)
+                    .ok_or_else(|| format!("Array index {} out of bounds", idx))?
+            }
+            _ => return Err(format!("Cannot descend into scalar at '{}'", token)),
+        };
+    }
+    Ok(current)
+}
+
+fn resolve_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Result<&'a mut Value, String> {
+    let mut current = doc;
+    for token in tokens {
+        current = match current {
+            Value::Object(map) => map
+                .get_mut(token)
+                .ok_or_else(|| format!("Path segment '{}' not found", token))?,
+            Value::Array(arr) => {
+                let idx = parse_index(token, arr.len())?;
+                arr.get_mut(idx)
+                    .ok_or_else(|| format!("Array index {} out of bounds", idx))?
+            }
+            _ => return Err(format!("Cannot descend into scalar at '{}'", token)),
+        };
+    }
+    Ok(current)
+}
+
+fn pointer<'a>(doc: &'a Value, path: &str) -> Result<&'a Value, String> {
+    resolve(doc, &parse_pointer(path)?)
+}
+
+fn pointer_mut<'a>(doc: &'a mut Value, path: &str) -> Result<&'a mut Value, String> {
+    resolve_mut(doc, &parse_pointer(path)?)
+}
+
+/// Parse an array index token. Leading zeros are rejected per RFC 6901.
+fn parse_index(token: &str, len: usize) -> Result<usize, String> {
+    if token == "-" {
+        return Ok(len);
+    }
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return Err(format!("Invalid array index '{}'", token));
+    }
+    token
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid array index '{}'", token))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent_tokens, last) = split_parent(path)?;
+    match resolve_mut(doc, &parent_tokens)? {
+        Value::Object(map) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        Value::Array(arr) => {
+            let idx = parse_index(&last, arr.len())?;
+            if idx > arr.len() {
+                return Err(format!("Array index {} out of bounds", idx));
+            }
+            arr.insert(idx, value);
+            Ok(())
+        }
+        _ => Err(format!("Parent of '{}' is not a container", path)),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent_tokens, last) = split_parent(path)?;
+    match resolve_mut(doc, &parent_tokens)? {
+        Value::Object(map) => map
+            .remove(&last)
+            .ok_or_else(|| format!("Path '{}' not found", path)),
+        Value::Array(arr) => {
+            let idx = parse_index(&last, arr.len())?;
+            if idx >= arr.len() {
+                return Err(format!("Array index {} out of bounds", idx));
+            }
+            Ok(arr.remove(idx))
+        }
+        _ => Err(format!("Parent of '{}' is not a container", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ops(value: Value) -> Vec<PatchOperation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_add_replace_remove() {
+        let mut doc = json!({
+            "pages": {"p1": {"shapes": {"s1": {"x": 0}}, "shapeOrder": ["s1"]}}
+        });
+        apply_patch(
+            &mut doc,
+            &ops(json!([
+                {"op": "replace", "path": "/pages/p1/shapes/s1/x", "value": 42},
+                {"op": "add", "path": "/pages/p1/shapes/s2", "value": {"x": 1}},
+                {"op": "add", "path": "/pages/p1/shapeOrder/-", "value": "s2"},
+                {"op": "remove", "path": "/pages/p1/shapeOrder/0"}
+            ])),
+        )
+        .unwrap();
+
+        assert_eq!(doc["pages"]["p1"]["shapes"]["s1"]["x"], 42);
+        assert_eq!(doc["pages"]["p1"]["shapes"]["s2"]["x"], 1);
+        assert_eq!(doc["pages"]["p1"]["shapeOrder"], json!(["s2"]));
+    }
+
+    #[test]
+    fn test_move_copy_and_escaping() {
+        let mut doc = json!({"a": {"b/c": 1, "d~e": 2}, "list": [1, 2, 3]});
+        apply_patch(
+            &mut doc,
+            &ops(json!([
+                {"op": "move", "from": "/a/b~1c", "path": "/moved"},
+                {"op": "copy", "from": "/a/d~0e", "path": "/list/1"}
+            ])),
+        )
+        .unwrap();
+
+        assert_eq!(doc["moved"], 1);
+        assert!(doc["a"].get("b/c").is_none());
+        assert_eq!(doc["list"], json!([1, 2, 2, 3]));
+    }
+
+    #[test]
+    fn test_failed_operation_leaves_document_untouched() {
+        let mut doc = json!({"name": "Original", "count": 1});
+        let original = doc.clone();
+
+        let result = apply_patch(
+            &mut doc,
+            &ops(json!([
+                {"op": "replace", "path": "/name", "value": "Changed"},
+                {"op": "test", "path": "/count", "value": 2}
+            ])),
+        );
+
+        assert!(result.unwrap_err().contains("operation 1 (test)"));
+        assert_eq!(doc, original);
+    }
+
+    #[test]
+    fn test_invalid_paths_are_rejected() {
+        let mut doc = json!({"list": [1]});
+        assert!(apply_patch(&mut doc, &ops(json!([{"op": "remove", "path": "/missing"}]))).is_err());
+        assert!(apply_patch(&mut doc, &ops(json!([{"op": "add", "path": "/list/5", "value": 0}]))).is_err());
+        assert!(apply_patch(&mut doc, &ops(json!([{"op": "replace", "path": "list", "value": 0}]))).is_err());
+        assert!(apply_patch(&mut doc, &ops(json!([{"op": "remove", "path": "/list/01"}]))).is_err());
+    }
+}
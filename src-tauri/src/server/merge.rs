@@ -0,0 +1,396 @@
+//! Three-way merge for full-document saves
+//!
+//! When a client saves a document based on a revision that is no longer
+//! current, the server merges the client's copy (`incoming`) with the stored
+//! copy (`current`) using the revision the client started from (`base`).
+//!
+//! Merging is structural: pages are matched by id, shapes by id within
+//! `pages[*].shapes`, and `pageOrder` / `shapeOrder` are merged as ordered
+//! id lists. Any other field is compared as a whole value. A field that was
+//! changed differently on both sides is reported as a conflict and the
+//! stored value is kept.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// Fields managed by the server or private to a client session. These never
+/// conflict; the incoming value is taken as-is.
+const VOLATILE_FIELDS: &[&str] = &[
+    "serverVersion",
+    "modifiedAt",
+    "lastModifiedBy",
+    "lastModifiedByName",
+    "activePageId",
+];
+
+/// A field changed differently on both sides of a merge
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConflict {
+    /// JSON pointer to the conflicting value (e.g. `/pages/p1/shapes/s1`)
+    pub path: String,
+    /// Value at the base revision (`None` if it did not exist)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<Value>,
+    /// Value currently stored on the server (`None` if deleted)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<Value>,
+    /// Value in the rejected save (`None` if deleted)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incoming: Option<Value>,
+}
+
+/// Result of a three-way merge
+#[derive(Debug, Clone)]
+pub struct MergeResult {
+    /// Merged document; conflicting fields keep the current value
+    pub merged: Value,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merge `incoming` into `current` using `base` as the common ancestor
+pub fn three_way_merge(base: &Value, current: &Value, incoming: &Value) -> MergeResult {
+    let mut conflicts = Vec::new();
+
+    let merged = match (base.as_object(), current.as_object(), incoming.as_object()) {
+        (Some(b), Some(c), Some(i)) => {
+            Value::Object(merge_document(b, c, i, &mut conflicts))
+        }
+        _ => merge_value("", Some(base), Some(current), Some(incoming), &mut conflicts)
+            .unwrap_or(Value::Null),
+    };
+
+    MergeResult { merged, conflicts }
+}
+
+fn merge_document(
+    base: &Map<String, Value>,
+    current: &Map<String, Value>,
+    incoming: &Map<String, Value>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Map<String, Value> {
+    let mut merged = Map::new();
+
+    for key in union_keys(base, current, incoming) {
+        let (b, c, i) = (base.get(&key), current.get(&key), incoming.get(&key));
+        let path = format!("/{}", escape(&key));
+
+        let value = if VOLATILE_FIELDS.contains(&key.as_str()) {
+            i.or(c).cloned()
+        } else if key == "pages" {
+            Some(Value::Object(merge_keyed(&path, b, c, i, conflicts, Some(merge_page))))
+        } else if key == "pageOrder" {
+            // Filled in below once the merged page set is known
+            None
+        } else {
+            merge_value(&path, b, c, i, conflicts)
+        };
+
+        if let Some(v) = value {
+            merged.insert(key, v);
+        }
+    }
+
+    if current.contains_key("pageOrder") || incoming.contains_key("pageOrder") {
+        let live = object_keys(merged.get("pages"));
+        let order = merge_order(
+            base.get("pageOrder"),
+            current.get("pageOrder"),
+            incoming.get("pageOrder"),
+            &live,
+        );
+        merged.insert("pageOrder".to_string(), order);
+    }
+
+    merged
+}
+
+/// Merge a single page. Only called when the page exists on all three sides.
+fn merge_page(
+    path: &str,
+    base: &Map<String, Value>,
+    current: &Map<String, Value>,
+    incoming: &Map<String, Value>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Map<String, Value> {
+    let mut merged = Map::new();
+
+    for key in union_keys(base, current, incoming) {
+        let (b, c, i) = (base.get(&key), current.get(&key), incoming.get(&key));
+        let field_path = format!("{}/{}", path, escape(&key));
+
+        let value = if key == "modifiedAt" {
+            i.or(c).cloned()
+        } else if key == "shapes" {
+            // Shapes are merged as whole values keyed by id
+            Some(Value::Object(merge_keyed(&field_path, b, c, i, conflicts, None)))
+        } else if key == "shapeOrder" {
+            None
+        } else {
+            merge_value(&field_path, b, c, i, conflicts)
+        };
+
+        if let Some(v) = value {
+            merged.insert(key, v);
+        }
+    }
+
+    if current.contains_key("shapeOrder") || incoming.contains_key("shapeOrder") {
+        let live = object_keys(merged.get("shapes"));
+        let order = merge_order(
+            base.get("shapeOrder"),
+            current.get("shapeOrder"),
+            incoming.get("shapeOrder"),
+            &live,
+        );
+        merged.insert("shapeOrder".to_string(), order);
+    }
+
+    merged
+}
+
+/// Structural merge for an entry present on all three sides
+type EntryMerge = fn(
+    &str,
+    &Map<String, Value>,
+    &Map<String, Value>,
+    &Map<String, Value>,
+    &mut Vec<MergeConflict>,
+) -> Map<String, Value>;
+
+/// Merge an id-keyed object (pages or shapes). Entries present on all three
+/// sides are merged with `merge_entry` if given; everything else, including
+/// additions and deletions, is resolved as whole values.
+fn merge_keyed(
+    path: &str,
+    base: Option<&Value>,
+    current: Option<&Value>,
+    incoming: Option<&Value>,
+    conflicts: &mut Vec<MergeConflict>,
+    merge_entry: Option<EntryMerge>,
+) -> Map<String, Value> {
+    let empty = Map::new();
+    let base = base.and_then(|v| v.as_object()).unwrap_or(&empty);
+    let current = current.and_then(|v| v.as_object()).unwrap_or(&empty);
+    let incoming = incoming.and_then(|v| v.as_object()).unwrap_or(&empty);
+
+    let mut merged = Map::new();
+    for id in union_keys(base, current, incoming) {
+        let (b, c, i) = (base.get(&id), current.get(&id), incoming.get(&id));
+        let entry_path = format!("{}/{}", path, escape(&id));
+
+        let value = match (
+            merge_entry,
+            b.and_then(|v| v.as_object()),
+            c.and_then(|v| v.as_object()),
+            i.and_then(|v| v.as_object()),
+        ) {
+            (Some(merge_entry), Some(bo), Some(co), Some(io)) if c != i => {
+                Some(Value::Object(merge_entry(&entry_path, bo, co, io, conflicts)))
+            }
+            _ => merge_value(&entry_path, b, c, i, conflicts),
+        };
+
+        if let Some(v) = value {
+            merged.insert(id, v);
+        }
+    }
+    merged
+}
+
+/// Whole-value three-way merge. `None` means the value is absent (deleted or
+/// never added). On conflict the current value is kept and reported.
+fn merge_value(
+    path: &str,
+    base: Option<&Value>,
+    current: Option<&Value>,
+    incoming: Option<&Value>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Value> {
+    if current == incoming || base == incoming {
+        return current.cloned();
+    }
+    if base == current {
+        return incoming.cloned();
+    }
+    conflicts.push(MergeConflict {
+        path: path.to_string(),
+        base: base.cloned(),
+        current: current.cloned(),
+        incoming: incoming.cloned(),
+    });
+    current.cloned()
+}
+
+/// Merge an ordered list of ids, keeping only ids present in `live`.
+///
+/// If only one side reordered, its order wins. If both did, the current
+/// order is kept and ids added by the incoming side are inserted after
+/// their nearest preceding id in the incoming list.
+fn merge_order(
+    base: Option<&Value>,
+    current: Option<&Value>,
+    incoming: Option<&Value>,
+    live: &HashSet<String>,
+) -> Value {
+    let base = id_list(base);
+    let current = id_list(current);
+    let incoming = id_list(incoming);
+
+    let mut order: Vec<String> = if current == incoming || base == incoming {
+        current.clone()
+    } else if base == current {
+        incoming.clone()
+    } else {
+        let mut order = current.clone();
+        for (idx, id) in incoming.iter().enumerate() {
+            if order.contains(id) {
+                continue;
+            }
+            let anchor = incoming[..idx]
+                .iter()
+                .rev()
+                .find_map(|prev| order.iter().position(|o| o == prev));
+            match anchor {
+                Some(pos) => order.insert(pos + 1, id.clone()),
+                None => order.insert(0, id.clone()),
+            }
+        }
+        order
+    };
+
+    order.retain(|id| live.contains(id));
+
+    // Ids can only be in `live` without being ordered if one side added them
+    // to the map but not to the order list; append rather than drop them.
+    let mut seen: HashSet<String> = HashSet::new();
+    order.retain(|id| seen.insert(id.clone()));
+    let mut missing: Vec<&String> = live.iter().filter(|id| !seen.contains(*id)).collect();
+    missing.sort();
+    order.extend(missing.into_iter().cloned());
+
+    Value::Array(order.into_iter().map(Value::String).collect())
+}
+
+fn id_list(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn object_keys(value: Option<&Value>) -> HashSet<String> {
+    value
+        .and_then(|v| v.as_object())
+        .map(|m| m.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Keys of all three maps in first-seen order (current, then incoming, then base)
+fn union_keys(
+    base: &Map<String, Value>,
+    current: &Map<String, Value>,
+    incoming: &Map<String, Value>,
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    current
+        .keys()
+        .chain(incoming.keys())
+        .chain(base.keys())
+        .filter(|k| seen.insert((*k).clone()))
+        .cloned()
+        .collect()
+}
+
+/// Escape a key for use as a JSON pointer segment
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc(shapes: Value, order: Value) -> Value {
+        json!({
+            "id": "doc-1",
+            "name": "Doc",
+            "pages": {"p1": {"id": "p1", "name": "Page 1", "shapes": shapes, "shapeOrder": order}},
+            "pageOrder": ["p1"],
+            "serverVersion": 1
+        })
+    }
+
+    #[test]
+    fn test_non_overlapping_shape_edits_merge() {
+        let base = doc(json!({"a": {"x": 0}, "b": {"x": 0}}), json!(["a", "b"]));
+        let current = doc(json!({"a": {"x": 5}, "b": {"x": 0}}), json!(["a", "b"]));
+        let incoming = doc(
+            json!({"a": {"x": 0}, "b": {"x": 7}, "c": {"x": 1}}),
+            json!(["a", "b", "c"]),
+        );
+
+        let result = three_way_merge(&base, &current, &incoming);
+        assert!(result.conflicts.is_empty());
+
+        let page = &result.merged["pages"]["p1"];
+        assert_eq!(page["shapes"]["a"]["x"], 5);
+        assert_eq!(page["shapes"]["b"]["x"], 7);
+        assert_eq!(page["shapes"]["c"]["x"], 1);
+        assert_eq!(page["shapeOrder"], json!(["a", "b", "c"]));
+    }
+
+    #[test]
+    fn test_same_shape_edited_on_both_sides_conflicts() {
+        let base = doc(json!({"a": {"x": 0}}), json!(["a"]));
+        let current = doc(json!({"a": {"x": 1}}), json!(["a"]));
+        let incoming = doc(json!({"a": {"x": 2}}), json!(["a"]));
+
+        let result = three_way_merge(&base, &current, &incoming);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].path, "/pages/p1/shapes/a");
+        assert_eq!(result.conflicts[0].incoming, Some(json!({"x": 2})));
+        assert_eq!(result.merged["pages"]["p1"]["shapes"]["a"]["x"], 1);
+    }
+
+    #[test]
+    fn test_delete_vs_edit_conflicts_and_delete_vs_untouched_applies() {
+        let base = doc(json!({"a": {"x": 0}, "b": {"x": 0}}), json!(["a", "b"]));
+        // Current deleted both shapes; incoming edited `a` only
+        let current = doc(json!({}), json!([]));
+        let incoming = doc(json!({"a": {"x": 9}, "b": {"x": 0}}), json!(["a", "b"]));
+
+        let result = three_way_merge(&base, &current, &incoming);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].path, "/pages/p1/shapes/a");
+        assert_eq!(result.conflicts[0].current, None);
+        assert!(result.merged["pages"]["p1"]["shapes"].get("b").is_none());
+        assert_eq!(result.merged["pages"]["p1"]["shapeOrder"], json!([]));
+    }
+
+    #[test]
+    fn test_pages_added_on_both_sides() {
+        let base = doc(json!({}), json!([]));
+        let mut current = base.clone();
+        current["pages"]["p2"] = json!({"id": "p2", "shapes": {}, "shapeOrder": []});
+        current["pageOrder"] = json!(["p1", "p2"]);
+        let mut incoming = base.clone();
+        incoming["pages"]["p3"] = json!({"id": "p3", "shapes": {}, "shapeOrder": []});
+        incoming["pageOrder"] = json!(["p1", "p3"]);
+        incoming["name"] = json!("Renamed");
+        incoming["serverVersion"] = json!(0);
+
+        let result = three_way_merge(&base, &current, &incoming);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.merged["pageOrder"], json!(["p1", "p3", "p2"]));
+        assert_eq!(result.merged["name"], "Renamed");
+        assert!(result.merged["pages"].get("p2").is_some());
+        assert!(result.merged["pages"].get("p3").is_some());
+    }
+}
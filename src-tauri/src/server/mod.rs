@@ -13,16 +13,37 @@
 //! - Authentication is required for all connections
 //! - Consider firewall rules for additional protection
 
+pub mod backup;
+pub mod blob_inventory;
+pub mod blob_gc;
+pub mod blob_policy;
+pub mod blob_scrub;
 pub mod blobs;
+pub mod copy;
 pub mod documents;
+pub mod folders;
+pub mod history;
+pub mod json_patch;
+pub mod merge;
+pub mod pages;
 pub mod permissions;
+pub mod previews;
 pub mod protocol;
+pub mod quotas;
+pub mod ranges;
+pub mod schema;
+pub mod search;
+pub mod templates;
+pub mod trash;
+pub mod uploads;
+pub mod validation;
+pub mod watcher;
 
 use axum::{
     body::Body,
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        DefaultBodyLimit, Path, Query, State,
     },
     http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
@@ -38,11 +59,26 @@ use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 
+use backup::{BackupConfig, BackupInfo, BackupKind, BackupStore, VerifyReport};
+use blob_gc::{BlobGcConfig, GcReport};
+use blob_inventory::{BlobPage, BlobQuery, BlobUsageSummary};
+use blob_policy::{BlobPolicyConfig, ContentType};
+use blob_scrub::{BlobScrubConfig, ScrubReport};
 use blobs::BlobStore;
-use documents::DocumentStore;
-use permissions::{check_read_permission, check_write_permission, check_delete_permission, to_error_string};
+use documents::{DocumentStore, SaveOutcome};
+use folders::Folder;
+use templates::TemplateDetails;
+use permissions::{can_manage_folder, can_manage_template, check_read_permission, check_write_permission, check_delete_permission, check_trash_permission, error_codes, get_user_permission, to_error_string, Permission};
 use protocol::*;
+use quotas::{QuotaConfig, UsageReport, UsageSnapshot};
+use ranges::ByteRange;
+use uploads::{UploadError, UploadStore};
+use validation::ValidationMode;
+use watcher::{DocChange, StoreSync};
 use crate::auth::{UserStore, create_token, verify_password, TokenConfig};
+use crate::storage::encrypted::{self, EncryptedStorage, KeySecret, KeySource};
+use crate::fs_util;
+use crate::storage::{self, Storage, StorageBackend};
 
 /// Network access mode for the server
 #[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -86,6 +122,61 @@ pub struct ServerConfig {
     pub max_connections: u16,
     /// Port to listen on
     pub port: u16,
+    /// Days a deleted document stays in the trash before it is purged
+    /// (0 = never purge)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Where documents, blobs and users are persisted. Switching backends
+    /// migrates existing data on the next server start.
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    /// Whether structurally invalid documents are rejected or repaired on save
+    #[serde(default)]
+    pub validation_mode: ValidationMode,
+    /// Per-user and server-wide storage limits
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    /// Encryption at rest for team documents and blobs (`None` = plaintext).
+    /// Enabling it encrypts existing data on the next server start.
+    #[serde(default)]
+    pub encryption: Option<KeySource>,
+    /// Scheduled backups of the team store
+    #[serde(default)]
+    pub backups: BackupConfig,
+    /// Collection of blobs no document uses any more
+    #[serde(default)]
+    pub blob_gc: BlobGcConfig,
+    /// Size limit and allowed content types for blob uploads
+    #[serde(default)]
+    pub blob_policy: BlobPolicyConfig,
+    /// Integrity checks of stored blobs
+    #[serde(default)]
+    pub blob_scrub: BlobScrubConfig,
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+/// File in the app data directory holding the last applied config, so the
+/// MCP server applies the same upload policy and quotas
+const CONFIG_FILENAME: &str = "server_config.json";
+
+impl ServerConfig {
+    /// The config last saved in `app_data_dir`, or the defaults if there is
+    /// none (or it cannot be read)
+    pub fn load(app_data_dir: &std::path::Path) -> Self {
+        std::fs::read(app_data_dir.join(CONFIG_FILENAME))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, app_data_dir: &std::path::Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| format!("Serialize error: {}", e))?;
+        fs_util::write_atomic(&app_data_dir.join(CONFIG_FILENAME), json)
+            .map_err(|e| format!("Failed to save server config: {}", e))
+    }
 }
 
 impl Default for ServerConfig {
@@ -94,6 +185,15 @@ impl Default for ServerConfig {
             network_mode: NetworkMode::Lan,
             max_connections: 10,
             port: 9876,
+            trash_retention_days: default_trash_retention_days(),
+            storage_backend: StorageBackend::default(),
+            validation_mode: ValidationMode::default(),
+            quotas: QuotaConfig::default(),
+            encryption: None,
+            backups: BackupConfig::default(),
+            blob_gc: BlobGcConfig::default(),
+            blob_policy: BlobPolicyConfig::default(),
+            blob_scrub: BlobScrubConfig::default(),
         }
     }
 }
@@ -158,29 +258,146 @@ pub struct ServerState {
     user_store: Option<Arc<UserStore>>,
     /// Token config for creating JWTs
     token_config: TokenConfig,
+    /// How documents failing structural validation are handled on save
+    validation_mode: ValidationMode,
+    /// Storage limits enforced on document saves and blob uploads
+    quotas: QuotaConfig,
+    /// Grace period and schedule for blob collection
+    blob_gc: BlobGcConfig,
+    /// Size limit and allowed content types for blob uploads
+    blob_policy: BlobPolicyConfig,
+    /// Encryption layer of the team storage, when enabled (for re-keying)
+    encryption: Option<Arc<EncryptedStorage>>,
+    /// Storage backend below the encryption layer, for backups
+    raw_storage: Arc<dyn Storage>,
+    /// Picks up changes other store instances made to the same storage
+    store_sync: Arc<StoreSync>,
+    /// Chunked blob uploads in progress
+    uploads: Arc<UploadStore>,
 }
 
 impl ServerState {
     fn new(
         app_data_dir: PathBuf,
+        config: &ServerConfig,
+        storage_passphrase: Option<String>,
         jwt_secret: String,
         user_store: Option<Arc<UserStore>>,
         token_config: TokenConfig,
-    ) -> Self {
+    ) -> Result<Self, String> {
+        // Opening a backend other than the active one migrates the data
+        let storage = storage::open(&app_data_dir, config.storage_backend)?;
+        let secret = match &config.encryption {
+            Some(KeySource::Passphrase) => Some(KeySecret::Passphrase(
+                storage_passphrase.ok_or("Enter the storage passphrase to start the server")?,
+            )),
+            Some(KeySource::KeyFile { path }) => Some(KeySecret::KeyFile(path.clone())),
+            None => None,
+        };
+        let raw_storage = storage.clone();
+        let (storage, encryption) = match secret {
+            Some(secret) => {
+                let encrypted = encrypted::unlock(storage, &secret)?;
+                (encrypted.clone() as Arc<dyn Storage>, Some(encrypted))
+            }
+            // Data encrypted earlier stays readable while its key is unlocked
+            None => (encrypted::attach(storage)?, None),
+        };
+        if let Some(user_store) = &user_store {
+            user_store.attach_storage(storage.clone())?;
+        }
+
+        let doc_store = Arc::new(DocumentStore::with_storage(storage.clone()));
+        let blob_store = Arc::new(BlobStore::with_storage(storage));
+        // Blob access follows the documents using each blob
+        if blob_store.get_blob_count() > 0 {
+            blob_store.sync_references(&doc_store.blob_references_by_document())?;
+        }
+        let references = blob_store.clone();
+        doc_store.on_blob_references(move |doc_id, hashes| {
+            if let Err(e) = references.set_document_references(doc_id, hashes) {
+                log::warn!("Failed to update blob references of {}: {}", doc_id, e);
+            }
+        });
+        quotas::enforce(&doc_store, &blob_store, config.quotas.clone());
+        let store_sync = Arc::new(StoreSync::new(doc_store.clone(), Some(blob_store.clone())));
+
+        let uploads = Arc::new(UploadStore::new(&app_data_dir));
+
         let (broadcast_tx, _) = broadcast::channel(100);
-        Self {
+        Ok(Self {
             broadcast_tx,
             client_count: AtomicU16::new(0),
             next_client_id: AtomicU64::new(1),
             clients: RwLock::new(HashMap::new()),
-            doc_store: Arc::new(DocumentStore::new(app_data_dir.clone())),
-            blob_store: Arc::new(BlobStore::new(app_data_dir)),
+            doc_store,
+            blob_store,
             jwt_secret,
             user_store,
             token_config,
+            validation_mode: config.validation_mode,
+            quotas: config.quotas.clone(),
+            blob_gc: config.blob_gc.clone(),
+            blob_policy: config.blob_policy.clone(),
+            encryption,
+            raw_storage,
+            store_sync,
+            uploads,
+        })
+    }
+
+    /// Tell every client about documents changed outside this server
+    fn broadcast_doc_changes(&self, changes: Vec<DocChange>) {
+        for change in changes {
+            let event = DocEvent {
+                event_type: change.event_type,
+                doc_id: change.doc_id,
+                metadata: change.metadata,
+                user_id: "system".to_string(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                self.broadcast_to_all(event_data, None);
+            }
         }
     }
 
+    /// Names shown in the blob inventory
+    fn blob_inventory_names(&self) -> blob_inventory::Names {
+        let users = self
+            .user_store
+            .as_ref()
+            .map(|store| store.list_users())
+            .unwrap_or_default();
+        blob_inventory::Names {
+            documents: self.doc_store.list_documents().into_iter().map(|d| (d.id, d.name)).collect(),
+            users: users.into_iter().map(|u| (u.id, u.display_name)).collect(),
+        }
+    }
+
+    /// A page of the blob inventory
+    fn blob_inventory(&self, query: &BlobQuery) -> BlobPage {
+        blob_inventory::page(self.blob_store.list_blobs(), query, &self.blob_inventory_names())
+    }
+
+    /// Blob storage per uploader and per document
+    fn blob_usage(&self) -> BlobUsageSummary {
+        blob_inventory::summarize(&self.blob_store.list_blobs(), &self.blob_inventory_names())
+    }
+
+    /// Storage usage of every user, named from the user store when available
+    fn usage_report(&self) -> UsageReport {
+        let usage = UsageSnapshot::collect(&self.doc_store, &self.blob_store);
+        let users = self
+            .user_store
+            .as_ref()
+            .map(|store| store.list_users())
+            .unwrap_or_default();
+        self.quotas.report(
+            &usage,
+            users.iter().map(|u| (u.id.as_str(), u.display_name.as_str())),
+        )
+    }
+
     fn next_client_id(&self) -> u64 {
         self.next_client_id.fetch_add(1, Ordering::Relaxed)
     }
@@ -216,6 +433,18 @@ impl ServerState {
     }
 }
 
+/// Document and blob stores of the running collaboration server
+#[derive(Clone)]
+pub struct TeamStores {
+    pub doc_store: Arc<DocumentStore>,
+    pub blob_store: Arc<BlobStore>,
+}
+
+/// The running server's stores (`None` while stopped). Other components in
+/// the app (the MCP server) write through them instead of their own store
+/// instances, so neither instance overwrites the other's index.
+pub type SharedStores = Arc<std::sync::RwLock<Option<TeamStores>>>;
+
 /// WebSocket server manager
 pub struct WebSocketServer {
     /// Whether the server is currently running
@@ -236,6 +465,12 @@ pub struct WebSocketServer {
     user_store: RwLock<Option<Arc<UserStore>>>,
     /// Token configuration
     token_config: RwLock<TokenConfig>,
+    /// Background maintenance tasks, aborted when the server stops
+    maintenance_tasks: RwLock<Vec<tokio::task::JoinHandle<()>>>,
+    /// Passphrase for encrypted storage, used by the next start only
+    storage_passphrase: RwLock<Option<String>>,
+    /// Stores of the running server, published for the MCP server
+    shared_stores: SharedStores,
 }
 
 impl Default for WebSocketServer {
@@ -256,11 +491,29 @@ impl WebSocketServer {
             jwt_secret: RwLock::new("diagrammer-jwt-secret-change-in-production".to_string()),
             user_store: RwLock::new(None),
             token_config: RwLock::new(TokenConfig::default()),
+            maintenance_tasks: RwLock::new(Vec::new()),
+            storage_passphrase: RwLock::new(None),
+            shared_stores: Arc::new(std::sync::RwLock::new(None)),
         }
     }
 
-    /// Set the app data directory (called during Tauri setup)
+    /// Slot holding the running server's stores, for the MCP server
+    pub fn shared_stores(&self) -> SharedStores {
+        self.shared_stores.clone()
+    }
+
+    fn publish_stores(&self, stores: Option<TeamStores>) {
+        if let Ok(mut shared) = self.shared_stores.write() {
+            *shared = stores;
+        }
+    }
+
+    /// Set the app data directory (called during Tauri setup). A config
+    /// saved there by an earlier run replaces the defaults.
     pub async fn set_app_data_dir(&self, dir: PathBuf) {
+        if dir.join(CONFIG_FILENAME).is_file() {
+            *self.config.write().await = ServerConfig::load(&dir);
+        }
         *self.app_data_dir.write().await = Some(dir);
     }
 
@@ -279,6 +532,11 @@ impl WebSocketServer {
         *self.token_config.write().await = config;
     }
 
+    /// Set the passphrase unlocking encrypted storage on the next start
+    pub async fn set_storage_passphrase(&self, passphrase: String) {
+        *self.storage_passphrase.write().await = Some(passphrase);
+    }
+
     /// Check if the server is currently running
     pub fn is_running(&self) -> bool {
         self.running.load(Ordering::Relaxed)
@@ -294,6 +552,9 @@ impl WebSocketServer {
         if self.is_running() {
             return Err("Cannot change configuration while server is running".to_string());
         }
+        if let Some(dir) = self.app_data_dir.read().await.as_ref() {
+            config.save(dir)?;
+        }
         *self.config.write().await = config;
         Ok(())
     }
@@ -371,19 +632,86 @@ impl WebSocketServer {
         let app_data_dir = self.app_data_dir.read().await
             .clone()
             .ok_or("App data directory not set")?;
+        if let Err(e) = config.save(&app_data_dir) {
+            log::warn!("{}", e);
+        }
+
+        // Bind before opening the stores or starting any background task,
+        // so a port in use leaves nothing behind
+        let bind_addr = match config.network_mode {
+            NetworkMode::Localhost => format!("127.0.0.1:{}", port),
+            NetworkMode::Lan => format!("0.0.0.0:{}", port),
+        };
+
+        let listener = tokio::net::TcpListener::bind(&bind_addr)
+            .await
+            .map_err(|e| format!("Failed to bind to {}: {}", bind_addr, e))?;
+
+        let actual_port = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to get local address: {}", e))?
+            .port();
 
         let jwt_secret = self.jwt_secret.read().await.clone();
         let user_store = self.user_store.read().await.clone();
         let token_config = self.token_config.read().await.clone();
+        let storage_passphrase = self.storage_passphrase.write().await.take();
 
         // Create server state with document store
         let server_state = Arc::new(ServerState::new(
-            app_data_dir,
+            app_data_dir.clone(),
+            &config,
+            storage_passphrase,
             jwt_secret,
             user_store,
             token_config,
-        ));
+        )?);
         *self.state.write().await = Some(server_state.clone());
+        self.publish_stores(Some(TeamStores {
+            doc_store: server_state.doc_store.clone(),
+            blob_store: server_state.blob_store.clone(),
+        }));
+
+        // Start background maintenance
+        {
+            let mut tasks = self.maintenance_tasks.write().await;
+            tasks.push(trash::spawn_purge_task(
+                server_state.doc_store.clone(),
+                server_state.blob_store.clone(),
+                config.trash_retention_days,
+            ));
+            let state_for_watch = server_state.clone();
+            match watcher::spawn_watch_task(
+                server_state.raw_storage.as_ref(),
+                server_state.store_sync.clone(),
+                move |changes| state_for_watch.broadcast_doc_changes(changes),
+            ) {
+                Ok(task) => tasks.push(task),
+                Err(e) => log::warn!("External changes will not be picked up: {}", e),
+            }
+            if config.blob_gc.enabled {
+                tasks.push(blob_gc::spawn_gc_task(
+                    server_state.doc_store.clone(),
+                    server_state.blob_store.clone(),
+                    config.blob_gc.clone(),
+                ));
+            }
+            if config.blob_scrub.enabled {
+                tasks.push(blob_scrub::spawn_scrub_task(
+                    server_state.doc_store.clone(),
+                    server_state.blob_store.clone(),
+                    config.blob_scrub.clone(),
+                ));
+            }
+            if config.backups.enabled {
+                tasks.push(backup::spawn_backup_task(
+                    Arc::new(BackupStore::new(&app_data_dir)),
+                    server_state.raw_storage.clone(),
+                    server_state.doc_store.clone(),
+                    config.backups.clone(),
+                ));
+            }
+        }
 
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
 
@@ -392,36 +720,7 @@ impl WebSocketServer {
             *tx = Some(shutdown_tx);
         }
 
-        // Create CORS layer
-        let cors = CorsLayer::new()
-            .allow_origin(Any)
-            .allow_methods(Any)
-            .allow_headers(Any);
-
-        // Create router with WebSocket and blob endpoints
-        let app = Router::new()
-            .route("/ws", get(ws_handler))
-            .route("/health", get(health_handler))
-            .route("/api/blobs/:hash", post(blob_upload_handler))
-            .route("/api/blobs/:hash", get(blob_download_handler))
-            .route("/api/blobs/:hash", head(blob_exists_handler))
-            .with_state(server_state)
-            .layer(cors);
-
-        // Bind address based on network mode
-        let bind_addr = match config.network_mode {
-            NetworkMode::Localhost => format!("127.0.0.1:{}", port),
-            NetworkMode::Lan => format!("0.0.0.0:{}", port),
-        };
-
-        let listener = tokio::net::TcpListener::bind(&bind_addr)
-            .await
-            .map_err(|e| format!("Failed to bind to {}: {}", bind_addr, e))?;
-
-        let actual_port = listener
-            .local_addr()
-            .map_err(|e| format!("Failed to get local address: {}", e))?
-            .port();
+        let app = router(server_state);
 
         // Update state
         self.running.store(true, Ordering::Relaxed);
@@ -481,21 +780,177 @@ impl WebSocketServer {
             let _ = shutdown_tx.send(());
         }
 
+        for task in self.maintenance_tasks.write().await.drain(..) {
+            task.abort();
+        }
+
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
         self.running.store(false, Ordering::Relaxed);
         self.port.store(0, Ordering::Relaxed);
+        self.publish_stores(None);
         *self.state.write().await = None;
 
         log::info!("WebSocket server stop requested");
         Ok(())
     }
 
+    /// Storage usage per user and quota limits (for the host's admin UI)
+    pub async fn usage_report(&self) -> Option<UsageReport> {
+        self.state.read().await.as_ref().map(|state| state.usage_report())
+    }
+
+    /// A page of stored blobs with their uploaders and documents (for the
+    /// host's admin UI)
+    pub async fn blob_inventory(&self, query: BlobQuery) -> Option<BlobPage> {
+        self.state.read().await.as_ref().map(|state| state.blob_inventory(&query))
+    }
+
+    /// Blob storage per uploader and per document (for the host's admin UI)
+    pub async fn blob_usage(&self) -> Option<BlobUsageSummary> {
+        self.state.read().await.as_ref().map(|state| state.blob_usage())
+    }
+
+    /// Re-encrypt team storage under a new key and record the new key
+    /// source in the config. Returns the number of values re-encrypted.
+    pub async fn rekey_storage(&self, source: KeySource, passphrase: Option<String>) -> Result<usize, String> {
+        let encryption = self
+            .state
+            .read()
+            .await
+            .as_ref()
+            .ok_or("Server not running")?
+            .encryption
+            .clone()
+            .ok_or("Team storage is not encrypted")?;
+        let secret = match &source {
+            KeySource::Passphrase => KeySecret::Passphrase(passphrase.ok_or("A new passphrase is required")?),
+            KeySource::KeyFile { path } => KeySecret::KeyFile(path.clone()),
+        };
+        let count = tokio::task::spawn_blocking(move || encryption.rekey(&secret))
+            .await
+            .map_err(|e| format!("Re-key task failed: {}", e))??;
+
+        // The old key no longer opens the storage, so the next start (and
+        // the MCP server) must see the new source
+        let mut config = self.config.write().await;
+        config.encryption = Some(source);
+        if let Some(dir) = self.app_data_dir.read().await.as_ref() {
+            config
+                .save(dir)
+                .map_err(|e| format!("Storage was re-keyed, but the new key source was not saved: {}", e))?;
+        }
+        Ok(count)
+    }
+
+    /// Collect unreferenced blobs now, or only report them with `dry_run`
+    pub async fn collect_blob_garbage(&self, dry_run: bool) -> Result<GcReport, String> {
+        let state = self.state.read().await.clone().ok_or("Server not running")?;
+        tokio::task::spawn_blocking(move || blob_gc::run(&state.doc_store, &state.blob_store, &state.blob_gc, dry_run))
+            .await
+            .map_err(|e| format!("Blob collection task failed: {}", e))?
+    }
+
+    /// Verify every stored blob against its hash and the index, and with
+    /// `repair` quarantine corrupt blobs and re-index unindexed ones
+    pub async fn scrub_blobs(&self, repair: bool) -> Result<ScrubReport, String> {
+        let state = self.state.read().await.clone().ok_or("Server not running")?;
+        tokio::task::spawn_blocking(move || blob_scrub::scrub(&state.doc_store, &state.blob_store, repair))
+            .await
+            .map_err(|e| format!("Blob scrub task failed: {}", e))?
+    }
+
+    /// Back up the team store now. All storage writes (including blob
+    /// uploads, user changes and MCP) are paused during the snapshot.
+    pub async fn create_backup(&self) -> Result<BackupInfo, String> {
+        let app_data_dir = self.app_data_dir.read().await.clone().ok_or("App data directory not set")?;
+        let running = self
+            .state
+            .read()
+            .await
+            .as_ref()
+            .map(|state| (state.raw_storage.clone(), state.doc_store.clone()));
+        tokio::task::spawn_blocking(move || {
+            let backups = BackupStore::new(&app_data_dir);
+            match running {
+                Some((storage, doc_store)) => backups.create(storage.as_ref(), Some(&doc_store), BackupKind::Manual),
+                None => backups.create(storage::open_configured(&app_data_dir)?.as_ref(), None, BackupKind::Manual),
+            }
+        })
+        .await
+        .map_err(|e| format!("Backup task failed: {}", e))?
+    }
+
+    /// Backups on disk, newest first
+    pub async fn list_backups(&self) -> Result<Vec<BackupInfo>, String> {
+        let app_data_dir = self.app_data_dir.read().await.clone().ok_or("App data directory not set")?;
+        tokio::task::spawn_blocking(move || BackupStore::new(&app_data_dir).list())
+            .await
+            .map_err(|e| format!("Backup task failed: {}", e))
+    }
+
+    /// Check a backup's entries against its manifest
+    pub async fn verify_backup(&self, id: String) -> Result<VerifyReport, String> {
+        let app_data_dir = self.app_data_dir.read().await.clone().ok_or("App data directory not set")?;
+        tokio::task::spawn_blocking(move || BackupStore::new(&app_data_dir).verify(&id))
+            .await
+            .map_err(|e| format!("Backup task failed: {}", e))?
+    }
+
+    /// Replace the team store with a backup (server must be stopped). The
+    /// current data is backed up first so the restore can be undone.
+    pub async fn restore_backup(&self, id: String) -> Result<BackupInfo, String> {
+        if self.is_running() {
+            return Err("Stop the server before restoring a backup".to_string());
+        }
+        let app_data_dir = self.app_data_dir.read().await.clone().ok_or("App data directory not set")?;
+        let user_store = self.user_store.read().await.clone();
+        tokio::task::spawn_blocking(move || {
+            let backups = BackupStore::new(&app_data_dir);
+            let storage = storage::open_configured(&app_data_dir)?;
+            // Verifies the archive and takes a pre-restore backup first
+            let info = backups.restore(&id, storage.as_ref())?;
+            if let Some(user_store) = user_store {
+                user_store.reload()?;
+            }
+            Ok(info)
+        })
+        .await
+        .map_err(|e| format!("Backup task failed: {}", e))?
+    }
+
     /// Get the document store (for direct access)
     pub async fn get_doc_store(&self) -> Option<Arc<DocumentStore>> {
         self.state.read().await.as_ref().map(|s| s.doc_store.clone())
     }
 
+    /// Tell clients about a document the MCP server changed, without waiting
+    /// for the watcher. Writes made through the shared stores are already in
+    /// the index, so a storage sync does not report them; they are broadcast
+    /// directly.
+    pub async fn document_changed(&self, doc_id: &str) {
+        let Some(state) = self.state.read().await.clone() else {
+            return;
+        };
+        let sync = state.store_sync.clone();
+        let mut changes = match tokio::task::spawn_blocking(move || sync.sync()).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                log::warn!("Storage sync task failed: {}", e);
+                Vec::new()
+            }
+        };
+        if !changes.iter().any(|change| change.doc_id == doc_id) {
+            let metadata = state.doc_store.get_metadata(doc_id);
+            changes.push(DocChange {
+                doc_id: doc_id.to_string(),
+                event_type: if metadata.is_some() { DocEventType::Updated } else { DocEventType::Deleted },
+                metadata,
+            });
+        }
+        state.broadcast_doc_changes(changes);
+    }
+
     /// Broadcast a document event to all connected clients
     /// Used when documents are saved via Tauri commands (not WebSocket)
     pub async fn broadcast_doc_event(&self, doc_id: &str, event_type: DocEventType, user_id: Option<String>) {
@@ -540,9 +995,132 @@ fn extract_jwt_from_headers(headers: &HeaderMap, jwt_secret: &str) -> Result<Jwt
         .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Invalid token: {}", e)))
 }
 
-/// Upload a blob (POST /api/blobs/:hash)
+/// Whether the caller can read at least one document using a blob.
+/// Blobs they cannot read are reported as missing, not forbidden, so hashes
+/// cannot be probed.
+fn can_read_blob(state: &ServerState, hash: &str, claims: &JwtClaims) -> bool {
+    user_can_read_blob(state, hash, Some(&claims.sub), Some(&claims.role))
+}
+
+fn user_can_read_blob(state: &ServerState, hash: &str, user_id: Option<&str>, role: Option<&str>) -> bool {
+    state.blob_store.get_metadata(hash).is_some_and(|metadata| {
+        metadata
+            .documents
+            .iter()
+            .any(|doc_id| check_read_permission(&state.doc_store, doc_id, user_id, role).is_ok())
+    })
+}
+
+/// Refuse a write that would make `doc_id` reference a stored blob the
+/// writer cannot already read. Referencing documents grant read access to a
+/// blob, so knowing its hash must not be enough. Blobs uploaded for this
+/// document are accepted, and hashes without a stored blob grant nothing.
+fn check_added_blob_references(
+    state: &ServerState,
+    doc_id: &str,
+    document: &serde_json::Value,
+    user_id: Option<&str>,
+    role: Option<&str>,
+) -> Result<(), String> {
+    let existing = state.doc_store.document_blob_references(doc_id).unwrap_or_default();
+    for hash in documents::blob_references(document) {
+        if existing.contains(&hash) {
+            continue;
+        }
+        let Some(metadata) = state.blob_store.get_metadata(&hash) else {
+            continue;
+        };
+        if !metadata.documents.iter().any(|id| id == doc_id) && !user_can_read_blob(state, &hash, user_id, role) {
+            return Err(format!("{}: No access to blob {}", error_codes::ACCESS_DENIED, hash));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the caller may upload blobs for `doc_id`: they must be able to
+/// edit it, unless it has not been saved yet
+fn authorize_blob_upload(state: &ServerState, claims: &JwtClaims, doc_id: &str) -> Result<(), (StatusCode, String)> {
+    match check_write_permission(&state.doc_store, doc_id, Some(&claims.sub), Some(&claims.role)) {
+        Ok(_) | Err(permissions::PermissionError::DocumentNotFound) => Ok(()),
+        Err(e) => Err((StatusCode::FORBIDDEN, e.to_string())),
+    }
+}
+
+/// Query parameters of a blob upload
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlobUploadParams {
+    /// Document the blob is embedded in
+    doc_id: Option<String>,
+}
+
+/// Upload a blob (POST /api/blobs/:hash?docId=...)
+///
+/// The caller must be able to edit the target document, unless it has not
+/// been saved yet. The blob is readable through that document from then on.
+/// The body is buffered, so large files should use an upload session
+/// (`/api/blobs/uploads`) instead.
+/// Router with the WebSocket and blob endpoints
+fn router(server_state: Arc<ServerState>) -> Router {
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(Any);
+
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/health", get(health_handler))
+        .route("/api/blobs/uploads", post(upload_create_handler))
+        .route(
+            "/api/blobs/uploads/:upload_id",
+            get(upload_status_handler)
+                .patch(upload_chunk_handler)
+                .delete(upload_abort_handler)
+                .layer(DefaultBodyLimit::max(uploads::MAX_CHUNK_SIZE)),
+        )
+        .route("/api/blobs/uploads/:upload_id/complete", post(upload_complete_handler))
+        .route(
+            "/api/blobs/:hash",
+            post(blob_upload_handler)
+                .layer(DefaultBodyLimit::max(
+                    usize::try_from(server_state.blob_policy.max_blob_bytes).unwrap_or(usize::MAX),
+                ))
+                .layer(axum::middleware::from_fn_with_state(
+                    server_state.clone(),
+                    reject_oversized_upload,
+                )),
+        )
+        .route("/api/blobs/:hash", get(blob_download_handler))
+        .route("/api/blobs/:hash", head(blob_exists_handler))
+        .route("/api/blobs/:hash/thumb", get(blob_thumbnail_handler))
+        .with_state(server_state)
+        .layer(cors)
+}
+
+/// Refuse a one-shot upload whose declared `Content-Length` exceeds the
+/// size limit before its body is read
+async fn reject_oversized_upload(
+    State(state): State<Arc<ServerState>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(size) = declared {
+        if let Err(e) = state.blob_policy.check_size(size) {
+            return (StatusCode::PAYLOAD_TOO_LARGE, e).into_response();
+        }
+    }
+    next.run(request).await
+}
+
 async fn blob_upload_handler(
     Path(hash): Path<String>,
+    Query(params): Query<BlobUploadParams>,
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
     body: axum::body::Bytes,
@@ -553,27 +1131,34 @@ async fn blob_upload_handler(
         Err((status, msg)) => return (status, msg).into_response(),
     };
 
-    // Extract MIME type from Content-Type header (default to application/octet-stream)
-    let mime_type = headers
+    let Some(doc_id) = params.doc_id.filter(|id| !id.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing docId query parameter".to_string()).into_response();
+    };
+    if let Err((status, msg)) = authorize_blob_upload(&state, &claims, &doc_id) {
+        return (status, msg).into_response();
+    }
+
+    // The declared Content-Type only disambiguates text; the content decides
+    if let Err(e) = state.blob_policy.check_size(body.len() as u64) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, e).into_response();
+    }
+    let declared = headers
         .get(header::CONTENT_TYPE)
         .and_then(|v| v.to_str().ok())
-        .unwrap_or("application/octet-stream")
-        .to_string();
+        .unwrap_or("");
+    let content = match state.blob_policy.resolve(&body, true, declared) {
+        Ok(content) => content,
+        Err(e) => return (StatusCode::UNSUPPORTED_MEDIA_TYPE, e).into_response(),
+    };
 
-    // Save blob with hash verification
-    match state.blob_store.save_blob(&hash, &body, &mime_type, &claims.sub) {
-        Ok(metadata) => {
-            let json = serde_json::json!({
-                "success": true,
-                "hash": metadata.hash,
-                "size": metadata.size,
-                "mimeType": metadata.mime_type,
-            });
-            (StatusCode::OK, axum::Json(json)).into_response()
-        }
+    // Save blob with hash verification; new blobs are checked against quotas
+    match state.blob_store.save_blob(&hash, &body, &content.mime_type, &claims.sub) {
+        Ok(metadata) => blob_uploaded_response(&state, metadata, &doc_id, &content).await,
         Err(e) => {
             if e.contains("Hash mismatch") {
                 (StatusCode::BAD_REQUEST, e).into_response()
+            } else if e.starts_with(error_codes::QUOTA_EXCEEDED) {
+                (StatusCode::INSUFFICIENT_STORAGE, e).into_response()
             } else {
                 (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
             }
@@ -581,46 +1166,209 @@ async fn blob_upload_handler(
     }
 }
 
+/// Associate a stored blob with the document it was uploaded for, record
+/// its content type, generate its preview and build the upload response
+async fn blob_uploaded_response(
+    state: &Arc<ServerState>,
+    metadata: blobs::BlobMetadata,
+    doc_id: &str,
+    content: &ContentType,
+) -> Response {
+    let recorded = state
+        .blob_store
+        .add_document(&metadata.hash, doc_id)
+        .and_then(|_| state.blob_store.set_content_type(&metadata.hash, &content.mime_type, content.active));
+    if let Err(e) = recorded {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+
+    let blob_store = state.blob_store.clone();
+    let hash = metadata.hash.clone();
+    let preview = match tokio::task::spawn_blocking(move || previews::ensure(&blob_store, &hash)).await {
+        Ok(Ok(preview)) => Some(preview),
+        Ok(Err(e)) => {
+            log::warn!("Failed to generate preview of blob {}: {}", metadata.hash, e);
+            None
+        }
+        Err(e) => {
+            log::warn!("Preview task for blob {} failed: {}", metadata.hash, e);
+            None
+        }
+    };
+
+    let json = serde_json::json!({
+        "success": true,
+        "hash": metadata.hash,
+        "size": metadata.size,
+        "mimeType": content.mime_type,
+        "preview": preview,
+    });
+    (StatusCode::OK, axum::Json(json)).into_response()
+}
+
+/// Blobs never change, but access to them can be revoked
+const BLOB_CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
+/// Sandbox for active content opened despite `Content-Disposition`, and
+/// for every SVG
+const ACTIVE_CONTENT_CSP: &str = "sandbox; default-src 'none'";
+
+/// Stream `len` bytes of a blob from `start`, reading a chunk at a time
+fn blob_body(blob_store: Arc<BlobStore>, hash: String, start: u64, len: u64) -> Body {
+    let step = blob_store.stream_chunk_size();
+    let stream = futures_util::stream::try_unfold(start, move |pos| {
+        let blob_store = blob_store.clone();
+        let hash = hash.clone();
+        let end = start + len;
+        async move {
+            if pos >= end {
+                return Ok(None);
+            }
+            let want = usize::try_from((end - pos).min(step)).unwrap_or(usize::MAX);
+            let chunk = tokio::task::spawn_blocking(move || blob_store.read_range(&hash, pos, want))
+                .await
+                .map_err(|e| format!("Blob read task failed: {}", e))??;
+            if chunk.is_empty() {
+                return Err("Blob is shorter than its recorded size".to_string());
+            }
+            let next = pos + chunk.len() as u64;
+            Ok(Some((axum::body::Bytes::from(chunk), next)))
+        }
+    });
+    Body::from_stream(stream)
+}
+
 /// Download a blob (GET /api/blobs/:hash)
+///
+/// Streams the content and supports single `Range` requests. The ETag is
+/// the content hash, so `If-None-Match` revalidation is answered with 304.
 async fn blob_download_handler(
     Path(hash): Path<String>,
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     // Validate JWT
-    if let Err((status, msg)) = extract_jwt_from_headers(&headers, &state.jwt_secret) {
-        return (status, msg).into_response();
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let metadata = match state.blob_store.get_metadata(&hash) {
+        Some(metadata) if can_read_blob(&state, &hash, &claims) => metadata,
+        _ => return (StatusCode::NOT_FOUND, format!("Blob not found: {}", hash)).into_response(),
+    };
+
+    let etag = ranges::etag(&hash);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| ranges::etag_matches(v, &etag));
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .header(header::CACHE_CONTROL, BLOB_CACHE_CONTROL)
+            .body(Body::empty())
+            .unwrap_or_else(|_| StatusCode::NOT_MODIFIED.into_response());
     }
 
-    // Get metadata for MIME type
-    let mime_type = state.blob_store
-        .get_metadata(&hash)
-        .map(|m| m.mime_type)
-        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let size = metadata.size;
+    let range = ranges::parse_range(headers.get(header::RANGE).and_then(|v| v.to_str().ok()), size);
+    let (status, start, len) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap_or_else(|_| StatusCode::RANGE_NOT_SATISFIABLE.into_response());
+        }
+    };
 
-    // Load blob
-    match state.blob_store.load_blob(&hash) {
-        Ok(data) => {
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, &metadata.mime_type)
+        .header(header::CONTENT_LENGTH, len)
+        .header(header::ETAG, etag)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, BLOB_CACHE_CONTROL)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+    // Never render content that can run scripts in the webview's origin
+    if blob_policy::is_active(&metadata) {
+        response = response.header(header::CONTENT_DISPOSITION, "attachment");
+    }
+    if blob_policy::needs_sandbox(&metadata) {
+        response = response.header(header::CONTENT_SECURITY_POLICY, ACTIVE_CONTENT_CSP);
+    }
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, start + len - 1, size));
+    }
+    response
+        .body(blob_body(state.blob_store.clone(), hash, start, len))
+        .unwrap_or_else(|_| {
             Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, mime_type)
-                .header(header::CONTENT_LENGTH, data.len())
-                .body(Body::from(data))
-                .unwrap_or_else(|_| {
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from("Failed to build response"))
-                        .unwrap()
-                })
-        }
-        Err(e) => {
-            if e.contains("not found") {
-                (StatusCode::NOT_FOUND, e).into_response()
-            } else {
-                (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
-            }
-        }
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to build response"))
+                .unwrap()
+        })
+}
+
+/// Query parameters of a thumbnail request
+#[derive(Debug, serde::Deserialize)]
+struct ThumbnailParams {
+    /// Wanted longest side in pixels
+    size: Option<u32>,
+}
+
+/// Serve a blob's thumbnail (GET /api/blobs/:hash/thumb?size=256)
+///
+/// Returns the stored PNG closest to the requested size (the mid size by
+/// default), generating thumbnails first for blobs stored without them.
+/// Blobs that are not raster images have none (404).
+async fn blob_thumbnail_handler(
+    Path(hash): Path<String>,
+    Query(params): Query<ThumbnailParams>,
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if !can_read_blob(&state, &hash, &claims) {
+        return (StatusCode::NOT_FOUND, format!("Blob not found: {}", hash)).into_response();
     }
+
+    let size = params.size.unwrap_or(previews::THUMBNAIL_SIZES[1]);
+    let blob_store = state.blob_store.clone();
+    let task_hash = hash.clone();
+    let (size, png) = match tokio::task::spawn_blocking(move || previews::thumbnail(&blob_store, &task_hash, size)).await {
+        Ok(Ok(Some(thumbnail))) => thumbnail,
+        Ok(Ok(None)) => return (StatusCode::NOT_FOUND, format!("No thumbnail for blob {}", hash)).into_response(),
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let etag = format!("\"{}-{}\"", hash, size);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| ranges::etag_matches(v, &etag));
+    let status = if not_modified { StatusCode::NOT_MODIFIED } else { StatusCode::OK };
+    let builder = Response::builder()
+        .status(status)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, BLOB_CACHE_CONTROL);
+    let response = if not_modified {
+        builder.body(Body::empty())
+    } else {
+        builder
+            .header(header::CONTENT_TYPE, "image/png")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::CONTENT_LENGTH, png.len())
+            .body(Body::from(png))
+    };
+    response.unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
 }
 
 /// Check if a blob exists (HEAD /api/blobs/:hash)
@@ -630,18 +1378,39 @@ async fn blob_exists_handler(
     headers: HeaderMap,
 ) -> impl IntoResponse {
     // Validate JWT
-    if let Err((status, msg)) = extract_jwt_from_headers(&headers, &state.jwt_secret) {
-        return (status, msg).into_response();
-    }
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
 
-    if state.blob_store.exists(&hash) {
+    if state.blob_store.exists(&hash) && can_read_blob(&state, &hash, &claims) {
         // Return metadata in headers if available
         if let Some(metadata) = state.blob_store.get_metadata(&hash) {
-            Response::builder()
+            let mut response = Response::builder()
                 .status(StatusCode::NO_CONTENT)
-                .header(header::CONTENT_TYPE, metadata.mime_type)
+                .header(header::CONTENT_TYPE, &metadata.mime_type)
                 .header(header::CONTENT_LENGTH, metadata.size)
-                .header("X-Blob-Created-At", metadata.created_at.to_string())
+                .header(header::ETAG, ranges::etag(&metadata.hash))
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .header("X-Blob-Created-At", metadata.created_at.to_string());
+            if blob_policy::is_active(&metadata) {
+                response = response.header(header::CONTENT_DISPOSITION, "attachment");
+            }
+            if blob_policy::needs_sandbox(&metadata) {
+                response = response.header(header::CONTENT_SECURITY_POLICY, ACTIVE_CONTENT_CSP);
+            }
+            let preview = metadata.preview.unwrap_or_default();
+            for (name, value) in [
+                ("X-Blob-Width", preview.width),
+                ("X-Blob-Height", preview.height),
+                ("X-Blob-Page-Count", preview.page_count),
+            ] {
+                if let Some(value) = value {
+                    response = response.header(name, value);
+                }
+            }
+            response
                 .body(Body::empty())
                 .unwrap_or_else(|_| {
                     Response::builder()
@@ -657,17 +1426,201 @@ async fn blob_exists_handler(
     }
 }
 
-/// WebSocket upgrade handler
-async fn ws_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<Arc<ServerState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+/// Body of an upload session request
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateUploadRequest {
+    hash: String,
+    size: u64,
+    mime_type: Option<String>,
+    /// Document the blob is embedded in
+    doc_id: String,
 }
 
-/// Handle an individual WebSocket connection
-async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
-    let (mut ws_sender, mut ws_receiver) = socket.split();
+/// Response for an upload session, with the offset to continue from
+fn upload_session_response(status: StatusCode, session: &uploads::UploadSession) -> Response {
+    let mut response = (status, axum::Json(session)).into_response();
+    if let Ok(offset) = header::HeaderValue::from_str(&session.offset.to_string()) {
+        response.headers_mut().insert(UPLOAD_OFFSET, offset);
+    }
+    response
+}
+
+/// Map an upload error to a response. A client that lost track of the
+/// offset gets it back with the conflict.
+fn upload_error_response(error: UploadError) -> Response {
+    let status = match &error {
+        UploadError::NotFound => StatusCode::NOT_FOUND,
+        UploadError::OffsetMismatch { .. } | UploadError::Incomplete { .. } => StatusCode::CONFLICT,
+        UploadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        UploadError::HashMismatch(_) => StatusCode::BAD_REQUEST,
+        UploadError::TypeNotAllowed(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        UploadError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+        UploadError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let mut response = (status, error.to_string()).into_response();
+    let offset = match error {
+        UploadError::OffsetMismatch { expected } => Some(expected),
+        UploadError::Incomplete { offset, .. } => Some(offset),
+        _ => None,
+    };
+    if let Some(offset) = offset.and_then(|o| header::HeaderValue::from_str(&o.to_string()).ok()) {
+        response.headers_mut().insert(UPLOAD_OFFSET, offset);
+    }
+    response
+}
+
+/// Header carrying the byte offset of an upload chunk
+const UPLOAD_OFFSET: &str = "upload-offset";
+
+/// Open or resume a chunked upload (POST /api/blobs/uploads)
+async fn upload_create_handler(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<CreateUploadRequest>,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if let Err((status, msg)) = authorize_blob_upload(&state, &claims, &request.doc_id) {
+        return (status, msg).into_response();
+    }
+    if let Err(e) = state.blob_policy.check_size(request.size) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, e).into_response();
+    }
+    if let Err(e) = state.blob_policy.check_declared(request.mime_type.as_deref().unwrap_or("")) {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, e).into_response();
+    }
+    // Refused early so nothing is staged; completing the upload checks again
+    if let Err(e) = state.blob_store.check_upload(&request.hash, &claims.sub, request.size) {
+        return (StatusCode::INSUFFICIENT_STORAGE, e).into_response();
+    }
+
+    let mime_type = request.mime_type.as_deref().unwrap_or("application/octet-stream");
+    match state.uploads.create(&request.hash, request.size, mime_type, &request.doc_id, &claims.sub) {
+        Ok(session) => upload_session_response(StatusCode::OK, &session),
+        Err(e) => upload_error_response(e),
+    }
+}
+
+/// Report how much of an upload has arrived (GET /api/blobs/uploads/:upload_id)
+async fn upload_status_handler(
+    Path(upload_id): Path<String>,
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    match state.uploads.get(&upload_id, &claims.sub) {
+        Ok(session) => upload_session_response(StatusCode::OK, &session),
+        Err(e) => upload_error_response(e),
+    }
+}
+
+/// Append a chunk at the `Upload-Offset` header's position
+/// (PATCH /api/blobs/uploads/:upload_id)
+async fn upload_chunk_handler(
+    Path(upload_id): Path<String>,
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let Some(offset) = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing or invalid Upload-Offset header".to_string()).into_response();
+    };
+
+    let uploads = state.uploads.clone();
+    match tokio::task::spawn_blocking(move || uploads.append(&upload_id, &claims.sub, offset, &body)).await {
+        Ok(Ok(session)) => upload_session_response(StatusCode::OK, &session),
+        Ok(Err(e)) => upload_error_response(e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Verify a fully received upload and store the blob
+/// (POST /api/blobs/uploads/:upload_id/complete)
+async fn upload_complete_handler(
+    Path(upload_id): Path<String>,
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    // Access to the document may have changed since the session was opened
+    let session = match state.uploads.get(&upload_id, &claims.sub) {
+        Ok(session) => session,
+        Err(e) => return upload_error_response(e),
+    };
+    if let Err((status, msg)) = authorize_blob_upload(&state, &claims, &session.doc_id) {
+        return (status, msg).into_response();
+    }
+
+    let task_state = state.clone();
+    let completed = tokio::task::spawn_blocking(move || {
+        let uploads = &task_state.uploads;
+        // Markup is scanned in full when it fits, otherwise assumed active
+        let head = uploads.peek(&upload_id, &claims.sub, blob_policy::MARKUP_SCAN_BYTES)?;
+        let complete = session.size <= blob_policy::MARKUP_SCAN_BYTES as u64;
+        let content = match task_state.blob_policy.resolve(&head, complete, &session.mime_type) {
+            Ok(content) => content,
+            Err(e) => {
+                // The content cannot change, so resuming would not help
+                uploads.abort(&upload_id, &claims.sub)?;
+                return Err(UploadError::TypeNotAllowed(e));
+            }
+        };
+        let (session, metadata) = uploads.complete(&upload_id, &claims.sub, &task_state.blob_store, &content.mime_type)?;
+        Ok((session, metadata, content))
+    })
+    .await;
+    match completed {
+        Ok(Ok((session, metadata, content))) => blob_uploaded_response(&state, metadata, &session.doc_id, &content).await,
+        Ok(Err(e)) => upload_error_response(e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Discard an upload (DELETE /api/blobs/uploads/:upload_id)
+async fn upload_abort_handler(
+    Path(upload_id): Path<String>,
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match extract_jwt_from_headers(&headers, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    match state.uploads.abort(&upload_id, &claims.sub) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => upload_error_response(UploadError::NotFound),
+        Err(e) => upload_error_response(e),
+    }
+}
+
+/// WebSocket upgrade handler
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<ServerState>>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+/// Handle an individual WebSocket connection
+async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
+    let (mut ws_sender, mut ws_receiver) = socket.split();
 
     // Create channel for sending messages to this client
     let (tx, mut rx) = mpsc::channel::<Vec<u8>>(100);
@@ -783,10 +1736,38 @@ async fn handle_message(client_id: u64, msg_type: u8, data: &[u8], state: &Arc<S
         MESSAGE_DOC_LIST => handle_doc_list(client_id, data, state).await,
         MESSAGE_DOC_GET => handle_doc_get(client_id, data, state).await,
         MESSAGE_DOC_SAVE => handle_doc_save(client_id, data, state).await,
+        MESSAGE_DOC_PATCH => handle_doc_patch(client_id, data, state).await,
         MESSAGE_DOC_DELETE => handle_doc_delete(client_id, data, state).await,
         MESSAGE_JOIN_DOC => handle_join_doc(client_id, data, state).await,
         MESSAGE_DOC_SHARE => handle_doc_share(client_id, data, state).await,
         MESSAGE_DOC_TRANSFER => handle_doc_transfer(client_id, data, state).await,
+        MESSAGE_DOC_HISTORY => handle_doc_history(client_id, data, state).await,
+        MESSAGE_DOC_VERSION_GET => handle_doc_version_get(client_id, data, state).await,
+        MESSAGE_DOC_VERSION_RESTORE => handle_doc_version_restore(client_id, data, state).await,
+        MESSAGE_TRASH_LIST => handle_trash_list(client_id, data, state).await,
+        MESSAGE_TRASH_RESTORE => handle_trash_restore(client_id, data, state).await,
+        MESSAGE_SEARCH => handle_search(client_id, data, state).await,
+        MESSAGE_FOLDER_LIST => handle_folder_list(client_id, data, state).await,
+        MESSAGE_FOLDER_CREATE => handle_folder_create(client_id, data, state).await,
+        MESSAGE_FOLDER_RENAME => handle_folder_rename(client_id, data, state).await,
+        MESSAGE_FOLDER_MOVE => handle_folder_move(client_id, data, state).await,
+        MESSAGE_FOLDER_DELETE => handle_folder_delete(client_id, data, state).await,
+        MESSAGE_FOLDER_SHARES => handle_folder_shares(client_id, data, state).await,
+        MESSAGE_DOC_MOVE => handle_doc_move(client_id, data, state).await,
+        MESSAGE_DOC_TAGS => handle_doc_tags(client_id, data, state).await,
+        MESSAGE_DOC_DUPLICATE => handle_doc_duplicate(client_id, data, state).await,
+        MESSAGE_PAGE_COPY => handle_page_copy(client_id, data, state).await,
+        MESSAGE_PAGE_GET => handle_page_get(client_id, data, state).await,
+        MESSAGE_PAGE_CREATE => handle_page_create(client_id, data, state).await,
+        MESSAGE_PAGE_RENAME => handle_page_rename(client_id, data, state).await,
+        MESSAGE_PAGE_REORDER => handle_page_reorder(client_id, data, state).await,
+        MESSAGE_PAGE_DELETE => handle_page_delete(client_id, data, state).await,
+        MESSAGE_TEMPLATE_LIST => handle_template_list(client_id, data, state).await,
+        MESSAGE_TEMPLATE_SAVE => handle_template_save(client_id, data, state).await,
+        MESSAGE_TEMPLATE_DELETE => handle_template_delete(client_id, data, state).await,
+        MESSAGE_DOC_FROM_TEMPLATE => handle_doc_from_template(client_id, data, state).await,
+        MESSAGE_USAGE_REPORT => handle_usage_report(client_id, data, state).await,
+        MESSAGE_BLOB_INVENTORY => handle_blob_inventory(client_id, data, state).await,
         _ => {
             log::warn!("Unknown message type {} from client {}", msg_type, client_id);
         }
@@ -938,10 +1919,7 @@ fn validate_jwt(token: &str, secret: &str) -> Result<JwtClaims, String> {
     ).map_err(|e| format!("JWT validation failed: {}", e))?;
 
     // Check expiration
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
+    let now = crate::clock::now_secs();
 
     if token_data.claims.exp < now {
         return Err("Token expired".to_string());
@@ -1065,7 +2043,7 @@ async fn handle_doc_get(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
     let response = match state.doc_store.get_document(&request.doc_id) {
         Ok(doc) => DocGetResponse {
             request_id: request.request_id,
-            document: Some(doc),
+            document: Some(if request.skeleton { pages::skeleton(&doc) } else { doc }),
             error: None,
         },
         Err(e) => DocGetResponse {
@@ -1122,6 +2100,10 @@ async fn handle_doc_save(client_id: u64, data: &[u8], state: &Arc<ServerState>)
                 request_id: request.request_id,
                 success: false,
                 error: Some(to_error_string(&perm_err)),
+                server_version: None,
+                document: None,
+                conflicts: None,
+                repairs: None,
             };
             if let Ok(data) = encode_message(MESSAGE_DOC_SAVE, &response) {
                 send_to_client(client_id, data, state).await;
@@ -1130,14 +2112,53 @@ async fn handle_doc_save(client_id: u64, data: &[u8], state: &Arc<ServerState>)
         }
     }
 
-    let response = match state.doc_store.save_document(request.document) {
-        Ok(()) => {
+    let saved = match state.doc_store.save_document_with_base(
+        request.document,
+        request.base_version,
+        state.validation_mode,
+        |document| check_added_blob_references(state, &doc_id, document, user_id.as_deref(), role.as_deref()),
+    ) {
+        Ok(SaveOutcome::Saved { metadata, repairs }) => {
+            // Send the repaired document back so the client can adopt it
+            let document = if repairs.is_empty() {
+                None
+            } else {
+                state.doc_store.get_document(&doc_id).ok()
+            };
+            Ok((metadata, document, repairs))
+        }
+        Ok(SaveOutcome::Merged { metadata, document, repairs }) => Ok((metadata, Some(document), repairs)),
+        Ok(SaveOutcome::Conflict { server_version, conflicts }) => {
+            let response = DocSaveResponse {
+                request_id: request.request_id,
+                success: false,
+                error: Some(format!(
+                    "{}: Document was modified concurrently ({} conflicting change(s))",
+                    error_codes::VERSION_CONFLICT,
+                    conflicts.len()
+                )),
+                server_version: Some(server_version),
+                document: None,
+                conflicts: Some(conflicts),
+                repairs: None,
+            };
+            if let Ok(data) = encode_message(MESSAGE_DOC_SAVE, &response) {
+                send_to_client(client_id, data, state).await;
+            }
+            return;
+        }
+        Err(e) => Err(e),
+    };
+
+    let response = match saved {
+        Ok((metadata, stored_document, repairs)) => {
+            let server_version = metadata.server_version;
+
             // Broadcast document event to all clients
-            let metadata = state.doc_store.get_metadata(&doc_id);
             let event = DocEvent {
                 event_type: if doc_exists { DocEventType::Updated } else { DocEventType::Created },
                 doc_id: doc_id.clone(),
-                metadata,
+                metadata: Some(metadata),
                 user_id: user_id_for_event,
             };
 
@@ -1149,12 +2170,20 @@ async fn handle_doc_save(client_id: u64, data: &[u8], state: &Arc<ServerState>)
                 request_id: request.request_id,
                 success: true,
                 error: None,
+                server_version: Some(server_version),
+                document: stored_document,
+                conflicts: None,
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
             }
         }
         Err(e) => DocSaveResponse {
             request_id: request.request_id,
             success: false,
             error: Some(e),
+            server_version: None,
+            document: None,
+            conflicts: None,
+            repairs: None,
         },
     };
 
@@ -1163,6 +2192,117 @@ async fn handle_doc_save(client_id: u64, data: &[u8], state: &Arc<ServerState>)
     }
 }
 
+/// Handle incremental document patch request
+///
+/// Applies RFC 6902 operations against the stored document if the client's
+/// base version matches. The patch is relayed to clients viewing the document
+/// and a metadata-only `DocEvent` goes to everyone else's document list.
+async fn handle_doc_patch(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocPatchRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc patch request: {}", e);
+            return;
+        }
+    };
+
+    // Get user info for permission check and event
+    let (user_id, role) = client_identity(client_id, state).await;
+    let user_id_for_event = user_id.clone().unwrap_or_default();
+
+    // Patches only apply to existing documents, so always check write permission
+    if let Err(perm_err) = check_write_permission(
+        &state.doc_store,
+        &request.doc_id,
+        user_id.as_deref(),
+        role.as_deref(),
+    ) {
+        let response = DocPatchResponse {
+            request_id: request.request_id,
+            success: false,
+            server_version: None,
+            error: Some(to_error_string(&perm_err)),
+            document: None,
+            repairs: None,
+        };
+        if let Ok(data) = encode_message(MESSAGE_DOC_PATCH, &response) {
+            send_to_client(client_id, data, state).await;
+        }
+        return;
+    }
+
+    let response = match state.doc_store.apply_patch(
+        &request.doc_id,
+        request.base_version,
+        &request.operations,
+        state.validation_mode,
+        |document| check_added_blob_references(state, &request.doc_id, document, user_id.as_deref(), role.as_deref()),
+    ) {
+        Ok((metadata, repairs)) => {
+            let server_version = metadata.server_version;
+
+            // Relay the patch to other clients editing this document. A
+            // repaired document no longer matches the operations, so they
+            // reload it instead when the `DocEvent` below is newer than theirs.
+            if repairs.is_empty() {
+                let patch_event = DocPatchEvent {
+                    doc_id: request.doc_id.clone(),
+                    base_version: request.base_version,
+                    server_version,
+                    operations: request.operations,
+                    user_id: user_id_for_event.clone(),
+                };
+                if let Ok(event_data) = encode_message(MESSAGE_DOC_PATCH_EVENT, &patch_event) {
+                    state.broadcast_to_doc(&request.doc_id, event_data, Some(client_id));
+                }
+            }
+
+            // Refresh document lists; clients that applied the patch can skip
+            // reloading since metadata.serverVersion matches what they hold
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: request.doc_id.clone(),
+                metadata: Some(metadata),
+                user_id: user_id_for_event,
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, Some(client_id));
+            }
+
+            // Send the repaired document back so the client can adopt it
+            let document = if repairs.is_empty() {
+                None
+            } else {
+                state.doc_store.get_document(&request.doc_id).ok()
+            };
+            DocPatchResponse {
+                request_id: request.request_id,
+                success: true,
+                server_version: Some(server_version),
+                error: None,
+                document,
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
+            }
+        }
+        Err(e) => DocPatchResponse {
+            request_id: request.request_id,
+            success: false,
+            // Report the current version so the client can rebase
+            server_version: state
+                .doc_store
+                .get_metadata(&request.doc_id)
+                .map(|m| m.server_version),
+            error: Some(e),
+            document: None,
+            repairs: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_DOC_PATCH, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
 /// Handle document delete request
 async fn handle_doc_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
     let request: DocDeleteRequest = match decode_payload(data) {
@@ -1174,11 +2314,12 @@ async fn handle_doc_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>
     };
 
     // Get user info for permission check and event
-    let (user_id, role) = {
+    let (user_id, username, role) = {
         let clients = state.clients.read().await;
         let client = clients.get(&client_id);
         (
             client.and_then(|c| c.user_id.clone()),
+            client.and_then(|c| c.username.clone()),
             client.and_then(|c| c.role.clone()),
         )
     };
@@ -1202,7 +2343,11 @@ async fn handle_doc_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>
         return;
     }
 
-    let response = match state.doc_store.delete_document(&request.doc_id) {
+    let response = match state.doc_store.delete_document(
+        &request.doc_id,
+        user_id.as_deref(),
+        username.as_deref(),
+    ) {
         Ok(deleted) => {
             if deleted {
                 // Broadcast delete event
@@ -1413,53 +2558,1565 @@ async fn handle_doc_transfer(client_id: u64, data: &[u8], state: &Arc<ServerStat
     }
 }
 
-/// Send data to a specific client
-async fn send_to_client(client_id: u64, data: Vec<u8>, state: &Arc<ServerState>) {
-    let clients = state.clients.read().await;
-    if let Some(client) = clients.get(&client_id) {
-        let _ = client.tx.send(data).await;
-    }
-}
+/// Handle document version history list request
+async fn handle_doc_history(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocHistoryRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc history request: {}", e);
+            return;
+        }
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    let (user_id, role) = client_identity(client_id, state).await;
 
-    #[tokio::test]
-    async fn test_server_lifecycle() {
-        let server = WebSocketServer::new();
+    // Anyone who can read the document can browse its history
+    let result = check_read_permission(
+        &state.doc_store,
+        &request.doc_id,
+        user_id.as_deref(),
+        role.as_deref(),
+    )
+    .map_err(|e| to_error_string(&e))
+    .and_then(|_| state.doc_store.list_versions(&request.doc_id));
 
-        // Set app data dir for test
-        let temp_dir = tempfile::tempdir().unwrap();
-        server.set_app_data_dir(temp_dir.path().to_path_buf()).await;
+    let response = match result {
+        Ok(versions) => DocHistoryResponse {
+            request_id: request.request_id,
+            success: true,
+            versions,
+            error: None,
+        },
+        Err(e) => DocHistoryResponse {
+            request_id: request.request_id,
+            success: false,
+            versions: Vec::new(),
+            error: Some(e),
+        },
+    };
 
-        assert!(!server.is_running());
+    if let Ok(data) = encode_message(MESSAGE_DOC_HISTORY, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
 
-        // Start server on random port
-        let result = server.start(0).await;
-        assert!(result.is_ok());
-        assert!(server.is_running());
+/// Handle request for a document at a recorded version
+async fn handle_doc_version_get(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocVersionGetRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc version get request: {}", e);
+            return;
+        }
+    };
 
-        let status = server.status().await;
-        assert!(status.running);
-        assert!(status.port > 0);
+    let (user_id, role) = client_identity(client_id, state).await;
 
-        // Stop server
-        let result = server.stop().await;
-        assert!(result.is_ok());
+    let result = check_read_permission(
+        &state.doc_store,
+        &request.doc_id,
+        user_id.as_deref(),
+        role.as_deref(),
+    )
+    .map_err(|e| to_error_string(&e))
+    .and_then(|_| state.doc_store.get_version(&request.doc_id, request.server_version));
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
-        assert!(!server.is_running());
+    let response = match result {
+        Ok(doc) => DocVersionGetResponse {
+            request_id: request.request_id,
+            document: Some(doc),
+            error: None,
+        },
+        Err(e) => DocVersionGetResponse {
+            request_id: request.request_id,
+            document: None,
+            error: Some(e),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_DOC_VERSION_GET, &response) {
+        send_to_client(client_id, data, state).await;
     }
+}
 
-    #[tokio::test]
-    async fn test_server_status() {
-        let server = WebSocketServer::new();
+/// Handle request to restore a recorded document version
+async fn handle_doc_version_restore(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocVersionRestoreRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc version restore request: {}", e);
+            return;
+        }
+    };
 
-        let status = server.status().await;
-        assert!(!status.running);
-        assert_eq!(status.port, 0);
-        assert_eq!(status.connected_clients, 0);
-        assert!(status.address.is_empty());
+    let (user_id, username, role) = client_identity_named(client_id, state).await;
+
+    // Restoring overwrites the current content, so it needs edit access
+    let result = check_write_permission(
+        &state.doc_store,
+        &request.doc_id,
+        user_id.as_deref(),
+        role.as_deref(),
+    )
+    .map_err(|e| to_error_string(&e))
+    .and_then(|_| {
+        state.doc_store.restore_version(
+            &request.doc_id,
+            request.server_version,
+            user_id.as_deref(),
+            username.as_deref(),
+        )
+    });
+
+    let response = match result {
+        Ok(metadata) => {
+            let server_version = metadata.server_version;
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: request.doc_id.clone(),
+                metadata: Some(metadata),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+
+            DocVersionRestoreResponse {
+                request_id: request.request_id,
+                success: true,
+                server_version: Some(server_version),
+                error: None,
+            }
+        }
+        Err(e) => DocVersionRestoreResponse {
+            request_id: request.request_id,
+            success: false,
+            server_version: None,
+            error: Some(e),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_DOC_VERSION_RESTORE, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle trash list request
+async fn handle_trash_list(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: TrashListRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode trash list request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+
+    let response = match user_id {
+        Some(user_id) => {
+            // Only owners (and admins, who get implicit Owner) see trashed docs
+            let entries = state
+                .doc_store
+                .list_trash()
+                .into_iter()
+                .filter(|entry| {
+                    get_user_permission(&entry.metadata, &user_id, role.as_deref())
+                        == Permission::Owner
+                })
+                .collect();
+            TrashListResponse {
+                request_id: request.request_id,
+                entries,
+                error: None,
+            }
+        }
+        None => TrashListResponse {
+            request_id: request.request_id,
+            entries: Vec::new(),
+            error: Some(format!(
+                "{}: Authentication required",
+                error_codes::NOT_AUTHENTICATED
+            )),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_TRASH_LIST, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle trash restore request
+async fn handle_trash_restore(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: TrashRestoreRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode trash restore request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+
+    let result = check_trash_permission(
+        &state.doc_store,
+        &request.doc_id,
+        user_id.as_deref(),
+        role.as_deref(),
+    )
+    .map_err(|e| to_error_string(&e))
+    .and_then(|_| state.doc_store.restore_from_trash(&request.doc_id));
+
+    let response = match result {
+        Ok(metadata) => {
+            // The document reappears in everyone's list
+            let event = DocEvent {
+                event_type: DocEventType::Created,
+                doc_id: request.doc_id.clone(),
+                metadata: Some(metadata),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+
+            TrashRestoreResponse {
+                request_id: request.request_id,
+                success: true,
+                error: None,
+            }
+        }
+        Err(e) => TrashRestoreResponse {
+            request_id: request.request_id,
+            success: false,
+            error: Some(e),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_TRASH_RESTORE, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Default and maximum number of hits returned by a search
+const SEARCH_DEFAULT_LIMIT: usize = 50;
+const SEARCH_MAX_LIMIT: usize = 500;
+
+/// Handle full-text search request
+///
+/// Only documents the caller can read are searched, so the hit count and
+/// snippets never reveal anything about other documents.
+async fn handle_search(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: SearchRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode search request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+
+    let response = match user_id {
+        Some(user_id) => {
+            let limit = request
+                .limit
+                .unwrap_or(SEARCH_DEFAULT_LIMIT)
+                .min(SEARCH_MAX_LIMIT);
+            let hits = state.doc_store.search(&request.query, limit, |doc_id| {
+                check_read_permission(&state.doc_store, doc_id, Some(&user_id), role.as_deref()).is_ok()
+            });
+            SearchResponse {
+                request_id: request.request_id,
+                hits,
+                error: None,
+            }
+        }
+        None => SearchResponse {
+            request_id: request.request_id,
+            hits: Vec::new(),
+            error: Some(format!(
+                "{}: Authentication required",
+                error_codes::NOT_AUTHENTICATED
+            )),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_SEARCH, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Get the (user_id, role) of a connected client
+async fn client_identity(client_id: u64, state: &Arc<ServerState>) -> (Option<String>, Option<String>) {
+    let clients = state.clients.read().await;
+    let client = clients.get(&client_id);
+    (
+        client.and_then(|c| c.user_id.clone()),
+        client.and_then(|c| c.role.clone()),
+    )
+}
+
+/// Get the (user_id, username, role) of a connected client
+async fn client_identity_named(
+    client_id: u64,
+    state: &Arc<ServerState>,
+) -> (Option<String>, Option<String>, Option<String>) {
+    let clients = state.clients.read().await;
+    let client = clients.get(&client_id);
+    (
+        client.and_then(|c| c.user_id.clone()),
+        client.and_then(|c| c.username.clone()),
+        client.and_then(|c| c.role.clone()),
+    )
+}
+
+/// Handle folder list request
+///
+/// Folders are visible to every authenticated user; document visibility is
+/// still governed by per-document permissions.
+async fn handle_folder_list(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderListRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder list request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, _) = client_identity(client_id, state).await;
+    let response = match user_id {
+        Some(_) => FolderListResponse {
+            request_id: request.request_id,
+            folders: state.doc_store.list_folders(),
+            error: None,
+        },
+        None => FolderListResponse {
+            request_id: request.request_id,
+            folders: Vec::new(),
+            error: Some(format!(
+                "{}: Authentication required",
+                error_codes::NOT_AUTHENTICATED
+            )),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_FOLDER_LIST, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Check that a client may manage an existing folder
+fn check_folder_access(
+    state: &ServerState,
+    folder_id: &str,
+    user_id: Option<&str>,
+    role: Option<&str>,
+) -> Result<(), String> {
+    let user_id = match user_id {
+        Some(id) if !id.is_empty() => id,
+        _ => return Err(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+    };
+    let folder = state.doc_store.get_folder(folder_id).ok_or_else(|| {
+        format!("{}: Folder '{}' not found", error_codes::FOLDER_NOT_FOUND, folder_id)
+    })?;
+    if can_manage_folder(&folder, user_id, role) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}: Only the folder owner or an admin can change this folder",
+            error_codes::ACCESS_DENIED
+        ))
+    }
+}
+
+/// Reply to a folder change and broadcast it on success
+async fn send_folder_result(
+    client_id: u64,
+    state: &Arc<ServerState>,
+    msg_type: u8,
+    request_id: String,
+    result: Result<Folder, String>,
+    event_type: FolderEventType,
+    user_id: Option<String>,
+) {
+    let response = match result {
+        Ok(folder) => {
+            let event = FolderEvent {
+                event_type,
+                folder: folder.clone(),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_FOLDER_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+            FolderResponse {
+                request_id,
+                success: true,
+                error: None,
+                folder: Some(folder),
+            }
+        }
+        Err(e) => FolderResponse {
+            request_id,
+            success: false,
+            error: Some(e),
+            folder: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(msg_type, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle folder create request
+async fn handle_folder_create(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderCreateRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder create request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, username) = {
+        let clients = state.clients.read().await;
+        let client = clients.get(&client_id);
+        (
+            client.and_then(|c| c.user_id.clone()),
+            client.and_then(|c| c.username.clone()),
+        )
+    };
+
+    let result = match &user_id {
+        Some(id) => state.doc_store.create_folder(
+            &request.name,
+            request.parent_id.as_deref(),
+            Some((id, username.as_deref().unwrap_or(""))),
+            &request.default_shares,
+        ),
+        None => Err(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+    };
+    send_folder_result(
+        client_id,
+        state,
+        MESSAGE_FOLDER_CREATE,
+        request.request_id,
+        result,
+        FolderEventType::Created,
+        user_id,
+    )
+    .await;
+}
+
+/// Handle folder rename request
+async fn handle_folder_rename(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderRenameRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder rename request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_folder_access(state, &request.folder_id, user_id.as_deref(), role.as_deref())
+        .and_then(|_| state.doc_store.rename_folder(&request.folder_id, &request.name));
+    send_folder_result(
+        client_id,
+        state,
+        MESSAGE_FOLDER_RENAME,
+        request.request_id,
+        result,
+        FolderEventType::Updated,
+        user_id,
+    )
+    .await;
+}
+
+/// Handle folder move request
+async fn handle_folder_move(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderMoveRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder move request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_folder_access(state, &request.folder_id, user_id.as_deref(), role.as_deref())
+        .and_then(|_| state.doc_store.move_folder(&request.folder_id, request.parent_id.as_deref()));
+    send_folder_result(
+        client_id,
+        state,
+        MESSAGE_FOLDER_MOVE,
+        request.request_id,
+        result,
+        FolderEventType::Updated,
+        user_id,
+    )
+    .await;
+}
+
+/// Handle folder default shares request
+async fn handle_folder_shares(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderSharesRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder shares request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_folder_access(state, &request.folder_id, user_id.as_deref(), role.as_deref())
+        .and_then(|_| {
+            state
+                .doc_store
+                .set_folder_default_shares(&request.folder_id, &request.default_shares)
+        });
+    send_folder_result(
+        client_id,
+        state,
+        MESSAGE_FOLDER_SHARES,
+        request.request_id,
+        result,
+        FolderEventType::Updated,
+        user_id,
+    )
+    .await;
+}
+
+/// Handle folder delete request
+///
+/// Documents in the folder are re-filed under its parent; each one gets a
+/// `DocEvent` so document lists pick up the new `folderId`.
+async fn handle_folder_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: FolderDeleteRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode folder delete request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_folder_access(state, &request.folder_id, user_id.as_deref(), role.as_deref())
+        .and_then(|_| state.doc_store.delete_folder(&request.folder_id));
+    let result = result.map(|(folder, moved)| {
+        for metadata in moved {
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: metadata.id.clone(),
+                metadata: Some(metadata),
+                user_id: user_id.clone().unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+        }
+        folder
+    });
+    send_folder_result(
+        client_id,
+        state,
+        MESSAGE_FOLDER_DELETE,
+        request.request_id,
+        result,
+        FolderEventType::Deleted,
+        user_id,
+    )
+    .await;
+}
+
+/// Reply to a document organize request and broadcast the new metadata
+async fn send_organize_result(
+    client_id: u64,
+    state: &Arc<ServerState>,
+    msg_type: u8,
+    request_id: String,
+    result: Result<documents::DocumentMetadata, String>,
+    user_id: Option<String>,
+) {
+    let response = match result {
+        Ok(metadata) => {
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: metadata.id.clone(),
+                metadata: Some(metadata.clone()),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+            DocOrganizeResponse {
+                request_id,
+                success: true,
+                error: None,
+                metadata: Some(metadata),
+            }
+        }
+        Err(e) => DocOrganizeResponse {
+            request_id,
+            success: false,
+            error: Some(e),
+            metadata: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(msg_type, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle document move request (requires edit permission on the document)
+async fn handle_doc_move(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocMoveRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc move request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_write_permission(&state.doc_store, &request.doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| {
+            state
+                .doc_store
+                .move_document(&request.doc_id, request.folder_id.as_deref())
+        });
+    send_organize_result(client_id, state, MESSAGE_DOC_MOVE, request.request_id, result, user_id).await;
+}
+
+/// Handle document tags request (requires edit permission on the document)
+async fn handle_doc_tags(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocTagsRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc tags request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_write_permission(&state.doc_store, &request.doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| state.doc_store.set_document_tags(&request.doc_id, &request.tags));
+    send_organize_result(client_id, state, MESSAGE_DOC_TAGS, request.request_id, result, user_id).await;
+}
+
+/// Handle document duplicate request
+///
+/// Anyone who can read a document can duplicate it; the copy belongs to
+/// the caller and does not carry over the source's shares.
+async fn handle_doc_duplicate(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocDuplicateRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc duplicate request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, username, role) = client_identity_named(client_id, state).await;
+
+    let result = check_read_permission(&state.doc_store, &request.doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| {
+            state.doc_store.duplicate_document(
+                &request.doc_id,
+                request.name.as_deref(),
+                (
+                    user_id.as_deref().unwrap_or_default(),
+                    username.as_deref().unwrap_or_default(),
+                ),
+                state.validation_mode,
+            )
+        });
+
+    let response = match result {
+        Ok((metadata, repairs)) => {
+            let event = DocEvent {
+                event_type: DocEventType::Created,
+                doc_id: metadata.id.clone(),
+                metadata: Some(metadata.clone()),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+            DocDuplicateResponse {
+                request_id: request.request_id,
+                success: true,
+                error: None,
+                metadata: Some(metadata),
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
+            }
+        }
+        Err(e) => DocDuplicateResponse {
+            request_id: request.request_id,
+            success: false,
+            error: Some(e),
+            metadata: None,
+            repairs: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_DOC_DUPLICATE, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle page copy request (read access to the source, edit access to
+/// the target)
+async fn handle_page_copy(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageCopyRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page copy request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_read_permission(&state.doc_store, &request.source_doc_id, user_id.as_deref(), role.as_deref())
+        .and_then(|_| {
+            check_write_permission(&state.doc_store, &request.target_doc_id, user_id.as_deref(), role.as_deref())
+        })
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| {
+            state
+                .doc_store
+                .copy_page(&request.source_doc_id, &request.page_id, &request.target_doc_id, state.validation_mode)
+        });
+
+    let response = match result {
+        Ok((metadata, page_id, repairs)) => {
+            let server_version = metadata.server_version;
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: metadata.id.clone(),
+                metadata: Some(metadata),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+            PageCopyResponse {
+                request_id: request.request_id,
+                success: true,
+                error: None,
+                page_id: Some(page_id),
+                server_version: Some(server_version),
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
+            }
+        }
+        Err(e) => PageCopyResponse {
+            request_id: request.request_id,
+            success: false,
+            error: Some(e),
+            page_id: None,
+            server_version: None,
+            repairs: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_PAGE_COPY, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle page get request (lazy loading after a skeleton DOC_GET)
+async fn handle_page_get(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageGetRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page get request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_read_permission(&state.doc_store, &request.doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| state.doc_store.get_document(&request.doc_id))
+        .and_then(|doc| {
+            let page = pages::get_page(&doc, &request.page_id)?;
+            Ok((page, doc.get("serverVersion").and_then(|v| v.as_u64())))
+        });
+
+    let response = match result {
+        Ok((page, server_version)) => PageGetResponse {
+            request_id: request.request_id,
+            page: Some(page),
+            server_version,
+            error: None,
+        },
+        Err(e) => PageGetResponse {
+            request_id: request.request_id,
+            page: None,
+            server_version: None,
+            error: Some(e),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_PAGE_GET, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle page create request
+async fn handle_page_create(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageCreateRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page create request: {}", e);
+            return;
+        }
+    };
+    run_page_edit(
+        client_id,
+        state,
+        MESSAGE_PAGE_CREATE,
+        request.request_id,
+        &request.doc_id,
+        PageEventType::Created,
+        |doc| {
+            let page = pages::create_page(doc, &request.name, request.index)?;
+            Ok(page.get("id").and_then(|v| v.as_str()).map(String::from))
+        },
+    )
+    .await;
+}
+
+/// Handle page rename request
+async fn handle_page_rename(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageRenameRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page rename request: {}", e);
+            return;
+        }
+    };
+    run_page_edit(
+        client_id,
+        state,
+        MESSAGE_PAGE_RENAME,
+        request.request_id,
+        &request.doc_id,
+        PageEventType::Renamed,
+        |doc| {
+            pages::rename_page(doc, &request.page_id, &request.name)?;
+            Ok(Some(request.page_id.clone()))
+        },
+    )
+    .await;
+}
+
+/// Handle page reorder request
+async fn handle_page_reorder(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageReorderRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page reorder request: {}", e);
+            return;
+        }
+    };
+    run_page_edit(
+        client_id,
+        state,
+        MESSAGE_PAGE_REORDER,
+        request.request_id,
+        &request.doc_id,
+        PageEventType::Reordered,
+        |doc| pages::reorder_pages(doc, &request.page_order).map(|_| None),
+    )
+    .await;
+}
+
+/// Handle page delete request
+async fn handle_page_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: PageDeleteRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode page delete request: {}", e);
+            return;
+        }
+    };
+    run_page_edit(
+        client_id,
+        state,
+        MESSAGE_PAGE_DELETE,
+        request.request_id,
+        &request.doc_id,
+        PageEventType::Deleted,
+        |doc| {
+            pages::delete_page(doc, &request.page_id)?;
+            Ok(Some(request.page_id.clone()))
+        },
+    )
+    .await;
+}
+
+/// Apply a page edit for a client with edit access and reply with a
+/// `PageResponse`. The change is relayed to other clients on the document
+/// as a `PageEvent` and to document lists as a metadata-only `DocEvent`.
+///
+/// `edit` returns the ID of the page it touched, if any.
+async fn run_page_edit(
+    client_id: u64,
+    state: &Arc<ServerState>,
+    message_type: u8,
+    request_id: String,
+    doc_id: &str,
+    event_type: PageEventType,
+    edit: impl FnOnce(&mut serde_json::Value) -> Result<Option<String>, String>,
+) {
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = check_write_permission(&state.doc_store, doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| {
+            state.doc_store.edit_pages(doc_id, state.validation_mode, |doc| {
+                let page_id = edit(doc)?;
+                let page = page_id
+                    .as_deref()
+                    .and_then(|id| pages::get_page(doc, id).ok())
+                    .map(|page| pages::summary(&page));
+                Ok((page_id, page, pages::page_order(doc)))
+            })
+        });
+
+    let response = match result {
+        Ok((metadata, (page_id, page, page_order), repairs)) => {
+            let server_version = metadata.server_version;
+            let user_id = user_id.unwrap_or_default();
+
+            let page_event = PageEvent {
+                event_type,
+                doc_id: doc_id.to_string(),
+                page_id: page_id.clone(),
+                page,
+                page_order,
+                server_version,
+                user_id: user_id.clone(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_PAGE_EVENT, &page_event) {
+                state.broadcast_to_doc(doc_id, event_data, Some(client_id));
+            }
+
+            let event = DocEvent {
+                event_type: DocEventType::Updated,
+                doc_id: doc_id.to_string(),
+                metadata: Some(metadata),
+                user_id,
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, Some(client_id));
+            }
+
+            PageResponse {
+                request_id,
+                success: true,
+                error: None,
+                page_id,
+                server_version: Some(server_version),
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
+            }
+        }
+        Err(e) => PageResponse {
+            request_id,
+            success: false,
+            error: Some(e),
+            page_id: None,
+            server_version: None,
+            repairs: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(message_type, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle template list request
+async fn handle_template_list(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: TemplateListRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode template list request: {}", e);
+            return;
+        }
+    };
+
+    let mut templates = state.doc_store.list_templates();
+    if let Some(category) = &request.category {
+        templates.retain(|t| t.category.eq_ignore_ascii_case(category));
+    }
+    let response = TemplateListResponse {
+        request_id: request.request_id,
+        templates,
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_TEMPLATE_LIST, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle template save request (read access to the document is enough)
+async fn handle_template_save(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: TemplateSaveRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode template save request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, username, role) = client_identity_named(client_id, state).await;
+
+    let result = check_read_permission(&state.doc_store, &request.doc_id, user_id.as_deref(), role.as_deref())
+        .map_err(|e| to_error_string(&e))
+        .and_then(|_| {
+            let details = TemplateDetails {
+                name: &request.name,
+                description: &request.description,
+                category: &request.category,
+            };
+            state.doc_store.save_as_template(
+                &request.doc_id,
+                details,
+                (
+                    user_id.as_deref().unwrap_or_default(),
+                    username.as_deref().unwrap_or_default(),
+                ),
+            )
+        });
+    send_template_result(client_id, state, MESSAGE_TEMPLATE_SAVE, request.request_id, result).await;
+}
+
+/// Handle template delete request (template owner or admin)
+async fn handle_template_delete(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: TemplateDeleteRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode template delete request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let result = match (user_id.as_deref(), state.doc_store.get_template(&request.template_id)) {
+        (None, _) => Err(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+        (_, None) => Err(format!(
+            "{}: Template '{}' not found",
+            error_codes::TEMPLATE_NOT_FOUND,
+            request.template_id
+        )),
+        (Some(user_id), Some(template)) if !can_manage_template(&template, user_id, role.as_deref()) => Err(format!(
+            "{}: Only the template owner or an admin can delete this template",
+            error_codes::ACCESS_DENIED
+        )),
+        _ => state.doc_store.delete_template(&request.template_id),
+    };
+    send_template_result(client_id, state, MESSAGE_TEMPLATE_DELETE, request.request_id, result).await;
+}
+
+/// Reply to a template change
+async fn send_template_result(
+    client_id: u64,
+    state: &Arc<ServerState>,
+    msg_type: u8,
+    request_id: String,
+    result: Result<templates::TemplateInfo, String>,
+) {
+    let response = match result {
+        Ok(template) => TemplateResponse {
+            request_id,
+            success: true,
+            error: None,
+            template: Some(template),
+        },
+        Err(e) => TemplateResponse {
+            request_id,
+            success: false,
+            error: Some(e),
+            template: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(msg_type, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle create-from-template request. The new document belongs to the
+/// caller and inherits the default shares of the folder it is filed in.
+async fn handle_doc_from_template(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: DocFromTemplateRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode doc from template request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, username) = {
+        let clients = state.clients.read().await;
+        let client = clients.get(&client_id);
+        (
+            client.and_then(|c| c.user_id.clone()),
+            client.and_then(|c| c.username.clone()),
+        )
+    };
+
+    let result = match &user_id {
+        Some(id) => state.doc_store.create_from_template(
+            &request.template_id,
+            request.name.as_deref(),
+            request.folder_id.as_deref(),
+            (id, username.as_deref().unwrap_or("")),
+            state.validation_mode,
+        ),
+        None => Err(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+    };
+
+    let response = match result {
+        Ok((metadata, repairs)) => {
+            let event = DocEvent {
+                event_type: DocEventType::Created,
+                doc_id: metadata.id.clone(),
+                metadata: Some(metadata.clone()),
+                user_id: user_id.unwrap_or_default(),
+            };
+            if let Ok(event_data) = encode_message(MESSAGE_DOC_EVENT, &event) {
+                state.broadcast_to_all(event_data, None);
+            }
+            DocDuplicateResponse {
+                request_id: request.request_id,
+                success: true,
+                error: None,
+                metadata: Some(metadata),
+                repairs: if repairs.is_empty() { None } else { Some(repairs) },
+            }
+        }
+        Err(e) => DocDuplicateResponse {
+            request_id: request.request_id,
+            success: false,
+            error: Some(e),
+            metadata: None,
+            repairs: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_DOC_FROM_TEMPLATE, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle storage usage report request. Admins see every user; other
+/// users only see their own row.
+async fn handle_usage_report(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: UsageReportRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode usage report request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let response = match user_id {
+        Some(user_id) => {
+            let mut report = state.usage_report();
+            if role.as_deref() != Some("admin") {
+                report.users.retain(|u| u.user_id == user_id);
+            }
+            UsageReportResponse {
+                request_id: request.request_id,
+                report: Some(report),
+                error: None,
+            }
+        }
+        None => UsageReportResponse {
+            request_id: request.request_id,
+            report: None,
+            error: Some(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_USAGE_REPORT, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Handle blob inventory request (admins only)
+async fn handle_blob_inventory(client_id: u64, data: &[u8], state: &Arc<ServerState>) {
+    let request: BlobInventoryRequest = match decode_payload(data) {
+        Ok(r) => r,
+        Err(e) => {
+            log::warn!("Failed to decode blob inventory request: {}", e);
+            return;
+        }
+    };
+
+    let (user_id, role) = client_identity(client_id, state).await;
+    let error = match (user_id, role.as_deref()) {
+        (None, _) => Some(format!("{}: Authentication required", error_codes::NOT_AUTHENTICATED)),
+        (Some(_), Some("admin")) => None,
+        (Some(_), _) => Some(format!("{}: Only admins can list blobs", error_codes::ACCESS_DENIED)),
+    };
+    let response = match error {
+        Some(error) => BlobInventoryResponse {
+            request_id: request.request_id,
+            page: None,
+            summary: None,
+            error: Some(error),
+        },
+        None => BlobInventoryResponse {
+            request_id: request.request_id,
+            page: Some(state.blob_inventory(&request.query)),
+            summary: request.summary.then(|| state.blob_usage()),
+            error: None,
+        },
+    };
+
+    if let Ok(data) = encode_message(MESSAGE_BLOB_INVENTORY, &response) {
+        send_to_client(client_id, data, state).await;
+    }
+}
+
+/// Send data to a specific client
+async fn send_to_client(client_id: u64, data: Vec<u8>, state: &Arc<ServerState>) {
+    let clients = state.clients.read().await;
+    if let Some(client) = clients.get(&client_id) {
+        let _ = client.tx.send(data).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_server_lifecycle() {
+        let server = WebSocketServer::new();
+
+        // Set app data dir for test
+        let temp_dir = tempfile::tempdir().unwrap();
+        server.set_app_data_dir(temp_dir.path().to_path_buf()).await;
+
+        assert!(!server.is_running());
+
+        // Start server on random port
+        let result = server.start(0).await;
+        assert!(result.is_ok());
+        assert!(server.is_running());
+
+        let status = server.status().await;
+        assert!(status.running);
+        assert!(status.port > 0);
+
+        // Stop server
+        let result = server.stop().await;
+        assert!(result.is_ok());
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        assert!(!server.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_failed_bind_leaves_nothing_running() {
+        let server = WebSocketServer::new();
+        let temp_dir = tempfile::tempdir().unwrap();
+        server.set_app_data_dir(temp_dir.path().to_path_buf()).await;
+
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        assert!(server.start(port).await.is_err());
+
+        assert!(!server.is_running());
+        assert!(server.state.read().await.is_none());
+        assert!(server.maintenance_tasks.read().await.is_empty());
+        assert!(server.shared_stores().read().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blob_upload_body_limit_follows_policy() {
+        use tower::ServiceExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = ServerConfig::default();
+        config.blob_policy.max_blob_bytes = 4 * 1024 * 1024;
+        let state = ServerState::new(
+            temp_dir.path().to_path_buf(),
+            &config,
+            None,
+            "secret".to_string(),
+            None,
+            TokenConfig::default(),
+        )
+        .unwrap();
+        let app = router(Arc::new(state));
+        let upload = |size: usize| {
+            axum::http::Request::post("/api/blobs/abc?docId=doc-1")
+                .header(header::CONTENT_LENGTH, size)
+                .body(Body::from(vec![0u8; size]))
+                .unwrap()
+        };
+
+        // Declared too large: refused before the body is read
+        let response = app.clone().oneshot(upload(5 * 1024 * 1024)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Above axum's 2 MB default but within the policy: reaches the handler
+        let response = app.oneshot(upload(3 * 1024 * 1024)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_rekey_survives_restart() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let server = WebSocketServer::new();
+        server.set_app_data_dir(temp_dir.path().to_path_buf()).await;
+        let mut config = ServerConfig::default();
+        config.network_mode = NetworkMode::Localhost;
+        config.encryption = Some(KeySource::KeyFile {
+            path: temp_dir.path().join("storage.key"),
+        });
+        server.set_config(config).await.unwrap();
+        server.start(0).await.unwrap();
+        server
+            .rekey_storage(KeySource::Passphrase, Some("correct horse battery staple".to_string()))
+            .await
+            .unwrap();
+        server.stop().await.unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        // A fresh process picks up the new key source from the saved config
+        let restarted = WebSocketServer::new();
+        restarted.set_app_data_dir(temp_dir.path().to_path_buf()).await;
+        assert_eq!(restarted.get_config().await.encryption, Some(KeySource::Passphrase));
+        restarted
+            .set_storage_passphrase("correct horse battery staple".to_string())
+            .await;
+        restarted.start(0).await.unwrap();
+        restarted.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_status() {
+        let server = WebSocketServer::new();
+
+        let status = server.status().await;
+        assert!(!status.running);
+        assert_eq!(status.port, 0);
+        assert_eq!(status.connected_clients, 0);
+        assert!(status.address.is_empty());
+    }
+
+    #[test]
+    fn test_blob_references_cannot_grant_access() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state = ServerState::new(
+            temp_dir.path().to_path_buf(),
+            &ServerConfig::default(),
+            None,
+            "secret".to_string(),
+            None,
+            TokenConfig::default(),
+        )
+        .unwrap();
+
+        // Alice uploads a file for her private document
+        let data = b"alice's file";
+        let hash = BlobStore::compute_hash(data);
+        state.blob_store.save_blob(&hash, data, "text/plain", "alice").unwrap();
+        state.blob_store.add_document(&hash, "doc-a").unwrap();
+        state
+            .doc_store
+            .save_document(serde_json::json!({
+                "id": "doc-a", "name": "A", "ownerId": "alice", "blobReferences": [hash]
+            }))
+            .unwrap();
+
+        // Mallory creates a document claiming the hash
+        let hijack = serde_json::json!({
+            "id": "doc-m", "name": "M", "ownerId": "mallory", "blobReferences": [hash]
+        });
+        let save = |document: serde_json::Value| {
+            state.doc_store.save_document_with_base(document, None, ValidationMode::Lenient, |document| {
+                check_added_blob_references(&state, "doc-m", document, Some("mallory"), Some("user"))
+            })
+        };
+        let err = save(hijack).unwrap_err();
+        assert!(err.starts_with(error_codes::ACCESS_DENIED));
+        assert!(state.doc_store.get_metadata("doc-m").is_none());
+
+        // ...nor can she add it to a document of her own with a patch
+        save(serde_json::json!({"id": "doc-m", "name": "M", "ownerId": "mallory"})).unwrap();
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "add", "path": "/blobReferences", "value": [hash]}
+        ]))
+        .unwrap();
+        let err = state
+            .doc_store
+            .apply_patch("doc-m", 1, &ops, ValidationMode::Lenient, |document| {
+                check_added_blob_references(&state, "doc-m", document, Some("mallory"), Some("user"))
+            })
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::ACCESS_DENIED));
+        assert!(!user_can_read_blob(&state, &hash, Some("mallory"), Some("user")));
+        assert_eq!(state.blob_store.get_metadata(&hash).unwrap().documents, vec!["doc-a".to_string()]);
+
+        // Alice can reference her own file from another document
+        let copy = serde_json::json!({"id": "doc-b", "ownerId": "alice", "blobReferences": [hash]});
+        assert!(check_added_blob_references(&state, "doc-b", &copy, Some("alice"), Some("user")).is_ok());
+    }
+
+    /// Register a connected client as `user_id` and return its receiver
+    async fn connect(
+        state: &Arc<ServerState>,
+        client_id: u64,
+        user_id: Option<&str>,
+        role: &str,
+    ) -> mpsc::Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(16);
+        state.clients.write().await.insert(client_id, ClientState {
+            id: client_id,
+            user_id: user_id.map(String::from),
+            username: user_id.map(String::from),
+            role: Some(role.to_string()),
+            current_doc_id: None,
+            authenticated: user_id.is_some(),
+            tx,
+        });
+        rx
+    }
+
+    /// Send one message as `client_id` and return the reply's payload
+    async fn request(
+        state: &Arc<ServerState>,
+        rx: &mut mpsc::Receiver<Vec<u8>>,
+        client_id: u64,
+        msg_type: u8,
+        payload: serde_json::Value,
+    ) -> serde_json::Value {
+        let data = encode_message(msg_type, &payload).unwrap();
+        handle_message(client_id, msg_type, &data, state).await;
+        let reply = rx.try_recv().expect("no reply");
+        assert_eq!(decode_message_type(&reply), Some(msg_type));
+        decode_payload(&reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_new_messages_are_denied_without_access() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(
+            ServerState::new(
+                temp_dir.path().to_path_buf(),
+                &ServerConfig::default(),
+                None,
+                "secret".to_string(),
+                None,
+                TokenConfig::default(),
+            )
+            .unwrap(),
+        );
+
+        // Alice's private document, folder, template and trashed document;
+        // Mallory only owns a document of her own
+        let doc = |id: &str, owner: &str| {
+            serde_json::json!({
+                "id": id,
+                "name": "Quarterly plan",
+                "ownerId": owner,
+                "pageOrder": ["p1", "p2"],
+                "pages": {
+                    "p1": {"id": "p1", "name": "One", "shapes": {}, "shapeOrder": []},
+                    "p2": {"id": "p2", "name": "Two", "shapes": {}, "shapeOrder": []}
+                }
+            })
+        };
+        let docs = &state.doc_store;
+        docs.save_document(doc("doc-a", "alice")).unwrap();
+        docs.save_document(doc("doc-t", "alice")).unwrap();
+        docs.delete_document("doc-t", Some("alice"), None).unwrap();
+        docs.save_document(doc("doc-m", "mallory")).unwrap();
+        let folder = docs.create_folder("Alice's", None, Some(("alice", "alice")), &[]).unwrap();
+        let details = TemplateDetails { name: "Plan", description: "", category: "" };
+        let template = docs.save_as_template("doc-a", details, ("alice", "alice")).unwrap();
+
+        let mut rx = connect(&state, 1, Some("mallory"), "user").await;
+        let denied = [
+            (MESSAGE_DOC_PATCH, serde_json::json!({
+                "requestId": "1", "docId": "doc-a", "baseVersion": 1,
+                "operations": [{"op": "replace", "path": "/name", "value": "Mine"}]
+            })),
+            (MESSAGE_PAGE_CREATE, serde_json::json!({"requestId": "1", "docId": "doc-a", "name": "New"})),
+            (MESSAGE_PAGE_RENAME, serde_json::json!({"requestId": "1", "docId": "doc-a", "pageId": "p1", "name": "X"})),
+            (MESSAGE_PAGE_REORDER, serde_json::json!({"requestId": "1", "docId": "doc-a", "pageOrder": ["p2", "p1"]})),
+            (MESSAGE_PAGE_DELETE, serde_json::json!({"requestId": "1", "docId": "doc-a", "pageId": "p2"})),
+            (MESSAGE_DOC_DUPLICATE, serde_json::json!({"requestId": "1", "docId": "doc-a"})),
+            // Neither from Alice's document nor into it
+            (MESSAGE_PAGE_COPY, serde_json::json!({
+                "requestId": "1", "sourceDocId": "doc-a", "pageId": "p1", "targetDocId": "doc-m"
+            })),
+            (MESSAGE_PAGE_COPY, serde_json::json!({
+                "requestId": "1", "sourceDocId": "doc-m", "pageId": "p1", "targetDocId": "doc-a"
+            })),
+            (MESSAGE_FOLDER_RENAME, serde_json::json!({"requestId": "1", "folderId": folder.id, "name": "Mine"})),
+            (MESSAGE_FOLDER_MOVE, serde_json::json!({"requestId": "1", "folderId": folder.id, "parentId": null})),
+            (MESSAGE_FOLDER_SHARES, serde_json::json!({
+                "requestId": "1", "folderId": folder.id,
+                "defaultShares": [{"userId": "mallory", "userName": "mallory", "permission": "editor"}]
+            })),
+            (MESSAGE_FOLDER_DELETE, serde_json::json!({"requestId": "1", "folderId": folder.id})),
+            (MESSAGE_TEMPLATE_SAVE, serde_json::json!({"requestId": "1", "docId": "doc-a", "name": "Stolen"})),
+            (MESSAGE_TEMPLATE_DELETE, serde_json::json!({"requestId": "1", "templateId": template.id})),
+            (MESSAGE_TRASH_RESTORE, serde_json::json!({"requestId": "1", "docId": "doc-t"})),
+            (MESSAGE_DOC_HISTORY, serde_json::json!({"requestId": "1", "docId": "doc-a"})),
+            (MESSAGE_BLOB_INVENTORY, serde_json::json!({"requestId": "1"})),
+        ];
+        for (msg_type, payload) in denied {
+            let reply = request(&state, &mut rx, 1, msg_type, payload).await;
+            assert_ne!(reply["success"], true, "message {} was allowed", msg_type);
+            let error = reply["error"].as_str().unwrap_or_default();
+            assert!(error.contains("_FORBIDDEN") || error.starts_with(error_codes::ACCESS_DENIED), "message {}: {}", msg_type, error);
+        }
+
+        // Listing messages only show Mallory her own data
+        let reply = request(&state, &mut rx, 1, MESSAGE_SEARCH, serde_json::json!({"requestId": "1", "query": "quarterly"})).await;
+        let hits: Vec<&str> = reply["hits"].as_array().unwrap().iter().filter_map(|h| h["docId"].as_str()).collect();
+        assert_eq!(hits, vec!["doc-m"]);
+        let reply = request(&state, &mut rx, 1, MESSAGE_TRASH_LIST, serde_json::json!({"requestId": "1"})).await;
+        assert_eq!(reply["entries"], serde_json::json!([]));
+        let reply = request(&state, &mut rx, 1, MESSAGE_USAGE_REPORT, serde_json::json!({"requestId": "1"})).await;
+        let users: Vec<&str> = reply["report"]["users"].as_array().unwrap().iter().filter_map(|u| u["userId"].as_str()).collect();
+        assert_eq!(users, vec!["mallory"]);
+
+        // Creating a folder needs a signed-in user
+        let mut anonymous = connect(&state, 2, None, "user").await;
+        let reply = request(&state, &mut anonymous, 2, MESSAGE_FOLDER_CREATE, serde_json::json!({"requestId": "1", "name": "Anon"})).await;
+        assert!(reply["error"].as_str().unwrap().starts_with(error_codes::NOT_AUTHENTICATED));
+
+        // Nothing was changed
+        let metadata = docs.get_metadata("doc-a").unwrap();
+        assert_eq!((metadata.server_version, metadata.name.as_str()), (1, "Quarterly plan"));
+        assert_eq!(docs.get_metadata("doc-m").unwrap().server_version, 1);
+        assert_eq!(docs.list_documents().len(), 2);
+        assert_eq!(docs.list_trash().len(), 1);
+        assert_eq!(docs.list_folders().len(), 1);
+        assert_eq!(docs.get_folder(&folder.id).unwrap().name, "Alice's");
+        assert_eq!(docs.list_templates().len(), 1);
+
+        // An admin gets the reports
+        let mut admin = connect(&state, 3, Some("root"), "admin").await;
+        let reply = request(&state, &mut admin, 3, MESSAGE_BLOB_INVENTORY, serde_json::json!({"requestId": "1"})).await;
+        assert!(reply["error"].is_null());
+        let reply = request(&state, &mut admin, 3, MESSAGE_USAGE_REPORT, serde_json::json!({"requestId": "1"})).await;
+        assert!(reply["report"]["users"].as_array().unwrap().len() >= 2);
     }
 }
@@ -0,0 +1,203 @@
+//! Page-level operations on team documents
+//!
+//! Large documents are opened as a skeleton (document fields, `pageOrder`
+//! and page names, no shapes) and their pages fetched on demand. The
+//! edits below change a single page entry plus `pageOrder` /
+//! `activePageId`, so clients that only hold some pages can apply them.
+
+use super::copy::new_id;
+use crate::clock::now_ms;
+use super::permissions::error_codes;
+use serde_json::{json, Value};
+
+/// Page fields kept in a skeleton
+const SKELETON_PAGE_FIELDS: [&str; 4] = ["id", "name", "createdAt", "modifiedAt"];
+
+fn page_not_found(page_id: &str) -> String {
+    format!("{}: Page {} not found", error_codes::PAGE_NOT_FOUND, page_id)
+}
+
+fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Page name must not be empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// The document's `pageOrder`
+pub fn page_order(doc: &Value) -> Vec<String> {
+    doc.get("pageOrder")
+        .and_then(|v| v.as_array())
+        .map(|ids| ids.iter().filter_map(|id| id.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
+
+/// A page's ID, name and timestamps, plus a `shapeCount`
+pub fn summary(page: &Value) -> Value {
+    let shape_count = page.get("shapes").and_then(|v| v.as_object()).map_or(0, |s| s.len());
+    let mut summary: serde_json::Map<String, Value> = page
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| SKELETON_PAGE_FIELDS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    summary.insert("shapeCount".to_string(), json!(shape_count));
+    Value::Object(summary)
+}
+
+/// The document with every page reduced to its `summary`
+pub fn skeleton(doc: &Value) -> Value {
+    let mut skeleton = doc.clone();
+    if let Some(pages) = skeleton.get_mut("pages").and_then(|v| v.as_object_mut()) {
+        for page in pages.values_mut() {
+            *page = summary(page);
+        }
+    }
+    skeleton
+}
+
+/// A single page of a document
+pub fn get_page(doc: &Value, page_id: &str) -> Result<Value, String> {
+    doc.get("pages")
+        .and_then(|pages| pages.get(page_id))
+        .cloned()
+        .ok_or_else(|| page_not_found(page_id))
+}
+
+/// Add an empty page at `index` in `pageOrder` (the end by default).
+/// Returns the new page.
+pub fn create_page(doc: &mut Value, name: &str, index: Option<usize>) -> Result<Value, String> {
+    let name = validate_name(name)?;
+    let mut order = page_order(doc);
+    let now = now_ms();
+    let id = new_id();
+    let page = json!({
+        "id": id,
+        "name": name,
+        "shapes": {},
+        "shapeOrder": [],
+        "createdAt": now,
+        "modifiedAt": now,
+    });
+
+    let obj = doc.as_object_mut().ok_or("Document is not a JSON object")?;
+    let pages = obj.entry("pages").or_insert_with(|| json!({}));
+    pages
+        .as_object_mut()
+        .ok_or("Document pages is not a JSON object")?
+        .insert(id.clone(), page.clone());
+    order.insert(index.unwrap_or(order.len()).min(order.len()), id.clone());
+    obj.insert("pageOrder".to_string(), json!(order));
+    if !obj.get("activePageId").is_some_and(|v| v.is_string()) {
+        obj.insert("activePageId".to_string(), json!(id));
+    }
+    Ok(page)
+}
+
+/// Rename a page
+pub fn rename_page(doc: &mut Value, page_id: &str, name: &str) -> Result<(), String> {
+    let name = validate_name(name)?;
+    let page = doc
+        .get_mut("pages")
+        .and_then(|pages| pages.get_mut(page_id))
+        .and_then(|page| page.as_object_mut())
+        .ok_or_else(|| page_not_found(page_id))?;
+    page.insert("name".to_string(), json!(name));
+    page.insert("modifiedAt".to_string(), json!(now_ms()));
+    Ok(())
+}
+
+/// Replace `pageOrder`; `order` must list every page exactly once
+pub fn reorder_pages(doc: &mut Value, order: &[String]) -> Result<(), String> {
+    let mut current = page_order(doc);
+    let mut requested = order.to_vec();
+    current.sort();
+    requested.sort();
+    if current != requested {
+        return Err("Page order must list every page of the document exactly once".to_string());
+    }
+    doc["pageOrder"] = json!(order);
+    Ok(())
+}
+
+/// Delete a page. The last page cannot be deleted; if the active page is
+/// deleted, its neighbour becomes active.
+pub fn delete_page(doc: &mut Value, page_id: &str) -> Result<(), String> {
+    let mut order = page_order(doc);
+    let position = order.iter().position(|id| id == page_id).ok_or_else(|| page_not_found(page_id))?;
+    if order.len() == 1 {
+        return Err("Cannot delete the only page of a document".to_string());
+    }
+    order.remove(position);
+
+    if let Some(pages) = doc.get_mut("pages").and_then(|v| v.as_object_mut()) {
+        pages.remove(page_id);
+    }
+    if doc.get("activePageId").and_then(|v| v.as_str()) == Some(page_id) {
+        doc["activePageId"] = json!(order[position.min(order.len() - 1)]);
+    }
+    doc["pageOrder"] = json!(order);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc() -> Value {
+        json!({
+            "id": "doc-1",
+            "name": "Doc",
+            "pageOrder": ["p1", "p2"],
+            "activePageId": "p2",
+            "pages": {
+                "p1": {"id": "p1", "name": "One", "shapes": {"s1": {"id": "s1"}}, "shapeOrder": ["s1"], "createdAt": 1, "modifiedAt": 1},
+                "p2": {"id": "p2", "name": "Two", "shapes": {}, "shapeOrder": [], "createdAt": 1, "modifiedAt": 1}
+            }
+        })
+    }
+
+    #[test]
+    fn test_skeleton_drops_page_contents() {
+        let skeleton = skeleton(&doc());
+        assert_eq!(skeleton["pageOrder"], json!(["p1", "p2"]));
+        assert_eq!(
+            skeleton["pages"]["p1"],
+            json!({"id": "p1", "name": "One", "createdAt": 1, "modifiedAt": 1, "shapeCount": 1})
+        );
+        assert_eq!(get_page(&doc(), "p1").unwrap()["shapeOrder"], json!(["s1"]));
+        assert!(get_page(&doc(), "p3").unwrap_err().starts_with(error_codes::PAGE_NOT_FOUND));
+    }
+
+    #[test]
+    fn test_create_rename_reorder() {
+        let mut doc = doc();
+        let page = create_page(&mut doc, "  Three ", Some(0)).unwrap();
+        let id = page["id"].as_str().unwrap().to_string();
+        assert_eq!(page["name"], "Three");
+        assert_eq!(doc["pageOrder"], json!([id, "p1", "p2"]));
+        assert!(create_page(&mut doc, " ", None).is_err());
+
+        rename_page(&mut doc, "p1", "First").unwrap();
+        assert_eq!(doc["pages"]["p1"]["name"], "First");
+        assert!(rename_page(&mut doc, "missing", "x").is_err());
+
+        reorder_pages(&mut doc, &["p2".to_string(), "p1".to_string(), id.clone()]).unwrap();
+        assert_eq!(doc["pageOrder"], json!(["p2", "p1", id]));
+        assert!(reorder_pages(&mut doc, &["p1".to_string(), "p2".to_string()]).is_err());
+        assert!(reorder_pages(&mut doc, &["p1".to_string(), "p1".to_string(), id]).is_err());
+    }
+
+    #[test]
+    fn test_delete_moves_active_page() {
+        let mut doc = doc();
+        delete_page(&mut doc, "p2").unwrap();
+        assert_eq!(doc["pageOrder"], json!(["p1"]));
+        assert_eq!(doc["activePageId"], "p1");
+        assert!(doc["pages"].get("p2").is_none());
+        assert!(delete_page(&mut doc, "p1").is_err());
+        assert!(delete_page(&mut doc, "p2").is_err());
+    }
+}
@@ -12,6 +12,8 @@
 //! - No implicit access for unshared documents
 
 use super::documents::{DocumentMetadata, DocumentStore};
+use super::folders::Folder;
+use super::templates::TemplateInfo;
 
 /// Permission levels for document access (ordered from most to least privileged)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -113,6 +115,24 @@ pub mod error_codes {
     pub const EDIT_FORBIDDEN: &str = "ERR_EDIT_FORBIDDEN";
     /// Permission level insufficient for view operation
     pub const VIEW_FORBIDDEN: &str = "ERR_VIEW_FORBIDDEN";
+    /// Update was based on a stale server version
+    pub const VERSION_CONFLICT: &str = "ERR_VERSION_CONFLICT";
+    /// Document schema is newer than this server understands
+    pub const UNSUPPORTED_VERSION: &str = "ERR_UNSUPPORTED_VERSION";
+    /// Document failed structural validation
+    pub const INVALID_DOCUMENT: &str = "ERR_INVALID_DOCUMENT";
+    /// Folder not found
+    pub const FOLDER_NOT_FOUND: &str = "ERR_FOLDER_NOT_FOUND";
+    /// Page not found in the document
+    pub const PAGE_NOT_FOUND: &str = "ERR_PAGE_NOT_FOUND";
+    /// Template not found
+    pub const TEMPLATE_NOT_FOUND: &str = "ERR_TEMPLATE_NOT_FOUND";
+    /// Change would exceed a storage quota
+    pub const QUOTA_EXCEEDED: &str = "ERR_QUOTA_EXCEEDED";
+    /// Blob is larger than the configured maximum
+    pub const BLOB_TOO_LARGE: &str = "ERR_BLOB_TOO_LARGE";
+    /// Blob content type is not on the allowlist
+    pub const BLOB_TYPE_NOT_ALLOWED: &str = "ERR_BLOB_TYPE_NOT_ALLOWED";
 }
 
 /// Get effective permission for a user on a document
@@ -213,6 +233,47 @@ pub fn check_delete_permission(
     check_permission(doc_store, doc_id, user_id, user_role, Permission::Owner)
 }
 
+/// Check permission to restore a trashed document (requires Owner, as
+/// recorded at deletion time)
+pub fn check_trash_permission(
+    doc_store: &DocumentStore,
+    doc_id: &str,
+    user_id: Option<&str>,
+    user_role: Option<&str>,
+) -> Result<Permission, PermissionError> {
+    let user_id = match user_id {
+        Some(id) if !id.is_empty() => id,
+        _ => return Err(PermissionError::NotAuthenticated),
+    };
+
+    let entry = doc_store
+        .get_trash_entry(doc_id)
+        .ok_or(PermissionError::DocumentNotFound)?;
+
+    let actual = get_user_permission(&entry.metadata, user_id, user_role);
+    if actual >= Permission::Owner {
+        Ok(actual)
+    } else {
+        Err(PermissionError::AccessDenied {
+            required: Permission::Owner,
+            actual,
+        })
+    }
+}
+
+/// Whether a user may rename, move, delete or set default shares on a
+/// folder: its creator, or an admin. Any authenticated user may create
+/// folders and file documents they can edit into any folder.
+pub fn can_manage_folder(folder: &Folder, user_id: &str, user_role: Option<&str>) -> bool {
+    user_role == Some("admin") || folder.owner_id.as_deref() == Some(user_id)
+}
+
+/// Whether a user may delete a template: its creator, or an admin. Any
+/// authenticated user may list templates and create documents from them.
+pub fn can_manage_template(template: &TemplateInfo, user_id: &str, user_role: Option<&str>) -> bool {
+    user_role == Some("admin") || template.owner_id.as_deref() == Some(user_id)
+}
+
 /// Convert PermissionError to protocol error string
 pub fn to_error_string(err: &PermissionError) -> String {
     match err {
@@ -269,6 +330,9 @@ mod tests {
             },
             last_modified_by: None,
             last_modified_by_name: None,
+            folder_id: None,
+            tags: None,
+            server_version: 0,
         }
     }
 
@@ -393,4 +457,39 @@ mod tests {
         assert_eq!(Permission::Viewer.as_str(), "view");
         assert_eq!(Permission::None.as_str(), "none");
     }
+
+    #[test]
+    fn test_folder_management() {
+        let folder = Folder {
+            id: "f1".to_string(),
+            name: "Team".to_string(),
+            parent_id: None,
+            owner_id: Some("user-1".to_string()),
+            owner_name: None,
+            default_shares: Vec::new(),
+            created_at: 0,
+            modified_at: 0,
+        };
+        assert!(can_manage_folder(&folder, "user-1", None));
+        assert!(can_manage_folder(&folder, "user-2", Some("admin")));
+        assert!(!can_manage_folder(&folder, "user-2", Some("user")));
+    }
+
+    #[test]
+    fn test_template_management() {
+        let template = TemplateInfo {
+            id: "t1".to_string(),
+            name: "Retro".to_string(),
+            description: String::new(),
+            category: String::new(),
+            owner_id: Some("user-1".to_string()),
+            owner_name: None,
+            source_doc_id: None,
+            page_count: 1,
+            created_at: 0,
+        };
+        assert!(can_manage_template(&template, "user-1", None));
+        assert!(can_manage_template(&template, "user-2", Some("admin")));
+        assert!(!can_manage_template(&template, "user-2", Some("user")));
+    }
 }
@@ -0,0 +1,219 @@
+//! Thumbnails and preview metadata for blobs
+//!
+//! Raster images are decoded with the pure-Rust `image` crate and scaled so
+//! their longest side fits each of [`THUMBNAIL_SIZES`] (never upscaled). The
+//! thumbnails are stored as PNG in the previews space, keyed
+//! `<hash>/<size>`, and served from `/api/blobs/:hash/thumb`, so documents
+//! can reference a preview instead of embedding a base64 one. Dimensions,
+//! and the page count of PDFs, are recorded as a [`BlobPreview`] in the
+//! blob's metadata.
+//!
+//! Previews are derived data: they are generated on upload, and on first
+//! request for blobs stored before that.
+
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+use super::blobs::BlobStore;
+
+/// Longest side in pixels of the generated thumbnails
+pub const THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+/// Blobs larger than this are not decoded
+const MAX_SOURCE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Images with a side longer than this are not decoded
+const MAX_DIMENSION: u32 = 16_384;
+
+/// Memory the decoder may allocate for one image
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// What is known about a blob's content
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobPreview {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Number of pages of a PDF
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<u32>,
+    /// Thumbnail sizes available, smallest first
+    #[serde(default)]
+    pub thumbnails: Vec<u32>,
+}
+
+/// Rendered thumbnails, as (size, PNG bytes)
+pub type Thumbnails = Vec<(u32, Vec<u8>)>;
+
+/// Decode a raster image and render its thumbnails
+fn analyze_image(data: &[u8]) -> Result<(BlobPreview, Thumbnails), String> {
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    let mut thumbnails = Vec::new();
+    for size in THUMBNAIL_SIZES {
+        let scaled = if image.width().max(image.height()) > size {
+            image.thumbnail(size, size)
+        } else {
+            image.clone()
+        };
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(scaled.to_rgba8())
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+        thumbnails.push((size, png));
+    }
+
+    let preview = BlobPreview {
+        width: Some(image.width()),
+        height: Some(image.height()),
+        page_count: None,
+        thumbnails: THUMBNAIL_SIZES.to_vec(),
+    };
+    Ok((preview, thumbnails))
+}
+
+/// Count the pages of a PDF
+fn pdf_page_count(data: &[u8]) -> Result<u32, String> {
+    let document = lopdf::Document::load_mem(data).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    Ok(document.get_pages().len() as u32)
+}
+
+/// Work out the preview of some content. Content that is not a supported
+/// image or PDF, or cannot be parsed, gets an empty preview.
+pub fn analyze(data: &[u8]) -> (BlobPreview, Thumbnails) {
+    let result = if data.starts_with(b"%PDF-") {
+        pdf_page_count(data).map(|pages| {
+            let preview = BlobPreview {
+                page_count: Some(pages),
+                ..Default::default()
+            };
+            (preview, Vec::new())
+        })
+    } else if image::guess_format(data).is_ok() {
+        analyze_image(data)
+    } else {
+        return Default::default();
+    };
+    result.unwrap_or_else(|e| {
+        log::warn!("No preview for blob: {}", e);
+        Default::default()
+    })
+}
+
+/// Generate and store the preview of a blob
+pub fn generate(blob_store: &BlobStore, hash: &str) -> Result<BlobPreview, String> {
+    let metadata = blob_store
+        .get_metadata(hash)
+        .ok_or_else(|| format!("Blob not found: {}", hash))?;
+    let (preview, thumbnails) = if metadata.size > MAX_SOURCE_BYTES {
+        Default::default()
+    } else {
+        analyze(&blob_store.load_blob(hash)?)
+    };
+    blob_store.save_preview(hash, &preview, thumbnails)?;
+    Ok(preview)
+}
+
+/// The stored preview of a blob, generating it first if there is none yet
+pub fn ensure(blob_store: &BlobStore, hash: &str) -> Result<BlobPreview, String> {
+    match blob_store.get_metadata(hash).and_then(|m| m.preview) {
+        Some(preview) => Ok(preview),
+        None => generate(blob_store, hash),
+    }
+}
+
+/// The thumbnail closest to `size`: the smallest at least that large, or the
+/// largest available. Returns the chosen size and PNG bytes, or `None` if
+/// the blob has no thumbnails.
+pub fn thumbnail(blob_store: &BlobStore, hash: &str, size: u32) -> Result<Option<(u32, Vec<u8>)>, String> {
+    let preview = ensure(blob_store, hash)?;
+    let chosen = preview
+        .thumbnails
+        .iter()
+        .copied()
+        .filter(|s| *s >= size)
+        .min()
+        .or_else(|| preview.thumbnails.iter().copied().max());
+    let Some(chosen) = chosen else {
+        return Ok(None);
+    };
+    Ok(blob_store.load_thumbnail(hash, chosen)?.map(|png| (chosen, png)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]));
+        let mut data = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_image_thumbnails() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().to_path_buf());
+        let data = png(600, 300);
+        let hash = BlobStore::compute_hash(&data);
+        store.save_blob(&hash, &data, "image/png", "u1").unwrap();
+
+        let preview = generate(&store, &hash).unwrap();
+        assert_eq!((preview.width, preview.height), (Some(600), Some(300)));
+        assert_eq!(preview.thumbnails, THUMBNAIL_SIZES.to_vec());
+        assert_eq!(store.get_metadata(&hash).unwrap().preview, Some(preview));
+
+        let (size, thumb) = thumbnail(&store, &hash, 200).unwrap().unwrap();
+        assert_eq!(size, 256);
+        let decoded = image::load_from_memory(&thumb).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 128));
+        assert_eq!(thumbnail(&store, &hash, 4000).unwrap().unwrap().0, 512);
+
+        // Thumbnails go with the blob
+        store.delete_blob(&hash).unwrap();
+        assert!(store.load_thumbnail(&hash, 128).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pdf_and_other_content() {
+        let mut document = lopdf::Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let kids: Vec<lopdf::Object> = (0..3)
+            .map(|_| {
+                document
+                    .add_object(lopdf::dictionary! { "Type" => "Page", "Parent" => pages_id })
+                    .into()
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            lopdf::Object::Dictionary(lopdf::dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 3 }),
+        );
+        let catalog_id = document.add_object(lopdf::dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog_id);
+        let mut pdf = Vec::new();
+        document.save_to(&mut pdf).unwrap();
+
+        let (preview, thumbnails) = analyze(&pdf);
+        assert_eq!(preview.page_count, Some(3));
+        assert!(thumbnails.is_empty());
+
+        assert_eq!(analyze(b"plain text"), Default::default());
+        assert_eq!(analyze(b"%PDF-1.4 truncated").0, BlobPreview::default());
+    }
+}
@@ -4,7 +4,17 @@
 //! the server and clients for team document synchronization.
 
 use serde::{Deserialize, Serialize};
+use super::blob_inventory::{BlobPage, BlobQuery, BlobUsageSummary};
 use super::documents::DocumentMetadata;
+use super::folders::Folder;
+use super::history::VersionInfo;
+use super::json_patch::PatchOperation;
+use super::trash::TrashEntry;
+use super::merge::MergeConflict;
+use super::quotas::UsageReport;
+use super::search::SearchHit;
+use super::templates::TemplateInfo;
+use super::validation::ValidationIssue;
 
 /// Message types for the sync protocol
 /// Must match the TypeScript MESSAGE_* constants in protocol.ts
@@ -22,6 +32,37 @@ pub const MESSAGE_JOIN_DOC: u8 = 10;
 pub const MESSAGE_AUTH_LOGIN: u8 = 11;
 pub const MESSAGE_DOC_SHARE: u8 = 12;
 pub const MESSAGE_DOC_TRANSFER: u8 = 13;
+pub const MESSAGE_DOC_PATCH: u8 = 14;
+pub const MESSAGE_DOC_PATCH_EVENT: u8 = 15;
+pub const MESSAGE_DOC_HISTORY: u8 = 16;
+pub const MESSAGE_DOC_VERSION_GET: u8 = 17;
+pub const MESSAGE_DOC_VERSION_RESTORE: u8 = 18;
+pub const MESSAGE_TRASH_LIST: u8 = 19;
+pub const MESSAGE_TRASH_RESTORE: u8 = 20;
+pub const MESSAGE_SEARCH: u8 = 21;
+pub const MESSAGE_FOLDER_LIST: u8 = 22;
+pub const MESSAGE_FOLDER_CREATE: u8 = 23;
+pub const MESSAGE_FOLDER_RENAME: u8 = 24;
+pub const MESSAGE_FOLDER_MOVE: u8 = 25;
+pub const MESSAGE_FOLDER_DELETE: u8 = 26;
+pub const MESSAGE_FOLDER_SHARES: u8 = 27;
+pub const MESSAGE_FOLDER_EVENT: u8 = 28;
+pub const MESSAGE_DOC_MOVE: u8 = 29;
+pub const MESSAGE_DOC_TAGS: u8 = 30;
+pub const MESSAGE_DOC_DUPLICATE: u8 = 31;
+pub const MESSAGE_PAGE_COPY: u8 = 32;
+pub const MESSAGE_PAGE_GET: u8 = 33;
+pub const MESSAGE_PAGE_CREATE: u8 = 34;
+pub const MESSAGE_PAGE_RENAME: u8 = 35;
+pub const MESSAGE_PAGE_REORDER: u8 = 36;
+pub const MESSAGE_PAGE_DELETE: u8 = 37;
+pub const MESSAGE_PAGE_EVENT: u8 = 38;
+pub const MESSAGE_TEMPLATE_LIST: u8 = 39;
+pub const MESSAGE_TEMPLATE_SAVE: u8 = 40;
+pub const MESSAGE_TEMPLATE_DELETE: u8 = 41;
+pub const MESSAGE_DOC_FROM_TEMPLATE: u8 = 42;
+pub const MESSAGE_USAGE_REPORT: u8 = 43;
+pub const MESSAGE_BLOB_INVENTORY: u8 = 44;
 
 /// Authentication request with JWT token (sent by client)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,6 +119,9 @@ pub struct DocListResponse {
 pub struct DocGetRequest {
     pub request_id: String,
     pub doc_id: String,
+    /// Return pages without their shapes (fetch them with PAGE_GET)
+    #[serde(default)]
+    pub skeleton: bool,
 }
 
 /// Document get response
@@ -97,6 +141,10 @@ pub struct DocGetResponse {
 pub struct DocSaveRequest {
     pub request_id: String,
     pub document: serde_json::Value,
+    /// `serverVersion` the client's copy was loaded at. When stale, the
+    /// server three-way merges instead of overwriting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_version: Option<u64>,
 }
 
 /// Document save response
@@ -107,6 +155,19 @@ pub struct DocSaveResponse {
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// `serverVersion` after the save (or current version on conflict)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    /// Stored document, present when the save was merged with newer changes
+    /// or structurally repaired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<serde_json::Value>,
+    /// Fields changed on both sides; present when the save was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflicts: Option<Vec<MergeConflict>>,
+    /// Structural problems fixed before saving (lenient validation mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<Vec<ValidationIssue>>,
 }
 
 /// Document delete request
@@ -204,6 +265,564 @@ pub struct DocTransferResponse {
     pub error: Option<String>,
 }
 
+/// Incremental document update (RFC 6902 JSON Patch)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocPatchRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    /// `serverVersion` the operations were computed against
+    pub base_version: u64,
+    pub operations: Vec<PatchOperation>,
+}
+
+/// Document patch response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocPatchResponse {
+    pub request_id: String,
+    pub success: bool,
+    /// New `serverVersion` after the patch (or current version on conflict)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stored document, present when the patched document was repaired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<serde_json::Value>,
+    /// Structural problems fixed before saving (lenient validation mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<Vec<ValidationIssue>>,
+}
+
+/// Patch broadcast to clients that joined the document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocPatchEvent {
+    pub doc_id: String,
+    pub base_version: u64,
+    pub server_version: u64,
+    pub operations: Vec<PatchOperation>,
+    pub user_id: String,
+}
+
+/// Document version history list request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocHistoryRequest {
+    pub request_id: String,
+    pub doc_id: String,
+}
+
+/// Document version history list response (newest first)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocHistoryResponse {
+    pub request_id: String,
+    pub success: bool,
+    pub versions: Vec<VersionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Request for the full document at a recorded version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocVersionGetRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub server_version: u64,
+}
+
+/// Document version get response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocVersionGetResponse {
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Request to restore a recorded version as the current document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocVersionRestoreRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub server_version: u64,
+}
+
+/// Document version restore response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocVersionRestoreResponse {
+    pub request_id: String,
+    pub success: bool,
+    /// New `serverVersion` created by the restore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Trash list request (returns entries the caller owns, or all for admins)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashListRequest {
+    pub request_id: String,
+}
+
+/// Trash list response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashListResponse {
+    pub request_id: String,
+    pub entries: Vec<TrashEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Restore a document from the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashRestoreRequest {
+    pub request_id: String,
+    pub doc_id: String,
+}
+
+/// Trash restore response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashRestoreResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Full-text search across the documents the caller can read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequest {
+    pub request_id: String,
+    pub query: String,
+    /// Maximum number of hits (server default if omitted)
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Search response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResponse {
+    pub request_id: String,
+    pub hits: Vec<SearchHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Folder list request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderListRequest {
+    pub request_id: String,
+}
+
+/// Folder list response (the whole tree, flat)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderListResponse {
+    pub request_id: String,
+    pub folders: Vec<Folder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Create a folder; the caller becomes its owner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderCreateRequest {
+    pub request_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Shares inherited by documents created in the folder
+    #[serde(default)]
+    pub default_shares: Vec<ShareEntry>,
+}
+
+/// Rename a folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderRenameRequest {
+    pub request_id: String,
+    pub folder_id: String,
+    pub name: String,
+}
+
+/// Move a folder under another one (no parent = top level)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderMoveRequest {
+    pub request_id: String,
+    pub folder_id: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+/// Delete a folder; its contents move to its parent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderDeleteRequest {
+    pub request_id: String,
+    pub folder_id: String,
+}
+
+/// Replace a folder's default shares
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSharesRequest {
+    pub request_id: String,
+    pub folder_id: String,
+    pub default_shares: Vec<ShareEntry>,
+}
+
+/// Response to folder create/rename/move/delete/shares
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The folder after the change (before removal, for deletes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<Folder>,
+}
+
+/// Folder event types
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FolderEventType {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// Folder change broadcast to all clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderEvent {
+    pub event_type: FolderEventType,
+    pub folder: Folder,
+    pub user_id: String,
+}
+
+/// File a document in a folder (no folder = top level)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocMoveRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+}
+
+/// Replace a document's tags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocTagsRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub tags: Vec<String>,
+}
+
+/// Response to DOC_MOVE and DOC_TAGS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocOrganizeResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<DocumentMetadata>,
+}
+
+/// Duplicate a document; the caller owns the copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocDuplicateRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    /// Name of the copy (defaults to "<name> (copy)")
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Response to DOC_DUPLICATE and DOC_FROM_TEMPLATE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocDuplicateResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Metadata of the new document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<DocumentMetadata>,
+    /// Structural problems fixed before saving (lenient validation mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<Vec<ValidationIssue>>,
+}
+
+/// Copy a page into another (or the same) document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageCopyRequest {
+    pub request_id: String,
+    pub source_doc_id: String,
+    pub page_id: String,
+    pub target_doc_id: String,
+}
+
+/// Page copy response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageCopyResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// ID of the new page in the target document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_id: Option<String>,
+    /// Target `serverVersion` after the copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    /// Structural problems fixed before saving (lenient validation mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<Vec<ValidationIssue>>,
+}
+
+/// Fetch a single page of a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageGetRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub page_id: String,
+}
+
+/// Page get response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageGetResponse {
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<serde_json::Value>,
+    /// Document `serverVersion` the page was read at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Add an empty page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageCreateRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub name: String,
+    /// Position in `pageOrder` (defaults to the end)
+    #[serde(default)]
+    pub index: Option<usize>,
+}
+
+/// Rename a page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageRenameRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub page_id: String,
+    pub name: String,
+}
+
+/// Replace a document's page order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageReorderRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub page_order: Vec<String>,
+}
+
+/// Delete a page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageDeleteRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub page_id: String,
+}
+
+/// Response to PAGE_CREATE, PAGE_RENAME, PAGE_REORDER and PAGE_DELETE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Page that was created, renamed or deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<u64>,
+    /// Structural problems fixed before saving (lenient validation mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<Vec<ValidationIssue>>,
+}
+
+/// Page event types
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PageEventType {
+    Created,
+    Renamed,
+    Reordered,
+    Deleted,
+}
+
+/// Page change broadcast to clients that joined the document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageEvent {
+    pub event_type: PageEventType,
+    pub doc_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_id: Option<String>,
+    /// Summary of the created or renamed page (no shapes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<serde_json::Value>,
+    /// `pageOrder` after the change
+    pub page_order: Vec<String>,
+    pub server_version: u64,
+    pub user_id: String,
+}
+
+/// Template list request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateListRequest {
+    pub request_id: String,
+    /// Only list templates in this category
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Template list response, sorted by category and name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateListResponse {
+    pub request_id: String,
+    pub templates: Vec<TemplateInfo>,
+}
+
+/// Save a copy of a document as a template; the caller owns the template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateSaveRequest {
+    pub request_id: String,
+    pub doc_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub category: String,
+}
+
+/// Delete a template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateDeleteRequest {
+    pub request_id: String,
+    pub template_id: String,
+}
+
+/// Response to TEMPLATE_SAVE and TEMPLATE_DELETE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateResponse {
+    pub request_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateInfo>,
+}
+
+/// Create a document from a template; the caller owns the new document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocFromTemplateRequest {
+    pub request_id: String,
+    pub template_id: String,
+    /// Name of the new document (defaults to the template name)
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Folder to file the new document in
+    #[serde(default)]
+    pub folder_id: Option<String>,
+}
+
+/// Storage usage report request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReportRequest {
+    pub request_id: String,
+}
+
+/// Storage usage report response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReportResponse {
+    pub request_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<UsageReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Blob inventory request (admins only)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobInventoryRequest {
+    pub request_id: String,
+    #[serde(default)]
+    pub query: BlobQuery,
+    /// Also return usage by uploader and by document
+    #[serde(default)]
+    pub summary: bool,
+}
+
+/// Blob inventory response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobInventoryResponse {
+    pub request_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<BlobPage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<BlobUsageSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 /// Error response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -277,6 +896,9 @@ mod tests {
                 shared_with: None,
                 last_modified_by: None,
                 last_modified_by_name: None,
+                folder_id: None,
+                tags: None,
+                server_version: 0,
             }),
             user_id: "user-1".to_string(),
         };
@@ -288,4 +910,45 @@ mod tests {
         assert_eq!(decoded.event_type, DocEventType::Created);
         assert_eq!(decoded.doc_id, "doc-1");
     }
+
+    #[test]
+    fn test_decode_doc_patch_request() {
+        let json = serde_json::json!({
+            "requestId": "req-1",
+            "docId": "doc-1",
+            "baseVersion": 3,
+            "operations": [
+                {"op": "replace", "path": "/name", "value": "Renamed"},
+                {"op": "move", "from": "/pageOrder/0", "path": "/pageOrder/1"}
+            ]
+        });
+        let mut encoded = vec![MESSAGE_DOC_PATCH];
+        encoded.extend(serde_json::to_vec(&json).unwrap());
+
+        let decoded: DocPatchRequest = decode_payload(&encoded).unwrap();
+        assert_eq!(decoded.base_version, 3);
+        assert_eq!(decoded.operations.len(), 2);
+        assert_eq!(decoded.operations[1].name(), "move");
+    }
+
+    #[test]
+    fn test_doc_get_skeleton_defaults_to_full_document() {
+        let mut encoded = vec![MESSAGE_DOC_GET];
+        encoded.extend(serde_json::to_vec(&serde_json::json!({"requestId": "req-1", "docId": "doc-1"})).unwrap());
+        let decoded: DocGetRequest = decode_payload(&encoded).unwrap();
+        assert!(!decoded.skeleton);
+
+        let event = PageEvent {
+            event_type: PageEventType::Reordered,
+            doc_id: "doc-1".to_string(),
+            page_id: None,
+            page: None,
+            page_order: vec!["p2".to_string(), "p1".to_string()],
+            server_version: 4,
+            user_id: "u1".to_string(),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["eventType"], "reordered");
+        assert!(json.get("pageId").is_none());
+    }
 }
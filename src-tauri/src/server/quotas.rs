@@ -0,0 +1,458 @@
+//! Storage quotas for team documents and blobs
+//!
+//! Limits apply to the bytes and number of documents a user owns (blobs
+//! count for the user who first uploaded them) and to the store as a whole.
+//! Only live documents count; trashed ones are purged by retention.
+//!
+//! A change is refused only when it would leave usage above a limit *and*
+//! higher than before, so users who are already over quota (e.g. after the
+//! limit was lowered) can still edit and shrink their documents.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use super::blobs::BlobStore;
+use super::documents::DocumentStore;
+use super::permissions::error_codes;
+
+/// Byte and document-count limits; `None` means unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<u64>,
+}
+
+/// Quota settings (part of the server config)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaConfig {
+    /// Limits for every user without an override
+    #[serde(default)]
+    pub per_user: QuotaLimits,
+    /// Limits for the whole store
+    #[serde(default)]
+    pub global: QuotaLimits,
+    /// Limits for specific users, by user ID, replacing `per_user`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub user_overrides: HashMap<String, QuotaLimits>,
+}
+
+/// Storage used by one user or the whole store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub document_count: u64,
+    pub document_bytes: u64,
+    pub blob_count: u64,
+    pub blob_bytes: u64,
+}
+
+impl Usage {
+    pub fn total_bytes(&self) -> u64 {
+        self.document_bytes + self.blob_bytes
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.document_count += other.document_count;
+        self.document_bytes += other.document_bytes;
+        self.blob_count += other.blob_count;
+        self.blob_bytes += other.blob_bytes;
+    }
+}
+
+/// Current usage per user and in total
+#[derive(Debug, Clone, Default)]
+pub struct UsageSnapshot {
+    pub per_user: HashMap<String, Usage>,
+    pub total: Usage,
+}
+
+impl UsageSnapshot {
+    /// Current usage from the stores' cached totals
+    pub fn collect(doc_store: &DocumentStore, blob_store: &BlobStore) -> Self {
+        let mut snapshot = doc_store.document_usage();
+        snapshot.merge(&blob_store.blob_usage());
+        snapshot
+    }
+
+    /// Add another snapshot's usage to this one
+    pub fn merge(&mut self, other: &UsageSnapshot) {
+        self.total.add(&other.total);
+        for (user_id, usage) in &other.per_user {
+            self.per_user.entry(user_id.clone()).or_default().add(usage);
+        }
+    }
+
+    /// Count a live document of `size` bytes
+    pub fn add_document(&mut self, owner_id: Option<&str>, size: u64) {
+        self.total.document_count += 1;
+        self.total.document_bytes += size;
+        if let Some(owner_id) = owner_id.filter(|id| !id.is_empty()) {
+            let usage = self.per_user.entry(owner_id.to_string()).or_default();
+            usage.document_count += 1;
+            usage.document_bytes += size;
+        }
+    }
+
+    /// Stop counting a document added with `add_document`
+    pub fn remove_document(&mut self, owner_id: Option<&str>, size: u64) {
+        let remove = |usage: &mut Usage| {
+            usage.document_count = usage.document_count.saturating_sub(1);
+            usage.document_bytes = usage.document_bytes.saturating_sub(size);
+        };
+        remove(&mut self.total);
+        if let Some(usage) = owner_id.and_then(|id| self.per_user.get_mut(id)) {
+            remove(usage);
+        }
+    }
+
+    /// Count a stored blob of `size` bytes
+    pub fn add_blob(&mut self, uploaded_by: &str, size: u64) {
+        self.total.blob_count += 1;
+        self.total.blob_bytes += size;
+        if !uploaded_by.is_empty() {
+            let usage = self.per_user.entry(uploaded_by.to_string()).or_default();
+            usage.blob_count += 1;
+            usage.blob_bytes += size;
+        }
+    }
+
+    pub fn user(&self, user_id: &str) -> Usage {
+        self.per_user.get(user_id).copied().unwrap_or_default()
+    }
+}
+
+/// Check every document write and every new blob against `config`, under
+/// the respective store's write lock so concurrent writes cannot both pass
+pub fn enforce(doc_store: &Arc<DocumentStore>, blob_store: &Arc<BlobStore>, config: QuotaConfig) {
+    let blobs = blob_store.clone();
+    let document_config = config.clone();
+    doc_store.on_write_check(move |write| {
+        let mut usage = write.usage.clone();
+        usage.merge(&blobs.blob_usage());
+        document_config.check_document_save(&usage, write.owner_id, write.old_size, write.new_size)
+    });
+    // The document store's check already holds the blob store
+    let documents: Weak<DocumentStore> = Arc::downgrade(doc_store);
+    blob_store.on_upload_check(move |upload| {
+        let mut usage = documents.upgrade().map(|d| d.document_usage()).unwrap_or_default();
+        usage.merge(upload.usage);
+        config.check_blob_upload(&usage, upload.user_id, upload.size)
+    });
+}
+
+/// One user's row in the usage report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsage {
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub usage: Usage,
+    pub total_bytes: u64,
+    pub limits: QuotaLimits,
+}
+
+/// Usage report for admins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    /// Users sorted by total bytes, largest first
+    pub users: Vec<UserUsage>,
+    pub total: Usage,
+    pub total_bytes: u64,
+    pub global_limits: QuotaLimits,
+}
+
+/// Human-readable byte count for error messages
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+impl QuotaLimits {
+    /// Refuse `after` if it exceeds a limit and grew compared to `before`
+    fn check(&self, scope: &str, before: &Usage, after: &Usage) -> Result<(), String> {
+        if let Some(max) = self.max_bytes {
+            if after.total_bytes() > max && after.total_bytes() > before.total_bytes() {
+                return Err(format!(
+                    "{}: {} storage quota of {} exceeded ({} would be used)",
+                    error_codes::QUOTA_EXCEEDED,
+                    scope,
+                    format_bytes(max),
+                    format_bytes(after.total_bytes())
+                ));
+            }
+        }
+        if let Some(max) = self.max_documents {
+            if after.document_count > max && after.document_count > before.document_count {
+                return Err(format!(
+                    "{}: {} quota of {} document(s) reached",
+                    error_codes::QUOTA_EXCEEDED,
+                    scope,
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl QuotaConfig {
+    pub fn limits_for(&self, user_id: &str) -> &QuotaLimits {
+        self.user_overrides.get(user_id).unwrap_or(&self.per_user)
+    }
+
+    /// Check a document save. `old_size` is the stored size of the document
+    /// (`None` when it is new); the owner is charged for the difference.
+    pub fn check_document_save(
+        &self,
+        usage: &UsageSnapshot,
+        owner_id: Option<&str>,
+        old_size: Option<u64>,
+        new_size: u64,
+    ) -> Result<(), String> {
+        let apply = |before: &Usage| {
+            let mut after = *before;
+            if old_size.is_none() {
+                after.document_count += 1;
+            }
+            after.document_bytes = (after.document_bytes + new_size).saturating_sub(old_size.unwrap_or(0));
+            after
+        };
+        if let Some(owner_id) = owner_id.filter(|id| !id.is_empty()) {
+            let before = usage.user(owner_id);
+            self.limits_for(owner_id).check("Owner's", &before, &apply(&before))?;
+        }
+        self.global.check("Server", &usage.total, &apply(&usage.total))
+    }
+
+    /// Check uploading a new blob of `size` bytes
+    pub fn check_blob_upload(&self, usage: &UsageSnapshot, user_id: &str, size: u64) -> Result<(), String> {
+        let apply = |before: &Usage| Usage {
+            blob_count: before.blob_count + 1,
+            blob_bytes: before.blob_bytes + size,
+            ..*before
+        };
+        let before = usage.user(user_id);
+        self.limits_for(user_id).check("Your", &before, &apply(&before))?;
+        self.global.check("Server", &usage.total, &apply(&usage.total))
+    }
+
+    /// Usage of every known user plus anyone else who owns data
+    pub fn report<'a>(&self, usage: &UsageSnapshot, users: impl IntoIterator<Item = (&'a str, &'a str)>) -> UsageReport {
+        let mut names: HashMap<&str, &str> = users.into_iter().collect();
+        for user_id in usage.per_user.keys() {
+            names.entry(user_id.as_str()).or_insert("");
+        }
+        let mut rows: Vec<UserUsage> = names
+            .into_iter()
+            .map(|(user_id, username)| {
+                let user_usage = usage.user(user_id);
+                UserUsage {
+                    user_id: user_id.to_string(),
+                    username: (!username.is_empty()).then(|| username.to_string()),
+                    usage: user_usage,
+                    total_bytes: user_usage.total_bytes(),
+                    limits: self.limits_for(user_id).clone(),
+                }
+            })
+            .collect();
+        rows.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes).then_with(|| a.user_id.cmp(&b.user_id)));
+
+        UsageReport {
+            users: rows,
+            total: usage.total,
+            total_bytes: usage.total.total_bytes(),
+            global_limits: self.global.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> UsageSnapshot {
+        let alice = Usage {
+            document_count: 2,
+            document_bytes: 600,
+            blob_count: 1,
+            blob_bytes: 300,
+        };
+        UsageSnapshot {
+            per_user: HashMap::from([("alice".to_string(), alice)]),
+            total: alice,
+        }
+    }
+
+    fn limits(max_bytes: Option<u64>, max_documents: Option<u64>) -> QuotaLimits {
+        QuotaLimits { max_bytes, max_documents }
+    }
+
+    #[test]
+    fn test_per_user_limits_and_overrides() {
+        let config = QuotaConfig {
+            per_user: limits(Some(1000), Some(2)),
+            user_overrides: HashMap::from([("bob".to_string(), limits(None, None))]),
+            ..Default::default()
+        };
+        let usage = snapshot();
+
+        // Updating in place stays within the byte limit
+        assert!(config.check_document_save(&usage, Some("alice"), Some(300), 400).is_ok());
+        let err = config.check_document_save(&usage, Some("alice"), Some(300), 500).unwrap_err();
+        assert!(err.starts_with(error_codes::QUOTA_EXCEEDED));
+        // Third document
+        assert!(config.check_document_save(&usage, Some("alice"), None, 10).is_err());
+        assert!(config.check_blob_upload(&usage, "alice", 101).is_err());
+        assert!(config.check_blob_upload(&usage, "alice", 100).is_ok());
+
+        // Overridden users are unlimited; unowned documents only count globally
+        assert!(config.check_document_save(&usage, Some("bob"), None, 5000).is_ok());
+        assert!(config.check_document_save(&usage, None, None, 5000).is_ok());
+    }
+
+    #[test]
+    fn test_over_quota_users_can_shrink() {
+        let config = QuotaConfig {
+            per_user: limits(Some(500), Some(1)),
+            global: limits(Some(800), None),
+            ..Default::default()
+        };
+        let usage = snapshot();
+        assert!(config.check_document_save(&usage, Some("alice"), Some(300), 200).is_ok());
+        assert!(config.check_document_save(&usage, Some("alice"), Some(300), 300).is_ok());
+        let err = config.check_blob_upload(&usage, "carol", 10).unwrap_err();
+        assert!(err.contains("Server storage quota of 800 bytes"));
+    }
+
+    #[test]
+    fn test_every_document_write_is_checked() {
+        use super::super::json_patch::PatchOperation;
+        use super::super::validation::ValidationMode;
+
+        let dir = tempfile::tempdir().unwrap();
+        let doc_store = Arc::new(DocumentStore::new(dir.path().to_path_buf()));
+        let blob_store = Arc::new(BlobStore::new(dir.path().to_path_buf()));
+        let config = QuotaConfig {
+            per_user: limits(Some(400), Some(1)),
+            ..Default::default()
+        };
+        enforce(&doc_store, &blob_store, config);
+
+        doc_store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "Doc", "ownerId": "alice"}))
+            .unwrap();
+        let usage = doc_store.document_usage();
+        assert_eq!(usage.user("alice").document_count, 1);
+
+        // Duplicating would be a second document
+        let err = doc_store
+            .duplicate_document("doc-1", None, ("alice", "Alice"), ValidationMode::Lenient)
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::QUOTA_EXCEEDED));
+
+        // Growing the document past the byte limit with a patch
+        let ops: Vec<PatchOperation> = serde_json::from_value(serde_json::json!([
+            {"op": "add", "path": "/description", "value": "x".repeat(400)}
+        ]))
+        .unwrap();
+        let err = doc_store
+            .apply_patch("doc-1", 1, &ops, ValidationMode::Lenient, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.starts_with(error_codes::QUOTA_EXCEEDED));
+        assert_eq!(doc_store.get_metadata("doc-1").unwrap().server_version, 1);
+
+        // Cached totals follow writes and deletes
+        doc_store
+            .save_document(serde_json::json!({"id": "doc-1", "name": "Renamed", "ownerId": "alice"}))
+            .unwrap();
+        let size = doc_store.document_size("doc-1").unwrap();
+        assert_eq!(doc_store.document_usage().user("alice").document_bytes, size);
+        doc_store.delete_document("doc-1", None, None).unwrap();
+        assert_eq!(doc_store.document_usage().total, Usage::default());
+    }
+
+    #[test]
+    fn test_new_blobs_are_checked_under_the_lock() {
+        use super::super::uploads::{UploadError, UploadStore};
+
+        let dir = tempfile::tempdir().unwrap();
+        let doc_store = Arc::new(DocumentStore::new(dir.path().to_path_buf()));
+        let blob_store = Arc::new(BlobStore::new(dir.path().to_path_buf()));
+        let config = QuotaConfig {
+            per_user: limits(Some(100), None),
+            ..Default::default()
+        };
+        enforce(&doc_store, &blob_store, config);
+
+        // Concurrent uploads cannot both fit under the limit
+        let blobs: Vec<Vec<u8>> = (0..8).map(|i| vec![i; 60]).collect();
+        let stored = std::thread::scope(|scope| {
+            let uploads: Vec<_> = blobs
+                .iter()
+                .map(|data| {
+                    let blob_store = &blob_store;
+                    scope.spawn(move || {
+                        blob_store.save_blob(&BlobStore::compute_hash(data), data, "text/plain", "alice")
+                    })
+                })
+                .collect();
+            uploads.into_iter().filter_map(|u| u.join().unwrap().ok()).count()
+        });
+        assert_eq!(stored, 1);
+        assert_eq!(blob_store.blob_usage().user("alice").blob_bytes, 60);
+
+        // Re-uploads are deduplicated and pass
+        for data in &blobs {
+            let hash = BlobStore::compute_hash(data);
+            if blob_store.exists(&hash) {
+                blob_store.save_blob(&hash, data, "text/plain", "alice").unwrap();
+            }
+        }
+
+        // A chunked upload opened while there was room is checked again
+        let uploads = UploadStore::new(dir.path());
+        let data = vec![9u8; 30];
+        let hash = BlobStore::compute_hash(&data);
+        blob_store.check_upload(&hash, "alice", 30).unwrap();
+        let session = uploads.create(&hash, 30, "text/plain", "doc-1", "alice").unwrap();
+        uploads.append(&session.upload_id, "alice", 0, &data).unwrap();
+        let other = vec![10u8; 20];
+        blob_store.save_blob(&BlobStore::compute_hash(&other), &other, "text/plain", "alice").unwrap();
+        let err = uploads.complete(&session.upload_id, "alice", &blob_store, "text/plain").unwrap_err();
+        assert!(matches!(err, UploadError::QuotaExceeded(_)));
+        // The session is kept, so the upload can complete once there is room
+        assert!(uploads.get(&session.upload_id, "alice").is_ok());
+    }
+
+    #[test]
+    fn test_report_includes_idle_users() {
+        let config = QuotaConfig {
+            per_user: limits(Some(2048), None),
+            ..Default::default()
+        };
+        let report = config.report(&snapshot(), [("alice", "Alice"), ("bob", "Bob")]);
+        assert_eq!(report.users.len(), 2);
+        assert_eq!(report.users[0].user_id, "alice");
+        assert_eq!(report.users[0].total_bytes, 900);
+        assert_eq!(report.users[1].username.as_deref(), Some("Bob"));
+        assert_eq!(report.users[1].limits.max_bytes, Some(2048));
+        assert_eq!(report.total_bytes, 900);
+        assert_eq!(format_bytes(1536), "1.5 KB");
+    }
+}
@@ -0,0 +1,102 @@
+//! Conditional and range request handling for blob downloads
+//!
+//! Blobs are content-addressed, so the hash is a strong ETag that never
+//! changes. Only single byte ranges are served; a multi-range request gets
+//! the whole blob, which RFC 9110 allows.
+
+/// Part of a blob to send
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// No usable `Range` header: send everything
+    Full,
+    /// Inclusive byte positions
+    Partial { start: u64, end: u64 },
+    /// The range lies outside the blob (416)
+    Unsatisfiable,
+}
+
+/// Strong ETag for a blob
+pub fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether an `If-None-Match` header matches an ETag
+pub fn etag_matches(if_none_match: &str, tag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        // Weak comparison, as If-None-Match requires
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == tag
+    })
+}
+
+/// Interpret a `Range` header for a blob of `size` bytes
+pub fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    let range = if first.is_empty() {
+        // Suffix range: the last N bytes
+        match last.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => (size.saturating_sub(n), size.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        }
+    } else {
+        let Ok(start) = first.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        let end = if last.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            match last.parse::<u64>() {
+                Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            }
+        };
+        (start, end)
+    };
+
+    if size == 0 || range.0 >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial {
+        start: range.0,
+        end: range.1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(parse_range(Some("bytes=90-"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(parse_range(Some("bytes=-500"), 100), ByteRange::Partial { start: 0, end: 99 });
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), ByteRange::Partial { start: 50, end: 99 });
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=9-3"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let tag = etag("abc");
+        assert!(etag_matches("\"abc\"", &tag));
+        assert!(etag_matches("\"x\", W/\"abc\"", &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"abcd\"", &tag));
+    }
+}
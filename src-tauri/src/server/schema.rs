@@ -0,0 +1,158 @@
+//! Document schema migrations
+//!
+//! Team documents carry a `version` field (`DOCUMENT_VERSION` in
+//! `src/types/Document.ts`). The server treats documents as JSON, so any
+//! format change must come with a migration here that upgrades older
+//! documents in place. Migrations run in order, each taking a document from
+//! `from` to `from + 1`.
+//!
+//! Documents newer than [`CURRENT_SCHEMA_VERSION`] are refused: the server
+//! cannot know what a newer format means, and rewriting it would lose data.
+
+use serde_json::{json, Value};
+
+use super::permissions::error_codes;
+
+/// Newest document schema this server understands. Keep in sync with
+/// `DOCUMENT_VERSION` in `src/types/Document.ts`.
+pub const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+/// One upgrade step
+struct Migration {
+    /// Version this migration upgrades from (to `from + 1`)
+    from: u64,
+    description: &'static str,
+    apply: fn(&mut Value),
+}
+
+/// Registered migrations, ordered by `from`
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "fill in page containers and ordering for unversioned documents",
+    apply: migrate_v0_to_v1,
+}];
+
+/// Schema version of a document. Documents without a `version` field
+/// predate versioning and count as version 0.
+pub fn document_version(doc: &Value) -> Result<u64, String> {
+    match doc.get("version") {
+        None | Some(Value::Null) => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| format!("Invalid document version: {}", v)),
+    }
+}
+
+/// Upgrade a document to [`CURRENT_SCHEMA_VERSION`] in place.
+///
+/// Returns whether anything was changed. Fails with
+/// `ERR_UNSUPPORTED_VERSION` if the document is newer than this server.
+pub fn migrate(doc: &mut Value) -> Result<bool, String> {
+    if !doc.is_object() {
+        return Err("Document is not a JSON object".to_string());
+    }
+
+    let mut version = document_version(doc)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "{}: Document schema version {} is newer than supported version {}",
+            error_codes::UNSUPPORTED_VERSION,
+            version,
+            CURRENT_SCHEMA_VERSION
+        ));
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok(false);
+    }
+
+    let original = version;
+    while version < CURRENT_SCHEMA_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| format!("No migration registered from schema version {}", version))?;
+        (migration.apply)(doc);
+        version += 1;
+        doc["version"] = json!(version);
+        log::debug!("Applied document migration v{}: {}", migration.from, migration.description);
+    }
+
+    log::info!(
+        "Upgraded document {} from schema v{} to v{}",
+        doc.get("id").and_then(|v| v.as_str()).unwrap_or("?"),
+        original,
+        version
+    );
+    Ok(true)
+}
+
+/// v0 → v1: unversioned documents may lack the containers the editor
+/// requires. Orders are derived from object key order where missing.
+fn migrate_v0_to_v1(doc: &mut Value) {
+    let obj = match doc.as_object_mut() {
+        Some(obj) => obj,
+        None => return,
+    };
+
+    let pages = obj.entry("pages").or_insert_with(|| json!({}));
+    let page_ids: Vec<String> = pages
+        .as_object()
+        .map(|p| p.keys().cloned().collect())
+        .unwrap_or_default();
+    if let Some(pages) = pages.as_object_mut() {
+        for page in pages.values_mut().filter_map(|p| p.as_object_mut()) {
+            let shapes = page.entry("shapes").or_insert_with(|| json!({}));
+            let shape_ids: Vec<String> = shapes
+                .as_object()
+                .map(|s| s.keys().cloned().collect())
+                .unwrap_or_default();
+            page.entry("shapeOrder").or_insert_with(|| json!(shape_ids));
+        }
+    }
+
+    obj.entry("pageOrder").or_insert_with(|| json!(page_ids));
+    if !obj.contains_key("activePageId") {
+        if let Some(first) = page_ids.first() {
+            obj.insert("activePageId".to_string(), json!(first));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_covers_every_version() {
+        for (idx, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.from, idx as u64);
+        }
+        assert_eq!(MIGRATIONS.len() as u64, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_unversioned_document() {
+        let mut doc = json!({
+            "id": "doc-1",
+            "pages": {"p1": {"id": "p1", "shapes": {"a": {"id": "a"}}}}
+        });
+        assert!(migrate(&mut doc).unwrap());
+        assert_eq!(doc["version"], 1);
+        assert_eq!(doc["pageOrder"], json!(["p1"]));
+        assert_eq!(doc["activePageId"], "p1");
+        assert_eq!(doc["pages"]["p1"]["shapeOrder"], json!(["a"]));
+
+        // Already current: untouched
+        assert!(!migrate(&mut doc).unwrap());
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let mut doc = json!({"id": "doc-1", "version": CURRENT_SCHEMA_VERSION + 1});
+        let err = migrate(&mut doc).unwrap_err();
+        assert!(err.starts_with(error_codes::UNSUPPORTED_VERSION));
+
+        let mut doc = json!({"id": "doc-1", "version": "2"});
+        assert!(migrate(&mut doc).is_err());
+    }
+}
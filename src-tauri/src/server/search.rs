@@ -0,0 +1,328 @@
+//! Full-text search over team documents
+//!
+//! Each live document is reduced to a list of text fields (document name,
+//! page names, shape `text`/`label`, rich text page names and content) that
+//! is kept in memory and replaced whenever the document is saved, deleted or
+//! restored. A query matches a field when every whitespace-separated term
+//! occurs in it, case-insensitively.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Characters of context shown on each side of the first match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Which part of a document a hit came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchField {
+    DocumentName,
+    PageName,
+    ShapeText,
+    RichTextPageName,
+    RichTextContent,
+}
+
+/// One searchable text field of a document
+#[derive(Debug, Clone)]
+struct IndexedText {
+    field: SearchField,
+    /// Diagram page or rich text page the text belongs to
+    page_id: Option<String>,
+    shape_id: Option<String>,
+    text: String,
+    /// Lowercased `text`, matched against queries
+    folded: String,
+}
+
+/// A search result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub doc_id: String,
+    pub doc_name: String,
+    pub field: SearchField,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape_id: Option<String>,
+    /// Matched text with surrounding context
+    pub snippet: String,
+}
+
+/// In-memory text index of every live document
+#[derive(Default)]
+pub struct SearchIndex {
+    /// doc_id → (document name, indexed fields)
+    docs: RwLock<HashMap<String, (String, Vec<IndexedText>)>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a document's entries
+    pub fn index_document(&self, doc: &Value) {
+        let Some(doc_id) = doc.get("id").and_then(|v| v.as_str()) else {
+            return;
+        };
+        let name = doc.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let entries = extract(doc);
+        if let Ok(mut docs) = self.docs.write() {
+            docs.insert(doc_id.to_string(), (name, entries));
+        }
+    }
+
+    /// Drop a document's entries
+    pub fn remove_document(&self, doc_id: &str) {
+        if let Ok(mut docs) = self.docs.write() {
+            docs.remove(doc_id);
+        }
+    }
+
+    /// Replace the whole index
+    pub fn rebuild<'a>(&self, docs: impl IntoIterator<Item = &'a Value>) {
+        let fresh = SearchIndex::new();
+        for doc in docs {
+            fresh.index_document(doc);
+        }
+        if let (Ok(mut docs), Ok(fresh)) = (self.docs.write(), fresh.docs.into_inner()) {
+            *docs = fresh;
+        }
+    }
+
+    /// Find fields containing every term of `query`, in documents accepted
+    /// by `visible`. Hits are grouped by document (sorted by name), with
+    /// document-name hits first. At most `limit` hits are returned.
+    pub fn search(&self, query: &str, limit: usize, visible: impl Fn(&str) -> bool) -> Vec<SearchHit> {
+        let terms: Vec<String> = query.split_whitespace().map(|t| t.to_lowercase()).collect();
+        if terms.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let docs = match self.docs.read() {
+            Ok(docs) => docs,
+            Err(_) => return Vec::new(),
+        };
+        let mut candidates: Vec<(&String, &(String, Vec<IndexedText>))> =
+            docs.iter().filter(|(id, _)| visible(id)).collect();
+        candidates.sort_by(|a, b| a.1 .0.to_lowercase().cmp(&b.1 .0.to_lowercase()).then(a.0.cmp(b.0)));
+
+        let mut hits = Vec::new();
+        for (doc_id, (doc_name, entries)) in candidates {
+            for entry in entries {
+                if !terms.iter().all(|t| entry.folded.contains(t.as_str())) {
+                    continue;
+                }
+                hits.push(SearchHit {
+                    doc_id: doc_id.clone(),
+                    doc_name: doc_name.clone(),
+                    field: entry.field,
+                    page_id: entry.page_id.clone(),
+                    shape_id: entry.shape_id.clone(),
+                    snippet: snippet(&entry.text, &entry.folded, &terms[0]),
+                });
+                if hits.len() >= limit {
+                    return hits;
+                }
+            }
+        }
+        hits
+    }
+}
+
+/// Collect the searchable text of a document
+fn extract(doc: &Value) -> Vec<IndexedText> {
+    let mut entries = Vec::new();
+    let mut push = |field, page_id: Option<&str>, shape_id: Option<&str>, text: &str| {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            return;
+        }
+        entries.push(IndexedText {
+            field,
+            page_id: page_id.map(String::from),
+            shape_id: shape_id.map(String::from),
+            folded: text.to_lowercase(),
+            text,
+        });
+    };
+
+    if let Some(name) = doc.get("name").and_then(|v| v.as_str()) {
+        push(SearchField::DocumentName, None, None, name);
+    }
+
+    for (page_id, page) in ordered(doc.get("pages"), doc.get("pageOrder")) {
+        if let Some(name) = page.get("name").and_then(|v| v.as_str()) {
+            push(SearchField::PageName, Some(page_id), None, name);
+        }
+        for (shape_id, shape) in ordered(page.get("shapes"), page.get("shapeOrder")) {
+            for key in ["text", "label"] {
+                if let Some(text) = shape.get(key).and_then(|v| v.as_str()) {
+                    push(SearchField::ShapeText, Some(page_id), Some(shape_id), text);
+                }
+            }
+        }
+    }
+
+    if let Some(rich) = doc.get("richTextPages") {
+        for (page_id, page) in ordered(rich.get("pages"), rich.get("pageOrder")) {
+            if let Some(name) = page.get("name").and_then(|v| v.as_str()) {
+                push(SearchField::RichTextPageName, Some(page_id), None, name);
+            }
+            if let Some(html) = page.get("content").and_then(|v| v.as_str()) {
+                push(SearchField::RichTextContent, Some(page_id), None, &html_to_text(html));
+            }
+        }
+    }
+
+    entries
+}
+
+/// Entries of a JSON object map, in `order` first and then any remaining
+/// keys (group children are not listed in `shapeOrder`)
+fn ordered<'a>(map: Option<&'a Value>, order: Option<&'a Value>) -> Vec<(&'a str, &'a Value)> {
+    let Some(map) = map.and_then(|v| v.as_object()) else {
+        return Vec::new();
+    };
+    let mut result: Vec<(&str, &Value)> = order
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_str())
+        .filter_map(|id| map.get_key_value(id).map(|(k, v)| (k.as_str(), v)))
+        .collect();
+    for (key, value) in map {
+        if !result.iter().any(|(k, _)| *k == key.as_str()) {
+            result.push((key.as_str(), value));
+        }
+    }
+    result
+}
+
+/// Strip tags from rich text HTML and decode the common entities
+fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            // Block boundaries must not glue words together
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Text around the first occurrence of `term`, with ellipses where cut
+fn snippet(text: &str, folded: &str, term: &str) -> String {
+    // Lowercasing can change byte lengths, so work in characters
+    let chars: Vec<char> = text.chars().collect();
+    let match_at = folded
+        .find(term)
+        .map(|byte| folded[..byte].chars().count())
+        .unwrap_or(0)
+        .min(chars.len());
+    let start = match_at.saturating_sub(SNIPPET_CONTEXT);
+    let end = (match_at + term.chars().count() + SNIPPET_CONTEXT).min(chars.len());
+
+    let mut snippet: String = chars[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc() -> Value {
+        json!({
+            "id": "doc-1",
+            "name": "Network Plan",
+            "pageOrder": ["p1"],
+            "pages": {"p1": {
+                "id": "p1",
+                "name": "Backbone",
+                "shapes": {
+                    "s1": {"id": "s1", "type": "rectangle", "label": "Core Router"},
+                    "s2": {"id": "s2", "type": "text", "text": "Edge   firewall"}
+                },
+                "shapeOrder": ["s1", "s2"]
+            }},
+            "richTextPages": {
+                "pageOrder": ["r1"],
+                "activePageId": "r1",
+                "pages": {"r1": {
+                    "id": "r1",
+                    "name": "Notes",
+                    "content": "<p>The router &amp; firewall</p><p>are <b>redundant</b></p>"
+                }}
+            }
+        })
+    }
+
+    #[test]
+    fn test_search_finds_all_fields() {
+        let index = SearchIndex::new();
+        index.index_document(&doc());
+
+        let hits = index.search("ROUTER", 10, |_| true);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].field, SearchField::ShapeText);
+        assert_eq!(hits[0].page_id.as_deref(), Some("p1"));
+        assert_eq!(hits[0].shape_id.as_deref(), Some("s1"));
+        assert_eq!(hits[1].field, SearchField::RichTextContent);
+        assert_eq!(hits[1].page_id.as_deref(), Some("r1"));
+        assert!(hits[1].snippet.contains("router & firewall"));
+
+        // Every term must match the same field
+        assert_eq!(index.search("edge firewall", 10, |_| true)[0].shape_id.as_deref(), Some("s2"));
+        assert!(index.search("edge router", 10, |_| true).is_empty());
+        assert_eq!(index.search("backbone", 10, |_| true)[0].field, SearchField::PageName);
+        assert_eq!(index.search("plan", 10, |_| true)[0].field, SearchField::DocumentName);
+        assert_eq!(index.search("router", 1, |_| true).len(), 1);
+    }
+
+    #[test]
+    fn test_updates_and_visibility() {
+        let index = SearchIndex::new();
+        index.index_document(&doc());
+        assert!(index.search("router", 10, |id| id != "doc-1").is_empty());
+
+        let mut renamed = doc();
+        renamed["pages"]["p1"]["shapes"]["s1"]["label"] = json!("Switch");
+        index.index_document(&renamed);
+        assert_eq!(index.search("router", 10, |_| true).len(), 1);
+
+        index.remove_document("doc-1");
+        assert!(index.search("switch", 10, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_snippet_is_trimmed_around_match() {
+        let text = format!("{} needle {}", "a".repeat(100), "b".repeat(100));
+        let snippet = snippet(&text, &text.to_lowercase(), "needle");
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("needle"));
+        assert_eq!(snippet.chars().count(), 2 + 2 * SNIPPET_CONTEXT + "needle".len());
+    }
+}
@@ -0,0 +1,301 @@
+//! Document templates kept on the host
+//!
+//! Any team document can be saved as a template with a name, description
+//! and category. A template holds a cleaned copy of the document (fresh
+//! IDs, no sharing, locks, folder or server revision); documents created
+//! from it get fresh IDs again, so instances never collide with each other.
+//! Blob references are kept, and the blobs stay alive while any template
+//! refers to them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use super::copy;
+use super::permissions::error_codes;
+use crate::clock::now_ms;
+use crate::storage::{Space, Storage};
+
+/// Maximum length of a template name or category, in characters
+const MAX_NAME_LEN: usize = 128;
+
+/// Maximum length of a template description, in characters
+const MAX_DESCRIPTION_LEN: usize = 2000;
+
+/// Template listing entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_name: Option<String>,
+    /// Document the template was made from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_doc_id: Option<String>,
+    pub page_count: usize,
+    pub created_at: u64,
+}
+
+/// A stored template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub info: TemplateInfo,
+    pub document: Value,
+}
+
+/// Details supplied when saving a template
+#[derive(Debug, Clone)]
+pub struct TemplateDetails<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub category: &'a str,
+}
+
+fn not_found(template_id: &str) -> String {
+    format!("{}: Template '{}' not found", error_codes::TEMPLATE_NOT_FOUND, template_id)
+}
+
+fn clean_text(label: &str, text: &str, max_len: usize) -> Result<String, String> {
+    let text = text.trim();
+    if text.chars().count() > max_len {
+        return Err(format!("Template {} is longer than {} characters", label, max_len));
+    }
+    Ok(text.to_string())
+}
+
+/// Templates persisted one per key in the storage backend, with an
+/// in-memory listing
+pub struct TemplateStore {
+    storage: Arc<dyn Storage>,
+    index: RwLock<HashMap<String, TemplateInfo>>,
+}
+
+impl TemplateStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let store = Self {
+            storage,
+            index: RwLock::new(HashMap::new()),
+        };
+        store.reload();
+        store
+    }
+
+    /// Re-read the template listing from storage. Unreadable templates are
+    /// moved aside.
+    pub fn reload(&self) {
+        let keys = match self.storage.list(Space::Templates, "") {
+            Ok(keys) => keys,
+            Err(e) => {
+                log::error!("Failed to list templates: {}", e);
+                return;
+            }
+        };
+        let mut index = HashMap::new();
+        for key in keys {
+            match self.read(&key) {
+                Ok(template) => {
+                    index.insert(key, template.info);
+                }
+                Err(e) => {
+                    log::error!("Template {} is unreadable ({}), moving it aside", key, e);
+                    if let Err(e) = self.storage.quarantine(Space::Templates, &key) {
+                        log::warn!("Failed to move template {} aside: {}", key, e);
+                    }
+                }
+            }
+        }
+        if let Ok(mut current) = self.index.write() {
+            *current = index;
+        }
+    }
+
+    fn read(&self, template_id: &str) -> Result<Template, String> {
+        let data = self
+            .storage
+            .get(Space::Templates, template_id)?
+            .ok_or_else(|| not_found(template_id))?;
+        serde_json::from_slice(&data).map_err(|e| format!("Failed to parse template: {}", e))
+    }
+
+    /// Templates sorted by category, then name
+    pub fn list(&self) -> Vec<TemplateInfo> {
+        let mut templates: Vec<TemplateInfo> = self
+            .index
+            .read()
+            .map(|index| index.values().cloned().collect())
+            .unwrap_or_default();
+        templates.sort_by(|a, b| {
+            (a.category.to_lowercase(), a.name.to_lowercase()).cmp(&(b.category.to_lowercase(), b.name.to_lowercase()))
+        });
+        templates
+    }
+
+    pub fn get(&self, template_id: &str) -> Option<TemplateInfo> {
+        self.index.read().ok()?.get(template_id).cloned()
+    }
+
+    /// Every stored template document. Fails if any template cannot be
+    /// read or parsed, so callers never mistake it for an absent one.
+    pub fn documents(&self) -> Result<Vec<Value>, String> {
+        let mut documents = Vec::new();
+        for info in self.list() {
+            let Some(data) = self.storage.get(Space::Templates, &info.id)? else {
+                continue;
+            };
+            let template: Template = serde_json::from_slice(&data)
+                .map_err(|e| format!("Failed to parse template {}: {}", info.id, e))?;
+            documents.push(template.document);
+        }
+        Ok(documents)
+    }
+
+    /// Save a copy of `doc` as a new template
+    pub fn create(&self, doc: &Value, details: TemplateDetails, owner: (&str, &str)) -> Result<TemplateInfo, String> {
+        let name = clean_text("name", details.name, MAX_NAME_LEN)?;
+        if name.is_empty() {
+            return Err("Template name cannot be empty".to_string());
+        }
+        let description = clean_text("description", details.description, MAX_DESCRIPTION_LEN)?;
+        let category = clean_text("category", details.category, MAX_NAME_LEN)?;
+
+        let mut document = copy::duplicate_document(doc, Some(&name), owner)?;
+        if let Some(obj) = document.as_object_mut() {
+            obj.remove("folderId");
+        }
+
+        let info = TemplateInfo {
+            id: nanoid::nanoid!(),
+            name,
+            description,
+            category,
+            owner_id: Some(owner.0.to_string()),
+            owner_name: Some(owner.1.to_string()),
+            source_doc_id: doc.get("id").and_then(|v| v.as_str()).map(String::from),
+            page_count: document
+                .get("pageOrder")
+                .and_then(|v| v.as_array())
+                .map_or(0, |order| order.len()),
+            created_at: now_ms(),
+        };
+        let template = Template {
+            info: info.clone(),
+            document,
+        };
+        let data = serde_json::to_vec_pretty(&template).map_err(|e| format!("Serialize error: {}", e))?;
+        self.storage
+            .put(Space::Templates, &info.id, &data)
+            .map_err(|e| format!("Failed to save template: {}", e))?;
+
+        if let Ok(mut index) = self.index.write() {
+            index.insert(info.id.clone(), info.clone());
+        }
+        Ok(info)
+    }
+
+    /// A new document from a template, with fresh IDs, owned by `owner`.
+    /// Named after the template unless `name` is given.
+    pub fn instantiate(&self, template_id: &str, name: Option<&str>, owner: (&str, &str)) -> Result<Value, String> {
+        let template = self.read(template_id)?;
+        copy::duplicate_document(&template.document, Some(name.unwrap_or(&template.info.name)), owner)
+    }
+
+    pub fn remove(&self, template_id: &str) -> Result<TemplateInfo, String> {
+        let info = self.get(template_id).ok_or_else(|| not_found(template_id))?;
+        self.storage
+            .delete(Space::Templates, template_id)
+            .map_err(|e| format!("Failed to delete template: {}", e))?;
+        if let Ok(mut index) = self.index.write() {
+            index.remove(template_id);
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn doc() -> Value {
+        json!({
+            "id": "doc-1",
+            "name": "Retro",
+            "ownerId": "u1",
+            "folderId": "f1",
+            "sharedWith": [{"userId": "u2", "userName": "Bob", "permission": "edit", "sharedAt": 1}],
+            "serverVersion": 7,
+            "pageOrder": ["p1"],
+            "activePageId": "p1",
+            "pages": {"p1": {
+                "id": "p1",
+                "name": "Board",
+                "shapes": {"s1": {"id": "s1", "type": "file", "blobRef": "hash-1"}},
+                "shapeOrder": ["s1"]
+            }},
+            "blobReferences": ["hash-1"]
+        })
+    }
+
+    fn details<'a>(name: &'a str, category: &'a str) -> TemplateDetails<'a> {
+        TemplateDetails {
+            name,
+            description: "",
+            category,
+        }
+    }
+
+    #[test]
+    fn test_template_lifecycle() {
+        let dir = tempdir().unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        let store = TemplateStore::new(storage.clone());
+
+        let retro = store.create(&doc(), details(" Sprint retro ", "Agile"), ("u1", "Alice")).unwrap();
+        store.create(&doc(), details("Architecture", "Design"), ("u1", "Alice")).unwrap();
+        assert_eq!(retro.name, "Sprint retro");
+        assert_eq!(retro.page_count, 1);
+        assert_eq!(retro.source_doc_id.as_deref(), Some("doc-1"));
+        assert!(store.create(&doc(), details(" ", ""), ("u1", "Alice")).is_err());
+
+        let stored = store.read(&retro.id).unwrap().document;
+        assert!(stored.get("folderId").is_none());
+        assert!(stored.get("sharedWith").is_none());
+        assert!(stored.get("serverVersion").is_none());
+
+        let names: Vec<String> = TemplateStore::new(storage).list().into_iter().map(|t| t.name).collect();
+        assert_eq!(names, vec!["Sprint retro", "Architecture"]);
+
+        store.remove(&retro.id).unwrap();
+        assert!(store.remove(&retro.id).unwrap_err().starts_with(error_codes::TEMPLATE_NOT_FOUND));
+        assert_eq!(store.list().len(), 1);
+    }
+
+    #[test]
+    fn test_instances_get_fresh_ids() {
+        let dir = tempdir().unwrap();
+        let store = TemplateStore::new(Arc::new(FsStorage::new(dir.path().to_path_buf())));
+        let info = store.create(&doc(), details("Retro", ""), ("u1", "Alice")).unwrap();
+
+        let first = store.instantiate(&info.id, None, ("u2", "Bob")).unwrap();
+        let second = store.instantiate(&info.id, Some("Q3 retro"), ("u2", "Bob")).unwrap();
+        assert_eq!(first["name"], "Retro");
+        assert_eq!(second["name"], "Q3 retro");
+        assert_eq!(first["ownerId"], "u2");
+        assert_ne!(first["id"], second["id"]);
+        assert_ne!(first["pageOrder"], second["pageOrder"]);
+        assert_eq!(first["blobReferences"], json!(["hash-1"]));
+        assert_eq!(store.documents().unwrap().len(), 1);
+        assert!(store.instantiate("missing", None, ("u2", "Bob")).is_err());
+    }
+}
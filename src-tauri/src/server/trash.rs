@@ -0,0 +1,173 @@
+//! Trash bin for soft-deleted team documents
+//!
+//! Deleting a document moves it to `team_documents/trash/` instead of
+//! removing it. Owners and admins can list and restore trashed documents;
+//! a background task permanently purges entries older than the configured
+//! retention period and releases blobs no other document still references.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::blobs::BlobStore;
+use super::documents::{DocumentMetadata, DocumentStore};
+use crate::clock::now_ms;
+
+/// How often the purge task checks for expired trash entries
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A document sitting in the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    /// Metadata as it was at deletion (including owner and shares)
+    pub metadata: DocumentMetadata,
+    /// Deletion timestamp (Unix milliseconds)
+    pub deleted_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by_name: Option<String>,
+}
+
+/// Result of a purge run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeReport {
+    pub purged_documents: Vec<String>,
+    pub released_blobs: Vec<String>,
+}
+
+/// Permanently delete trash entries older than `retention_days` and release
+/// their blobs. A retention of 0 disables purging.
+pub fn purge_expired(
+    doc_store: &DocumentStore,
+    blob_store: &BlobStore,
+    retention_days: u64,
+    now: u64,
+) -> PurgeReport {
+    let mut report = PurgeReport::default();
+    if retention_days == 0 {
+        return report;
+    }
+
+    let cutoff = now.saturating_sub(retention_days.saturating_mul(MILLIS_PER_DAY));
+    let expired: Vec<String> = doc_store
+        .list_trash()
+        .into_iter()
+        .filter(|entry| entry.deleted_at < cutoff)
+        .map(|entry| entry.metadata.id)
+        .collect();
+    if expired.is_empty() {
+        return report;
+    }
+
+    let mut candidate_blobs = Vec::new();
+    for doc_id in expired {
+        match doc_store.purge_from_trash(&doc_id) {
+            Ok(blobs) => {
+                candidate_blobs.extend(blobs);
+                report.purged_documents.push(doc_id);
+            }
+            Err(e) => log::warn!("Failed to purge trashed document {}: {}", doc_id, e),
+        }
+    }
+
+    // Only release blobs that no remaining document (live or trashed) uses.
+    // If that cannot be determined, keep them for blob collection.
+    let still_referenced = match doc_store.referenced_blobs() {
+        Ok(referenced) => referenced,
+        Err(e) => {
+            log::warn!("Not releasing blobs of purged documents: {}", e);
+            candidate_blobs.clear();
+            Default::default()
+        }
+    };
+    candidate_blobs.sort();
+    candidate_blobs.dedup();
+    for hash in candidate_blobs {
+        if still_referenced.contains(&hash) {
+            continue;
+        }
+        match blob_store.delete_blob(&hash) {
+            Ok(true) => report.released_blobs.push(hash),
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to release blob {}: {}", hash, e),
+        }
+    }
+
+    log::info!(
+        "Purged {} trashed document(s), released {} blob(s)",
+        report.purged_documents.len(),
+        report.released_blobs.len()
+    );
+    report
+}
+
+/// Spawn the periodic purge task, which also applies version history
+/// retention. The caller aborts the handle on shutdown.
+pub fn spawn_purge_task(
+    doc_store: Arc<DocumentStore>,
+    blob_store: Arc<BlobStore>,
+    retention_days: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = now_ms();
+            let doc_store = doc_store.clone();
+            let blob_store = blob_store.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                doc_store.expire_history(now);
+                purge_expired(&doc_store, &blob_store, retention_days, now)
+            })
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_purge_expired_releases_unshared_blobs() {
+        let dir = tempdir().unwrap();
+        let doc_store = DocumentStore::new(dir.path().to_path_buf());
+        let blob_store = BlobStore::new(dir.path().to_path_buf());
+
+        let only_here = BlobStore::compute_hash(b"only here");
+        let shared = BlobStore::compute_hash(b"shared");
+        blob_store.save_blob(&only_here, b"only here", "text/plain", "u1").unwrap();
+        blob_store.save_blob(&shared, b"shared", "text/plain", "u1").unwrap();
+
+        doc_store
+            .save_document(serde_json::json!({
+                "id": "doc-1", "name": "Old", "blobReferences": [only_here, shared]
+            }))
+            .unwrap();
+        doc_store
+            .save_document(serde_json::json!({
+                "id": "doc-2", "name": "Keeps shared", "blobReferences": [shared]
+            }))
+            .unwrap();
+        doc_store.delete_document("doc-1", Some("u1"), Some("User")).unwrap();
+
+        let deleted_at = doc_store.get_trash_entry("doc-1").unwrap().deleted_at;
+
+        // Not yet expired
+        let report = purge_expired(&doc_store, &blob_store, 7, deleted_at + MILLIS_PER_DAY);
+        assert!(report.purged_documents.is_empty());
+
+        let report = purge_expired(&doc_store, &blob_store, 7, deleted_at + 8 * MILLIS_PER_DAY);
+        assert_eq!(report.purged_documents, vec!["doc-1".to_string()]);
+        assert_eq!(report.released_blobs, vec![only_here.clone()]);
+        assert!(!blob_store.exists(&only_here));
+        assert!(blob_store.exists(&shared));
+        assert!(doc_store.get_trash_entry("doc-1").is_none());
+    }
+}
@@ -0,0 +1,358 @@
+//! Resumable chunked blob uploads
+//!
+//! A client opens a session with the blob's hash and size, sends the content
+//! in chunks at increasing offsets and then completes the session, which
+//! verifies the hash and moves the staged file into the blob store. Sessions
+//! live on disk so an upload interrupted by a dropped connection or a restart
+//! resumes from the offset the server reports:
+//!
+//! ```text
+//! app_data_dir/blob_uploads/
+//!   <upload_id>.json   # Session
+//!   <upload_id>.part   # Content received so far
+//! ```
+//!
+//! The received length is the size of the `.part` file, so a chunk that was
+//! cut off half-way is resumed from wherever it stopped. Staged data is not
+//! encrypted even when team storage is; it is removed once the session is
+//! completed, aborted or expires.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::blobs::{BlobMetadata, BlobStore};
+use super::copy;
+use super::permissions::error_codes;
+use crate::clock::now_ms;
+use crate::fs_util;
+
+/// Largest chunk accepted in one request
+pub const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Sessions untouched for this long are discarded
+const SESSION_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// An upload in progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSession {
+    pub upload_id: String,
+    /// Expected SHA-256 hash of the complete blob
+    pub hash: String,
+    /// Expected size in bytes
+    pub size: u64,
+    pub mime_type: String,
+    /// Document the blob is uploaded for
+    pub doc_id: String,
+    pub user_id: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// Bytes received so far (derived from the staged file, not stored)
+    #[serde(default)]
+    pub offset: u64,
+}
+
+/// Why an upload request failed
+#[derive(Debug, PartialEq)]
+pub enum UploadError {
+    /// No such session for this user
+    NotFound,
+    /// The chunk does not start where the received data ends
+    OffsetMismatch { expected: u64 },
+    /// The chunk would run past the declared size
+    TooLarge { size: u64 },
+    /// Completed before all bytes arrived
+    Incomplete { offset: u64, size: u64 },
+    /// The staged content does not hash to the declared hash
+    HashMismatch(String),
+    /// The content is of a type that may not be uploaded
+    TypeNotAllowed(String),
+    /// Storing the blob would exceed a storage quota; the session is kept
+    QuotaExceeded(String),
+    Io(String),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::NotFound => write!(f, "Upload session not found"),
+            UploadError::OffsetMismatch { expected } => write!(f, "Upload offset mismatch: expected {}", expected),
+            UploadError::TooLarge { size } => write!(f, "Chunk exceeds declared size of {} bytes", size),
+            UploadError::Incomplete { offset, size } => {
+                write!(f, "Upload incomplete: received {} of {} bytes", offset, size)
+            }
+            UploadError::HashMismatch(msg) | UploadError::TypeNotAllowed(msg) | UploadError::QuotaExceeded(msg) => {
+                write!(f, "{}", msg)
+            }
+            UploadError::Io(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Staged upload sessions
+pub struct UploadStore {
+    dir: PathBuf,
+    /// Serializes changes to sessions and their staged files
+    lock: Mutex<()>,
+}
+
+impl UploadStore {
+    pub fn new(app_data_dir: &Path) -> Self {
+        let dir = app_data_dir.join("blob_uploads");
+        fs_util::remove_stale_temp_files(&dir, false);
+        Self {
+            dir,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn session_path(&self, upload_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", upload_id))
+    }
+
+    fn part_path(&self, upload_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", upload_id))
+    }
+
+    /// Read a session, filling in the received length
+    fn read(&self, upload_id: &str) -> Option<UploadSession> {
+        // Upload IDs come from clients and must not escape the directory
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return None;
+        }
+        let data = fs::read(self.session_path(upload_id)).ok()?;
+        let mut session: UploadSession = serde_json::from_slice(&data).ok()?;
+        session.offset = fs::metadata(self.part_path(upload_id)).map(|m| m.len()).unwrap_or(0);
+        Some(session)
+    }
+
+    fn write(&self, session: &UploadSession) -> Result<(), UploadError> {
+        let data = serde_json::to_vec_pretty(session).map_err(|e| UploadError::Io(format!("Serialize error: {}", e)))?;
+        fs_util::write_atomic(&self.session_path(&session.upload_id), data)
+            .map_err(|e| UploadError::Io(format!("Failed to write upload session: {}", e)))
+    }
+
+    fn remove(&self, upload_id: &str) {
+        let _ = fs::remove_file(self.part_path(upload_id));
+        let _ = fs::remove_file(self.session_path(upload_id));
+    }
+
+    /// A session owned by `user_id`
+    pub fn get(&self, upload_id: &str, user_id: &str) -> Result<UploadSession, UploadError> {
+        self.read(upload_id)
+            .filter(|s| s.user_id == user_id)
+            .ok_or(UploadError::NotFound)
+    }
+
+    /// Open a session, or return the caller's existing session for the same
+    /// blob and document so it can be resumed
+    pub fn create(
+        &self,
+        hash: &str,
+        size: u64,
+        mime_type: &str,
+        doc_id: &str,
+        user_id: &str,
+    ) -> Result<UploadSession, UploadError> {
+        let _guard = self.lock.lock().map_err(|e| UploadError::Io(e.to_string()))?;
+        let now = now_ms();
+        let sessions = self.sessions();
+        for session in &sessions {
+            if now.saturating_sub(session.updated_at) > SESSION_TTL_MS {
+                log::info!("Discarding stale upload session {}", session.upload_id);
+                self.remove(&session.upload_id);
+            }
+        }
+        if let Some(existing) = sessions.into_iter().find(|s| {
+            s.hash == hash && s.size == size && s.doc_id == doc_id && s.user_id == user_id
+                && now.saturating_sub(s.updated_at) <= SESSION_TTL_MS
+        }) {
+            return Ok(existing);
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|e| UploadError::Io(format!("Failed to create upload directory: {}", e)))?;
+        let session = UploadSession {
+            upload_id: copy::new_id(),
+            hash: hash.to_string(),
+            size,
+            mime_type: mime_type.to_string(),
+            doc_id: doc_id.to_string(),
+            user_id: user_id.to_string(),
+            created_at: now,
+            updated_at: now,
+            offset: 0,
+        };
+        self.write(&session)?;
+        log::info!("Opened upload session {} for blob {} ({} bytes)", session.upload_id, hash, size);
+        Ok(session)
+    }
+
+    /// All stored sessions
+    fn sessions(&self) -> Vec<UploadSession> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                self.read(name.strip_suffix(".json")?)
+            })
+            .collect()
+    }
+
+    /// Append a chunk that starts at `offset`
+    pub fn append(&self, upload_id: &str, user_id: &str, offset: u64, data: &[u8]) -> Result<UploadSession, UploadError> {
+        let _guard = self.lock.lock().map_err(|e| UploadError::Io(e.to_string()))?;
+        let mut session = self.get(upload_id, user_id)?;
+        if offset != session.offset {
+            return Err(UploadError::OffsetMismatch { expected: session.offset });
+        }
+        if offset.saturating_add(data.len() as u64) > session.size {
+            return Err(UploadError::TooLarge { size: session.size });
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.part_path(upload_id))
+            .map_err(|e| UploadError::Io(format!("Failed to open staged upload: {}", e)))?;
+        file.write_all(data)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| UploadError::Io(format!("Failed to write staged upload: {}", e)))?;
+
+        session.offset += data.len() as u64;
+        session.updated_at = now_ms();
+        self.write(&session)?;
+        Ok(session)
+    }
+
+    /// The first `len` bytes received for a session
+    pub fn peek(&self, upload_id: &str, user_id: &str, len: usize) -> Result<Vec<u8>, UploadError> {
+        self.get(upload_id, user_id)?;
+        let file = match fs::File::open(self.part_path(upload_id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(UploadError::Io(format!("Failed to read staged upload: {}", e))),
+        };
+        let mut data = Vec::new();
+        file.take(len as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| UploadError::Io(format!("Failed to read staged upload: {}", e)))?;
+        Ok(data)
+    }
+
+    /// Verify the staged content and move it into the blob store as
+    /// `mime_type`
+    pub fn complete(
+        &self,
+        upload_id: &str,
+        user_id: &str,
+        blob_store: &BlobStore,
+        mime_type: &str,
+    ) -> Result<(UploadSession, BlobMetadata), UploadError> {
+        let _guard = self.lock.lock().map_err(|e| UploadError::Io(e.to_string()))?;
+        let session = self.get(upload_id, user_id)?;
+        if session.offset != session.size {
+            return Err(UploadError::Incomplete { offset: session.offset, size: session.size });
+        }
+
+        let part = self.part_path(upload_id);
+        if session.size == 0 {
+            fs::write(&part, b"").map_err(|e| UploadError::Io(format!("Failed to write staged upload: {}", e)))?;
+        }
+        match blob_store.save_blob_file(&session.hash, &part, mime_type, &session.user_id) {
+            Ok(metadata) => {
+                self.remove(upload_id);
+                Ok((session, metadata))
+            }
+            Err(e) if e.contains("Hash mismatch") => {
+                // The content is wrong, so resuming cannot fix it
+                self.remove(upload_id);
+                Err(UploadError::HashMismatch(e))
+            }
+            Err(e) if e.starts_with(error_codes::QUOTA_EXCEEDED) => Err(UploadError::QuotaExceeded(e)),
+            Err(e) => Err(UploadError::Io(e)),
+        }
+    }
+
+    /// Discard a session and its staged data. Returns whether it existed.
+    pub fn abort(&self, upload_id: &str, user_id: &str) -> Result<bool, UploadError> {
+        let _guard = self.lock.lock().map_err(|e| UploadError::Io(e.to_string()))?;
+        match self.get(upload_id, user_id) {
+            Ok(_) => {
+                self.remove(upload_id);
+                Ok(true)
+            }
+            Err(UploadError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_chunked_upload_resumes_and_verifies() {
+        let dir = tempdir().unwrap();
+        let uploads = UploadStore::new(dir.path());
+        let blobs = BlobStore::new(dir.path().to_path_buf());
+        let data = b"a blob sent in three chunks";
+        let hash = BlobStore::compute_hash(data);
+
+        let session = uploads.create(&hash, data.len() as u64, "text/plain", "doc-1", "u1").unwrap();
+        uploads.append(&session.upload_id, "u1", 0, &data[..10]).unwrap();
+
+        // Reopening resumes where the data ends; other users cannot see it
+        let resumed = uploads.create(&hash, data.len() as u64, "text/plain", "doc-1", "u1").unwrap();
+        assert_eq!(resumed.upload_id, session.upload_id);
+        assert_eq!(resumed.offset, 10);
+        assert_eq!(uploads.get(&session.upload_id, "u2").unwrap_err(), UploadError::NotFound);
+        assert_eq!(uploads.get("../escape", "u1").unwrap_err(), UploadError::NotFound);
+
+        assert_eq!(
+            uploads.append(&session.upload_id, "u1", 0, &data[..10]).unwrap_err(),
+            UploadError::OffsetMismatch { expected: 10 }
+        );
+        assert!(matches!(
+            uploads.append(&session.upload_id, "u1", 10, &[0; 64]).unwrap_err(),
+            UploadError::TooLarge { .. }
+        ));
+        assert!(matches!(
+            uploads.complete(&session.upload_id, "u1", &blobs, "text/plain").unwrap_err(),
+            UploadError::Incomplete { offset: 10, .. }
+        ));
+
+        assert_eq!(uploads.peek(&session.upload_id, "u1", 6).unwrap(), &data[..6]);
+        uploads.append(&session.upload_id, "u1", 10, &data[10..20]).unwrap();
+        uploads.append(&session.upload_id, "u1", 20, &data[20..]).unwrap();
+        let (_, metadata) = uploads.complete(&session.upload_id, "u1", &blobs, "text/plain").unwrap();
+        assert_eq!(metadata.hash, hash);
+        assert_eq!(blobs.load_blob(&hash).unwrap(), data);
+        assert!(uploads.peek(&session.upload_id, "u1", 4).is_err());
+        assert_eq!(uploads.get(&session.upload_id, "u1").unwrap_err(), UploadError::NotFound);
+    }
+
+    #[test]
+    fn test_hash_mismatch_discards_session() {
+        let dir = tempdir().unwrap();
+        let uploads = UploadStore::new(dir.path());
+        let blobs = BlobStore::new(dir.path().to_path_buf());
+        let hash = BlobStore::compute_hash(b"expected");
+
+        let session = uploads.create(&hash, 8, "text/plain", "doc-1", "u1").unwrap();
+        uploads.append(&session.upload_id, "u1", 0, b"tampered").unwrap();
+        assert!(matches!(
+            uploads.complete(&session.upload_id, "u1", &blobs, "text/plain").unwrap_err(),
+            UploadError::HashMismatch(_)
+        ));
+        assert!(!blobs.exists(&hash));
+        assert!(!uploads.abort(&session.upload_id, "u1").unwrap());
+    }
+}
@@ -0,0 +1,334 @@
+//! Structural validation of team documents
+//!
+//! Checks the cross-references in the `DiagramDocument` page/shape model
+//! that the renderer relies on when loading:
+//!
+//! - `pageOrder` and `activePageId` point at existing pages
+//! - each page's `shapeOrder` points at existing shapes, without duplicates
+//! - shapes are stored under their own `id`
+//! - connector `startShapeId` / `endShapeId` point at shapes on the page
+//! - group `childIds` point at other shapes on the page
+//!
+//! Shapes missing from `shapeOrder` are fine: group children render through
+//! their parent group. In [`ValidationMode::Lenient`] problems are repaired
+//! and reported; in [`ValidationMode::Strict`] the document is rejected.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+use super::permissions::error_codes;
+
+/// How many issues to include in a strict-mode error message
+const MAX_ISSUES_IN_ERROR: usize = 5;
+
+/// What to do with a structurally invalid document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Reject the document with `ERR_INVALID_DOCUMENT`
+    Strict,
+    /// Repair dangling references and report what was changed
+    #[default]
+    Lenient,
+}
+
+/// One structural problem, located by JSON pointer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationIssue {
+    pub path: String,
+    pub message: String,
+}
+
+/// Validate a document before it is persisted.
+///
+/// In lenient mode the document is repaired in place and the repairs are
+/// returned. In strict mode any issue is an error and the document is left
+/// untouched. Documents too malformed to repair (e.g. `pages` is not an
+/// object) are rejected in both modes.
+pub fn validate_document(doc: &mut Value, mode: ValidationMode) -> Result<Vec<ValidationIssue>, String> {
+    let issues = match mode {
+        ValidationMode::Lenient => repair(doc)?,
+        ValidationMode::Strict => repair(&mut doc.clone())?,
+    };
+
+    if mode == ValidationMode::Strict && !issues.is_empty() {
+        let summary: Vec<String> = issues
+            .iter()
+            .take(MAX_ISSUES_IN_ERROR)
+            .map(|i| format!("{} ({})", i.message, i.path))
+            .collect();
+        return Err(format!(
+            "{}: {} structural problem(s): {}",
+            error_codes::INVALID_DOCUMENT,
+            issues.len(),
+            summary.join("; ")
+        ));
+    }
+
+    if !issues.is_empty() {
+        log::warn!(
+            "Repaired {} structural problem(s) in document {}",
+            issues.len(),
+            doc.get("id").and_then(|v| v.as_str()).unwrap_or("?")
+        );
+    }
+    Ok(issues)
+}
+
+fn invalid(message: String) -> String {
+    format!("{}: {}", error_codes::INVALID_DOCUMENT, message)
+}
+
+/// Escape a key for use in a JSON pointer
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Fix every repairable problem and describe what was changed
+fn repair(doc: &mut Value) -> Result<Vec<ValidationIssue>, String> {
+    let mut issues = Vec::new();
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| invalid("document is not an object".to_string()))?;
+    if !obj.get("id").map(|v| v.is_string()).unwrap_or(false) {
+        return Err(invalid("document is missing a string 'id'".to_string()));
+    }
+
+    let page_ids: HashSet<String> = match obj.get_mut("pages") {
+        None => HashSet::new(),
+        Some(Value::Object(pages)) => {
+            for (page_id, page) in pages.iter_mut() {
+                repair_page(page_id, page, &mut issues)?;
+            }
+            pages.keys().cloned().collect()
+        }
+        Some(_) => return Err(invalid("'pages' is not an object".to_string())),
+    };
+
+    if let Some(order) = obj.get_mut("pageOrder") {
+        let order = order
+            .as_array_mut()
+            .ok_or_else(|| invalid("'pageOrder' is not an array".to_string()))?;
+        retain_known(order, &page_ids, "/pageOrder", "page", &mut issues);
+    }
+
+    let active = obj.get("activePageId").and_then(|v| v.as_str()).map(String::from);
+    if let Some(active) = active {
+        if !page_ids.contains(&active) {
+            let replacement = obj
+                .get("pageOrder")
+                .and_then(|v| v.as_array())
+                .and_then(|order| order.first().cloned())
+                .unwrap_or(Value::Null);
+            issues.push(ValidationIssue {
+                path: "/activePageId".to_string(),
+                message: format!("active page '{}' does not exist", active),
+            });
+            obj.insert("activePageId".to_string(), replacement);
+        }
+    }
+
+    Ok(issues)
+}
+
+fn repair_page(page_id: &str, page: &mut Value, issues: &mut Vec<ValidationIssue>) -> Result<(), String> {
+    let page_path = format!("/pages/{}", escape(page_id));
+    let page = page
+        .as_object_mut()
+        .ok_or_else(|| invalid(format!("page '{}' is not an object", page_id)))?;
+
+    let shapes = match page.get_mut("shapes") {
+        None => return Ok(()),
+        Some(Value::Object(shapes)) => shapes,
+        Some(_) => return Err(invalid(format!("'shapes' of page '{}' is not an object", page_id))),
+    };
+
+    for (shape_id, shape) in shapes.iter_mut() {
+        let path = format!("{}/shapes/{}/id", page_path, escape(shape_id));
+        let shape = shape
+            .as_object_mut()
+            .ok_or_else(|| invalid(format!("shape '{}' is not an object", shape_id)))?;
+        if shape.get("id").and_then(|v| v.as_str()) != Some(shape_id.as_str()) {
+            issues.push(ValidationIssue {
+                path,
+                message: format!("shape stored under '{}' has a different id", shape_id),
+            });
+            shape.insert("id".to_string(), json!(shape_id));
+        }
+    }
+
+    let shape_ids: HashSet<String> = shapes.keys().cloned().collect();
+    for (shape_id, shape) in shapes.iter_mut() {
+        let shape_path = format!("{}/shapes/{}", page_path, escape(shape_id));
+        if let Some(shape) = shape.as_object_mut() {
+            repair_shape_refs(shape_id, shape, &shape_ids, &shape_path, issues);
+        }
+    }
+
+    if let Some(order) = page.get_mut("shapeOrder") {
+        let order = order
+            .as_array_mut()
+            .ok_or_else(|| invalid(format!("'shapeOrder' of page '{}' is not an array", page_id)))?;
+        retain_known(order, &shape_ids, &format!("{}/shapeOrder", page_path), "shape", issues);
+    }
+    Ok(())
+}
+
+/// Clear connector endpoints and group children that point at missing shapes
+fn repair_shape_refs(
+    shape_id: &str,
+    shape: &mut Map<String, Value>,
+    shape_ids: &HashSet<String>,
+    shape_path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    match shape.get("type").and_then(|v| v.as_str()) {
+        Some("connector") => {
+            for field in ["startShapeId", "endShapeId"] {
+                let target = match shape.get(field).and_then(|v| v.as_str()) {
+                    Some(target) if !shape_ids.contains(target) => target.to_string(),
+                    _ => continue,
+                };
+                issues.push(ValidationIssue {
+                    path: format!("{}/{}", shape_path, field),
+                    message: format!("connector '{}' points at missing shape '{}'", shape_id, target),
+                });
+                // A detached endpoint falls back to its stored point
+                shape.insert(field.to_string(), Value::Null);
+            }
+        }
+        Some("group") => {
+            if let Some(children) = shape.get_mut("childIds").and_then(|v| v.as_array_mut()) {
+                let mut seen = HashSet::new();
+                let mut index = 0;
+                children.retain(|child| {
+                    let keep = match child.as_str() {
+                        Some(id) if id == shape_id => {
+                            issues.push(ValidationIssue {
+                                path: format!("{}/childIds/{}", shape_path, index),
+                                message: format!("group '{}' contains itself", shape_id),
+                            });
+                            false
+                        }
+                        Some(id) if shape_ids.contains(id) && seen.insert(id.to_string()) => true,
+                        _ => {
+                            issues.push(ValidationIssue {
+                                path: format!("{}/childIds/{}", shape_path, index),
+                                message: format!("group '{}' has missing or duplicate child {}", shape_id, child),
+                            });
+                            false
+                        }
+                    };
+                    index += 1;
+                    keep
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Drop order entries that are not known ids or are repeated
+fn retain_known(
+    order: &mut Vec<Value>,
+    known: &HashSet<String>,
+    path: &str,
+    kind: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let mut seen = HashSet::new();
+    let mut index = 0;
+    order.retain(|entry| {
+        let keep = match entry.as_str() {
+            Some(id) => known.contains(id) && seen.insert(id.to_string()),
+            None => false,
+        };
+        if !keep {
+            issues.push(ValidationIssue {
+                path: format!("{}/{}", path, index),
+                message: format!("missing or duplicate {} {}", kind, entry),
+            });
+        }
+        index += 1;
+        keep
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broken_doc() -> Value {
+        json!({
+            "id": "doc-1",
+            "pageOrder": ["p1", "gone", "p1"],
+            "activePageId": "gone",
+            "pages": {"p1": {
+                "id": "p1",
+                "shapes": {
+                    "a": {"id": "a", "type": "rectangle"},
+                    "b": {"id": "wrong", "type": "rectangle"},
+                    "c": {"id": "c", "type": "connector", "startShapeId": "a", "endShapeId": "deleted"},
+                    "g": {"id": "g", "type": "group", "childIds": ["b", "ghost", "g"]}
+                },
+                "shapeOrder": ["a", "c", "g", "missing", "a"]
+            }}
+        })
+    }
+
+    #[test]
+    fn test_valid_document_has_no_issues() {
+        let mut doc = json!({
+            "id": "doc-1",
+            "pageOrder": ["p1"],
+            "activePageId": "p1",
+            "pages": {"p1": {
+                "id": "p1",
+                "shapes": {
+                    "a": {"id": "a", "type": "rectangle"},
+                    "c": {"id": "c", "type": "connector", "startShapeId": "a", "endShapeId": null},
+                    "g": {"id": "g", "type": "group", "childIds": ["a"]}
+                },
+                "shapeOrder": ["g", "c"]
+            }}
+        });
+        let before = doc.clone();
+        assert!(validate_document(&mut doc, ValidationMode::Strict).unwrap().is_empty());
+        assert_eq!(doc, before);
+    }
+
+    #[test]
+    fn test_lenient_repairs_and_reports() {
+        let mut doc = broken_doc();
+        let issues = validate_document(&mut doc, ValidationMode::Lenient).unwrap();
+
+        assert_eq!(doc["pageOrder"], json!(["p1"]));
+        assert_eq!(doc["activePageId"], "p1");
+        let page = &doc["pages"]["p1"];
+        assert_eq!(page["shapeOrder"], json!(["a", "c", "g"]));
+        assert_eq!(page["shapes"]["b"]["id"], "b");
+        assert_eq!(page["shapes"]["c"]["startShapeId"], "a");
+        assert_eq!(page["shapes"]["c"]["endShapeId"], Value::Null);
+        assert_eq!(page["shapes"]["g"]["childIds"], json!(["b"]));
+
+        assert!(issues.iter().any(|i| i.path == "/pages/p1/shapes/c/endShapeId"));
+        assert_eq!(issues.len(), 9);
+
+        // Repaired output is clean
+        assert!(validate_document(&mut doc, ValidationMode::Strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_strict_rejects_without_modifying() {
+        let mut doc = broken_doc();
+        let err = validate_document(&mut doc, ValidationMode::Strict).unwrap_err();
+        assert!(err.starts_with(error_codes::INVALID_DOCUMENT));
+        assert_eq!(doc, broken_doc());
+
+        // Unrepairable documents are rejected in lenient mode too
+        let mut doc = json!({"id": "doc-1", "pages": []});
+        assert!(validate_document(&mut doc, ValidationMode::Lenient).is_err());
+    }
+}
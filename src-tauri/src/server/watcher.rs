@@ -0,0 +1,244 @@
+//! Keeps store instances in sync with changes made on disk
+//!
+//! The collaboration server and the MCP server each keep their own
+//! `DocumentStore` over the same storage, and a backup restore or another
+//! process can rewrite it underneath both. [`StoreSync`] reloads a store's
+//! indexes and reports which documents changed by comparing their
+//! `serverVersion` before and after. A store's own writes are already in its
+//! index, so they produce no events.
+//!
+//! [`spawn_watch_task`] runs a sync whenever the backend's files change
+//! (see [`WatchTarget`]), after letting a burst of writes settle.
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::blobs::BlobStore;
+use super::documents::{DocumentMetadata, DocumentStore};
+use super::protocol::DocEventType;
+use crate::storage::{Storage, WatchTarget};
+
+/// Quiet period after a file event before the store is reloaded
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// A document created, changed or removed by someone else
+#[derive(Debug, Clone)]
+pub struct DocChange {
+    pub doc_id: String,
+    pub event_type: DocEventType,
+    /// Current metadata (`None` for deletions)
+    pub metadata: Option<DocumentMetadata>,
+}
+
+/// Documents whose metadata differs between two index snapshots, by ID
+fn diff(before: &HashMap<String, DocumentMetadata>, after: &HashMap<String, DocumentMetadata>) -> Vec<DocChange> {
+    let mut changes: Vec<DocChange> = after
+        .values()
+        .filter_map(|metadata| {
+            let event_type = match before.get(&metadata.id) {
+                None => DocEventType::Created,
+                Some(old) if old.server_version != metadata.server_version || old.modified_at != metadata.modified_at => {
+                    DocEventType::Updated
+                }
+                Some(_) => return None,
+            };
+            Some(DocChange {
+                doc_id: metadata.id.clone(),
+                event_type,
+                metadata: Some(metadata.clone()),
+            })
+        })
+        .chain(before.keys().filter(|id| !after.contains_key(*id)).map(|id| DocChange {
+            doc_id: id.clone(),
+            event_type: DocEventType::Deleted,
+            metadata: None,
+        }))
+        .collect();
+    changes.sort_by(|a, b| a.doc_id.cmp(&b.doc_id));
+    changes
+}
+
+/// Reloads a document store (and optionally a blob store) from storage
+pub struct StoreSync {
+    doc_store: Arc<DocumentStore>,
+    blob_store: Option<Arc<BlobStore>>,
+    /// Keeps concurrent syncs from reporting the same change twice
+    lock: Mutex<()>,
+}
+
+impl StoreSync {
+    pub fn new(doc_store: Arc<DocumentStore>, blob_store: Option<Arc<BlobStore>>) -> Self {
+        Self {
+            doc_store,
+            blob_store,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Reload from storage and return the documents changed since the last
+    /// reload or local write
+    pub fn sync(&self) -> Vec<DocChange> {
+        let Ok(_guard) = self.lock.lock() else {
+            return Vec::new();
+        };
+        let snapshot = |store: &DocumentStore| -> HashMap<String, DocumentMetadata> {
+            store.list_documents().into_iter().map(|m| (m.id.clone(), m)).collect()
+        };
+        // Pausing writes keeps a half-finished local save from looking external
+        let reloaded = self.doc_store.pause_writes(|| {
+            let before = snapshot(&self.doc_store);
+            self.doc_store.reload_index();
+            (before, snapshot(&self.doc_store))
+        });
+        let changes = match reloaded {
+            Ok((before, after)) => diff(&before, &after),
+            Err(e) => {
+                log::warn!("Failed to reload document store: {}", e);
+                Vec::new()
+            }
+        };
+        if let Some(blob_store) = &self.blob_store {
+            blob_store.reload_index();
+            // Writers without a blob store leave the references behind
+            for change in &changes {
+                let hashes = self.doc_store.document_blob_references(&change.doc_id).unwrap_or_default();
+                if let Err(e) = blob_store.set_document_references(&change.doc_id, &hashes) {
+                    log::warn!("Failed to update blob references of {}: {}", change.doc_id, e);
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Whether a file event may reflect a change to the stored data
+fn is_relevant(target: &WatchTarget, event: &notify::Event) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    match &target.file_prefix {
+        Some(prefix) => event.paths.iter().any(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with(prefix.as_str()))
+                .unwrap_or(false)
+        }),
+        None => true,
+    }
+}
+
+/// Watch `storage` and sync whenever its files change, passing external
+/// changes to `on_changes`. The caller aborts the handle on shutdown, which
+/// also stops the watcher.
+pub fn spawn_watch_task(
+    storage: &dyn Storage,
+    sync: Arc<StoreSync>,
+    on_changes: impl Fn(Vec<DocChange>) + Send + Sync + 'static,
+) -> Result<tokio::task::JoinHandle<()>, String> {
+    let target = storage.watch_target().ok_or("Storage backend cannot be watched")?;
+    std::fs::create_dir_all(&target.dir).map_err(|e| format!("Failed to create {}: {}", target.dir.display(), e))?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let filter = target.clone();
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+        Ok(event) if is_relevant(&filter, &event) => {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Storage watch error: {}", e),
+    })
+    .map_err(|e| format!("Failed to create storage watcher: {}", e))?;
+    let mode = if target.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher
+        .watch(&target.dir, mode)
+        .map_err(|e| format!("Failed to watch {}: {}", target.dir.display(), e))?;
+    log::info!("Watching {} for external storage changes", target.dir.display());
+
+    Ok(tokio::spawn(async move {
+        // Dropped (and the OS watch removed) when the task ends
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            let sync = sync.clone();
+            match tokio::task::spawn_blocking(move || sync.sync()).await {
+                Ok(changes) if !changes.is_empty() => {
+                    log::info!("Picked up {} external document change(s)", changes.len());
+                    on_changes(changes);
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Storage sync task failed: {}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn doc(id: &str, name: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "name": name,
+            "pages": {},
+            "pageOrder": [],
+            "createdAt": 1,
+            "modifiedAt": 1
+        })
+    }
+
+    #[test]
+    fn test_sync_reports_only_external_changes() {
+        let dir = tempdir().unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        let ours = Arc::new(DocumentStore::with_storage(storage.clone()));
+        let theirs = DocumentStore::with_storage(storage);
+        let sync = StoreSync::new(ours.clone(), None);
+
+        ours.save_document(doc("mine", "Mine")).unwrap();
+        ours.save_document(doc("gone", "Gone")).unwrap();
+        assert!(sync.sync().is_empty());
+
+        theirs.reload_index();
+        theirs.save_document(doc("new", "New")).unwrap();
+        theirs.save_document(doc("mine", "Renamed")).unwrap();
+        theirs.delete_document("gone", None, None).unwrap();
+
+        let changes: Vec<(String, DocEventType)> =
+            sync.sync().into_iter().map(|c| (c.doc_id, c.event_type)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("gone".to_string(), DocEventType::Deleted),
+                ("mine".to_string(), DocEventType::Updated),
+                ("new".to_string(), DocEventType::Created),
+            ]
+        );
+        assert_eq!(ours.get_metadata("mine").unwrap().name, "Renamed");
+        assert!(sync.sync().is_empty());
+    }
+
+    #[test]
+    fn test_prefix_filter() {
+        let target = WatchTarget {
+            dir: "/data".into(),
+            recursive: false,
+            file_prefix: Some("team.db".to_string()),
+        };
+        let event = |path: &str| notify::Event::new(EventKind::Any).add_path(path.into());
+        assert!(is_relevant(&target, &event("/data/team.db-wal")));
+        assert!(!is_relevant(&target, &event("/data/users.json")));
+        let access = notify::Event::new(EventKind::Access(notify::event::AccessKind::Any)).add_path("/data/team.db".into());
+        assert!(!is_relevant(&target, &access));
+    }
+}
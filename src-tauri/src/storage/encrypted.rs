@@ -0,0 +1,789 @@
+//! Encryption at rest for team storage
+//!
+//! [`EncryptedStorage`] wraps another [`Storage`] and seals every value with
+//! XChaCha20-Poly1305 before it reaches the backend, so `DocumentStore`,
+//! `BlobStore` and friends work unchanged. Each value is stored as
+//! `MAGIC || nonce || ciphertext`, with `<space>/<key>` as associated data
+//! so sealed values cannot be swapped between keys.
+//!
+//! The host key is either derived from an admin passphrase with Argon2id or
+//! read from a key file holding 32 random bytes (hex). The KDF parameters
+//! and a key check value live unencrypted under the `encryption` meta key,
+//! so the data stays self-describing when it is copied between backends.
+//! Once every existing value has been sealed, a protected value without the
+//! sealed prefix is an error rather than plaintext.
+//!
+//! User accounts are not encrypted: the user store is needed to log in
+//! before the storage is unlocked.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{Space, Storage, StorageBackend, WatchTarget, WriteOp};
+use crate::fs_util;
+
+/// Meta-space key of the (unencrypted) encryption marker
+const MARKER_KEY: &str = "encryption";
+
+/// Prefix of every sealed value
+const MAGIC: &[u8; 4] = b"DGE1";
+
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// Sealed to verify a key before anything is decrypted with it
+const KEY_CHECK_PLAINTEXT: &[u8] = b"diagrammer key check";
+const KEY_CHECK_AAD: &[u8] = b"key-check";
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Where the host key comes from (stored in the server config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum KeySource {
+    /// Derived from a passphrase entered by an admin when the server starts
+    Passphrase,
+    /// Read from a key file, created with a random key if missing
+    KeyFile { path: PathBuf },
+}
+
+/// Secret used to unlock or re-key the storage
+#[derive(Clone)]
+pub enum KeySecret {
+    Passphrase(String),
+    KeyFile(PathBuf),
+}
+
+/// How the key in use was obtained
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Kdf {
+    Argon2id {
+        salt: String,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+    KeyFile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptionMarker {
+    kdf: Kdf,
+    /// `KEY_CHECK_PLAINTEXT` sealed with the key, hex-encoded
+    key_check: String,
+    /// False while existing plaintext values are being encrypted. Once
+    /// true, protected values without the sealed prefix are refused.
+    complete: bool,
+    /// Re-key in progress; values may be sealed with either key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending: Option<PendingRekey>,
+}
+
+/// A re-key that has not finished. It is written before any value is
+/// re-encrypted, and each key is sealed with the other, so either secret
+/// can unlock the storage and complete the re-key after a crash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingRekey {
+    kdf: Kdf,
+    /// Key check of the new key
+    key_check: String,
+    /// Old key sealed with the new key, hex-encoded
+    previous_key: String,
+    /// New key sealed with the old key, hex-encoded
+    next_key: String,
+}
+
+const PREVIOUS_KEY_AAD: &[u8] = b"previous-key";
+const NEXT_KEY_AAD: &[u8] = b"next-key";
+
+/// Keys unlocked in this process, by key check. Lets other components
+/// (e.g. the MCP server) open the same storage without asking again.
+static UNLOCKED: Mutex<Option<HashMap<String, [u8; KEY_LEN]>>> = Mutex::new(None);
+
+fn remember_key(key_check: &str, key: [u8; KEY_LEN]) {
+    if let Ok(mut unlocked) = UNLOCKED.lock() {
+        unlocked.get_or_insert_with(HashMap::new).insert(key_check.to_string(), key);
+    }
+}
+
+fn remembered_key(key_check: &str) -> Option<[u8; KEY_LEN]> {
+    UNLOCKED.lock().ok()?.as_ref()?.get(key_check).copied()
+}
+
+/// Remember the keys a marker needs, so [`attach`] can find them
+fn remember_keys(marker: &EncryptionMarker, keys: &Keys) {
+    match (&marker.pending, keys.previous) {
+        (Some(pending), Some(previous)) => {
+            remember_key(&pending.key_check, keys.current);
+            remember_key(&marker.key_check, previous);
+        }
+        _ => remember_key(&marker.key_check, keys.current),
+    }
+}
+
+fn remembered_keys(marker: &EncryptionMarker) -> Option<Keys> {
+    Some(match &marker.pending {
+        Some(pending) => Keys {
+            current: remembered_key(&pending.key_check)?,
+            previous: Some(remembered_key(&marker.key_check)?),
+            complete: marker.complete,
+        },
+        None => Keys {
+            current: remembered_key(&marker.key_check)?,
+            previous: None,
+            complete: marker.complete,
+        },
+    })
+}
+
+fn cipher_for(key: &[u8; KEY_LEN]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+fn seal_with(cipher: &XChaCha20Poly1305, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| "Encryption failed".to_string())?;
+    let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn is_sealed(value: &[u8]) -> bool {
+    value.len() >= MAGIC.len() + NONCE_LEN && value.starts_with(MAGIC)
+}
+
+/// Decrypt a sealed value
+fn open_with(cipher: &XChaCha20Poly1305, aad: &[u8], value: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_sealed(value) {
+        return Err("value is not encrypted".to_string());
+    }
+    let (nonce, ciphertext) = value[MAGIC.len()..].split_at(NONCE_LEN);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| "wrong key or corrupted data".to_string())
+}
+
+fn value_aad(space: Space, key: &str) -> Vec<u8> {
+    format!("{}/{}", space.as_str(), key).into_bytes()
+}
+
+fn update_aad(doc_id: &str) -> Vec<u8> {
+    format!("updates/{}", doc_id).into_bytes()
+}
+
+/// Whether a value is sealed. User accounts and the marker stay readable.
+fn is_protected(space: Space, key: &str) -> bool {
+    match space {
+        Space::Users => false,
+        Space::Meta => key != MARKER_KEY,
+        _ => true,
+    }
+}
+
+fn read_marker(storage: &dyn Storage) -> Result<Option<EncryptionMarker>, String> {
+    match storage.get(Space::Meta, MARKER_KEY)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse encryption marker: {}", e)),
+        None => Ok(None),
+    }
+}
+
+fn write_marker(storage: &dyn Storage, marker: &EncryptionMarker) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(marker).map_err(|e| format!("Serialize error: {}", e))?;
+    storage.put(Space::Meta, MARKER_KEY, &data)
+}
+
+/// Read a key file, creating it with a fresh random key if it is missing
+fn load_key_file(path: &Path) -> Result<[u8; KEY_LEN], String> {
+    if !path.exists() {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        fs_util::write_atomic(path, hex::encode(key))
+            .map_err(|e| format!("Failed to write key file {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+        }
+        log::info!("Created storage key file {}", path.display());
+        return Ok(key);
+    }
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read key file {}: {}", path.display(), e))?;
+    hex::decode(text.trim())
+        .ok()
+        .and_then(|bytes| <[u8; KEY_LEN]>::try_from(bytes).ok())
+        .ok_or_else(|| format!("Key file {} must contain {} hex-encoded bytes", path.display(), KEY_LEN))
+}
+
+/// Derive the key for `secret`. With `kdf` from an existing marker the same
+/// parameters are reused; otherwise fresh ones are chosen.
+fn derive_key(secret: &KeySecret, kdf: Option<&Kdf>) -> Result<([u8; KEY_LEN], Kdf), String> {
+    match (secret, kdf) {
+        (KeySecret::Passphrase(passphrase), None | Some(Kdf::Argon2id { .. })) => {
+            if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+                return Err(format!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN));
+            }
+            let kdf = match kdf {
+                Some(kdf) => kdf.clone(),
+                None => {
+                    let mut salt = [0u8; SALT_LEN];
+                    OsRng.fill_bytes(&mut salt);
+                    Kdf::Argon2id {
+                        salt: hex::encode(salt),
+                        m_cost: Params::DEFAULT_M_COST,
+                        t_cost: Params::DEFAULT_T_COST,
+                        p_cost: Params::DEFAULT_P_COST,
+                    }
+                }
+            };
+            let Kdf::Argon2id { salt, m_cost, t_cost, p_cost } = &kdf else {
+                unreachable!("passphrase keys always use Argon2id");
+            };
+            let salt = hex::decode(salt).map_err(|e| format!("Invalid salt in encryption marker: {}", e))?;
+            let params = Params::new(*m_cost, *t_cost, *p_cost, Some(KEY_LEN))
+                .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+            let mut key = [0u8; KEY_LEN];
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                .map_err(|e| format!("Key derivation failed: {}", e))?;
+            Ok((key, kdf))
+        }
+        (KeySecret::KeyFile(path), None | Some(Kdf::KeyFile)) => Ok((load_key_file(path)?, Kdf::KeyFile)),
+        (KeySecret::Passphrase(_), Some(Kdf::KeyFile)) => {
+            Err("Team storage is encrypted with a key file, not a passphrase".to_string())
+        }
+        (KeySecret::KeyFile(_), Some(Kdf::Argon2id { .. })) => {
+            Err("Team storage is encrypted with a passphrase, not a key file".to_string())
+        }
+    }
+}
+
+fn key_check_for(key: &[u8; KEY_LEN]) -> Result<String, String> {
+    seal_with(&cipher_for(key), KEY_CHECK_AAD, KEY_CHECK_PLAINTEXT).map(hex::encode)
+}
+
+fn new_marker(key: &[u8; KEY_LEN], kdf: Kdf, complete: bool) -> Result<EncryptionMarker, String> {
+    Ok(EncryptionMarker {
+        kdf,
+        key_check: key_check_for(key)?,
+        complete,
+        pending: None,
+    })
+}
+
+fn verify_key(key_check: &str, key: &[u8; KEY_LEN]) -> Result<(), String> {
+    let check = hex::decode(key_check).map_err(|e| format!("Invalid key check: {}", e))?;
+    match open_with(&cipher_for(key), KEY_CHECK_AAD, &check) {
+        Ok(plaintext) if plaintext == KEY_CHECK_PLAINTEXT => Ok(()),
+        _ => Err("Wrong passphrase or key file for the encrypted team storage".to_string()),
+    }
+}
+
+/// Seal `key` with `wrapping_key` (hex-encoded)
+fn wrap_key(wrapping_key: &[u8; KEY_LEN], aad: &[u8], key: &[u8; KEY_LEN]) -> Result<String, String> {
+    seal_with(&cipher_for(wrapping_key), aad, key).map(hex::encode)
+}
+
+fn unwrap_key(wrapping_key: &[u8; KEY_LEN], aad: &[u8], wrapped: &str) -> Result<[u8; KEY_LEN], String> {
+    let sealed = hex::decode(wrapped).map_err(|e| format!("Invalid wrapped key: {}", e))?;
+    open_with(&cipher_for(wrapping_key), aad, &sealed)
+        .ok()
+        .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+        .ok_or_else(|| "Failed to recover the key of an unfinished re-key".to_string())
+}
+
+/// Keys for the marker, from `secret`. During an unfinished re-key either
+/// the old or the new secret works and yields both keys.
+fn unlock_keys(marker: &EncryptionMarker, secret: &KeySecret) -> Result<Keys, String> {
+    let Some(pending) = &marker.pending else {
+        let (key, _) = derive_key(secret, Some(&marker.kdf))?;
+        verify_key(&marker.key_check, &key)?;
+        return Ok(Keys {
+            current: key,
+            previous: None,
+            complete: marker.complete,
+        });
+    };
+
+    let old_key = derive_key(secret, Some(&marker.kdf))
+        .ok()
+        .map(|(key, _)| key)
+        .filter(|key| verify_key(&marker.key_check, key).is_ok());
+    let (current, previous) = match old_key {
+        Some(old_key) => (unwrap_key(&old_key, NEXT_KEY_AAD, &pending.next_key)?, old_key),
+        None => {
+            let (new_key, _) = derive_key(secret, Some(&pending.kdf))?;
+            verify_key(&pending.key_check, &new_key)?;
+            (new_key, unwrap_key(&new_key, PREVIOUS_KEY_AAD, &pending.previous_key)?)
+        }
+    };
+    Ok(Keys {
+        current,
+        previous: Some(previous),
+        complete: marker.complete,
+    })
+}
+
+/// Whether the data in `storage` is encrypted
+pub fn is_encrypted(storage: &dyn Storage) -> Result<bool, String> {
+    Ok(read_marker(storage)?.is_some())
+}
+
+/// Unlock encrypted storage with `secret`, or enable encryption if the data
+/// is not encrypted yet (sealing every existing value). An interrupted
+/// re-key is completed first.
+pub fn unlock(storage: Arc<dyn Storage>, secret: &KeySecret) -> Result<Arc<EncryptedStorage>, String> {
+    let (marker, keys) = match read_marker(storage.as_ref())? {
+        Some(marker) => {
+            let keys = unlock_keys(&marker, secret)?;
+            (marker, keys)
+        }
+        None => {
+            let (key, kdf) = derive_key(secret, None)?;
+            let marker = new_marker(&key, kdf, false)?;
+            write_marker(storage.as_ref(), &marker)?;
+            log::info!("Enabling encryption at rest for team storage");
+            let keys = Keys {
+                current: key,
+                previous: None,
+                complete: false,
+            };
+            (marker, keys)
+        }
+    };
+    remember_keys(&marker, &keys);
+
+    let encrypted = Arc::new(EncryptedStorage {
+        inner: storage,
+        keys: RwLock::new(keys),
+    });
+    if let Some(pending) = marker.pending {
+        let mut keys = encrypted.keys.write().map_err(|e| e.to_string())?;
+        let count = encrypted.finish_rekey(&mut keys, pending)?;
+        log::info!("Completed an interrupted re-key of team storage ({} value(s))", count);
+    } else if !marker.complete {
+        let mut keys = encrypted.keys.write().map_err(|e| e.to_string())?;
+        let sealed = encrypted.reseal(&keys, true)?;
+        write_marker(encrypted.inner.as_ref(), &EncryptionMarker { complete: true, ..marker })?;
+        keys.complete = true;
+        log::info!("Encrypted {} existing value(s) in team storage", sealed);
+    }
+    Ok(encrypted)
+}
+
+/// Wrap `storage` if its data is encrypted, using a key already unlocked in
+/// this process. Unencrypted storage is returned as-is.
+pub fn attach(storage: Arc<dyn Storage>) -> Result<Arc<dyn Storage>, String> {
+    let Some(marker) = read_marker(storage.as_ref())? else {
+        return Ok(storage);
+    };
+    let keys = remembered_keys(&marker)
+        .ok_or("Team storage is encrypted; start the collaboration server with the storage key first")?;
+    Ok(Arc::new(EncryptedStorage {
+        inner: storage,
+        keys: RwLock::new(keys),
+    }))
+}
+
+/// Keys in use by an [`EncryptedStorage`]
+struct Keys {
+    /// Seals new values
+    current: [u8; KEY_LEN],
+    /// Key being replaced by an unfinished re-key; values may still be
+    /// sealed with it
+    previous: Option<[u8; KEY_LEN]>,
+    /// Whether every protected value is sealed. Plaintext is accepted only
+    /// while encryption is first being enabled.
+    complete: bool,
+}
+
+impl Keys {
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        seal_with(&cipher_for(&self.current), aad, plaintext)
+    }
+
+    fn open(&self, aad: &[u8], value: Vec<u8>) -> Result<Vec<u8>, String> {
+        if !is_sealed(&value) {
+            // Plaintext left over from before encryption was enabled. After
+            // that it can only be data planted by someone else.
+            return if self.complete {
+                Err("value is not encrypted".to_string())
+            } else {
+                Ok(value)
+            };
+        }
+        match open_with(&cipher_for(&self.current), aad, &value) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(e) => match &self.previous {
+                Some(previous) => open_with(&cipher_for(previous), aad, &value),
+                None => Err(e),
+            },
+        }
+    }
+}
+
+/// Storage decorator that seals values on write and opens them on read
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    keys: RwLock<Keys>,
+}
+
+impl EncryptedStorage {
+    fn seal(&self, space: Space, key: &str, value: &[u8]) -> Result<Vec<u8>, String> {
+        if !is_protected(space, key) {
+            return Ok(value.to_vec());
+        }
+        let keys = self.keys.read().map_err(|e| e.to_string())?;
+        keys.seal(&value_aad(space, key), value)
+    }
+
+    fn open(&self, space: Space, key: &str, value: Vec<u8>) -> Result<Vec<u8>, String> {
+        if !is_protected(space, key) {
+            return Ok(value);
+        }
+        let keys = self.keys.read().map_err(|e| e.to_string())?;
+        keys.open(&value_aad(space, key), value)
+            .map_err(|e| format!("Failed to decrypt {}/{}: {}", space.as_str(), key, e))
+    }
+
+    /// Seal protected values and update logs with the current key. With
+    /// `plaintext_only`, values that are already sealed are left alone.
+    /// Returns how many values were written.
+    fn reseal(&self, keys: &Keys, plaintext_only: bool) -> Result<usize, String> {
+        let mut count = 0;
+        for space in Space::ALL {
+            for key in self.inner.list(space, "")? {
+                if !is_protected(space, &key) {
+                    continue;
+                }
+                let Some(value) = self.inner.get(space, &key)? else {
+                    continue;
+                };
+                if plaintext_only && is_sealed(&value) {
+                    continue;
+                }
+                let aad = value_aad(space, &key);
+                let plaintext = keys
+                    .open(&aad, value)
+                    .map_err(|e| format!("Failed to decrypt {}/{}: {}", space.as_str(), key, e))?;
+                self.inner.put(space, &key, &keys.seal(&aad, &plaintext)?)?;
+                count += 1;
+            }
+        }
+        for doc_id in self.inner.list_update_logs()? {
+            let updates = self.inner.load_updates(&doc_id)?;
+            if plaintext_only && updates.iter().all(|u| is_sealed(u)) {
+                continue;
+            }
+            let aad = update_aad(&doc_id);
+            let updates: Vec<Vec<u8>> = updates
+                .into_iter()
+                .map(|u| keys.open(&aad, u))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Failed to decrypt update log of {}: {}", doc_id, e))?;
+            self.inner.clear_updates(&doc_id)?;
+            for update in updates {
+                self.inner.append_update(&doc_id, &keys.seal(&aad, &update)?)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Re-encrypt everything under a key from `secret` (a new passphrase or
+    /// key file) and return the number of values re-encrypted. Reads and
+    /// writes wait until it finishes.
+    ///
+    /// The new key's parameters are recorded before anything is
+    /// re-encrypted, and values sealed with either key stay readable, so a
+    /// crash part-way is finished by the next [`unlock`] with either secret.
+    pub fn rekey(&self, secret: &KeySecret) -> Result<usize, String> {
+        let mut keys = self.keys.write().map_err(|e| e.to_string())?;
+        let pending = self.begin_rekey(&mut keys, secret)?;
+        let count = self.finish_rekey(&mut keys, pending)?;
+        log::info!("Re-encrypted {} value(s) in team storage with a new key", count);
+        Ok(count)
+    }
+
+    /// Record the pending re-key and switch to the new key for writes
+    fn begin_rekey(&self, keys: &mut Keys, secret: &KeySecret) -> Result<PendingRekey, String> {
+        let marker = read_marker(self.inner.as_ref())?.ok_or("Team storage is not encrypted")?;
+        if marker.pending.is_some() {
+            return Err("A previous re-key has not finished".to_string());
+        }
+        let (new_key, kdf) = derive_key(secret, None)?;
+        let old_key = keys.current;
+        let pending = PendingRekey {
+            kdf,
+            key_check: key_check_for(&new_key)?,
+            previous_key: wrap_key(&new_key, PREVIOUS_KEY_AAD, &old_key)?,
+            next_key: wrap_key(&old_key, NEXT_KEY_AAD, &new_key)?,
+        };
+        let marker = EncryptionMarker {
+            pending: Some(pending.clone()),
+            ..marker
+        };
+        write_marker(self.inner.as_ref(), &marker)?;
+
+        keys.current = new_key;
+        keys.previous = Some(old_key);
+        remember_keys(&marker, keys);
+        Ok(pending)
+    }
+
+    /// Re-encrypt every value with the new key, then make it the only key
+    fn finish_rekey(&self, keys: &mut Keys, pending: PendingRekey) -> Result<usize, String> {
+        let count = self.reseal(keys, false)?;
+        let marker = EncryptionMarker {
+            kdf: pending.kdf,
+            key_check: pending.key_check,
+            complete: true,
+            pending: None,
+        };
+        write_marker(self.inner.as_ref(), &marker)?;
+        keys.previous = None;
+        keys.complete = true;
+        remember_keys(&marker, keys);
+        Ok(count)
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn backend(&self) -> StorageBackend {
+        self.inner.backend()
+    }
+
+    fn get(&self, space: Space, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self.inner.get(space, key)? {
+            Some(value) => self.open(space, key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, space: Space, key: &str, value: &[u8]) -> Result<(), String> {
+        self.inner.put(space, key, &self.seal(space, key, value)?)
+    }
+
+    fn exists(&self, space: Space, key: &str) -> Result<bool, String> {
+        self.inner.exists(space, key)
+    }
+
+    fn delete(&self, space: Space, key: &str) -> Result<bool, String> {
+        self.inner.delete(space, key)
+    }
+
+    fn list(&self, space: Space, prefix: &str) -> Result<Vec<String>, String> {
+        let mut keys = self.inner.list(space, prefix)?;
+        if space == Space::Meta {
+            keys.retain(|k| k != MARKER_KEY);
+        }
+        Ok(keys)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), String> {
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Put { space, key, value } => Ok(WriteOp::Put {
+                    value: self.seal(space, &key, &value)?,
+                    space,
+                    key,
+                }),
+                delete => Ok(delete),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        self.inner.write_batch(ops)
+    }
+
+    /// Moves the sealed value aside without decrypting it
+    fn quarantine(&self, space: Space, key: &str) -> Result<(), String> {
+        self.inner.quarantine(space, key)
+    }
+
+    fn append_update(&self, doc_id: &str, update: &[u8]) -> Result<(), String> {
+        let keys = self.keys.read().map_err(|e| e.to_string())?;
+        self.inner.append_update(doc_id, &keys.seal(&update_aad(doc_id), update)?)
+    }
+
+    fn load_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>, String> {
+        let keys = self.keys.read().map_err(|e| e.to_string())?;
+        let aad = update_aad(doc_id);
+        self.inner
+            .load_updates(doc_id)?
+            .into_iter()
+            .map(|u| keys.open(&aad, u).map_err(|e| format!("Failed to decrypt update log of {}: {}", doc_id, e)))
+            .collect()
+    }
+
+    fn clear_updates(&self, doc_id: &str) -> Result<(), String> {
+        self.inner.clear_updates(doc_id)
+    }
+
+    fn list_update_logs(&self) -> Result<Vec<String>, String> {
+        self.inner.list_update_logs()
+    }
+
+    fn watch_target(&self) -> Option<WatchTarget> {
+        self.inner.watch_target()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::FsStorage;
+    use tempfile::tempdir;
+
+    const DOC: &[u8] = br#"{"id":"doc-1","name":"Secret plans"}"#;
+
+    fn passphrase(text: &str) -> KeySecret {
+        KeySecret::Passphrase(text.to_string())
+    }
+
+    fn file_contains(dir: &Path, needle: &[u8]) -> bool {
+        fn walk(dir: &Path, needle: &[u8]) -> bool {
+            std::fs::read_dir(dir).into_iter().flatten().flatten().any(|entry| {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, needle)
+                } else {
+                    std::fs::read(&path)
+                        .map(|data| data.windows(needle.len()).any(|w| w == needle))
+                        .unwrap_or(false)
+                }
+            })
+        }
+        walk(dir, needle)
+    }
+
+    #[test]
+    fn test_enabling_encrypts_existing_data() {
+        let dir = tempdir().unwrap();
+        let raw: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        raw.put(Space::Documents, "doc-1", DOC).unwrap();
+        raw.append_update("doc-1", b"Secret update").unwrap();
+        raw.put(Space::Users, "users", b"{}").unwrap();
+        assert!(!is_encrypted(raw.as_ref()).unwrap());
+
+        let storage = unlock(raw.clone(), &passphrase("correct horse")).unwrap();
+        assert!(is_encrypted(raw.as_ref()).unwrap());
+        assert!(!file_contains(&dir.path().join("team_documents"), b"Secret"));
+        assert_eq!(storage.get(Space::Documents, "doc-1").unwrap().unwrap(), DOC);
+        assert_eq!(storage.load_updates("doc-1").unwrap(), vec![b"Secret update".to_vec()]);
+        assert_eq!(raw.get(Space::Users, "users").unwrap().unwrap(), b"{}");
+        assert!(storage.list(Space::Meta, "").unwrap().is_empty());
+
+        // New writes are sealed too, and bound to their key
+        storage.put(Space::Blobs, "abcd", b"Secret blob").unwrap();
+        assert!(!file_contains(&dir.path().join("team_documents"), b"Secret"));
+        let sealed = raw.get(Space::Blobs, "abcd").unwrap().unwrap();
+        raw.put(Space::Blobs, "ef01", &sealed).unwrap();
+        assert!(storage.get(Space::Blobs, "ef01").is_err());
+
+        assert!(unlock(raw.clone(), &passphrase("wrong horse")).is_err());
+        assert!(unlock(raw.clone(), &KeySecret::KeyFile(dir.path().join("key"))).is_err());
+        let reopened = attach(raw).unwrap();
+        assert_eq!(reopened.get(Space::Documents, "doc-1").unwrap().unwrap(), DOC);
+    }
+
+    #[test]
+    fn test_rekey_to_key_file() {
+        let dir = tempdir().unwrap();
+        let raw: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().join("data")));
+        let storage = unlock(raw.clone(), &passphrase("first passphrase")).unwrap();
+        storage.put(Space::Documents, "doc-1", DOC).unwrap();
+        storage
+            .write_batch(vec![WriteOp::Put {
+                space: Space::Meta,
+                key: "index".to_string(),
+                value: b"{}".to_vec(),
+            }])
+            .unwrap();
+
+        let key_file = dir.path().join("storage.key");
+        assert_eq!(storage.rekey(&KeySecret::KeyFile(key_file.clone())).unwrap(), 2);
+        assert!(key_file.exists());
+        assert_eq!(storage.get(Space::Documents, "doc-1").unwrap().unwrap(), DOC);
+
+        assert!(unlock(raw.clone(), &passphrase("first passphrase")).is_err());
+        let reopened = unlock(raw, &KeySecret::KeyFile(key_file)).unwrap();
+        assert_eq!(reopened.get(Space::Meta, "index").unwrap().unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_short_passphrase_and_bad_key_file_rejected() {
+        let dir = tempdir().unwrap();
+        let raw: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        assert!(unlock(raw.clone(), &passphrase("short")).is_err());
+
+        let key_file = dir.path().join("bad.key");
+        std::fs::write(&key_file, "not hex").unwrap();
+        assert!(unlock(raw.clone(), &KeySecret::KeyFile(key_file)).is_err());
+        assert!(!is_encrypted(raw.as_ref()).unwrap());
+    }
+
+    #[test]
+    fn test_unsealed_values_refused_once_complete() {
+        let dir = tempdir().unwrap();
+        let raw: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+        let storage = unlock(raw.clone(), &passphrase("correct horse")).unwrap();
+
+        raw.put(Space::Documents, "planted", DOC).unwrap();
+        raw.append_update("planted", b"planted update").unwrap();
+        assert!(storage.get(Space::Documents, "planted").is_err());
+        assert!(storage.load_updates("planted").is_err());
+
+        // Attached handles and later unlocks refuse it as well
+        assert!(attach(raw.clone()).unwrap().get(Space::Documents, "planted").is_err());
+        let reopened = unlock(raw, &passphrase("correct horse")).unwrap();
+        assert!(reopened.get(Space::Documents, "planted").is_err());
+    }
+
+    #[test]
+    fn test_interrupted_rekey_completes_with_either_secret() {
+        for resume_with in ["old passphrase", "new passphrase"] {
+            let dir = tempdir().unwrap();
+            let raw: Arc<dyn Storage> = Arc::new(FsStorage::new(dir.path().to_path_buf()));
+            let storage = unlock(raw.clone(), &passphrase("old passphrase")).unwrap();
+            storage.put(Space::Documents, "doc-1", DOC).unwrap();
+            storage.put(Space::Documents, "doc-2", DOC).unwrap();
+
+            // Crash after the pending marker and one re-encrypted value
+            {
+                let mut keys = storage.keys.write().unwrap();
+                storage.begin_rekey(&mut keys, &passphrase("new passphrase")).unwrap();
+                let aad = value_aad(Space::Documents, "doc-1");
+                raw.put(Space::Documents, "doc-1", &keys.seal(&aad, DOC).unwrap()).unwrap();
+            }
+            // Both keys are in use, and both still read
+            assert_eq!(storage.get(Space::Documents, "doc-1").unwrap().unwrap(), DOC);
+            assert_eq!(storage.get(Space::Documents, "doc-2").unwrap().unwrap(), DOC);
+            drop(storage);
+
+            let resumed = unlock(raw.clone(), &passphrase(resume_with)).unwrap();
+            assert_eq!(resumed.get(Space::Documents, "doc-1").unwrap().unwrap(), DOC);
+            assert_eq!(resumed.get(Space::Documents, "doc-2").unwrap().unwrap(), DOC);
+            assert!(read_marker(raw.as_ref()).unwrap().unwrap().pending.is_none());
+
+            assert!(unlock(raw.clone(), &passphrase("old passphrase")).is_err());
+            let reopened = unlock(raw, &passphrase("new passphrase")).unwrap();
+            assert_eq!(reopened.get(Space::Documents, "doc-2").unwrap().unwrap(), DOC);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! Filesystem storage backend
+//!
+//! Keeps the original on-disk layout so existing installations need no
+//! migration:
+//!
+//! ```text
+//! app_data_dir/
+//!   users.json                      # Space::Users ("users")
+//!   team_documents/
+//!     docs/<id>.json                # Space::Documents
+//!     trash/<id>.json               # Space::Trash
+//!     revisions/<id>/<ver>.json     # Space::Revisions ("<id>/<ver>")
+//!     history/<id>/<name>.json      # Space::History ("<id>/<name>")
+//!     blobs/ab/cd/<hash>            # Space::Blobs
+//!     previews/<hash>/<size>        # Space::Previews ("<hash>/<size>")
+//!     templates/<id>.json           # Space::Templates
+//!     updates/<id>.log              # Update log
+//!     <name>.json                   # Space::Meta (index, blob_index, ...)
+//! ```
+//!
+//! Update logs are a sequence of frames, each a little-endian `u32` length
+//! followed by that many bytes.
+
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::{write_guard, Space, Storage, StorageBackend, WatchTarget};
+use crate::fs_util;
+
+/// The user space shares the app data directory with unrelated files
+/// (MCP config, storage marker), so only these keys are listed.
+const USER_KEYS: &[&str] = &["users"];
+
+const UPDATE_LOG_EXTENSION: &str = "log";
+
+/// Storage backed by plain files under the app data directory
+pub struct FsStorage {
+    app_data_dir: PathBuf,
+    documents_dir: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let documents_dir = app_data_dir.join("team_documents");
+        Self {
+            app_data_dir,
+            documents_dir,
+        }
+    }
+
+    /// Remove temp files left by writes a crash cut off (see
+    /// `fs_util::remove_stale_temp_files`). Returns how many were removed.
+    pub fn remove_stale_temp_files(&self) -> usize {
+        // The app data directory also holds unrelated files, so only its
+        // top level is swept
+        fs_util::remove_stale_temp_files(&self.app_data_dir, false)
+            + fs_util::remove_stale_temp_files(&self.documents_dir, true)
+    }
+
+    fn space_dir(&self, space: Space) -> PathBuf {
+        match space {
+            Space::Documents => self.documents_dir.join("docs"),
+            Space::Trash => self.documents_dir.join("trash"),
+            Space::Revisions => self.documents_dir.join("revisions"),
+            Space::History => self.documents_dir.join("history"),
+            Space::Meta => self.documents_dir.clone(),
+            Space::Blobs => self.documents_dir.join("blobs"),
+            Space::Previews => self.documents_dir.join("previews"),
+            Space::Templates => self.documents_dir.join("templates"),
+            Space::Users => self.app_data_dir.clone(),
+        }
+    }
+
+    /// Whether keys in a space may contain `/` separators
+    fn is_nested(space: Space) -> bool {
+        matches!(space, Space::Revisions | Space::History | Space::Previews)
+    }
+
+    /// File path for a key. Rejects keys that would escape the space.
+    pub fn path_for(&self, space: Space, key: &str) -> Result<PathBuf, String> {
+        let invalid = key.is_empty()
+            || key.contains('\\')
+            || key.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+            || (!Self::is_nested(space) && key.contains('/'));
+        if invalid {
+            return Err(format!("Invalid storage key: {:?}", key));
+        }
+
+        let dir = self.space_dir(space);
+        Ok(match space {
+            Space::Blobs => Self::blob_path(&dir, key),
+            // Previews are images, not JSON
+            Space::Previews => dir.join(key),
+            _ => dir.join(format!("{}.json", key)),
+        })
+    }
+
+    /// Two-level sharding: first 2 chars / next 2 chars / full hash
+    fn blob_path(blobs_dir: &Path, hash: &str) -> PathBuf {
+        if hash.len() < 4 || !hash.is_char_boundary(4) {
+            // Fallback for short hashes (shouldn't happen with SHA-256)
+            return blobs_dir.join(hash);
+        }
+        blobs_dir.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    fn update_log_dir(&self) -> PathBuf {
+        self.documents_dir.join("updates")
+    }
+
+    fn update_log_path(&self, doc_id: &str) -> Result<PathBuf, String> {
+        // Validate the ID the same way as a document key
+        self.path_for(Space::Documents, doc_id)?;
+        Ok(self
+            .update_log_dir()
+            .join(format!("{}.{}", doc_id, UPDATE_LOG_EXTENSION)))
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default()
+    }
+
+    /// `*.json` file stems directly inside `dir`
+    fn json_stems(dir: &Path) -> Vec<String> {
+        Self::dir_entries(dir)
+            .into_iter()
+            .filter(|p| p.is_file() && !fs_util::is_temp_file(p))
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json"))
+            .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+            .collect()
+    }
+
+    /// `<dir>/<name>.json` keys one level down, as `dir/name`
+    fn nested_keys(root: &Path) -> Vec<String> {
+        let mut keys = Vec::new();
+        for dir in Self::dir_entries(root).into_iter().filter(|p| p.is_dir()) {
+            let Some(dir_name) = dir.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            for stem in Self::json_stems(&dir) {
+                keys.push(format!("{}/{}", dir_name, stem));
+            }
+        }
+        keys
+    }
+
+    /// `<dir>/<name>` files one level down, as `dir/name`
+    fn nested_files(root: &Path) -> Vec<String> {
+        let mut keys = Vec::new();
+        for dir in Self::dir_entries(root).into_iter().filter(|p| p.is_dir()) {
+            let Some(dir_name) = dir.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            for path in Self::dir_entries(&dir) {
+                if !path.is_file() || fs_util::is_temp_file(&path) {
+                    continue;
+                }
+                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                    keys.push(format!("{}/{}", dir_name, name));
+                }
+            }
+        }
+        keys
+    }
+
+    /// Hashes found in the shard tree
+    fn blob_keys(blobs_dir: &Path) -> Vec<String> {
+        let mut keys = Vec::new();
+        for level1 in Self::dir_entries(blobs_dir).into_iter().filter(|p| p.is_dir()) {
+            for level2 in Self::dir_entries(&level1).into_iter().filter(|p| p.is_dir()) {
+                for path in Self::dir_entries(&level2) {
+                    if !path.is_file() || fs_util::is_temp_file(&path) {
+                        continue;
+                    }
+                    match path.file_name().and_then(|n| n.to_str()) {
+                        Some(name) if Self::blob_path(blobs_dir, name) == path => {
+                            keys.push(name.to_string())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// Remove now-empty directories between `path` and the space root
+    fn prune_empty_dirs(&self, space: Space, path: &Path) {
+        let root = self.space_dir(space);
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if d == root || !d.starts_with(&root) || fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+}
+
+impl Storage for FsStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::Filesystem
+    }
+
+    fn get(&self, space: Space, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path_for(space, key)?;
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn put(&self, space: Space, key: &str, value: &[u8]) -> Result<(), String> {
+        let _guard = write_guard();
+        let path = self.path_for(space, key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directories: {}", e))?;
+        }
+        fs_util::write_atomic(&path, value)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn get_range(&self, space: Space, key: &str, offset: u64, len: usize) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path_for(space, key)?;
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.take(len as u64).read_to_end(&mut data))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(Some(data))
+    }
+
+    fn ranged_reads(&self) -> bool {
+        true
+    }
+
+    fn put_file(&self, space: Space, key: &str, source: &Path) -> Result<(), String> {
+        let _guard = write_guard();
+        let path = self.path_for(space, key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directories: {}", e))?;
+        }
+        // A rename is atomic; across filesystems fall back to a copy
+        if fs::rename(source, &path).is_ok() {
+            return Ok(());
+        }
+        let value = fs::read(source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        fs_util::write_atomic(&path, value)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn exists(&self, space: Space, key: &str) -> Result<bool, String> {
+        Ok(self.path_for(space, key)?.is_file())
+    }
+
+    fn delete(&self, space: Space, key: &str) -> Result<bool, String> {
+        let _guard = write_guard();
+        let path = self.path_for(space, key)?;
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                if space != Space::Meta && space != Space::Users {
+                    self.prune_empty_dirs(space, &path);
+                }
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to delete {}: {}", path.display(), e)),
+        }
+    }
+
+    fn list(&self, space: Space, prefix: &str) -> Result<Vec<String>, String> {
+        let dir = self.space_dir(space);
+        let mut keys = match space {
+            Space::Blobs => Self::blob_keys(&dir),
+            Space::Revisions | Space::History => Self::nested_keys(&dir),
+            Space::Previews => Self::nested_files(&dir),
+            Space::Users => USER_KEYS
+                .iter()
+                .filter(|k| dir.join(format!("{}.json", k)).is_file())
+                .map(|k| k.to_string())
+                .collect(),
+            _ => Self::json_stems(&dir),
+        };
+        keys.retain(|k| k.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn quarantine(&self, space: Space, key: &str) -> Result<(), String> {
+        let _guard = write_guard();
+        let path = self.path_for(space, key)?;
+        if !path.exists() {
+            return Ok(());
+        }
+        fs_util::quarantine_corrupt(&path)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to quarantine {}: {}", path.display(), e))
+    }
+
+    fn append_update(&self, doc_id: &str, update: &[u8]) -> Result<(), String> {
+        let _guard = write_guard();
+        let path = self.update_log_path(doc_id)?;
+        fs::create_dir_all(self.update_log_dir())
+            .map_err(|e| format!("Failed to create directories: {}", e))?;
+        let len = u32::try_from(update.len()).map_err(|_| "Update too large".to_string())?;
+
+        let mut frame = Vec::with_capacity(4 + update.len());
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(update);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open update log: {}", e))?;
+        file.write_all(&frame)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Failed to append update: {}", e))
+    }
+
+    fn load_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>, String> {
+        let path = self.update_log_path(doc_id)?;
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read update log: {}", e)),
+        };
+
+        let mut updates = Vec::new();
+        let mut pos = 0;
+        while pos + 4 <= data.len() {
+            let len = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                as usize;
+            let start = pos + 4;
+            if start + len > data.len() {
+                break;
+            }
+            updates.push(data[start..start + len].to_vec());
+            pos = start + len;
+        }
+        if pos != data.len() {
+            // A crash mid-append leaves a partial trailing frame
+            log::warn!("Ignoring truncated entry at the end of update log for {}", doc_id);
+        }
+        Ok(updates)
+    }
+
+    fn clear_updates(&self, doc_id: &str) -> Result<(), String> {
+        let _guard = write_guard();
+        match fs::remove_file(self.update_log_path(doc_id)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to clear update log: {}", e)),
+        }
+    }
+
+    fn list_update_logs(&self) -> Result<Vec<String>, String> {
+        let mut ids: Vec<String> = Self::dir_entries(&self.update_log_dir())
+            .into_iter()
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(UPDATE_LOG_EXTENSION))
+            .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn watch_target(&self) -> Option<WatchTarget> {
+        Some(WatchTarget {
+            dir: self.documents_dir.clone(),
+            recursive: true,
+            file_prefix: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_layout_matches_original_paths() {
+        let dir = tempdir().unwrap();
+        let storage = FsStorage::new(dir.path().to_path_buf());
+        let docs = dir.path().join("team_documents");
+
+        storage.put(Space::Documents, "doc-1", b"{}").unwrap();
+        storage.put(Space::Revisions, "doc-1/4", b"{}").unwrap();
+        storage.put(Space::Meta, "index", b"{}").unwrap();
+        storage.put(Space::Users, "users", b"{}").unwrap();
+
+        assert!(docs.join("docs").join("doc-1.json").is_file());
+        assert!(docs.join("revisions").join("doc-1").join("4.json").is_file());
+        assert!(docs.join("index.json").is_file());
+        assert!(dir.path().join("users.json").is_file());
+        assert_eq!(storage.list(Space::Revisions, "doc-1/").unwrap(), vec!["doc-1/4"]);
+        assert_eq!(storage.list(Space::Meta, "").unwrap(), vec!["index"]);
+
+        // Deleting the last revision removes the per-document directory
+        assert!(storage.delete(Space::Revisions, "doc-1/4").unwrap());
+        assert!(!docs.join("revisions").join("doc-1").exists());
+    }
+
+    #[test]
+    fn test_blob_path_sharding() {
+        let dir = tempdir().unwrap();
+        let storage = FsStorage::new(dir.path().to_path_buf());
+
+        let hash = "abcd1234567890abcd1234567890abcd1234567890abcd1234567890abcd1234";
+        let path = storage.path_for(Space::Blobs, hash).unwrap();
+
+        // Should have two-level sharding
+        assert!(path.to_string_lossy().contains("ab"));
+        assert!(path.to_string_lossy().contains("cd"));
+        assert!(path.to_string_lossy().ends_with(hash));
+
+        storage.put(Space::Blobs, hash, b"data").unwrap();
+        assert_eq!(storage.list(Space::Blobs, "").unwrap(), vec![hash.to_string()]);
+    }
+
+    #[test]
+    fn test_ranged_read_and_file_put() {
+        let dir = tempdir().unwrap();
+        let storage = FsStorage::new(dir.path().to_path_buf());
+
+        let staged = dir.path().join("staged");
+        fs::write(&staged, b"0123456789").unwrap();
+        storage.put_file(Space::Blobs, "abcdef", &staged).unwrap();
+        assert!(!staged.exists());
+
+        assert_eq!(storage.get_range(Space::Blobs, "abcdef", 3, 4).unwrap().unwrap(), b"3456");
+        assert_eq!(storage.get_range(Space::Blobs, "abcdef", 8, 10).unwrap().unwrap(), b"89");
+        assert!(storage.get_range(Space::Blobs, "abcdef", 20, 4).unwrap().unwrap().is_empty());
+        assert!(storage.get_range(Space::Blobs, "missing", 0, 4).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_escaping_keys() {
+        let dir = tempdir().unwrap();
+        let storage = FsStorage::new(dir.path().to_path_buf());
+
+        assert!(storage.put(Space::Documents, "../users", b"{}").is_err());
+        assert!(storage.put(Space::Documents, "a/b", b"{}").is_err());
+        assert!(storage.put(Space::History, "doc/../../x", b"{}").is_err());
+        assert!(storage.get(Space::Documents, "").is_err());
+    }
+
+    #[test]
+    fn test_update_log_ignores_truncated_tail() {
+        let dir = tempdir().unwrap();
+        let storage = FsStorage::new(dir.path().to_path_buf());
+
+        storage.append_update("doc-1", b"first").unwrap();
+        storage.append_update("doc-1", b"second").unwrap();
+
+        let path = storage.update_log_path("doc-1").unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[9, 0, 0, 0, b'x']).unwrap();
+
+        let updates = storage.load_updates("doc-1").unwrap();
+        assert_eq!(updates, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(storage.list_update_logs().unwrap(), vec!["doc-1"]);
+
+        storage.clear_updates("doc-1").unwrap();
+        assert!(storage.load_updates("doc-1").unwrap().is_empty());
+    }
+}
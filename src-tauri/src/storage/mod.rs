@@ -0,0 +1,440 @@
+//! Pluggable persistence for documents, blobs, users and the update log
+//!
+//! `DocumentStore`, `BlobStore`, `HistoryStore` and `UserStore` keep their
+//! in-memory indexes and business logic, and read or write bytes through a
+//! [`Storage`] implementation:
+//!
+//! - [`fs::FsStorage`] keeps the original on-disk layout (one JSON file per
+//!   document, sharded blob tree, `users.json`).
+//! - [`sqlite::SqliteStorage`] keeps everything in a single SQLite database
+//!   with transactional batches, safe for concurrent processes.
+//!
+//! The backend is chosen by `ServerConfig::storage_backend`. The active
+//! backend is recorded in `storage.json`; opening a different backend copies
+//! all data across once (see [`open`]). Either backend can be wrapped in
+//! [`encrypted::EncryptedStorage`] for encryption at rest.
+
+pub mod encrypted;
+pub mod fs;
+pub mod sqlite;
+
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use crate::fs_util;
+
+const MARKER_FILENAME: &str = "storage.json";
+
+/// Available storage backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Filesystem,
+    Sqlite,
+}
+
+/// Logical keyspaces. Keys are plain strings; spaces holding per-document
+/// data (`Revisions`, `History`) use `<doc_id>/<name>` keys, and blob
+/// previews use `<hash>/<name>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Space {
+    /// Live team documents, keyed by document ID
+    Documents,
+    /// Soft-deleted documents, keyed by document ID
+    Trash,
+    /// Merge-base revisions and version history snapshots, keyed
+    /// `<doc_id>/<server_version>`
+    Revisions,
+    /// Version history indexes, keyed `<doc_id>/versions`
+    History,
+    /// Store indexes and settings (`index`, `blob_index`, ...)
+    Meta,
+    /// Blob contents, keyed by SHA-256 hash
+    Blobs,
+    /// Thumbnails derived from blobs, keyed `<hash>/<size>`
+    Previews,
+    /// User accounts (`users`)
+    Users,
+    /// Document templates, keyed by template ID
+    Templates,
+}
+
+impl Space {
+    /// Every keyspace, in migration order
+    pub const ALL: [Space; 9] = [
+        Space::Users,
+        Space::Blobs,
+        Space::Previews,
+        Space::Templates,
+        Space::Documents,
+        Space::Trash,
+        Space::Revisions,
+        Space::History,
+        Space::Meta,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Space::Documents => "documents",
+            Space::Trash => "trash",
+            Space::Revisions => "revisions",
+            Space::History => "history",
+            Space::Meta => "meta",
+            Space::Blobs => "blobs",
+            Space::Previews => "previews",
+            Space::Users => "users",
+            Space::Templates => "templates",
+        }
+    }
+}
+
+/// One write in a batch
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    Put { space: Space, key: String, value: Vec<u8> },
+    Delete { space: Space, key: String },
+}
+
+/// Byte-level persistence backend.
+///
+/// Every single-key write is atomic: readers see the old or the new value,
+/// never a partial one. `write_batch` is additionally all-or-nothing on
+/// backends that support transactions.
+pub trait Storage: Send + Sync {
+    fn backend(&self) -> StorageBackend;
+
+    /// Read a value, `None` if the key does not exist
+    fn get(&self, space: Space, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    fn put(&self, space: Space, key: &str, value: &[u8]) -> Result<(), String>;
+
+    /// Read at most `len` bytes of a value starting at `offset`, `None` if
+    /// the key does not exist. Reads the whole value unless the backend
+    /// supports ranged reads.
+    fn get_range(&self, space: Space, key: &str, offset: u64, len: usize) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.get(space, key)?.map(|value| {
+            let start = usize::try_from(offset).unwrap_or(usize::MAX).min(value.len());
+            let end = start.saturating_add(len).min(value.len());
+            value[start..end].to_vec()
+        }))
+    }
+
+    /// Whether `get_range` reads only the requested bytes
+    fn ranged_reads(&self) -> bool {
+        false
+    }
+
+    /// Store the contents of a file as a value. The file may be moved into
+    /// place, so callers must not use it afterwards.
+    fn put_file(&self, space: Space, key: &str, path: &Path) -> Result<(), String> {
+        let value = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        self.put(space, key, &value)
+    }
+
+    /// Whether a key exists, without reading its value
+    fn exists(&self, space: Space, key: &str) -> Result<bool, String> {
+        Ok(self.get(space, key)?.is_some())
+    }
+
+    /// Delete a value. Returns whether it existed.
+    fn delete(&self, space: Space, key: &str) -> Result<bool, String>;
+
+    /// List keys in a space that start with `prefix` (use `""` for all)
+    fn list(&self, space: Space, prefix: &str) -> Result<Vec<String>, String>;
+
+    /// Apply several writes. Transactional backends commit them together.
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), String> {
+        let _guard = write_guard();
+        for op in ops {
+            match op {
+                WriteOp::Put { space, key, value } => self.put(space, &key, &value)?,
+                WriteOp::Delete { space, key } => {
+                    self.delete(space, &key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete every key in a space that starts with `prefix`
+    fn delete_prefix(&self, space: Space, prefix: &str) -> Result<usize, String> {
+        let keys = self.list(space, prefix)?;
+        let count = keys.len();
+        self.write_batch(
+            keys.into_iter()
+                .map(|key| WriteOp::Delete { space, key })
+                .collect(),
+        )?;
+        Ok(count)
+    }
+
+    /// Move an unreadable value aside so the next write does not destroy it
+    fn quarantine(&self, space: Space, key: &str) -> Result<(), String> {
+        let _guard = write_guard();
+        let Some(value) = self.get(space, key)? else {
+            return Ok(());
+        };
+        let millis = crate::clock::now_ms();
+        self.write_batch(vec![
+            WriteOp::Put {
+                space,
+                key: format!("{}.corrupt-{}", key, millis),
+                value,
+            },
+            WriteOp::Delete {
+                space,
+                key: key.to_string(),
+            },
+        ])
+    }
+
+    /// Append an entry to a document's update log
+    fn append_update(&self, doc_id: &str, update: &[u8]) -> Result<(), String>;
+
+    /// All update log entries for a document, oldest first
+    fn load_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>, String>;
+
+    /// Drop a document's update log (e.g. after compaction into a snapshot)
+    fn clear_updates(&self, doc_id: &str) -> Result<(), String>;
+
+    /// Document IDs that have an update log
+    fn list_update_logs(&self) -> Result<Vec<String>, String>;
+
+    /// Location to watch for changes made outside this handle (`None` if
+    /// the backend cannot be watched)
+    fn watch_target(&self) -> Option<WatchTarget> {
+        None
+    }
+}
+
+/// Where a backend's data lives on disk, so changes made through other
+/// handles (another store instance, another process, a restore) can be
+/// noticed
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchTarget {
+    pub dir: PathBuf,
+    pub recursive: bool,
+    /// Only changes to files whose name starts with this prefix count
+    pub file_prefix: Option<String>,
+}
+
+/// Held shared by every backend write and exclusively by [`pause_writes`],
+/// so a backup sees one consistent state across all store instances
+static WRITE_GATE: RwLock<()> = RwLock::new(());
+
+thread_local! {
+    /// Backend writes in progress on this thread (backends and wrappers
+    /// call each other, and only the outermost call takes the gate)
+    static WRITE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Taken by backends around each write; see [`write_guard`]
+pub(crate) struct WriteGuard {
+    _gate: Option<RwLockReadGuard<'static, ()>>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        WRITE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Wait until writes are not paused and keep them allowed until the guard
+/// is dropped
+pub(crate) fn write_guard() -> WriteGuard {
+    let outermost = WRITE_DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get() == 1
+    });
+    WriteGuard {
+        _gate: outermost.then(|| WRITE_GATE.read().unwrap_or_else(|e| e.into_inner())),
+    }
+}
+
+/// Run `f` while every storage write in this process waits. `f` must not
+/// write to storage itself.
+pub fn pause_writes<T>(f: impl FnOnce() -> T) -> T {
+    let _paused = WRITE_GATE.write().unwrap_or_else(|e| e.into_inner());
+    f()
+}
+
+/// Marker recording which backend currently holds the data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StorageMarker {
+    backend: StorageBackend,
+}
+
+/// Backend recorded in `storage.json` (filesystem if absent)
+pub fn configured_backend(app_data_dir: &Path) -> StorageBackend {
+    std::fs::read_to_string(app_data_dir.join(MARKER_FILENAME))
+        .ok()
+        .and_then(|data| serde_json::from_str::<StorageMarker>(&data).ok())
+        .map(|marker| marker.backend)
+        .unwrap_or_default()
+}
+
+fn open_backend(app_data_dir: &Path, backend: StorageBackend) -> Result<Arc<dyn Storage>, String> {
+    let removed = match backend {
+        StorageBackend::Filesystem => fs::FsStorage::new(app_data_dir.to_path_buf()).remove_stale_temp_files(),
+        // Config, marker and key files are still written atomically
+        StorageBackend::Sqlite => fs_util::remove_stale_temp_files(app_data_dir, false),
+    };
+    if removed > 0 {
+        log::info!("Removed {} leftover temporary file(s)", removed);
+    }
+    Ok(match backend {
+        StorageBackend::Filesystem => Arc::new(fs::FsStorage::new(app_data_dir.to_path_buf())),
+        StorageBackend::Sqlite => Arc::new(sqlite::SqliteStorage::open(
+            &app_data_dir.join(sqlite::DATABASE_FILENAME),
+        )?),
+    })
+}
+
+/// Open whichever backend currently holds the data. Used by components that
+/// do not choose the backend themselves (user store at startup, MCP).
+pub fn open_configured(app_data_dir: &Path) -> Result<Arc<dyn Storage>, String> {
+    open_backend(app_data_dir, configured_backend(app_data_dir))
+}
+
+/// Open `backend`, migrating data from the previously active backend if it
+/// differs. The old backend's data is left in place as a fallback; it is
+/// replaced wholesale if that backend is switched back to later.
+pub fn open(app_data_dir: &Path, backend: StorageBackend) -> Result<Arc<dyn Storage>, String> {
+    let current = configured_backend(app_data_dir);
+    let target = open_backend(app_data_dir, backend)?;
+
+    if current != backend {
+        let source = open_backend(app_data_dir, current)?;
+        log::info!("Migrating storage from {:?} to {:?}", current, backend);
+        let copied = migrate(source.as_ref(), target.as_ref())?;
+        log::info!("Storage migration complete: {} entries copied", copied);
+    }
+
+    if current != backend || !app_data_dir.join(MARKER_FILENAME).exists() {
+        let json = serde_json::to_string_pretty(&StorageMarker { backend })
+            .map_err(|e| format!("Serialize error: {}", e))?;
+        fs_util::write_atomic(&app_data_dir.join(MARKER_FILENAME), json)
+            .map_err(|e| format!("Failed to write storage marker: {}", e))?;
+    }
+
+    Ok(target)
+}
+
+/// Replace everything in `target` with the keys and update logs of
+/// `source`. Returns the number of entries copied.
+///
+/// The target is cleared first: it may hold stale data from an earlier
+/// switch away from it, and documents, blobs or revisions deleted since
+/// must not come back.
+pub fn migrate(source: &dyn Storage, target: &dyn Storage) -> Result<usize, String> {
+    for space in Space::ALL {
+        target.delete_prefix(space, "")?;
+    }
+    for doc_id in target.list_update_logs()? {
+        target.clear_updates(&doc_id)?;
+    }
+
+    let mut copied = 0;
+    for space in Space::ALL {
+        for key in source.list(space, "")? {
+            if let Some(value) = source.get(space, &key)? {
+                target.put(space, &key, &value)?;
+                copied += 1;
+            }
+        }
+    }
+    for doc_id in source.list_update_logs()? {
+        target.clear_updates(&doc_id)?;
+        for update in source.load_updates(&doc_id)? {
+            target.append_update(&doc_id, &update)?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pause_writes_holds_back_other_threads() {
+        let dir = tempdir().unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(fs::FsStorage::new(dir.path().to_path_buf()));
+        let writer = pause_writes(|| {
+            let handle = storage.clone();
+            let writer = std::thread::spawn(move || handle.put(Space::Documents, "doc-1", b"{}").unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert!(!storage.exists(Space::Documents, "doc-1").unwrap());
+            writer
+        });
+        writer.join().unwrap();
+        assert!(storage.exists(Space::Documents, "doc-1").unwrap());
+    }
+
+    #[test]
+    fn test_open_migrates_between_backends() {
+        let dir = tempdir().unwrap();
+
+        let fs_storage = open(dir.path(), StorageBackend::Filesystem).unwrap();
+        fs_storage.put(Space::Documents, "doc-1", b"{\"id\":\"doc-1\"}").unwrap();
+        fs_storage.put(Space::History, "doc-1/3", b"{}").unwrap();
+        fs_storage.put(Space::Users, "users", b"{}").unwrap();
+        fs_storage.append_update("doc-1", b"update-1").unwrap();
+        assert_eq!(configured_backend(dir.path()), StorageBackend::Filesystem);
+
+        let sqlite = open(dir.path(), StorageBackend::Sqlite).unwrap();
+        assert_eq!(configured_backend(dir.path()), StorageBackend::Sqlite);
+        assert_eq!(
+            sqlite.get(Space::Documents, "doc-1").unwrap().unwrap(),
+            b"{\"id\":\"doc-1\"}"
+        );
+        assert_eq!(sqlite.list(Space::History, "doc-1/").unwrap(), vec!["doc-1/3"]);
+        assert!(sqlite.get(Space::Users, "users").unwrap().is_some());
+        assert_eq!(sqlite.load_updates("doc-1").unwrap(), vec![b"update-1".to_vec()]);
+
+        // Reopening the same backend does not migrate again
+        sqlite.put(Space::Documents, "doc-2", b"{}").unwrap();
+        let reopened = open_configured(dir.path()).unwrap();
+        assert_eq!(reopened.backend(), StorageBackend::Sqlite);
+        assert_eq!(reopened.list(Space::Documents, "").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_round_trip_does_not_resurrect_deleted_data() {
+        let dir = tempdir().unwrap();
+
+        let fs_storage = open(dir.path(), StorageBackend::Filesystem).unwrap();
+        fs_storage.put(Space::Documents, "doc-1", b"{}").unwrap();
+        fs_storage.put(Space::Documents, "doc-2", b"{}").unwrap();
+        fs_storage.put(Space::Blobs, "abcdef", b"blob").unwrap();
+        fs_storage.put(Space::Revisions, "doc-2/1", b"{}").unwrap();
+        fs_storage.append_update("doc-2", b"update-1").unwrap();
+
+        // Delete on SQLite, then switch back to the filesystem
+        let sqlite = open(dir.path(), StorageBackend::Sqlite).unwrap();
+        sqlite.delete(Space::Documents, "doc-2").unwrap();
+        sqlite.delete(Space::Blobs, "abcdef").unwrap();
+        sqlite.delete_prefix(Space::Revisions, "doc-2/").unwrap();
+        sqlite.clear_updates("doc-2").unwrap();
+        sqlite.put(Space::Documents, "doc-3", b"{}").unwrap();
+        drop(sqlite);
+
+        let fs_storage = open(dir.path(), StorageBackend::Filesystem).unwrap();
+        let mut docs = fs_storage.list(Space::Documents, "").unwrap();
+        docs.sort();
+        assert_eq!(docs, vec!["doc-1", "doc-3"]);
+        assert!(fs_storage.list(Space::Blobs, "").unwrap().is_empty());
+        assert!(fs_storage.list(Space::Revisions, "").unwrap().is_empty());
+        assert!(fs_storage.load_updates("doc-2").unwrap().is_empty());
+
+        // And once more to SQLite, which also held stale data
+        fs_storage.delete(Space::Documents, "doc-1").unwrap();
+        let sqlite = open(dir.path(), StorageBackend::Sqlite).unwrap();
+        assert_eq!(sqlite.list(Space::Documents, "").unwrap(), vec!["doc-3"]);
+    }
+}
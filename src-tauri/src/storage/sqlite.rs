@@ -0,0 +1,290 @@
+//! SQLite storage backend
+//!
+//! Stores every keyspace in one database file (`diagrammer.db` in the app
+//! data directory). Batches run in a single transaction, and WAL mode plus a
+//! busy timeout let the collaboration server and the MCP server share the
+//! database safely.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{write_guard, Space, Storage, StorageBackend, WatchTarget, WriteOp};
+
+/// Database file name inside the app data directory
+pub const DATABASE_FILENAME: &str = "diagrammer.db";
+
+/// How long a writer waits for another process's lock before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        space TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (space, key)
+    );
+    CREATE TABLE IF NOT EXISTS update_log (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        doc_id TEXT NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS update_log_doc ON update_log (doc_id, seq);
+";
+
+/// Storage backed by a single SQLite database
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    path: PathBuf,
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+impl SqliteStorage {
+    /// Open (or create) the database at `path`
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directories: {}", e))?;
+        }
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(db_error)?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(db_error)?;
+        conn.pragma_update(None, "synchronous", "NORMAL").map_err(db_error)?;
+        conn.execute_batch(SCHEMA).map_err(db_error)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.conn.lock().map_err(|e| e.to_string())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::Sqlite
+    }
+
+    fn get(&self, space: Space, key: &str) -> Result<Option<Vec<u8>>, String> {
+        self.conn()?
+            .query_row(
+                "SELECT value FROM entries WHERE space = ?1 AND key = ?2",
+                params![space.as_str(), key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    fn get_range(&self, space: Space, key: &str, offset: u64, len: usize) -> Result<Option<Vec<u8>>, String> {
+        // substr() counts bytes of a BLOB from 1
+        self.conn()?
+            .query_row(
+                "SELECT substr(value, ?3, ?4) FROM entries WHERE space = ?1 AND key = ?2",
+                params![
+                    space.as_str(),
+                    key,
+                    i64::try_from(offset).unwrap_or(i64::MAX).saturating_add(1),
+                    i64::try_from(len).unwrap_or(i64::MAX)
+                ],
+                |row| row.get::<_, Option<Vec<u8>>>(0),
+            )
+            .optional()
+            .map(|value| value.map(Option::unwrap_or_default))
+            .map_err(db_error)
+    }
+
+    fn ranged_reads(&self) -> bool {
+        true
+    }
+
+    fn put(&self, space: Space, key: &str, value: &[u8]) -> Result<(), String> {
+        let _guard = write_guard();
+        self.conn()?
+            .execute(
+                "INSERT OR REPLACE INTO entries (space, key, value) VALUES (?1, ?2, ?3)",
+                params![space.as_str(), key, value],
+            )
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    fn exists(&self, space: Space, key: &str) -> Result<bool, String> {
+        self.conn()?
+            .query_row(
+                "SELECT 1 FROM entries WHERE space = ?1 AND key = ?2",
+                params![space.as_str(), key],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(db_error)
+    }
+
+    fn delete(&self, space: Space, key: &str) -> Result<bool, String> {
+        let _guard = write_guard();
+        self.conn()?
+            .execute(
+                "DELETE FROM entries WHERE space = ?1 AND key = ?2",
+                params![space.as_str(), key],
+            )
+            .map(|n| n > 0)
+            .map_err(db_error)
+    }
+
+    fn list(&self, space: Space, prefix: &str) -> Result<Vec<String>, String> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT key FROM entries WHERE space = ?1 AND substr(key, 1, ?2) = ?3 ORDER BY key")
+            .map_err(db_error)?;
+        let keys = stmt
+            .query_map(
+                params![space.as_str(), prefix.chars().count() as i64, prefix],
+                |row| row.get(0),
+            )
+            .map_err(db_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(db_error)?;
+        Ok(keys)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), String> {
+        let _guard = write_guard();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(db_error)?;
+        for op in &ops {
+            match op {
+                WriteOp::Put { space, key, value } => tx.execute(
+                    "INSERT OR REPLACE INTO entries (space, key, value) VALUES (?1, ?2, ?3)",
+                    params![space.as_str(), key, value],
+                ),
+                WriteOp::Delete { space, key } => tx.execute(
+                    "DELETE FROM entries WHERE space = ?1 AND key = ?2",
+                    params![space.as_str(), key],
+                ),
+            }
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    fn append_update(&self, doc_id: &str, update: &[u8]) -> Result<(), String> {
+        let _guard = write_guard();
+        self.conn()?
+            .execute(
+                "INSERT INTO update_log (doc_id, data) VALUES (?1, ?2)",
+                params![doc_id, update],
+            )
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    fn load_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>, String> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT data FROM update_log WHERE doc_id = ?1 ORDER BY seq")
+            .map_err(db_error)?;
+        let updates = stmt
+            .query_map(params![doc_id], |row| row.get(0))
+            .map_err(db_error)?
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(db_error)?;
+        Ok(updates)
+    }
+
+    fn clear_updates(&self, doc_id: &str) -> Result<(), String> {
+        let _guard = write_guard();
+        self.conn()?
+            .execute("DELETE FROM update_log WHERE doc_id = ?1", params![doc_id])
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    fn list_update_logs(&self) -> Result<Vec<String>, String> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT doc_id FROM update_log ORDER BY doc_id")
+            .map_err(db_error)?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(db_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(db_error)?;
+        Ok(ids)
+    }
+
+    /// The database file and its WAL/SHM companions
+    fn watch_target(&self) -> Option<WatchTarget> {
+        Some(WatchTarget {
+            dir: self.path.parent()?.to_path_buf(),
+            recursive: false,
+            file_prefix: Some(self.path.file_name()?.to_string_lossy().into_owned()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_crud_and_prefix_listing() {
+        let dir = tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join(DATABASE_FILENAME)).unwrap();
+
+        storage.put(Space::History, "doc-1/1", b"a").unwrap();
+        storage.put(Space::History, "doc-1/2", b"b").unwrap();
+        storage.put(Space::History, "doc-10/1", b"c").unwrap();
+        storage.put(Space::Documents, "doc-1/1", b"other space").unwrap();
+
+        assert_eq!(storage.get(Space::History, "doc-1/2").unwrap().unwrap(), b"b");
+        assert_eq!(
+            storage.list(Space::History, "doc-1/").unwrap(),
+            vec!["doc-1/1", "doc-1/2"]
+        );
+        assert_eq!(storage.delete_prefix(Space::History, "doc-1/").unwrap(), 2);
+        assert_eq!(storage.list(Space::History, "").unwrap(), vec!["doc-10/1"]);
+        assert!(!storage.delete(Space::History, "doc-1/1").unwrap());
+        assert!(storage.get(Space::Documents, "doc-1/1").unwrap().is_some());
+
+        storage.put(Space::Blobs, "blob", b"0123456789").unwrap();
+        assert_eq!(storage.get_range(Space::Blobs, "blob", 3, 4).unwrap().unwrap(), b"3456");
+        assert_eq!(storage.get_range(Space::Blobs, "blob", 8, 10).unwrap().unwrap(), b"89");
+        assert!(storage.get_range(Space::Blobs, "blob", 20, 4).unwrap().unwrap().is_empty());
+        assert!(storage.get_range(Space::Blobs, "missing", 0, 4).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_batch_and_update_log_persist() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(DATABASE_FILENAME);
+        {
+            let storage = SqliteStorage::open(&path).unwrap();
+            storage
+                .write_batch(vec![
+                    WriteOp::Put { space: Space::Meta, key: "index".into(), value: b"{}".to_vec() },
+                    WriteOp::Put { space: Space::Blobs, key: "abcd".into(), value: vec![1, 2, 3] },
+                ])
+                .unwrap();
+            storage.append_update("doc-1", b"one").unwrap();
+            storage.append_update("doc-1", b"two").unwrap();
+        }
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.get(Space::Blobs, "abcd").unwrap().unwrap(), vec![1, 2, 3]);
+        assert_eq!(
+            storage.load_updates("doc-1").unwrap(),
+            vec![b"one".to_vec(), b"two".to_vec()]
+        );
+        storage.quarantine(Space::Meta, "index").unwrap();
+        assert!(storage.get(Space::Meta, "index").unwrap().is_none());
+        assert_eq!(storage.list(Space::Meta, "index.corrupt-").unwrap().len(), 1);
+    }
+}
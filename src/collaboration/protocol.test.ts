@@ -15,6 +15,14 @@ import {
   MESSAGE_AUTH_LOGIN,
   MESSAGE_DOC_SHARE,
   MESSAGE_DOC_TRANSFER,
+  MESSAGE_DOC_PATCH,
+  MESSAGE_DOC_PATCH_EVENT,
+  MESSAGE_SEARCH,
+  MESSAGE_FOLDER_EVENT,
+  MESSAGE_PAGE_GET,
+  MESSAGE_PAGE_EVENT,
+  MESSAGE_DOC_FROM_TEMPLATE,
+  MESSAGE_BLOB_INVENTORY,
   // Error codes
   ERR_ACCESS_DENIED,
   ERR_DOC_NOT_FOUND,
@@ -71,6 +79,17 @@ describe('Protocol Message Types', () => {
     expect(MESSAGE_DOC_SHARE).toBe(12);
     expect(MESSAGE_DOC_TRANSFER).toBe(13);
   });
+
+  it('matches the server message type values', () => {
+    expect(MESSAGE_DOC_PATCH).toBe(14);
+    expect(MESSAGE_DOC_PATCH_EVENT).toBe(15);
+    expect(MESSAGE_SEARCH).toBe(21);
+    expect(MESSAGE_FOLDER_EVENT).toBe(28);
+    expect(MESSAGE_PAGE_GET).toBe(33);
+    expect(MESSAGE_PAGE_EVENT).toBe(38);
+    expect(MESSAGE_DOC_FROM_TEMPLATE).toBe(42);
+    expect(MESSAGE_BLOB_INVENTORY).toBe(44);
+  });
 });
 
 describe('encodeMessage', () => {
@@ -443,6 +462,8 @@ describe('Message Channel Classification', () => {
       expect(isRequestMessage(MESSAGE_DOC_SHARE)).toBe(true);
       expect(isRequestMessage(MESSAGE_DOC_TRANSFER)).toBe(true);
       expect(isRequestMessage(MESSAGE_AUTH_LOGIN)).toBe(true);
+      expect(isRequestMessage(MESSAGE_DOC_PATCH)).toBe(true);
+      expect(isRequestMessage(MESSAGE_PAGE_GET)).toBe(true);
     });
 
     it('returns false for non-request messages', () => {
@@ -450,6 +471,9 @@ describe('Message Channel Classification', () => {
       expect(isRequestMessage(MESSAGE_AWARENESS)).toBe(false);
       expect(isRequestMessage(MESSAGE_DOC_EVENT)).toBe(false);
       expect(isRequestMessage(MESSAGE_AUTH)).toBe(false);
+      expect(isRequestMessage(MESSAGE_DOC_PATCH_EVENT)).toBe(false);
+      expect(isRequestMessage(MESSAGE_FOLDER_EVENT)).toBe(false);
+      expect(isRequestMessage(MESSAGE_PAGE_EVENT)).toBe(false);
     });
   });
 });
@@ -470,6 +494,8 @@ describe('getMessageTypeName', () => {
     expect(getMessageTypeName(MESSAGE_AUTH_LOGIN)).toBe('AUTH_LOGIN');
     expect(getMessageTypeName(MESSAGE_DOC_SHARE)).toBe('DOC_SHARE');
     expect(getMessageTypeName(MESSAGE_DOC_TRANSFER)).toBe('DOC_TRANSFER');
+    expect(getMessageTypeName(MESSAGE_DOC_PATCH)).toBe('DOC_PATCH');
+    expect(getMessageTypeName(MESSAGE_BLOB_INVENTORY)).toBe('BLOB_INVENTORY');
   });
 
   it('returns UNKNOWN for unknown types', () => {
@@ -5,7 +5,7 @@
  * src-tauri/src/server/protocol.rs
  */
 
-import type { DocumentMetadata, DiagramDocument } from '../types/Document';
+import type { DocumentMetadata, DocumentShare, DiagramDocument } from '../types/Document';
 
 // ============ Message Type Constants ============
 // Must match MESSAGE_* constants in Rust
@@ -52,6 +52,99 @@ export const MESSAGE_DOC_SHARE = 12;
 /** Document ownership transfer */
 export const MESSAGE_DOC_TRANSFER = 13;
 
+/** Incremental document update (JSON Patch) */
+export const MESSAGE_DOC_PATCH = 14;
+
+/** Patch broadcast to clients that joined the document */
+export const MESSAGE_DOC_PATCH_EVENT = 15;
+
+/** Document version history list */
+export const MESSAGE_DOC_HISTORY = 16;
+
+/** Fetch a recorded document version */
+export const MESSAGE_DOC_VERSION_GET = 17;
+
+/** Restore a recorded version as the current document */
+export const MESSAGE_DOC_VERSION_RESTORE = 18;
+
+/** Trash bin listing */
+export const MESSAGE_TRASH_LIST = 19;
+
+/** Restore a document from the trash */
+export const MESSAGE_TRASH_RESTORE = 20;
+
+/** Full-text search across team documents */
+export const MESSAGE_SEARCH = 21;
+
+/** Folder tree request/response */
+export const MESSAGE_FOLDER_LIST = 22;
+
+/** Create a folder */
+export const MESSAGE_FOLDER_CREATE = 23;
+
+/** Rename a folder */
+export const MESSAGE_FOLDER_RENAME = 24;
+
+/** Move a folder under another one */
+export const MESSAGE_FOLDER_MOVE = 25;
+
+/** Delete a folder */
+export const MESSAGE_FOLDER_DELETE = 26;
+
+/** Replace a folder's default shares */
+export const MESSAGE_FOLDER_SHARES = 27;
+
+/** Folder change broadcast */
+export const MESSAGE_FOLDER_EVENT = 28;
+
+/** File a document in a folder */
+export const MESSAGE_DOC_MOVE = 29;
+
+/** Replace a document's tags */
+export const MESSAGE_DOC_TAGS = 30;
+
+/** Duplicate a document */
+export const MESSAGE_DOC_DUPLICATE = 31;
+
+/** Copy a page into another document */
+export const MESSAGE_PAGE_COPY = 32;
+
+/** Fetch a single page (lazy page loading) */
+export const MESSAGE_PAGE_GET = 33;
+
+/** Add an empty page */
+export const MESSAGE_PAGE_CREATE = 34;
+
+/** Rename a page */
+export const MESSAGE_PAGE_RENAME = 35;
+
+/** Replace the page order */
+export const MESSAGE_PAGE_REORDER = 36;
+
+/** Delete a page */
+export const MESSAGE_PAGE_DELETE = 37;
+
+/** Page change broadcast to clients that joined the document */
+export const MESSAGE_PAGE_EVENT = 38;
+
+/** Template list request/response */
+export const MESSAGE_TEMPLATE_LIST = 39;
+
+/** Save a document as a template */
+export const MESSAGE_TEMPLATE_SAVE = 40;
+
+/** Delete a template */
+export const MESSAGE_TEMPLATE_DELETE = 41;
+
+/** Create a document from a template */
+export const MESSAGE_DOC_FROM_TEMPLATE = 42;
+
+/** Storage usage report (admins) */
+export const MESSAGE_USAGE_REPORT = 43;
+
+/** Blob inventory (admins) */
+export const MESSAGE_BLOB_INVENTORY = 44;
+
 // ============ Request/Response Types ============
 
 /** Authentication login request (username/password) */
@@ -88,6 +181,8 @@ export interface DocListResponse {
 export interface DocGetRequest {
   requestId: string;
   docId: string;
+  /** Return pages without their shapes (fetch them with PAGE_GET) */
+  skeleton?: boolean;
 }
 
 /** Document get response */
@@ -105,6 +200,8 @@ export interface DocSaveRequest {
   document: DiagramDocument;
   /** Expected server version for optimistic locking (optional for backwards compatibility) */
   expectedVersion?: number;
+  /** `serverVersion` the client's copy was loaded at. When stale, the server three-way merges instead of overwriting. */
+  baseVersion?: number;
 }
 
 /** Document save response */
@@ -118,6 +215,12 @@ export interface DocSaveResponse {
   errorCode?: 'VERSION_CONFLICT' | 'PERMISSION_DENIED' | 'NOT_FOUND' | 'SERVER_ERROR';
   /** Server's current version (returned on VERSION_CONFLICT) */
   serverVersion?: number;
+  /** Stored document, present when the save was merged with newer changes or structurally repaired */
+  document?: DiagramDocument;
+  /** Fields changed on both sides; present when the save was rejected */
+  conflicts?: MergeConflict[];
+  /** Structural problems fixed before saving (lenient validation mode) */
+  repairs?: ValidationIssue[];
 }
 
 /** Document delete request */
@@ -186,6 +289,603 @@ export interface DocTransferResponse {
   error?: string;
 }
 
+/** A single RFC 6902 JSON Patch operation */
+export type PatchOperation =
+  | { op: 'add'; path: string; value: unknown }
+  | { op: 'remove'; path: string }
+  | { op: 'replace'; path: string; value: unknown }
+  | { op: 'move'; from: string; path: string }
+  | { op: 'copy'; from: string; path: string }
+  | { op: 'test'; path: string; value: unknown };
+
+/** A field changed differently on both sides of a merge */
+export interface MergeConflict {
+  /** JSON pointer to the conflicting value (e.g. `/pages/p1/shapes/s1`) */
+  path: string;
+  /** Value at the base revision (absent if it did not exist) */
+  base?: unknown;
+  /** Value currently stored on the server (absent if deleted) */
+  current?: unknown;
+  /** Value in the rejected save (absent if deleted) */
+  incoming?: unknown;
+}
+
+/** One structural problem, located by JSON pointer */
+export interface ValidationIssue {
+  path: string;
+  message: string;
+}
+
+/** Incremental document update (RFC 6902 JSON Patch) */
+export interface DocPatchRequest {
+  requestId: string;
+  docId: string;
+  /** `serverVersion` the operations were computed against */
+  baseVersion: number;
+  operations: PatchOperation[];
+}
+
+/** Document patch response */
+export interface DocPatchResponse {
+  requestId: string;
+  success: boolean;
+  /** New `serverVersion` after the patch (or current version on conflict) */
+  serverVersion?: number;
+  error?: string;
+  /** Stored document, present when the patched document was repaired */
+  document?: DiagramDocument;
+  /** Structural problems fixed before saving (lenient validation mode) */
+  repairs?: ValidationIssue[];
+}
+
+/** Patch broadcast to clients that joined the document */
+export interface DocPatchEvent {
+  docId: string;
+  baseVersion: number;
+  serverVersion: number;
+  operations: PatchOperation[];
+  userId: string;
+}
+
+/** Description of one recorded document version */
+export interface VersionInfo {
+  /** `serverVersion` of the document when the snapshot was taken */
+  serverVersion: number;
+  /** Document name at that version */
+  name: string;
+  /** Snapshot timestamp in milliseconds */
+  savedAt: number;
+  authorId?: string;
+  authorName?: string;
+  /** Serialized size in bytes */
+  size: number;
+}
+
+/** Document version history list request */
+export interface DocHistoryRequest {
+  requestId: string;
+  docId: string;
+}
+
+/** Document version history list response (newest first) */
+export interface DocHistoryResponse {
+  requestId: string;
+  success: boolean;
+  versions: VersionInfo[];
+  error?: string;
+}
+
+/** Request for the full document at a recorded version */
+export interface DocVersionGetRequest {
+  requestId: string;
+  docId: string;
+  serverVersion: number;
+}
+
+/** Document version get response */
+export interface DocVersionGetResponse {
+  requestId: string;
+  document?: DiagramDocument;
+  error?: string;
+}
+
+/** Request to restore a recorded version as the current document */
+export interface DocVersionRestoreRequest {
+  requestId: string;
+  docId: string;
+  serverVersion: number;
+}
+
+/** Document version restore response */
+export interface DocVersionRestoreResponse {
+  requestId: string;
+  success: boolean;
+  /** New `serverVersion` created by the restore */
+  serverVersion?: number;
+  error?: string;
+}
+
+/** A document sitting in the trash */
+export interface TrashEntry {
+  /** Metadata as it was at deletion (including owner and shares) */
+  metadata: DocumentMetadata;
+  /** Deletion timestamp in milliseconds */
+  deletedAt: number;
+  deletedBy?: string;
+  deletedByName?: string;
+}
+
+/** Trash list request (returns entries the caller owns, or all for admins) */
+export interface TrashListRequest {
+  requestId: string;
+}
+
+/** Trash list response */
+export interface TrashListResponse {
+  requestId: string;
+  entries: TrashEntry[];
+  error?: string;
+}
+
+/** Restore a document from the trash */
+export interface TrashRestoreRequest {
+  requestId: string;
+  docId: string;
+}
+
+/** Trash restore response */
+export interface TrashRestoreResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+}
+
+/** Which part of a document a search hit came from */
+export type SearchField =
+  | 'documentName'
+  | 'pageName'
+  | 'shapeText'
+  | 'richTextPageName'
+  | 'richTextContent';
+
+/** A search result */
+export interface SearchHit {
+  docId: string;
+  docName: string;
+  field: SearchField;
+  pageId?: string;
+  shapeId?: string;
+  /** Matched text with surrounding context */
+  snippet: string;
+}
+
+/** Full-text search across the documents the caller can read */
+export interface SearchRequest {
+  requestId: string;
+  query: string;
+  /** Maximum number of hits (server default if omitted) */
+  limit?: number;
+}
+
+/** Search response */
+export interface SearchResponse {
+  requestId: string;
+  hits: SearchHit[];
+  error?: string;
+}
+
+/** A folder in the team document tree */
+export interface Folder {
+  id: string;
+  name: string;
+  /** Parent folder, absent for top-level folders */
+  parentId?: string;
+  ownerId?: string;
+  ownerName?: string;
+  /** Shares applied to documents created inside this folder */
+  defaultShares: DocumentShare[];
+  createdAt: number;
+  modifiedAt: number;
+}
+
+/** Folder list request */
+export interface FolderListRequest {
+  requestId: string;
+}
+
+/** Folder list response (the whole tree, flat) */
+export interface FolderListResponse {
+  requestId: string;
+  folders: Folder[];
+  error?: string;
+}
+
+/** Create a folder; the caller becomes its owner */
+export interface FolderCreateRequest {
+  requestId: string;
+  name: string;
+  parentId?: string;
+  /** Shares inherited by documents created in the folder */
+  defaultShares?: ShareEntry[];
+}
+
+/** Rename a folder */
+export interface FolderRenameRequest {
+  requestId: string;
+  folderId: string;
+  name: string;
+}
+
+/** Move a folder under another one (no parent = top level) */
+export interface FolderMoveRequest {
+  requestId: string;
+  folderId: string;
+  parentId?: string;
+}
+
+/** Delete a folder; its contents move to its parent */
+export interface FolderDeleteRequest {
+  requestId: string;
+  folderId: string;
+}
+
+/** Replace a folder's default shares */
+export interface FolderSharesRequest {
+  requestId: string;
+  folderId: string;
+  defaultShares: ShareEntry[];
+}
+
+/** Response to folder create/rename/move/delete/shares */
+export interface FolderResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  /** The folder after the change (before removal, for deletes) */
+  folder?: Folder;
+}
+
+/** Folder event types */
+export type FolderEventType = 'created' | 'updated' | 'deleted';
+
+/** Folder change broadcast to all clients */
+export interface FolderEvent {
+  eventType: FolderEventType;
+  folder: Folder;
+  userId: string;
+}
+
+/** File a document in a folder (no folder = top level) */
+export interface DocMoveRequest {
+  requestId: string;
+  docId: string;
+  folderId?: string;
+}
+
+/** Replace a document's tags */
+export interface DocTagsRequest {
+  requestId: string;
+  docId: string;
+  tags: string[];
+}
+
+/** Response to DOC_MOVE and DOC_TAGS */
+export interface DocOrganizeResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  metadata?: DocumentMetadata;
+}
+
+/** Duplicate a document; the caller owns the copy */
+export interface DocDuplicateRequest {
+  requestId: string;
+  docId: string;
+  /** Name of the copy (defaults to "<name> (copy)") */
+  name?: string;
+}
+
+/** Response to DOC_DUPLICATE and DOC_FROM_TEMPLATE */
+export interface DocDuplicateResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  /** Metadata of the new document */
+  metadata?: DocumentMetadata;
+  /** Structural problems fixed before saving (lenient validation mode) */
+  repairs?: ValidationIssue[];
+}
+
+/** Copy a page into another (or the same) document */
+export interface PageCopyRequest {
+  requestId: string;
+  sourceDocId: string;
+  pageId: string;
+  targetDocId: string;
+}
+
+/** Page copy response */
+export interface PageCopyResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  /** ID of the new page in the target document */
+  pageId?: string;
+  /** Target `serverVersion` after the copy */
+  serverVersion?: number;
+  /** Structural problems fixed before saving (lenient validation mode) */
+  repairs?: ValidationIssue[];
+}
+
+/** Fetch a single page of a document */
+export interface PageGetRequest {
+  requestId: string;
+  docId: string;
+  pageId: string;
+}
+
+/** Page get response */
+export interface PageGetResponse {
+  requestId: string;
+  page?: unknown;
+  /** Document `serverVersion` the page was read at */
+  serverVersion?: number;
+  error?: string;
+}
+
+/** Add an empty page */
+export interface PageCreateRequest {
+  requestId: string;
+  docId: string;
+  name: string;
+  /** Position in `pageOrder` (defaults to the end) */
+  index?: number;
+}
+
+/** Rename a page */
+export interface PageRenameRequest {
+  requestId: string;
+  docId: string;
+  pageId: string;
+  name: string;
+}
+
+/** Replace a document's page order */
+export interface PageReorderRequest {
+  requestId: string;
+  docId: string;
+  pageOrder: string[];
+}
+
+/** Delete a page */
+export interface PageDeleteRequest {
+  requestId: string;
+  docId: string;
+  pageId: string;
+}
+
+/** Response to PAGE_CREATE, PAGE_RENAME, PAGE_REORDER and PAGE_DELETE */
+export interface PageResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  /** Page that was created, renamed or deleted */
+  pageId?: string;
+  serverVersion?: number;
+  /** Structural problems fixed before saving (lenient validation mode) */
+  repairs?: ValidationIssue[];
+}
+
+/** Page event types */
+export type PageEventType = 'created' | 'renamed' | 'reordered' | 'deleted';
+
+/** Page change broadcast to clients that joined the document */
+export interface PageEvent {
+  eventType: PageEventType;
+  docId: string;
+  pageId?: string;
+  /** Summary of the created or renamed page (no shapes) */
+  page?: unknown;
+  /** `pageOrder` after the change */
+  pageOrder: string[];
+  serverVersion: number;
+  userId: string;
+}
+
+/** Template listing entry */
+export interface TemplateInfo {
+  id: string;
+  name: string;
+  description: string;
+  category: string;
+  ownerId?: string;
+  ownerName?: string;
+  /** Document the template was made from */
+  sourceDocId?: string;
+  pageCount: number;
+  createdAt: number;
+}
+
+/** Template list request */
+export interface TemplateListRequest {
+  requestId: string;
+  /** Only list templates in this category */
+  category?: string;
+}
+
+/** Template list response, sorted by category and name */
+export interface TemplateListResponse {
+  requestId: string;
+  templates: TemplateInfo[];
+}
+
+/** Save a copy of a document as a template; the caller owns the template */
+export interface TemplateSaveRequest {
+  requestId: string;
+  docId: string;
+  name: string;
+  description?: string;
+  category?: string;
+}
+
+/** Delete a template */
+export interface TemplateDeleteRequest {
+  requestId: string;
+  templateId: string;
+}
+
+/** Response to TEMPLATE_SAVE and TEMPLATE_DELETE */
+export interface TemplateResponse {
+  requestId: string;
+  success: boolean;
+  error?: string;
+  template?: TemplateInfo;
+}
+
+/** Create a document from a template; the caller owns the new document */
+export interface DocFromTemplateRequest {
+  requestId: string;
+  templateId: string;
+  /** Name of the new document (defaults to the template name) */
+  name?: string;
+  /** Folder to file the new document in */
+  folderId?: string;
+}
+
+/** Byte and document-count limits; absent means unlimited */
+export interface QuotaLimits {
+  maxBytes?: number;
+  maxDocuments?: number;
+}
+
+/** Storage used by one user or the whole store */
+export interface Usage {
+  documentCount: number;
+  documentBytes: number;
+  blobCount: number;
+  blobBytes: number;
+}
+
+/** One user's row in the usage report */
+export interface UserUsage {
+  userId: string;
+  username?: string;
+  usage: Usage;
+  totalBytes: number;
+  limits: QuotaLimits;
+}
+
+/** Usage report for admins */
+export interface UsageReport {
+  /** Users sorted by total bytes, largest first */
+  users: UserUsage[];
+  total: Usage;
+  totalBytes: number;
+  globalLimits: QuotaLimits;
+}
+
+/** Storage usage report request */
+export interface UsageReportRequest {
+  requestId: string;
+}
+
+/** Storage usage report response */
+export interface UsageReportResponse {
+  requestId: string;
+  report?: UsageReport;
+  error?: string;
+}
+
+/** Order of a blob inventory page */
+export type BlobSort = 'largest' | 'newest' | 'oldest';
+
+/** Which blobs to list */
+export interface BlobQuery {
+  offset?: number;
+  /** Page size (server default if omitted) */
+  limit?: number;
+  sort?: BlobSort;
+  /** Only blobs uploaded by this user */
+  uploadedBy?: string;
+  /** Only blobs used by this document */
+  documentId?: string;
+  /** Only blobs no document uses */
+  unreferenced?: boolean;
+}
+
+/** A document using a blob */
+export interface BlobDocument {
+  id: string;
+  name?: string;
+}
+
+/** One blob in an inventory page */
+export interface BlobEntry {
+  hash: string;
+  size: number;
+  mimeType: string;
+  createdAt: number;
+  uploadedBy: string;
+  uploaderName?: string;
+  documents: BlobDocument[];
+}
+
+/** A page of the blob inventory */
+export interface BlobPage {
+  blobs: BlobEntry[];
+  offset: number;
+  /** Blobs matching the query, across all pages */
+  matching: number;
+  totalBlobs: number;
+  totalBytes: number;
+}
+
+/** Blob storage charged to one uploader */
+export interface UserBlobUsage {
+  userId: string;
+  username?: string;
+  blobCount: number;
+  blobBytes: number;
+}
+
+/** Blob storage used by one document */
+export interface DocumentBlobUsage {
+  documentId: string;
+  name?: string;
+  blobCount: number;
+  blobBytes: number;
+  /** Bytes of blobs no other document uses */
+  exclusiveBytes: number;
+}
+
+/** Blob storage by uploader and by document */
+export interface BlobUsageSummary {
+  totalBlobs: number;
+  totalBytes: number;
+  unreferencedBlobs: number;
+  unreferencedBytes: number;
+  /** Largest first */
+  byUser: UserBlobUsage[];
+  /** Largest first */
+  byDocument: DocumentBlobUsage[];
+}
+
+/** Blob inventory request (admins only) */
+export interface BlobInventoryRequest {
+  requestId: string;
+  query?: BlobQuery;
+  /** Also return usage by uploader and by document */
+  summary?: boolean;
+}
+
+/** Blob inventory response */
+export interface BlobInventoryResponse {
+  requestId: string;
+  page?: BlobPage;
+  summary?: BlobUsageSummary;
+  error?: string;
+}
+
 /** Error response */
 export interface ErrorResponse {
   requestId?: string;
@@ -408,6 +1108,37 @@ export function getMessageChannel(msgType: number): MessageChannel {
     case MESSAGE_JOIN_DOC:
     case MESSAGE_DOC_SHARE:
     case MESSAGE_DOC_TRANSFER:
+    case MESSAGE_DOC_PATCH:
+    case MESSAGE_DOC_PATCH_EVENT:
+    case MESSAGE_DOC_HISTORY:
+    case MESSAGE_DOC_VERSION_GET:
+    case MESSAGE_DOC_VERSION_RESTORE:
+    case MESSAGE_TRASH_LIST:
+    case MESSAGE_TRASH_RESTORE:
+    case MESSAGE_SEARCH:
+    case MESSAGE_FOLDER_LIST:
+    case MESSAGE_FOLDER_CREATE:
+    case MESSAGE_FOLDER_RENAME:
+    case MESSAGE_FOLDER_MOVE:
+    case MESSAGE_FOLDER_DELETE:
+    case MESSAGE_FOLDER_SHARES:
+    case MESSAGE_FOLDER_EVENT:
+    case MESSAGE_DOC_MOVE:
+    case MESSAGE_DOC_TAGS:
+    case MESSAGE_DOC_DUPLICATE:
+    case MESSAGE_PAGE_COPY:
+    case MESSAGE_PAGE_GET:
+    case MESSAGE_PAGE_CREATE:
+    case MESSAGE_PAGE_RENAME:
+    case MESSAGE_PAGE_REORDER:
+    case MESSAGE_PAGE_DELETE:
+    case MESSAGE_PAGE_EVENT:
+    case MESSAGE_TEMPLATE_LIST:
+    case MESSAGE_TEMPLATE_SAVE:
+    case MESSAGE_TEMPLATE_DELETE:
+    case MESSAGE_DOC_FROM_TEMPLATE:
+    case MESSAGE_USAGE_REPORT:
+    case MESSAGE_BLOB_INVENTORY:
     case MESSAGE_ERROR:
       return 'document';
 
@@ -445,6 +1176,37 @@ export function isDocumentMessage(msgType: number): boolean {
          msgType === MESSAGE_JOIN_DOC ||
          msgType === MESSAGE_DOC_SHARE ||
          msgType === MESSAGE_DOC_TRANSFER ||
+         msgType === MESSAGE_DOC_PATCH ||
+         msgType === MESSAGE_DOC_PATCH_EVENT ||
+         msgType === MESSAGE_DOC_HISTORY ||
+         msgType === MESSAGE_DOC_VERSION_GET ||
+         msgType === MESSAGE_DOC_VERSION_RESTORE ||
+         msgType === MESSAGE_TRASH_LIST ||
+         msgType === MESSAGE_TRASH_RESTORE ||
+         msgType === MESSAGE_SEARCH ||
+         msgType === MESSAGE_FOLDER_LIST ||
+         msgType === MESSAGE_FOLDER_CREATE ||
+         msgType === MESSAGE_FOLDER_RENAME ||
+         msgType === MESSAGE_FOLDER_MOVE ||
+         msgType === MESSAGE_FOLDER_DELETE ||
+         msgType === MESSAGE_FOLDER_SHARES ||
+         msgType === MESSAGE_FOLDER_EVENT ||
+         msgType === MESSAGE_DOC_MOVE ||
+         msgType === MESSAGE_DOC_TAGS ||
+         msgType === MESSAGE_DOC_DUPLICATE ||
+         msgType === MESSAGE_PAGE_COPY ||
+         msgType === MESSAGE_PAGE_GET ||
+         msgType === MESSAGE_PAGE_CREATE ||
+         msgType === MESSAGE_PAGE_RENAME ||
+         msgType === MESSAGE_PAGE_REORDER ||
+         msgType === MESSAGE_PAGE_DELETE ||
+         msgType === MESSAGE_PAGE_EVENT ||
+         msgType === MESSAGE_TEMPLATE_LIST ||
+         msgType === MESSAGE_TEMPLATE_SAVE ||
+         msgType === MESSAGE_TEMPLATE_DELETE ||
+         msgType === MESSAGE_DOC_FROM_TEMPLATE ||
+         msgType === MESSAGE_USAGE_REPORT ||
+         msgType === MESSAGE_BLOB_INVENTORY ||
          msgType === MESSAGE_ERROR;
 }
 
@@ -458,6 +1220,34 @@ export function isRequestMessage(msgType: number): boolean {
          msgType === MESSAGE_DOC_DELETE ||
          msgType === MESSAGE_DOC_SHARE ||
          msgType === MESSAGE_DOC_TRANSFER ||
+         msgType === MESSAGE_DOC_PATCH ||
+         msgType === MESSAGE_DOC_HISTORY ||
+         msgType === MESSAGE_DOC_VERSION_GET ||
+         msgType === MESSAGE_DOC_VERSION_RESTORE ||
+         msgType === MESSAGE_TRASH_LIST ||
+         msgType === MESSAGE_TRASH_RESTORE ||
+         msgType === MESSAGE_SEARCH ||
+         msgType === MESSAGE_FOLDER_LIST ||
+         msgType === MESSAGE_FOLDER_CREATE ||
+         msgType === MESSAGE_FOLDER_RENAME ||
+         msgType === MESSAGE_FOLDER_MOVE ||
+         msgType === MESSAGE_FOLDER_DELETE ||
+         msgType === MESSAGE_FOLDER_SHARES ||
+         msgType === MESSAGE_DOC_MOVE ||
+         msgType === MESSAGE_DOC_TAGS ||
+         msgType === MESSAGE_DOC_DUPLICATE ||
+         msgType === MESSAGE_PAGE_COPY ||
+         msgType === MESSAGE_PAGE_GET ||
+         msgType === MESSAGE_PAGE_CREATE ||
+         msgType === MESSAGE_PAGE_RENAME ||
+         msgType === MESSAGE_PAGE_REORDER ||
+         msgType === MESSAGE_PAGE_DELETE ||
+         msgType === MESSAGE_TEMPLATE_LIST ||
+         msgType === MESSAGE_TEMPLATE_SAVE ||
+         msgType === MESSAGE_TEMPLATE_DELETE ||
+         msgType === MESSAGE_DOC_FROM_TEMPLATE ||
+         msgType === MESSAGE_USAGE_REPORT ||
+         msgType === MESSAGE_BLOB_INVENTORY ||
          msgType === MESSAGE_AUTH_LOGIN;
 }
 
@@ -503,6 +1293,37 @@ export function getMessageTypeName(msgType: number): string {
     case MESSAGE_AUTH_LOGIN: return 'AUTH_LOGIN';
     case MESSAGE_DOC_SHARE: return 'DOC_SHARE';
     case MESSAGE_DOC_TRANSFER: return 'DOC_TRANSFER';
+    case MESSAGE_DOC_PATCH: return 'DOC_PATCH';
+    case MESSAGE_DOC_PATCH_EVENT: return 'DOC_PATCH_EVENT';
+    case MESSAGE_DOC_HISTORY: return 'DOC_HISTORY';
+    case MESSAGE_DOC_VERSION_GET: return 'DOC_VERSION_GET';
+    case MESSAGE_DOC_VERSION_RESTORE: return 'DOC_VERSION_RESTORE';
+    case MESSAGE_TRASH_LIST: return 'TRASH_LIST';
+    case MESSAGE_TRASH_RESTORE: return 'TRASH_RESTORE';
+    case MESSAGE_SEARCH: return 'SEARCH';
+    case MESSAGE_FOLDER_LIST: return 'FOLDER_LIST';
+    case MESSAGE_FOLDER_CREATE: return 'FOLDER_CREATE';
+    case MESSAGE_FOLDER_RENAME: return 'FOLDER_RENAME';
+    case MESSAGE_FOLDER_MOVE: return 'FOLDER_MOVE';
+    case MESSAGE_FOLDER_DELETE: return 'FOLDER_DELETE';
+    case MESSAGE_FOLDER_SHARES: return 'FOLDER_SHARES';
+    case MESSAGE_FOLDER_EVENT: return 'FOLDER_EVENT';
+    case MESSAGE_DOC_MOVE: return 'DOC_MOVE';
+    case MESSAGE_DOC_TAGS: return 'DOC_TAGS';
+    case MESSAGE_DOC_DUPLICATE: return 'DOC_DUPLICATE';
+    case MESSAGE_PAGE_COPY: return 'PAGE_COPY';
+    case MESSAGE_PAGE_GET: return 'PAGE_GET';
+    case MESSAGE_PAGE_CREATE: return 'PAGE_CREATE';
+    case MESSAGE_PAGE_RENAME: return 'PAGE_RENAME';
+    case MESSAGE_PAGE_REORDER: return 'PAGE_REORDER';
+    case MESSAGE_PAGE_DELETE: return 'PAGE_DELETE';
+    case MESSAGE_PAGE_EVENT: return 'PAGE_EVENT';
+    case MESSAGE_TEMPLATE_LIST: return 'TEMPLATE_LIST';
+    case MESSAGE_TEMPLATE_SAVE: return 'TEMPLATE_SAVE';
+    case MESSAGE_TEMPLATE_DELETE: return 'TEMPLATE_DELETE';
+    case MESSAGE_DOC_FROM_TEMPLATE: return 'DOC_FROM_TEMPLATE';
+    case MESSAGE_USAGE_REPORT: return 'USAGE_REPORT';
+    case MESSAGE_BLOB_INVENTORY: return 'BLOB_INVENTORY';
     default: return `UNKNOWN(${msgType})`;
   }
 }
\ No newline at end of file